@@ -0,0 +1,258 @@
+use super::{
+    Config,
+    cache::{CacheManager, SiteCache, query_site},
+    config_files::ConfigFiles,
+    github::GitHub,
+    opml,
+    origins::Origins,
+    replace, sanitize,
+    state::StatePaths,
+};
+
+use anyhow::{Context, Result};
+use std::{collections::HashSet, path::Path};
+
+/// Add a site for the feed at `feed_url` to the [editable](ConfigFiles::editable) config file,
+/// and print the entry added.
+///
+/// The feed is fetched first, to check that it parses, and the site is named by the feed's title
+/// unless given a `name`. Fails, without changing anything, if a site already has the feed's URL
+/// or the name. The rest of the config file is kept as it was, and the fetch is kept in the new
+/// site's cache, so the next run doesn't fetch it again.
+pub async fn add(
+    config_files: &ConfigFiles,
+    cache_dir: &Path,
+    state: &StatePaths,
+    feed_url: &str,
+    name: Option<&str>,
+) -> Result<()> {
+    let config_path = config_files.editable();
+    let doc = opml::read_config(config_path)?;
+    let (names, urls) = taken(config_files, &doc)?;
+    if urls.contains(&opml::normalize_url(feed_url)) {
+        anyhow::bail!("{feed_url} is already in the config");
+    }
+    if let Some(name) = name
+        && names.contains(name)
+    {
+        anyhow::bail!("There's already a site named {name:?}, so give another with `--name`");
+    }
+
+    // Until the feed is fetched we might not know its name, so it's fetched as a site named by
+    // its URL, which is only used in log messages.
+    let config = config_files
+        .load_with_edit(&with_site(&doc, name.unwrap_or(feed_url), feed_url).to_string())
+        .context("Config with the new site would be invalid")?;
+    let mut cache = SiteCache::default();
+    let title = fetch(&config, state, name.unwrap_or(feed_url), &mut cache).await?;
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => title
+            .map(|title| sanitize::normalize_text(&title, &config.limits))
+            .filter(|title| !title.is_empty())
+            .or_else(|| {
+                reqwest::Url::parse(feed_url)
+                    .ok()?
+                    .host_str()
+                    .map(str::to_owned)
+            })
+            .unwrap_or_else(|| feed_url.to_owned()),
+    };
+    if names.contains(&name) {
+        anyhow::bail!(
+            "There's already a site named {name:?}, the feed's title, so give another with \
+             `--name`"
+        );
+    }
+
+    let updated = with_site(&doc, &name, feed_url);
+    let config = config_files
+        .load_with_edit(&updated.to_string())
+        .context("Config with the new site would be invalid")?;
+    replace::write_atomically(config_path, updated.to_string().as_bytes())
+        .context("Failed to write config")?;
+    let mut entry = toml_edit::DocumentMut::new();
+    entry["sites"] =
+        toml_edit::Item::ArrayOfTables(std::iter::once(site_table(&name, feed_url)).collect());
+    println!("Added to {}:\n\n{entry}", config_path.display());
+
+    // The config is already written, so failing to keep the fetch only costs fetching it again.
+    if let Err(e) = keep_fetch(&config, cache_dir, &name, cache).await {
+        log::warn!("Failed to cache the feed for {name}, so the next run fetches it again: {e:?}");
+    }
+    Ok(())
+}
+
+/// The names and [normalized](opml::normalize_url) feed URLs of the sites already in the config,
+/// including disabled ones.
+///
+/// If the config can't be loaded, because it doesn't exist yet, only the sites in `doc` are
+/// counted.
+fn taken(
+    config_files: &ConfigFiles,
+    doc: &toml_edit::DocumentMut,
+) -> Result<(HashSet<String>, HashSet<String>)> {
+    let (mut names, mut urls) = (HashSet::new(), HashSet::new());
+    if let Ok(config) = config_files.load_including_disabled() {
+        for site in &config.sites {
+            names.insert(site.name.to_string());
+            urls.insert(opml::normalize_url(&site.feed_url));
+        }
+    }
+    if let Some(sites) = doc.get("sites") {
+        let sites = sites
+            .as_array_of_tables()
+            .context("`sites` in the config file isn't a list of [[sites]]")?;
+        for site in sites {
+            let attr = |name| site.get(name).and_then(toml_edit::Item::as_str);
+            names.extend(attr("name").map(str::to_owned));
+            urls.extend(attr("feed_url").map(opml::normalize_url));
+        }
+    }
+    Ok((names, urls))
+}
+
+/// `doc` with a site added to the end of its `[[sites]]`.
+fn with_site(doc: &toml_edit::DocumentMut, name: &str, feed_url: &str) -> toml_edit::DocumentMut {
+    let mut doc = doc.clone();
+    doc.entry("sites")
+        .or_insert_with(|| toml_edit::ArrayOfTables::new().into())
+        .as_array_of_tables_mut()
+        .expect("Checked to be a list of [[sites]]")
+        .push(site_table(name, feed_url));
+    doc
+}
+
+/// The table of a site with only a name and feed URL, leaving everything else to the defaults.
+fn site_table(name: &str, feed_url: &str) -> toml_edit::Table {
+    let mut site = toml_edit::Table::new();
+    site["name"] = toml_edit::value(name);
+    site["feed_url"] = toml_edit::value(feed_url);
+    site
+}
+
+/// Fetch the site named `name` in `config` into `cache`, checking that its feed parses, and
+/// returning the feed's title if it has one.
+async fn fetch(
+    config: &Config,
+    state: &StatePaths,
+    name: &str,
+    cache: &mut SiteCache,
+) -> Result<Option<String>> {
+    let site = config
+        .sites
+        .iter()
+        .find(|site| *site.name == *name)
+        .context("The new site isn't in the config")?;
+    if site.feed_url.starts_with("https:") {
+        config.http.check_roots()?;
+    }
+    let client = config.http.client_builder().build()?;
+    let github = GitHub::new(config, state)?;
+    let origins = Origins::load(state);
+    let fetched = query_site(&client, config, &github, &origins, site, cache).await;
+    if let Err(e) = origins.save() {
+        log::warn!("{e:?}");
+    }
+    fetched.with_context(|| format!("Failed to fetch {}", site.feed_url))?;
+    let body = cache
+        .last_body
+        .as_ref()
+        .with_context(|| format!("{} didn't respond with a feed", site.feed_url))?;
+    let feed = feed_rs::parser::parse(std::io::Cursor::new(body.as_bytes()))
+        .with_context(|| format!("{} isn't a feed we can read", site.feed_url))?;
+    Ok(feed.title.map(|title| title.content))
+}
+
+/// Keep the fetch in `cache` as the cache of the site named `name`, and save it.
+async fn keep_fetch(config: &Config, cache_dir: &Path, name: &str, cache: SiteCache) -> Result<()> {
+    let site = config
+        .sites
+        .iter()
+        .find(|site| *site.name == *name)
+        .context("The new site isn't in the config")?;
+    let caches = CacheManager::new(
+        cache_dir.to_owned(),
+        config.cache_key,
+        std::slice::from_ref(site),
+    )?;
+    {
+        let guard = caches.cache_guard();
+        let mut kept = caches.get_mut(site, &guard).await?;
+        *kept = cache;
+        kept.mark_dirty();
+    }
+    if let Some((_, e)) = caches.save(config.max_concurrent_saves).await.pop() {
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, test_util::test_dir};
+
+    #[tokio::test]
+    async fn feeds_are_added_named_by_their_title_and_cached() {
+        let dir = test_dir("add");
+        let server = test_server::serve_http(|head| {
+            if head.starts_with("GET /feed.xml ") {
+                test_server::response(
+                    "200 OK",
+                    "application/rss+xml",
+                    "<rss version=\"2.0\"><channel><title> Example\r\n Feed </title>\
+                     <item><title>Post</title><link>https://example.com/1</link></item>\
+                     </channel></rss>",
+                )
+            } else {
+                test_server::response("200 OK", "text/html", "<html></html>")
+            }
+        })
+        .await;
+        let config_path = dir.join("config.toml");
+        let original = "# My feeds\nmin_fetch_interval = 0\n\n\
+                        [[sites]]\nname = \"Existing\"\nfeed_url = \"https://example.com/feed\"\n";
+        std::fs::write(&config_path, original).unwrap();
+        let config_files = ConfigFiles::new(vec![config_path.clone()]);
+        let (cache_dir, state) = (dir.join("cache"), StatePaths::new(dir.join("state")));
+        let feed_url = format!("http://{server}/feed.xml");
+
+        add(&config_files, &cache_dir, &state, &feed_url, None)
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        assert_eq!(
+            contents,
+            format!("{original}\n[[sites]]\nname = \"Example Feed\"\nfeed_url = \"{feed_url}\"\n")
+        );
+        // The fetch is kept, so the next run doesn't need to make it again.
+        let config = config_files.load().unwrap();
+        let caches = CacheManager::new(cache_dir.clone(), config.cache_key, &config.sites).unwrap();
+        assert!(caches.cache_path(&config.sites[1]).exists());
+
+        // Feeds and names already in the config, and pages which aren't feeds, are refused
+        // without changing it.
+        let errors = [
+            (feed_url.as_str(), None),
+            ("https://example.org/feed", Some("Existing")),
+            (&format!("http://{server}/page.html"), Some("Page")),
+        ];
+        let mut messages = Vec::new();
+        for (url, name) in errors {
+            let e = add(&config_files, &cache_dir, &state, url, name)
+                .await
+                .unwrap_err();
+            messages.push(format!("{e:#}"));
+        }
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), contents);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(messages[0], format!("{feed_url} is already in the config"));
+        assert!(messages[1].starts_with("There's already a site named \"Existing\""));
+        assert!(
+            messages[2].contains("isn't a feed we can read"),
+            "{}",
+            messages[2]
+        );
+    }
+}
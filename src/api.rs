@@ -0,0 +1,393 @@
+use super::{
+    CollectedArticles, Config, FeedEntryInfo, cache::CacheManager, collect_articles,
+    config_files::ConfigFiles, fetch_sites, github::GitHub, origins::Origins, page_context,
+    sanitize, state::StatePaths, status, summary::RunSummary, trace::ArticleTrace,
+};
+
+use std::{path::PathBuf, sync::Mutex};
+
+/// Reads the feeds of a jarss config, for use from other programs, like static site generators.
+///
+/// Every method blocks until it's done, running on a runtime of its own, so they mustn't be called
+/// from within an async runtime.
+///
+/// ```no_run
+/// let jarss = jarss::Jarss::builder()
+///     .config_path("blogroll.toml")
+///     .cache_dir("target/jarss")
+///     .build()?;
+/// let page = jarss.render_with_template(
+///     "{% for article in articles %}<a href=\"{{ article.link }}\">{{ article.title }}</a>\n{% endfor %}",
+/// )?;
+/// # Ok::<(), jarss::Error>(())
+/// ```
+pub struct Jarss {
+    config: Config,
+    caches: CacheManager,
+    offline: bool,
+    runtime: tokio::runtime::Runtime,
+    /// How the latest fetch went, or `None` if the feeds haven't been fetched yet.
+    fetched: Mutex<Option<RunSummary>>,
+}
+impl Jarss {
+    /// Start building a [`Jarss`], which reads the same config and cache as the `jarss` command
+    /// would by default.
+    pub fn builder() -> JarssBuilder {
+        JarssBuilder::default()
+    }
+
+    /// Fetch the feeds which are due, unless [offline](JarssBuilder::offline), and save their
+    /// caches.
+    ///
+    /// The other methods fetch the feeds the first time one of them is called, so this is only
+    /// needed to fetch them again, as a long-running program might. Sites which fail to fetch, or
+    /// whose caches can't be saved, are logged and then skipped.
+    pub fn refresh(&self) -> Result<(), Error> {
+        let summary = self.fetch()?;
+        *self.fetched.lock().unwrap() = Some(summary);
+        Ok(())
+    }
+
+    /// Fetch the feeds which are due, unless [offline](JarssBuilder::offline), and collect the
+    /// articles to show from the caches, newest first.
+    ///
+    /// The feeds are only fetched the first time this or another method is called, and again on
+    /// [`refresh`](Self::refresh). Sites which fail to fetch, or whose caches can't be read, are
+    /// logged and then skipped, as they are by the `jarss` command. Sites which have never been
+    /// fetched have no articles.
+    pub fn collect_articles(&self) -> Result<Vec<Article>, Error> {
+        let (collected, _) = self.collect()?;
+        Ok(collected.articles.iter().map(Article::from).collect())
+    }
+
+    /// Fetch and collect the articles like [`collect_articles`](Self::collect_articles), and
+    /// render them with the given [`tera`] template.
+    ///
+    /// The template gets the same context as the `jarss` command's page templates, except for the
+    /// search index, so `output_url` isn't available.
+    pub fn render_with_template(&self, template: &str) -> Result<String, Error> {
+        let (collected, summary) = self.collect()?;
+        let CollectedArticles {
+            articles,
+            metadata,
+            random_picks,
+            site_stats,
+            ..
+        } = collected;
+        let tera_ctx = self.runtime.block_on(async {
+            let statuses =
+                status::site_statuses(&self.config, &self.caches, std::time::SystemTime::now())
+                    .await;
+            let sites = status::site_infos(&self.config, &self.caches, &metadata, &articles).await;
+            let site_errors = status::site_errors(&self.config, &self.caches, &summary).await;
+            page_context(
+                &self.config,
+                &articles,
+                &random_picks,
+                &site_stats,
+                &statuses,
+                &sites,
+                &site_errors,
+            )
+        });
+        let mut tera = sanitize::tera(&self.config.render);
+        tera.add_raw_template("output", template)
+            .map_err(|e| Error::Template(e.into()))?;
+        tera.render("output", &tera_ctx)
+            .map_err(|e| Error::Template(e.into()))
+    }
+
+    /// Collect the articles, fetching the feeds first if they haven't been yet.
+    fn collect(&self) -> Result<(CollectedArticles, RunSummary), Error> {
+        let summary = {
+            let mut fetched = self.fetched.lock().unwrap();
+            match &*fetched {
+                Some(summary) => summary.clone(),
+                None => fetched.insert(self.fetch()?).clone(),
+            }
+        };
+        let collected = self.runtime.block_on(collect_articles(
+            &self.config,
+            &self.caches,
+            &ArticleTrace::new(None),
+        ));
+        Ok((collected, summary))
+    }
+
+    /// Fetch the feeds which are due, unless offline, and save their caches.
+    fn fetch(&self) -> Result<RunSummary, Error> {
+        self.runtime.block_on(async {
+            let mut summary = RunSummary::new(&self.config);
+            if !self.offline {
+                let state = self.caches.state();
+                let github =
+                    GitHub::new(&self.config, state).map_err(|e| Error::Config(e.into()))?;
+                let origins = Origins::load(state);
+                let sites = self.config.sites.iter().collect::<Vec<_>>();
+                fetch_sites(
+                    &self.config,
+                    &self.caches,
+                    &github,
+                    &origins,
+                    &sites,
+                    &mut summary,
+                )
+                .await
+                .map_err(|e| Error::Fetch(e.into()))?;
+            }
+            Ok(summary)
+        })
+    }
+}
+
+/// Builds a [`Jarss`].
+#[derive(Default)]
+pub struct JarssBuilder {
+    config_paths: Vec<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    offline: bool,
+}
+impl JarssBuilder {
+    /// Read the config from this file, instead of `jarss.toml` in the config directory.
+    ///
+    /// This can be given more than once, to merge the files in order, as with `jarss -c`.
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_paths.push(path.into());
+        self
+    }
+
+    /// Keep the caches in this directory, instead of `jarss` in the cache directory.
+    ///
+    /// Sharing it with the `jarss` command is fine, as long as they don't run at the same time.
+    pub fn cache_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(path.into());
+        self
+    }
+
+    /// Keep the state, like what notifications have been sent, in this directory.
+    ///
+    /// By default, this is the cache directory if one is given, and otherwise `jarss` in the state
+    /// directory, as with `jarss --state`.
+    pub fn state_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_dir = Some(path.into());
+        self
+    }
+
+    /// Whether to only read the caches, without fetching anything.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Load the config, and set up the caches it uses.
+    pub fn build(self) -> Result<Jarss, Error> {
+        let config_paths = if self.config_paths.is_empty() {
+            vec![super::default_config_path().map_err(|e| Error::Config(e.into()))?]
+        } else {
+            self.config_paths
+        };
+        let default_cache_dir = self.cache_dir.is_none();
+        let cache_dir = match self.cache_dir {
+            Some(cache_dir) => cache_dir,
+            None => super::default_cache_dir().map_err(|e| Error::Cache(e.into()))?,
+        };
+        let config = ConfigFiles::new(config_paths)
+            .load()
+            .map_err(|e| Error::Config(e.into()))?;
+        let state = StatePaths::resolve(self.state_dir, &cache_dir, default_cache_dir);
+        state.migrate_from(&cache_dir);
+        let caches = CacheManager::new(cache_dir, config.cache_key, &config.sites)
+            .map_err(|e| Error::Cache(e.into()))?
+            .with_state(state);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Runtime)?;
+        Ok(Jarss {
+            config,
+            caches,
+            offline: self.offline,
+            runtime,
+            fetched: Mutex::new(None),
+        })
+    }
+}
+
+/// An article collected from the feeds.
+///
+/// The strings have already been through the same limits and sanitizing as those given to
+/// templates.
+#[derive(Clone, Debug, serde::Serialize)]
+#[non_exhaustive]
+pub struct Article {
+    /// The title of the site, as given by its feed. Plain text.
+    pub site: String,
+    /// The site's homepage, if its feed gives one.
+    pub site_link: Option<String>,
+    /// The title of the article. Plain text.
+    pub title: String,
+    /// The link to the article.
+    pub link: String,
+    /// The ID of the article in its feed, which stays the same as long as the feed carries it.
+    pub id: String,
+    /// When the article was published, or else last updated.
+    pub published: chrono::DateTime<chrono::Utc>,
+    /// The summary of the article, if the feed gives one, as sanitized HTML.
+    pub summary_html: Option<String>,
+    /// The content of the article, if the feed gives it, as sanitized HTML.
+    pub content_html: Option<String>,
+    /// The start of the summary, or else the content, as plain text.
+    pub summary_text: Option<String>,
+    /// How many of the configured sites carried the article.
+    pub mentions: usize,
+    /// Whether the article first showed up in its site's feed in the latest fetch of it.
+    pub is_new: bool,
+    /// The article's position among those collected, from 1.
+    pub rank: usize,
+}
+impl From<&FeedEntryInfo> for Article {
+    fn from(info: &FeedEntryInfo) -> Self {
+        Self {
+            site: info.site.to_string(),
+            site_link: info.site_link.as_deref().map(str::to_owned),
+            title: info.title.to_string(),
+            link: info.link.to_string(),
+            id: info.id.to_string(),
+            published: info.published,
+            summary_html: info.summary.as_deref().map(str::to_owned),
+            content_html: info.content.as_deref().map(str::to_owned),
+            summary_text: info.summary_text.as_deref().map(str::to_owned),
+            mentions: info.mentions,
+            is_new: info.is_new,
+            rank: info.rank,
+        }
+    }
+}
+
+/// The error type of a failed [`Jarss`] operation.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The config couldn't be read, or isn't valid.
+    Config(Box<dyn std::error::Error + Send + Sync>),
+    /// The cache directory couldn't be found, or its caches can't be told apart.
+    Cache(Box<dyn std::error::Error + Send + Sync>),
+    /// The feeds couldn't be fetched at all. A site failing to fetch isn't an error.
+    Fetch(Box<dyn std::error::Error + Send + Sync>),
+    /// The template couldn't be parsed or rendered.
+    Template(Box<dyn std::error::Error + Send + Sync>),
+    /// The runtime the feeds are fetched on couldn't be started.
+    Runtime(std::io::Error),
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(e) => write!(f, "Invalid config: {e}"),
+            Self::Cache(e) => write!(f, "Error with the caches: {e}"),
+            Self::Fetch(e) => write!(f, "Error fetching feeds: {e}"),
+            Self::Template(e) => write!(f, "Error with the template: {e}"),
+            Self::Runtime(e) => write!(f, "Error starting the runtime: {e}"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Config(e) | Self::Cache(e) | Self::Fetch(e) | Self::Template(e) => Some(&**e),
+            Self::Runtime(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, test_util::test_dir};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn feeds_are_fetched_once_until_refreshed() {
+        // The server runs on a thread of its own, since `Jarss` blocks on its own runtime.
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&requests);
+        let (send_addr, server) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let addr = test_server::serve_http(move |_| {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    test_server::response(
+                        "200 OK",
+                        "application/rss+xml",
+                        "<rss version=\"2.0\"><channel><title>Blog</title>\
+                         <link>https://blog.example/</link>\
+                         <item><title>Hello</title><link>https://blog.example/hello</link>\
+                         <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item></channel></rss>",
+                    )
+                })
+                .await;
+                send_addr.send(addr).unwrap();
+                std::future::pending::<()>().await;
+            });
+        });
+        let server = server.recv().unwrap();
+        let dir = test_dir("api");
+        let config_path = dir.join("jarss.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "min_fetch_interval = 0\n\
+                 [[sites]]\nname = \"Blog\"\nfeed_url = \"http://{server}/feed\"\n"
+            ),
+        )
+        .unwrap();
+        let jarss = Jarss::builder()
+            .config_path(&config_path)
+            .cache_dir(dir.join("cache"))
+            .state_dir(dir.join("state"))
+            .build()
+            .unwrap();
+
+        let articles = jarss.collect_articles().unwrap();
+        assert_eq!(articles.len(), 1);
+        assert_eq!(articles[0].site, "Blog");
+        assert_eq!(
+            articles[0].site_link.as_deref(),
+            Some("https://blog.example/")
+        );
+        assert_eq!(articles[0].title, "Hello");
+        assert_eq!(articles[0].rank, 1);
+        let page = jarss
+            .render_with_template("{% for article in articles %}{{ article.title }}{% endfor %}")
+            .unwrap();
+        assert_eq!(page, "Hello");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        jarss.refresh().unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // A template which doesn't parse is the template's fault.
+        let e = jarss.render_with_template("{% if %}").unwrap_err();
+        assert!(matches!(e, Error::Template(_)), "{e}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_configs_are_config_errors() {
+        let dir = test_dir("api-missing");
+        let e = Jarss::builder()
+            .config_path(dir.join("missing.toml"))
+            .cache_dir(&dir)
+            .build()
+            .err()
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(matches!(e, Error::Config(_)), "{e}");
+    }
+}
@@ -0,0 +1,227 @@
+use super::FeedEntryInfo;
+
+use anyhow::Result;
+use std::{fmt::Write as _, path::Path};
+
+/// The title of the merged feed.
+const FEED_TITLE: &str = "jarss";
+
+/// Render the articles, in order, as an Atom feed to be written to `path`.
+///
+/// The feed's ID comes from where it's written, so it stays the same across runs and readers
+/// don't think it's a new feed.
+pub fn render_feed(path: &Path, articles: &[FeedEntryInfo]) -> Result<String> {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let id = blake3::hash(absolute.as_os_str().as_encoded_bytes()).to_hex();
+    let updated = articles
+        .iter()
+        .map(|article| article.published)
+        .max()
+        .unwrap_or_else(chrono::Utc::now);
+
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    writeln!(feed, "  <id>urn:jarss:{}</id>", &id[..32])?;
+    writeln!(feed, "  <title>{}</title>", escape(FEED_TITLE))?;
+    writeln!(feed, "  <updated>{}</updated>", updated.to_rfc3339())?;
+    writeln!(
+        feed,
+        "  <generator uri=\"{}\">jarss</generator>",
+        escape(env!("CARGO_PKG_REPOSITORY"))
+    )?;
+    for article in articles {
+        let link = escape(&article.link);
+        let published = article.published.to_rfc3339();
+        feed.push_str("  <entry>\n");
+        writeln!(feed, "    <id>{link}</id>")?;
+        writeln!(feed, "    <title>{}</title>", escape(&article.title))?;
+        writeln!(feed, "    <link href=\"{link}\" />")?;
+        writeln!(feed, "    <published>{published}</published>")?;
+        writeln!(feed, "    <updated>{published}</updated>")?;
+        writeln!(
+            feed,
+            "    <author><name>{}</name></author>",
+            escape(&article.site)
+        )?;
+        writeln!(feed, "    <category term=\"{}\" />", escape(&article.site))?;
+        if let Some(summary) = &article.summary {
+            writeln!(
+                feed,
+                "    <summary type=\"html\">{}</summary>",
+                escape(summary)
+            )?;
+        }
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+    Ok(feed)
+}
+
+/// Escape text for XML, dropping any characters XML can't represent at all.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() || matches!(c, '\u{FFFE}' | '\u{FFFF}') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An article from `site` titled `title`, published at the given hour of 1 January 2024.
+    fn article(site: &str, title: &str, summary: Option<&str>, hour: u32) -> FeedEntryInfo {
+        let published = chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc();
+        let link: Box<str> = format!("https://example.com/{hour}?a=1&b=2").into();
+        FeedEntryInfo {
+            site: site.into(),
+            published,
+            publish_date: published.date_naive(),
+            id: link.clone(),
+            site_link: None,
+            mentions: 1,
+            title: title.into(),
+            summary: summary.map(Into::into),
+            content: None,
+            summary_text: None,
+            suspect: false,
+            link_display: link.clone(),
+            link,
+            last_seen_in_feed: None,
+            original_link: None,
+            also_on: Vec::new(),
+            is_new: false,
+            rank: 0,
+            raw: None,
+            site_index: 0,
+            feed_position: 0,
+        }
+    }
+
+    /// The articles the fixture was rendered from.
+    fn articles() -> Vec<FeedEntryInfo> {
+        vec![
+            article(
+                "Tom & Jerry's",
+                "<Cats> \"and\" mice\u{7}",
+                Some("<p>A &amp; B</p>"),
+                12,
+            ),
+            article("Plain", "Nothing to escape", None, 9),
+        ]
+    }
+
+    #[test]
+    fn feeds_match_the_fixture() {
+        let feed = render_feed(Path::new("/srv/www/feed.xml"), &articles()).unwrap();
+        assert_eq!(feed, include_str!("../testdata/atom-feed.xml"));
+    }
+
+    #[test]
+    fn feeds_parse_back_to_the_articles() {
+        let feed = render_feed(Path::new("/srv/www/feed.xml"), &articles()).unwrap();
+        let parsed = feed_rs::parser::parse(feed.as_bytes()).unwrap();
+        assert_eq!(parsed.feed_type, feed_rs::model::FeedType::Atom);
+        let entries = parsed
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.id.as_str(),
+                    entry.title.as_ref().unwrap().content.as_str(),
+                    entry.links[0].href.as_str(),
+                    entry.authors[0].name.as_str(),
+                    entry
+                        .summary
+                        .as_ref()
+                        .map(|summary| summary.content.as_str()),
+                )
+            })
+            .collect::<Vec<_>>();
+        // Control characters XML can't hold are dropped, and everything else comes back as it was.
+        assert_eq!(
+            entries,
+            [
+                (
+                    "https://example.com/12?a=1&b=2",
+                    "<Cats> \"and\" mice",
+                    "https://example.com/12?a=1&b=2",
+                    "Tom & Jerry's",
+                    Some("<p>A &amp; B</p>"),
+                ),
+                (
+                    "https://example.com/9?a=1&b=2",
+                    "Nothing to escape",
+                    "https://example.com/9?a=1&b=2",
+                    "Plain",
+                    None,
+                ),
+            ]
+        );
+        // The feed was last updated by its newest article, wherever it is in the list.
+        assert_eq!(
+            parsed.updated.unwrap().to_rfc3339(),
+            "2024-01-01T12:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn feed_ids_come_from_where_theyre_written() {
+        let id = |path: &Path, articles: &[FeedEntryInfo]| {
+            let feed = render_feed(path, articles).unwrap();
+            feed_rs::parser::parse(feed.as_bytes()).unwrap().id
+        };
+        let path = Path::new("/srv/www/feed.xml");
+        assert_eq!(id(path, &articles()), id(path, &[]));
+        assert_ne!(id(path, &[]), id(Path::new("/srv/www/other.xml"), &[]));
+        // Relative paths are the same feed as the absolute paths they refer to.
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            id(Path::new("feed.xml"), &[]),
+            id(&cwd.join("feed.xml"), &[])
+        );
+    }
+
+    #[test]
+    fn empty_feeds_were_updated_now() {
+        let before = chrono::Utc::now();
+        let feed = render_feed(Path::new("/srv/www/feed.xml"), &[]).unwrap();
+        let parsed = feed_rs::parser::parse(feed.as_bytes()).unwrap();
+        assert!(parsed.entries.is_empty());
+        let updated = parsed.updated.unwrap();
+        // The feed's times are only to the second.
+        assert!(
+            updated >= before - chrono::Duration::seconds(1),
+            "{updated}"
+        );
+        assert!(updated <= chrono::Utc::now(), "{updated}");
+    }
+
+    #[test]
+    fn escaping_drops_what_xml_cant_hold() {
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;"
+        );
+        assert_eq!(escape("tab\tnewline\nreturn\r"), "tab\tnewline\nreturn\r");
+        assert_eq!(
+            escape("nul\0 bell\u{7} del\u{7F} \u{FFFE}\u{FFFF}é"),
+            "nul bell del é"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use super::SiteConfig;
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+/// The credentials to authenticate to a site with, using HTTP basic auth.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct BasicAuth {
+    pub username: Box<str>,
+    #[serde(flatten)]
+    pub password: Password,
+}
+
+/// Where the password for a site's auth comes from, which is shared by every kind of auth.
+///
+/// Exactly one of these must be given.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Password {
+    pub password: Option<Box<str>>,
+    /// An environment variable holding the password, so it needn't be in the config.
+    pub password_env: Option<Box<str>>,
+    /// A file holding the password, so it needn't be in the config. Line breaks at the end of the
+    /// file are ignored.
+    pub password_file: Option<PathBuf>,
+}
+impl Password {
+    /// Check that exactly one source is given for the password of the `auth` table of `site`.
+    fn check(&self, auth: &str, site: &str) -> Result<()> {
+        let sources = [
+            self.password.is_some(),
+            self.password_env.is_some(),
+            self.password_file.is_some(),
+        ];
+        if sources.into_iter().filter(|&given| given).count() != 1 {
+            anyhow::bail!(
+                "`{auth}` for site {site} needs exactly one of `password`, `password_env`, and \
+                 `password_file`"
+            );
+        }
+        Ok(())
+    }
+
+    /// Read the password, which is described as `what` in errors.
+    pub fn read(&self, what: &str) -> Result<Box<str>> {
+        if let Some(password) = &self.password {
+            Ok(password.clone())
+        } else if let Some(var) = &self.password_env {
+            std::env::var(var.as_ref())
+                .map(String::into_boxed_str)
+                .with_context(|| format!("Failed to read {what} from ${var}"))
+        } else if let Some(path) = &self.password_file {
+            let password = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {what} from {}", path.display()))?;
+            Ok(password.trim_end_matches(['\r', '\n']).into())
+        } else {
+            anyhow::bail!("No {what} is given")
+        }
+    }
+}
+
+/// Check that the given site's auth settings make sense, so mistakes are caught when the config
+/// is loaded rather than on every fetch.
+pub fn check(site: &SiteConfig) -> Result<()> {
+    if site.basic_auth.is_some() && site.digest_auth.is_some() {
+        anyhow::bail!(
+            "Site {} has both `basic_auth` and `digest_auth`, only one can be used",
+            site.name
+        );
+    }
+    if let Some(basic_auth) = &site.basic_auth {
+        basic_auth.password.check("basic_auth", &site.name)?;
+    }
+    if let Some(digest_auth) = &site.digest_auth {
+        digest_auth.password.check("digest_auth", &site.name)?;
+        // It's sent in a header, which only holds visible ASCII.
+        if !digest_auth
+            .username
+            .chars()
+            .all(|c| c == ' ' || c.is_ascii_graphic())
+        {
+            anyhow::bail!(
+                "Site {} has a `digest_auth` username with characters other than printable \
+                 ASCII, which digest auth can't send",
+                site.name
+            );
+        }
+    }
+    for name in site.headers.keys().chain(site.header_env.keys()) {
+        http::HeaderName::try_from(name.as_ref())
+            .with_context(|| format!("Invalid header name {name:?} for site {}", site.name))?;
+    }
+    Ok(())
+}
+
+/// Add the given site's extra headers and basic auth to a request for it.
+///
+/// The values are marked sensitive, so they aren't logged along with the request.
+pub fn apply(
+    site: &SiteConfig,
+    mut req: reqwest::RequestBuilder,
+) -> Result<reqwest::RequestBuilder> {
+    let env_headers = site.header_env.iter().map(|(name, var)| {
+        let value = std::env::var(var.as_ref()).with_context(|| {
+            format!(
+                "Failed to read header {name} for site {} from ${var}",
+                site.name
+            )
+        })?;
+        anyhow::Ok((name, value.into_boxed_str()))
+    });
+    let headers = site
+        .headers
+        .iter()
+        .map(|(name, value)| Ok((name, value.clone())))
+        .chain(env_headers);
+    for header in headers {
+        let (name, value) = header?;
+        let mut value = http::HeaderValue::from_str(&value)
+            .with_context(|| format!("Invalid value for header {name} for site {}", site.name))?;
+        value.set_sensitive(true);
+        req = req.header(name.as_ref(), value);
+    }
+    if let Some(basic_auth) = &site.basic_auth {
+        let password = basic_auth
+            .password
+            .read(&format!("basic auth password for site {}", site.name))?;
+        req = req.basic_auth(&basic_auth.username, Some(password));
+    }
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(auth: &str) -> Result<crate::Config> {
+        crate::config_files::to_config(
+            toml::from_str(&format!(
+                r#"
+                min_fetch_interval = 0
+                [[sites]]
+                name = "a"
+                feed_url = "https://a.example/feed"
+                {auth}
+                "#
+            ))
+            .unwrap(),
+            false,
+        )
+    }
+
+    #[test]
+    fn both_kinds_of_auth_take_the_same_password_sources() {
+        for auth in ["basic_auth", "digest_auth"] {
+            for source in [
+                r#"password = "p""#,
+                r#"password_env = "VAR""#,
+                r#"password_file = "pw.txt""#,
+            ] {
+                let config = format!(r#"{auth} = {{ username = "u", {source} }}"#);
+                assert!(load(&config).is_ok(), "{config}");
+            }
+            for sources in ["", r#", password = "p", password_file = "pw.txt""#] {
+                let config = format!(r#"{auth} = {{ username = "u"{sources} }}"#);
+                let e = load(&config).unwrap_err();
+                assert!(
+                    format!("{e:#}").contains("needs exactly one of"),
+                    "{config}: {e:#}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn digest_usernames_must_be_printable_ascii() {
+        for username in ["Mufasa", "first last", "o'brien \\\"quoted\\\""] {
+            let config = format!(r#"digest_auth = {{ username = "{username}", password = "p" }}"#);
+            assert!(load(&config).is_ok(), "{config}");
+        }
+        for username in ["josé", "tab\\there", "new\\nline"] {
+            let config = format!(r#"digest_auth = {{ username = "{username}", password = "p" }}"#);
+            let e = load(&config).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "Site a has a `digest_auth` username with characters other than printable ASCII, \
+                 which digest auth can't send",
+                "{config}"
+            );
+        }
+        // Basic auth encodes the username, so it can be anything.
+        assert!(load(r#"basic_auth = { username = "josé", password = "p" }"#).is_ok());
+    }
+
+    #[test]
+    fn headers_and_basic_auth_are_added_marked_sensitive() {
+        let config = load(
+            r#"headers = { "X-Token" = "secret" }
+               basic_auth = { username = "u", password = "p" }"#,
+        )
+        .unwrap();
+        let site = &config.sites[0];
+        let request = apply(site, reqwest::Client::new().get(&*site.feed_url))
+            .unwrap()
+            .build()
+            .unwrap();
+        let token = &request.headers()["x-token"];
+        assert_eq!(token, "secret");
+        assert!(token.is_sensitive());
+        let authorization = &request.headers()[http::header::AUTHORIZATION];
+        assert_eq!(authorization, "Basic dTpw");
+        assert!(authorization.is_sensitive());
+
+        let e = load(r#"headers = { "Not a header" = "x" }"#).unwrap_err();
+        assert!(format!("{e:#}").contains("Invalid header name"), "{e:#}");
+    }
+
+    #[test]
+    fn passwords_are_read_from_their_source() {
+        let path = std::env::temp_dir().join(format!("jarss-test-{}-password", std::process::id()));
+        std::fs::write(&path, "from file\r\n").unwrap();
+        let from_file = Password {
+            password_file: Some(path.clone()),
+            ..Password::default()
+        };
+        assert_eq!(&*from_file.read("the password").unwrap(), "from file");
+        std::fs::remove_file(&path).unwrap();
+        assert!(from_file.read("the password").is_err());
+
+        let given = Password {
+            password: Some("given".into()),
+            ..Password::default()
+        };
+        assert_eq!(&*given.read("the password").unwrap(), "given");
+        let from_unset_env = Password {
+            password_env: Some("JARSS_TEST_UNSET_PASSWORD".into()),
+            ..Password::default()
+        };
+        let e = from_unset_env.read("the password").unwrap_err();
+        assert!(format!("{e}").contains("$JARSS_TEST_UNSET_PASSWORD"), "{e}");
+    }
+}
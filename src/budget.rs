@@ -0,0 +1,260 @@
+use super::{
+    FeedEntryInfo,
+    errors::{ErrorCode, WithCode as _},
+};
+
+use anyhow::Result;
+use std::{collections::HashMap, path::Path};
+
+/// How many of the largest articles to list when an output is over budget.
+const LARGEST_ARTICLES_SHOWN: usize = 5;
+
+/// How many characters of each article's title to show when listing the largest ones.
+const TITLE_CHARS_SHOWN: usize = 60;
+
+/// A limit on how large each output file can be, so a misconfiguration can't produce a huge page.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct OutputBudget {
+    /// The largest each output file can be, in bytes.
+    pub max_output_bytes: u64,
+    /// Whether an output over budget fails the render, leaving the previous output in place,
+    /// rather than only being warned about.
+    #[serde(default)]
+    pub enforce: bool,
+}
+impl OutputBudget {
+    /// Check an output of `size` bytes, rendered from `articles`, against the budget, before it's
+    /// written to `path`.
+    ///
+    /// An output over budget is warned about along with what contributed most to it, and is an
+    /// error if the budget is enforced.
+    pub fn check(&self, path: &Path, size: usize, articles: &[FeedEntryInfo]) -> Result<()> {
+        if size as u64 <= self.max_output_bytes {
+            return Ok(());
+        }
+        log::warn!(
+            "Output {} is {size} bytes, over the budget of {} bytes. {}",
+            path.display(),
+            self.max_output_bytes,
+            contributors(articles)
+        );
+        if self.enforce {
+            return Err(anyhow::anyhow!(
+                "Output {} is over its size budget, so it wasn't written",
+                path.display()
+            ))
+            .code(ErrorCode::OutputTooLarge);
+        }
+        Ok(())
+    }
+}
+
+/// Describe the articles and sites which contributed most to an output.
+///
+/// Each article's contribution is estimated from the size of its values, since that's what
+/// templates render.
+fn contributors(articles: &[FeedEntryInfo]) -> String {
+    let mut sizes = articles
+        .iter()
+        .map(|article| {
+            let size = serde_json::to_vec(article).map_or(0, |json| json.len());
+            (article, size)
+        })
+        .collect::<Vec<_>>();
+    let mut sites = HashMap::<&str, (usize, usize)>::new();
+    for (article, size) in &sizes {
+        let (count, total) = sites.entry(&article.site).or_default();
+        *count += 1;
+        *total += size;
+    }
+    let mut sites = sites.into_iter().collect::<Vec<_>>();
+    sites.sort_by_key(|(site, (_, total))| (std::cmp::Reverse(*total), *site));
+    sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+    let largest = sizes
+        .iter()
+        .take(LARGEST_ARTICLES_SHOWN)
+        .map(|(article, size)| {
+            let title = article
+                .title
+                .chars()
+                .take(TITLE_CHARS_SHOWN)
+                .collect::<String>();
+            format!("{title:?} from {} (~{size} bytes)", article.site)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sites = sites
+        .iter()
+        .map(|(site, (count, total))| format!("{site}: {count} articles (~{total} bytes)"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("The largest articles are {largest}. By site, {sites}.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::ErrorCode, test_server, test_util::test_dir};
+
+    /// An article from `site` titled `title`, with a summary of `summary_bytes` bytes.
+    fn article(site: &str, title: &str, summary_bytes: usize) -> FeedEntryInfo {
+        let published = chrono::DateTime::UNIX_EPOCH;
+        let link: Box<str> = format!("https://{site}.example/{title}").into();
+        FeedEntryInfo {
+            site: site.into(),
+            published,
+            publish_date: published.date_naive(),
+            id: link.clone(),
+            site_link: None,
+            mentions: 1,
+            title: title.into(),
+            summary: Some("x".repeat(summary_bytes).into()),
+            content: None,
+            summary_text: None,
+            suspect: false,
+            link_display: link.clone(),
+            link,
+            last_seen_in_feed: None,
+            original_link: None,
+            also_on: Vec::new(),
+            is_new: false,
+            rank: 0,
+            raw: None,
+            site_index: 0,
+            feed_position: 0,
+        }
+    }
+
+    #[test]
+    fn outputs_are_only_over_budget_past_the_limit() {
+        let path = Path::new("out.html");
+        let articles = [article("a", "Title", 10)];
+        for enforce in [false, true] {
+            let budget = OutputBudget {
+                max_output_bytes: 100,
+                enforce,
+            };
+            budget.check(path, 0, &articles).unwrap();
+            budget.check(path, 100, &articles).unwrap();
+            let res = budget.check(path, 101, &articles);
+            if enforce {
+                let e = res.unwrap_err();
+                assert_eq!(ErrorCode::of(&e), ErrorCode::OutputTooLarge);
+                assert_eq!(
+                    e.to_string(),
+                    "Output out.html is over its size budget, so it wasn't written"
+                );
+            } else {
+                res.unwrap();
+            }
+        }
+        // Sizes too large for the budget's type are still over it.
+        let budget = OutputBudget {
+            max_output_bytes: 0,
+            enforce: true,
+        };
+        assert!(budget.check(path, usize::MAX, &[]).is_err());
+    }
+
+    #[test]
+    fn the_largest_contributors_are_listed_first() {
+        let articles = [
+            article("small", "Tiny", 0),
+            article("big", "Huge", 5000),
+            article("small", "Medium", 1000),
+            article("big", &"Long title ".repeat(10), 2000),
+            article("mid", "Middling", 3000),
+            article("small", "Little", 100),
+            article("small", "Smaller", 50),
+        ];
+        let size = |article: &FeedEntryInfo| serde_json::to_vec(article).unwrap().len();
+        let big = size(&articles[1]) + size(&articles[3]);
+        let mid = size(&articles[4]);
+        let small = [0, 2, 5, 6]
+            .map(|i| size(&articles[i]))
+            .iter()
+            .sum::<usize>();
+        let described = contributors(&articles);
+        // Only the five largest articles, with their titles cut short.
+        let long_title = "Long title "
+            .repeat(10)
+            .chars()
+            .take(60)
+            .collect::<String>();
+        assert_eq!(
+            described,
+            format!(
+                "The largest articles are \"Huge\" from big (~{}), \"Middling\" from mid (~{}), \
+                 {long_title:?} from big (~{}), \"Medium\" from small (~{}), \"Little\" from small \
+                 (~{}). By site, big: 2 articles (~{big} bytes), mid: 1 articles (~{mid} bytes), \
+                 small: 4 articles (~{small} bytes).",
+                format_args!("{} bytes", size(&articles[1])),
+                format_args!("{} bytes", size(&articles[4])),
+                format_args!("{} bytes", size(&articles[3])),
+                format_args!("{} bytes", size(&articles[2])),
+                format_args!("{} bytes", size(&articles[5])),
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn enforced_budgets_keep_the_previous_output() {
+        let dir = test_dir("budget");
+        let published = chrono::Utc::now().to_rfc2822();
+        let addr = test_server::serve_http(move |_| {
+            let items = (0..20)
+                .map(|i| {
+                    format!(
+                        "<item><title>Article {i}</title><link>https://example.com/{i}</link>\
+                         <pubDate>{published}</pubDate>\
+                         <description>{}</description></item>",
+                        "words ".repeat(100),
+                    )
+                })
+                .collect::<String>();
+            test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                &format!(
+                    "<rss version=\"2.0\"><channel><title>Feed</title>{items}</channel></rss>"
+                ),
+            )
+        })
+        .await;
+        let out_html = dir.join("out.html");
+        let render = async |enforce: bool| {
+            let config_path = dir.join(format!("jarss-{enforce}.toml"));
+            std::fs::write(
+                &config_path,
+                format!(
+                    "min_fetch_interval = 0\n\
+                     output_budget = {{ max_output_bytes = 1000, enforce = {enforce} }}\n\
+                     [[sites]]\nname = \"a\"\nfeed_url = \"http://{addr}/feed\"\n"
+                ),
+            )
+            .unwrap();
+            let paths = [&config_path, &dir.join("cache"), &out_html]
+                .map(|path| path.to_str().unwrap().to_owned());
+            let args = [
+                "jarss", "--config", &paths[0], "--cache", &paths[1], "run", &paths[2],
+            ];
+            crate::dispatch(
+                <crate::Args as clap::Parser>::parse_from(args)
+                    .try_into()
+                    .unwrap(),
+            )
+            .await
+        };
+        std::fs::write(&out_html, "the last page").unwrap();
+        let e = render(true).await.unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::OutputTooLarge);
+        assert_eq!(std::fs::read_to_string(&out_html).unwrap(), "the last page");
+
+        // Without enforcing it, going over the budget is only warned about.
+        render(false).await.unwrap();
+        let page = std::fs::read_to_string(&out_html).unwrap();
+        assert!(page.len() > 1000 && page.contains("Article 19"), "{page}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,52 +1,79 @@
-use super::{Config, SiteConfig};
+use super::{
+    Config, SiteConfig, auth, digest_auth, discover,
+    errors::{ErrorCode, HttpStatusError, WithCode as _},
+    github::{self, GitHub},
+    hooks,
+    origins::Origins,
+    post, replace, sanitize,
+    state::StatePaths,
+    urls,
+};
 
 use anyhow::{Context, Result};
 use futures::Stream;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
 use tokio::{fs::File, sync::Mutex};
 
+/// Fetch the given site, updating its cache with the response.
+///
+/// This should only be called if the site [isn't throttled](super::throttle::is_throttled).
+/// `agent` must not follow redirects itself. If the feed has permanently moved, where it moved to
+/// is fetched instead from then on.
 pub async fn query_site(
     agent: &reqwest::Client,
     config: &Config,
+    github: &GitHub,
+    origins: &Origins,
     site: &SiteConfig,
     cache: &mut SiteCache,
 ) -> Result<()> {
-    let now = SystemTime::now();
-    // Check if we've recently fetched, so we don't spam.
-    if cache
-        .last_fetch_time
-        .is_some_and(|time| time + Duration::from_secs(config.min_fetch_interval) > now)
-    {
-        log::info!(
-            "Site {} has been fetched recently, will not be fetched again",
+    log::info!("Querying {}", site.name);
+    let (mut req, via_github_api) = match &site.pre_fetch_command {
+        Some(command) => {
+            let pre_fetch = hooks::pre_fetch(command, config.hook_timeout)
+                .await
+                .context("Error running pre-fetch command")
+                .code(ErrorCode::HookFailed)?;
+            let req = post::request(agent, site, pre_fetch.url)?;
+            let req = if pre_fetch.headers.contains_key(http::header::ACCEPT) {
+                req
+            } else {
+                req.header(http::header::ACCEPT, FEED_ACCEPT)
+            };
+            (req.headers(pre_fetch.headers), false)
+        }
+        None => match github
+            .api_request(agent, site)
+            .filter(|_| site.method == post::Method::Get)
+        {
+            Some(req) => (req, true),
+            None => {
+                let url = cache.current_redirect(site).unwrap_or(&site.feed_url);
+                (
+                    post::request(agent, site, url)?.header(http::header::ACCEPT, FEED_ACCEPT),
+                    false,
+                )
+            }
+        },
+    };
+    // Where the feed actually is, as opposed to the API or a hook's URL.
+    let fetching_feed_url = site.pre_fetch_command.is_none() && !via_github_api;
+    if fetching_feed_url && let Some(redirect) = cache.current_redirect(site) {
+        log::warn!(
+            "Feed for {} has moved to {redirect}, consider updating its `feed_url`",
             site.name
         );
-        return Ok(());
     }
-    // Check if we've been asked to retry later.
-    if cache
-        .last_retry_after
-        .is_some_and(|retry_after| retry_after >= now)
+    // Conditional requests only make sense for GETs.
+    if let Some(last_headers) = cache
+        .last_headers
+        .as_ref()
+        .filter(|_| site.method == post::Method::Get)
     {
-        log::warn!(
-            "Site {} has 429 `retry-after`ed us, will not fetch for {}s",
-            site.name,
-            cache
-                .last_retry_after
-                .unwrap()
-                .duration_since(now)
-                .unwrap()
-                .as_secs(),
-        );
-        return Ok(());
-    }
-    log::info!("Querying {}", site.name);
-    let mut req = agent.get(site.feed_url.as_ref());
-    if let Some(last_headers) = cache.last_headers.as_ref() {
         if let Some(etag) = last_headers.get("etag") {
             log::debug!("Found Etag {etag}");
             req = req.header("if-none-match", etag.as_ref());
@@ -54,18 +81,96 @@ pub async fn query_site(
             log::debug!("Found Last-Modified {last_modified}");
             req = req.header("if-modified-since", last_modified.as_ref());
         } else {
+            log::debug!(
+                "{} gave neither an ETag nor Last-Modified, so whether its feed changed is told \
+                 from the body",
+                site.name
+            );
+        }
+    }
+    let req = auth::apply(site, req)?;
+    let mut request = req.build().context("Error building request")?;
+    let credentials = site
+        .digest_auth
+        .as_ref()
+        .map(|digest_auth| digest_auth.credentials(&site.name))
+        .transpose()?;
+    // Kept unauthorized, in case the server wants a new challenge answered.
+    let unauthorized = credentials.as_ref().and_then(|_| request.try_clone());
+    if let (Some(credentials), Some(challenge)) = (&credentials, &mut cache.digest_challenge) {
+        challenge.authorize(&mut request, credentials)?;
+        cache.dirty = true;
+    }
+    log::debug!("Sending request {request:?}");
+    let (mut res, mut permanent_redirect) = send_following_redirects(agent, request)
+        .await
+        .inspect_err(|_| origins.record_failure(site, cache))?;
+    if res.status() == http::StatusCode::UNAUTHORIZED
+        && let (Some(credentials), Some(mut request)) = (&credentials, unauthorized)
+    {
+        let Some(mut challenge) = digest_auth::Challenge::from_headers(res.headers()) else {
+            return Err(anyhow::Error::new(HttpStatusError(res.status()))
+                .context("Server didn't offer a digest auth challenge we support"));
+        };
+        log::debug!("Answering digest auth challenge from {}", site.name);
+        challenge.authorize(&mut request, credentials)?;
+        (res, permanent_redirect) = send_following_redirects(agent, request)
+            .await
+            .inspect_err(|_| origins.record_failure(site, cache))?;
+        cache.digest_challenge =
+            (res.status() != http::StatusCode::UNAUTHORIZED).then_some(challenge);
+        cache.dirty = true;
+    }
+    github.record_rate_limit(res.url(), res.headers());
+    let retry_after_header = res.headers().get(http::header::RETRY_AFTER).cloned();
+    let retry_after = retry_after_header
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, SystemTime::now()))
+        .filter(|_| {
+            matches!(
+                res.status(),
+                http::StatusCode::TOO_MANY_REQUESTS | http::StatusCode::SERVICE_UNAVAILABLE
+            )
+        });
+    origins.record_response(site, cache, res.status(), res.headers(), retry_after);
+    let fetched = matches!(
+        res.status(),
+        http::StatusCode::OK | http::StatusCode::NOT_MODIFIED
+    );
+    if fetching_feed_url && permanent_redirect && fetched {
+        let moved_to = res.url().as_str();
+        if cache.current_redirect(site) != Some(moved_to) {
             log::warn!(
-                "Uncached request sent to {} (only ok if this is our first request)",
+                "Feed for {} has moved to {moved_to}, consider updating its `feed_url`",
                 site.name
             );
+            cache.redirected_url = Some(moved_to.into());
+            cache.redirected_from = Some(site.feed_url.clone());
+            cache.dirty = true;
         }
     }
-    log::debug!("Sending request {req:?}");
-    let res = req.send().await.context("Error fetching feed")?;
     match res.status() {
         http::status::StatusCode::OK => {
-            log::info!("New content from {}", site.name);
-            cache.last_headers = Some(
+            let content_type = res
+                .headers()
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .map(Box::<str>::from);
+            if content_type.as_deref().is_some_and(is_html_content_type) {
+                if config.strict_content_type {
+                    return Err(anyhow::anyhow!(
+                        "Feed for {} is served as an HTML page, not a feed",
+                        site.name
+                    ))
+                    .code(ErrorCode::FetchNotAFeed);
+                }
+                log::warn!(
+                    "Feed for {} is served as an HTML page, not a feed, so it likely won't parse",
+                    site.name
+                );
+            }
+            let last_headers = Some(
                 res.headers()
                     .into_iter()
                     .map(|(key, value)| {
@@ -81,53 +186,508 @@ pub async fn query_site(
                     .collect::<Result<HashMap<_, _>, _>>()
                     .context("Error parsing HTTP headers")?,
             );
-            cache.last_body = Some(
-                res.text()
-                    .await
-                    .context("Failed to read feed contents")?
-                    .into_boxed_str(),
-            );
-            cache.last_fetch_time = Some(SystemTime::now());
+            let final_url = res.url().clone();
+            let body = read_limited_text(res, config.limits.max_body_bytes).await?;
+            let body = if via_github_api {
+                github::releases_feed(site, &body).code(ErrorCode::ParseFailed)?
+            } else {
+                body
+            };
+            let body_hash = *blake3::hash(body.as_bytes()).as_bytes();
+            if cache.body_hash == Some(body_hash) {
+                // Servers which don't support conditional requests send the same body again, which
+                // is as good as a 304.
+                log::debug!("Content from {} is unchanged", site.name);
+                cache.record_fetch(false, false);
+                let now = SystemTime::now();
+                cache.last_fetch_time = Some(now);
+                cache.record_seen_entries(site, &config.limits, now);
+                cache.last_retry_after = None;
+                cache.failing_since = None;
+                // Only the cache file is written again, not the body's.
+                cache.dirty = true;
+                return Ok(());
+            }
+            log::info!("New content from {}", site.name);
+            // Only kept once the whole body is read, so a failed read is retried from scratch.
+            cache.last_headers = last_headers;
+            cache.content_type = content_type;
+            let format = FeedFormat::detect(&body);
+            if let Some(previous) = cache.format.filter(|&previous| previous != format) {
+                log::warn!(
+                    "Feed for {} changed format from {previous} to {format}",
+                    site.name
+                );
+            }
+            if format == FeedFormat::Html {
+                suggest_alternates(site, &body, &final_url);
+            }
+            cache.format = Some(format);
+            cache.record_fetch(false, true);
+            cache.last_body = Some(body.into_boxed_str());
+            cache.body_hash = Some(body_hash);
+            cache.body_dirty = true;
+            let now = SystemTime::now();
+            cache.last_fetch_time = Some(now);
+            cache.record_seen_entries(site, &config.limits, now);
             cache.last_retry_after = None;
+            cache.failing_since = None;
+            cache.feed_url = Some(site.feed_url.clone());
+            cache.dirty = true;
             Ok(())
         }
         http::status::StatusCode::NOT_MODIFIED => {
             log::debug!("No new content from {}", site.name);
-            cache.last_fetch_time = Some(SystemTime::now());
+            cache.record_fetch(true, false);
+            let now = SystemTime::now();
+            cache.last_fetch_time = Some(now);
+            cache.record_seen_entries(site, &config.limits, now);
+            cache.failing_since = None;
+            cache.feed_url = Some(site.feed_url.clone());
+            // Even with nothing new, the fetch time has to be saved for throttling.
+            cache.dirty = true;
             Ok(())
         }
-        http::status::StatusCode::TOO_MANY_REQUESTS => {
-            log::warn!("Received 429 Too Many Requests from {}", site.name);
-            // We were told to wait before the next request
-            if let Some(retry_after) = res.headers().get("retry-after") {
-                let Ok(Ok(interval)) = retry_after.to_str().map(str::parse::<u64>) else {
-                    log::warn!("Malformed `retry-after` header: {retry_after:?}");
-                    return Ok(());
-                };
-                cache.last_retry_after = Some(SystemTime::now() + Duration::from_secs(interval));
-                Ok(())
-            } else {
-                log::error!("429 without `retry-after` header from {}", site.name);
-                Ok(())
+        status @ (http::status::StatusCode::TOO_MANY_REQUESTS
+        | http::status::StatusCode::SERVICE_UNAVAILABLE) => {
+            let too_many_requests = status == http::status::StatusCode::TOO_MANY_REQUESTS;
+            log::warn!("Received {status} from {}", site.name);
+            // We may have been told to wait before the next request, which applies to the
+            // whole origin too.
+            match (retry_after_header, retry_after) {
+                (Some(_), Some(until)) => {
+                    cache.last_retry_after = Some(until);
+                    cache.dirty = true;
+                }
+                (Some(header), None) => log::warn!("Malformed `retry-after` header: {header:?}"),
+                (None, _) if too_many_requests => {
+                    log::error!("429 without `retry-after` header from {}", site.name);
+                }
+                (None, _) => {}
+            }
+            // The site wasn't fetched, even if it said when to come back.
+            Err(HttpStatusError(status).into())
+        }
+        status => Err(HttpStatusError(status).into()),
+    }
+}
+
+/// What identifies an entry across fetches: its ID, or its link if it has none.
+pub fn entry_id(entry: &feed_rs::model::Entry) -> &str {
+    match entry.links.first() {
+        Some(link) if entry.id.is_empty() => &link.href,
+        _ => &entry.id,
+    }
+}
+
+/// When a `retry-after` header says to retry, given either as a number of seconds or as an
+/// HTTP-date.
+///
+/// Dates in the past mean to retry now.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return now.checked_add(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(SystemTime::from(date).max(now))
+}
+
+/// Send a request, following any redirects.
+///
+/// Also returns whether every redirect followed was permanent, so the new location can be used
+/// from now on. This is false if there were no redirects.
+async fn send_following_redirects(
+    agent: &reqwest::Client,
+    mut request: reqwest::Request,
+) -> Result<(reqwest::Response, bool)> {
+    use http::{StatusCode, header};
+    let mut redirects = 0;
+    let mut all_permanent = true;
+    loop {
+        let next = request
+            .try_clone()
+            .context("Request can't be sent more than once")?;
+        let res = agent.execute(next).await.context("Error fetching feed")?;
+        let status = res.status();
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok());
+        let (true, Some(location)) = (status.is_redirection(), location) else {
+            return Ok((res, redirects > 0 && all_permanent));
+        };
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            anyhow::bail!("Too many redirects, the last was to {location}");
+        }
+        let url = request
+            .url()
+            .join(location)
+            .with_context(|| format!("Invalid redirect to {location:?}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Redirected to a non-HTTP URL {url}");
+        }
+        log::debug!("Following {status} redirect to {url}");
+        // Like browsers, follow all but these redirects of POSTs with a GET.
+        if request.method() == http::Method::POST
+            && !matches!(
+                status,
+                StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+            )
+        {
+            *request.method_mut() = http::Method::GET;
+            *request.body_mut() = None;
+            request.headers_mut().remove(header::CONTENT_TYPE);
+        }
+        all_permanent &= matches!(
+            status,
+            StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+        );
+        // Credentials are only for the host they were given for, including any configured
+        // headers, which are marked sensitive.
+        if url.origin() != request.url().origin() {
+            let headers = request.headers_mut();
+            let sensitive = headers
+                .iter()
+                .filter(|(_, value)| value.is_sensitive())
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            for name in sensitive
+                .into_iter()
+                .chain([header::AUTHORIZATION, header::COOKIE])
+            {
+                headers.remove(name);
             }
         }
-        status if !status.is_client_error() && !status.is_server_error() => {
-            anyhow::bail!("Received unexpected status code {status}")
+        *request.url_mut() = url;
+    }
+}
+
+/// Read a response's body as text, failing as soon as it's over `max_bytes` rather than reading
+/// the rest of it.
+///
+/// The body is decoded as [`reqwest::Response::text`] would, using the charset from its
+/// `content-type`.
+async fn read_limited_text(mut res: reqwest::Response, max_bytes: usize) -> Result<String> {
+    let too_large = || {
+        Err(anyhow::anyhow!(
+            "Feed is larger than `max_body_bytes` ({max_bytes} bytes), so it wasn't read"
+        ))
+        .code(ErrorCode::FetchTooLarge)
+    };
+    if res
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return too_large();
+    }
+    let headers = res.headers().clone();
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await.context("Failed to read feed contents")? {
+        if body.len() + chunk.len() > max_bytes {
+            return too_large();
         }
-        status => anyhow::bail!("Received error status code {status}"),
+        body.extend_from_slice(&chunk);
     }
+    let mut read = http::Response::new(body);
+    *read.headers_mut() = headers;
+    reqwest::Response::from(read)
+        .text()
+        .await
+        .context("Failed to read feed contents")
+}
+
+/// Whether a `content-type` is for an HTML page.
+fn is_html_content_type(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/html"))
+}
+
+/// Log the feeds advertised by a page which we expected to be a feed.
+fn suggest_alternates(site: &SiteConfig, html: &str, url: &reqwest::Url) {
+    let alternates = discover::alternates(html, url);
+    if alternates.is_empty() {
+        log::warn!(
+            "Feed for {} is an HTML page which doesn't advertise any feeds",
+            site.name
+        );
+    } else {
+        let urls = alternates
+            .iter()
+            .map(|alternate| alternate.url.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::warn!(
+            "Feed for {} is an HTML page, consider changing its `feed_url` to one of: {urls}",
+            site.name
+        );
+    }
+}
+
+/// The format a feed was served in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    JsonFeed,
+    /// An HTML page, which usually means the site moved its feed.
+    Html,
+    /// Something we can't parse.
+    Unknown,
+}
+impl FeedFormat {
+    /// Work out what format the given response body is in.
+    pub fn detect(body: &str) -> Self {
+        use feed_rs::model::FeedType;
+        match feed_rs::parser::parse(std::io::Cursor::new(body.as_bytes())) {
+            Ok(feed) => match feed.feed_type {
+                FeedType::Atom => Self::Atom,
+                FeedType::JSON => Self::JsonFeed,
+                FeedType::RSS0 | FeedType::RSS1 | FeedType::RSS2 => Self::Rss,
+            },
+            Err(_) => {
+                let start = body
+                    .trim_start()
+                    .get(..256)
+                    .unwrap_or(body)
+                    .to_ascii_lowercase();
+                if start.contains("<!doctype html") || start.contains("<html") {
+                    Self::Html
+                } else {
+                    Self::Unknown
+                }
+            }
+        }
+    }
+}
+impl std::fmt::Display for FeedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Rss => "rss",
+            Self::Atom => "atom",
+            Self::JsonFeed => "jsonfeed",
+            Self::Html => "html",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// The longest a cache file name can be before its extension, in bytes.
+///
+/// Most filesystems allow 255 bytes (or UTF-16 code units), so this leaves plenty of room.
+const MAX_FILE_STEM_BYTES: usize = 200;
+
+/// How many hex digits of the hash of the name go in each cache file name.
+const CACHE_FILE_HASH_LEN: usize = 8;
+
+/// File names which Windows treats as devices, whatever their extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// How long to remember an entry after it drops out of its feed.
+const LAST_SEEN_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The most fetches to remember in [`SiteCache::fetch_history`].
+const FETCH_HISTORY_LEN: usize = 256;
+
+/// The version of the layout of [`SiteCache`] in cache files, which is their first byte once
+/// decompressed.
+///
+/// postcard encodes fields by their position, so adding, removing, or reordering fields needs a new
+/// version, with [`SiteCache::decode`] keeping a way to decode the previous one. Versions 0 and 1
+/// can't be used, since they can't be told apart from files from before the layout had a version.
+const CACHE_SCHEMA_VERSION: u8 = 2;
+
+/// The `accept` header to fetch feeds with, preferring the feed formats we can parse.
+const FEED_ACCEPT: &str = "application/atom+xml, application/rss+xml, application/feed+json, \
+    application/json;q=0.9, application/xml;q=0.9, text/xml;q=0.9, */*;q=0.8";
+
+/// How many redirects to follow when fetching a feed before giving up.
+const MAX_REDIRECTS: usize = 10;
+
+/// The outcome of one successful fetch of a site, as remembered for tuning fetch intervals.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct FetchRecord {
+    pub time: SystemTime,
+    /// Whether the site responded with 304 Not Modified.
+    pub not_modified: bool,
+    /// Whether the feed's contents were different from the previous fetch.
+    pub changed: bool,
+}
+
+/// How the cache for each site is identified on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheKey {
+    /// Key caches by the site's name.
+    #[default]
+    Name,
+    /// Key caches by the site's feed URL, so renaming a site keeps its cache.
+    Url,
+}
+
+/// The file in the cache directory which is locked while the caches are in use, so that
+/// `jarss cache move` doesn't move them out from under a run.
+pub const LOCK_FILE_NAME: &str = "jarss.lock";
+
+/// Take a shared lock on the cache directory, which is held until the returned file is closed.
+///
+/// Any number of shared locks can be held at once, so this only keeps out an exclusive lock. If
+/// the directory doesn't exist yet, or can't be locked, there's no lock, and the caches are used
+/// without one.
+fn lock_shared(cache_dir: &Path) -> Option<std::fs::File> {
+    if !cache_dir.is_dir() {
+        return None;
+    }
+    let file = std::fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(cache_dir.join(LOCK_FILE_NAME))
+        .and_then(|file| file.lock_shared().map(|()| file));
+    match file {
+        Ok(file) => Some(file),
+        Err(e) => {
+            log::debug!("Using the caches without locking them: {e}");
+            None
+        }
+    }
+}
+
+/// Take an exclusive lock on the cache directory, held until the returned file is closed.
+///
+/// Fails, rather than waiting, if another jarss has the caches in use.
+pub fn lock_exclusive(cache_dir: &Path) -> Result<std::fs::File> {
+    let path = cache_dir.join(LOCK_FILE_NAME);
+    let file = std::fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open the lock file {}", path.display()))
+        .code(ErrorCode::CacheIo)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => Err(anyhow::anyhow!(
+            "Another jarss is using the caches in {}, so stop it first",
+            cache_dir.display()
+        )),
+        Err(std::fs::TryLockError::Error(e)) => Err(anyhow::Error::new(e))
+            .with_context(|| format!("Failed to lock {}", path.display()))
+            .code(ErrorCode::CacheIo),
+    }
+}
+
+/// A feed URL, normalized to compare it and hash it for [`CacheKey::Url`].
+///
+/// Trailing slashes are kept, so turning `unify_trailing_slashes` on doesn't move any caches.
+fn normalized_feed_url(feed_url: &str) -> String {
+    urls::normalize(feed_url, false)
 }
 
 pub struct CacheManager {
     cache_dir: PathBuf,
-    caches: papaya::HashMap<Box<str>, Mutex<SiteCache>>,
+    cache_key: CacheKey,
+    /// The caches, keyed by [`CacheManager::storage_key`], alongside the name of their site.
+    caches: papaya::HashMap<Box<str>, (Box<str>, Mutex<SiteCache>)>,
+    /// The old cache files which more than one of the sites would migrate from, so which site they
+    /// belong to can't be told.
+    shared_legacy_files: HashSet<PathBuf>,
+    /// The feed URLs, normalized, stored in the name-keyed cache files in the cache directory, so
+    /// they can be migrated to [`CacheKey::Url`] even if their site was renamed.
+    ///
+    /// This is only read from the directory when using [`CacheKey::Url`].
+    stored_urls: HashMap<PathBuf, String>,
+    /// Where the state kept alongside the caches goes, which is the cache directory unless
+    /// [set](Self::with_state) otherwise.
+    state: StatePaths,
+    /// The shared lock on the cache directory, if it could be taken.
+    _lock: Option<std::fs::File>,
 }
 impl CacheManager {
-    pub fn new(cache_dir: PathBuf) -> Self {
-        Self {
+    /// Manage the caches for `sites`, stored in `cache_dir`.
+    ///
+    /// Fails if two of the sites would share a cache file.
+    pub fn new(cache_dir: PathBuf, cache_key: CacheKey, sites: &[SiteConfig]) -> Result<Self> {
+        let mut manager = Self {
+            _lock: lock_shared(&cache_dir),
+            stored_urls: match cache_key {
+                CacheKey::Name => HashMap::new(),
+                CacheKey::Url => Self::read_stored_urls(&cache_dir),
+            },
+            state: StatePaths::new(cache_dir.clone()),
             cache_dir,
+            cache_key,
             caches: papaya::HashMap::new(),
+            shared_legacy_files: HashSet::new(),
+        };
+        let mut paths = HashMap::<PathBuf, &str>::new();
+        let mut legacy_paths = HashSet::new();
+        for site in sites {
+            let path = manager.cache_path(site);
+            if let Some(other) = paths.insert(path.clone(), &site.name) {
+                anyhow::bail!(
+                    "Sites {other} and {} would share the cache file {}",
+                    site.name,
+                    path.display()
+                );
+            }
+            for legacy_path in manager.legacy_paths(site) {
+                if !legacy_paths.insert(legacy_path.clone()) {
+                    manager.shared_legacy_files.insert(legacy_path);
+                }
+            }
         }
+        Ok(manager)
+    }
+
+    /// Switch to managing the caches for `sites`, keyed by `cache_key`, such as after the config
+    /// is reloaded.
+    ///
+    /// The caches of sites which aren't given any more are dropped without being saved, so they
+    /// should be saved first. Fails, leaving everything as it was, if two of the sites would share
+    /// a cache file.
+    pub fn set_sites(&mut self, cache_key: CacheKey, sites: &[SiteConfig]) -> Result<()> {
+        let mut manager =
+            Self::new(self.cache_dir.clone(), cache_key, sites)?.with_state(self.state.clone());
+        let live = sites
+            .iter()
+            .map(|site| (manager.storage_key(site), site.name.clone()))
+            .collect::<HashSet<_>>();
+        let caches = std::mem::take(&mut self.caches);
+        caches.pin().retain(|key, (site, _)| {
+            // A renamed site's cache is dropped too, since the name kept with it is outdated.
+            let keep = live.contains(&(key.clone(), site.clone()));
+            if !keep {
+                log::debug!("Dropping the cache for {site}, which is no longer configured");
+            }
+            keep
+        });
+        manager.caches = caches;
+        *self = manager;
+        Ok(())
+    }
+
+    /// Keep the state which isn't cache, like which articles the last fragment had, as `state`
+    /// says.
+    pub fn with_state(self, state: StatePaths) -> Self {
+        Self { state, ..self }
+    }
+
+    /// The directory the caches are stored in.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Where the state kept between runs which isn't cache is stored.
+    pub fn state(&self) -> &StatePaths {
+        &self.state
     }
 
     /// Return a guard for some operations that require it.
@@ -140,132 +700,782 @@ impl CacheManager {
         index: &SiteConfig,
         guard: &'a papaya::LocalGuard<'a>,
     ) -> Result<impl std::ops::DerefMut<Target = SiteCache> + use<'_, 'a>> {
-        if let Some(entry) = self.caches.get(&index.name, guard) {
+        let key = self.storage_key(index);
+        if let Some((_, entry)) = self.caches.get(&key, guard) {
             Ok(entry.lock().await)
         } else {
-            let cache = SiteCache::load_for_site(&self.cache_dir, index).await?;
-            let entry = self
-                .caches
-                .try_insert(index.name.clone(), Mutex::new(cache), guard)
-                .unwrap();
+            self.migrate_legacy_cache(index, &key)
+                .await
+                .code(ErrorCode::CacheIo)?;
+            let cache = SiteCache::load_for_site(&self.cache_dir, &key, &index.name).await?;
+            let (site, entry) =
+                match self
+                    .caches
+                    .try_insert(key, (index.name.clone(), Mutex::new(cache)), guard)
+                {
+                    Ok(inserted) => inserted,
+                    // Another task loaded it first, so the copy it loaded is the one to use.
+                    Err(occupied) => occupied.current,
+                };
+            if **site != *index.name {
+                return Err(anyhow::anyhow!(
+                    "Sites {site} and {} share a cache, so neither can be used",
+                    index.name
+                ))
+                .code(ErrorCode::CacheIo);
+            }
             Ok(entry.lock().await)
         }
     }
 
+    /// The path of the file the given site's cache is stored in.
+    pub fn cache_path(&self, site: &SiteConfig) -> PathBuf {
+        SiteCache::cache_file_path(&self.cache_dir, &self.storage_key(site))
+    }
+
+    /// The key identifying the given site's cache, both in memory and on disk.
+    pub fn storage_key(&self, site: &SiteConfig) -> Box<str> {
+        match self.cache_key {
+            CacheKey::Name => site.name.clone(),
+            CacheKey::Url => {
+                let hash = blake3::hash(normalized_feed_url(&site.feed_url).as_bytes()).to_hex();
+                format!("url-{}", &hash[..16]).into_boxed_str()
+            }
+        }
+    }
+
+    /// Where the given site's cache might have been stored by older versions, or under the other
+    /// [`CacheKey`], most likely first.
+    ///
+    /// Under [`CacheKey::Url`], name-keyed files which have the site's feed URL come first, then
+    /// the files for its name, unless they have the feed URL of something else.
+    fn legacy_paths(&self, site: &SiteConfig) -> Vec<PathBuf> {
+        let key = self.storage_key(site);
+        let mut paths = vec![SiteCache::legacy_cache_file_path(&self.cache_dir, &key)];
+        if self.cache_key == CacheKey::Url {
+            let feed_url = normalized_feed_url(&site.feed_url);
+            let mut by_url = self
+                .stored_urls
+                .iter()
+                .filter(|(_, url)| **url == feed_url)
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>();
+            by_url.sort();
+            paths.extend(by_url);
+            for path in [
+                SiteCache::cache_file_path(&self.cache_dir, &site.name),
+                SiteCache::legacy_cache_file_path(&self.cache_dir, &site.name),
+            ] {
+                if self
+                    .stored_urls
+                    .get(&path)
+                    .is_none_or(|url| *url == feed_url)
+                    && !paths.contains(&path)
+                {
+                    paths.push(path);
+                }
+            }
+        }
+        paths
+    }
+
+    /// The cache files in the cache directory which don't belong to any of `sites`, along with
+    /// any left over from corrupt caches or interrupted writes.
+    ///
+    /// A site's files are never listed, even if they haven't been written yet, and neither are the
+    /// old files it might be [migrated](Self::migrate_legacy_cache) from. Cache files which were
+    /// moved aside as corrupt are always listed, as are temporary ones, unless they're needed to
+    /// finish an interrupted [replacement](replace::write_atomically). Only cache files are
+    /// considered, so anything else in the directory is left alone.
+    pub fn orphaned_files(&self, sites: &[SiteConfig]) -> Result<Vec<PathBuf>> {
+        let live = sites
+            .iter()
+            .flat_map(|site| std::iter::once(self.cache_path(site)).chain(self.legacy_paths(site)))
+            .flat_map(|path| [SiteCache::body_file_path(&path), path])
+            .collect::<HashSet<_>>();
+        let entries = match std::fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to list cache directory")),
+        };
+        let mut orphans = Vec::new();
+        for entry in entries {
+            let entry = entry.context("Failed to list cache directory")?;
+            let path = entry.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let orphaned = if name.ends_with(".lz4") {
+                !live.contains(&path)
+            } else if name.ends_with(".lz4.corrupt") {
+                true
+            } else if let Some(replaced) = name.strip_suffix(".tmp")
+                && replaced.ends_with(".lz4")
+            {
+                !replace::is_replacing(&path.with_file_name(replaced))
+            } else {
+                false
+            };
+            if orphaned && entry.file_type().is_ok_and(|file_type| file_type.is_file()) {
+                orphans.push(path);
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Move a site's cache file from where it might have been stored before to where it's stored
+    /// now.
+    ///
+    /// This lets caches survive the naming of cache files changing, and switching
+    /// [`CacheKey::Name`] to [`CacheKey::Url`], even if the site is renamed at the same time.
+    /// Nothing happens if there's no old file, or if the
+    /// current file already exists. Old files which more than one site would migrate from are left
+    /// alone, since they can't be told apart.
+    async fn migrate_legacy_cache(&self, site: &SiteConfig, key: &str) -> Result<()> {
+        let new_path = SiteCache::cache_file_path(&self.cache_dir, key);
+        if tokio::fs::try_exists(&new_path).await? {
+            return Ok(());
+        }
+        for old_path in self.legacy_paths(site) {
+            if old_path == new_path || !tokio::fs::try_exists(&old_path).await? {
+                continue;
+            }
+            if self.shared_legacy_files.contains(&old_path) {
+                log::warn!(
+                    "Not migrating the cache for {} from {}, since it may belong to another site",
+                    site.name,
+                    old_path.display()
+                );
+                continue;
+            }
+            log::info!(
+                "Migrating cache for {} from {}",
+                site.name,
+                old_path.display()
+            );
+            tokio::fs::rename(&old_path, &new_path)
+                .await
+                .context("Failed to migrate cache file")?;
+            let old_body_path = SiteCache::body_file_path(&old_path);
+            if tokio::fs::try_exists(&old_body_path).await? {
+                tokio::fs::rename(&old_body_path, SiteCache::body_file_path(&new_path))
+                    .await
+                    .context("Failed to migrate cached body")?;
+            }
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    /// Read the feed URLs stored in the name-keyed cache files in `cache_dir`.
+    ///
+    /// Files which can't be read or decoded, or which don't have a feed URL, are left out, so
+    /// they're only found by their site's name.
+    fn read_stored_urls(cache_dir: &Path) -> HashMap<PathBuf, String> {
+        let Ok(entries) = std::fs::read_dir(cache_dir) else {
+            return HashMap::new();
+        };
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let name = path.file_name()?.to_str()?;
+                // Bodies aren't caches, and URL-keyed files are where caches are migrated to, not
+                // from.
+                if !name.ends_with(".lz4")
+                    || name.ends_with(".body.lz4")
+                    || name.starts_with("url-")
+                {
+                    return None;
+                }
+                let cache = std::fs::read(&path)
+                    .map_err(anyhow::Error::new)
+                    .and_then(|compressed| SiteCache::decode(&compressed))
+                    .inspect_err(|e| {
+                        log::debug!("Not reading the feed URL of {}: {e:#}", path.display());
+                    })
+                    .ok()?;
+                Some((path, normalized_feed_url(&cache.feed_url?)))
+            })
+            .collect()
+    }
+
+    /// The feeds in the caches in memory, parsed, with the [key](Self::storage_key) and site
+    /// name of each cache.
     pub fn feeds<'a>(
         &self,
         guard: &'a papaya::LocalGuard<'a>,
-    ) -> impl Stream<Item = (&'a str, Result<feed_rs::model::Feed>)> + use<'_, 'a> {
+    ) -> impl Stream<Item = (&'a str, &'a str, Result<CachedFeed>)> + use<'_, 'a> {
         use futures::StreamExt as _;
-        futures::stream::iter(self.caches.iter(guard)).filter_map(async move |(site, cache)| {
-            Some((
-                site.as_ref(),
-                feed_rs::parser::parse(std::io::Cursor::new(
-                    cache.lock().await.last_body.as_ref()?.as_bytes(),
+        futures::stream::iter(self.caches.iter(guard)).filter_map(
+            async move |(key, (site, cache))| {
+                let cache = cache.lock().await;
+                let body = cache.last_body.as_ref()?;
+                Some((
+                    key.as_ref(),
+                    site.as_ref(),
+                    feed_rs::parser::parse(std::io::Cursor::new(body.as_bytes()))
+                        .map(|feed| CachedFeed {
+                            feed,
+                            body_hash: cache
+                                .body_hash
+                                .unwrap_or_else(|| *blake3::hash(body.as_bytes()).as_bytes()),
+                            resolved_links: cache.resolved_links.clone(),
+                            entries_last_seen: cache.entries_last_seen.clone(),
+                            new_entries: cache.new_entries.clone(),
+                            last_fetch_time: cache.last_fetch_time,
+                        })
+                        .map_err(anyhow::Error::from),
                 ))
-                .map_err(anyhow::Error::from),
-            ))
-        })
+            },
+        )
+    }
+
+    /// Count what the caches in memory hold.
+    pub async fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats::default();
+        let guard = self.caches.guard();
+        for (_, (_, cache)) in self.caches.iter(&guard) {
+            let cache = cache.lock().await;
+            stats.caches += 1;
+            if let Some(body) = &cache.last_body {
+                stats.bodies += 1;
+                stats.body_bytes += body.len();
+            }
+            stats.remembered += cache.fetch_history.len()
+                + cache.entries_last_seen.len()
+                + cache.seen_entries.len()
+                + cache.new_entries.len()
+                + cache.resolved_links.len();
+        }
+        stats
     }
 
-    pub async fn save(&self) -> Result<()> {
+    /// Save the caches which changed this run, at most `max_concurrent` at a time.
+    ///
+    /// Each cache is saved independently, so one failing doesn't stop the rest from being saved.
+    /// Returns the sites whose caches couldn't be saved, along with the errors.
+    pub async fn save(&self, max_concurrent: usize) -> Vec<(Box<str>, anyhow::Error)> {
         use futures::StreamExt as _;
         let caches = self.caches.pin();
-        let mut saves = futures::stream::FuturesUnordered::new();
-        for (site, cache) in caches.iter() {
-            saves.push(async move {
-                cache
-                    .lock()
-                    .await
-                    .save_for_site(&self.cache_dir, site)
+        futures::stream::iter(caches.iter())
+            .map(async |(key, (site, cache))| {
+                let mut cache = cache.lock().await;
+                if !cache.dirty {
+                    log::debug!("Cache for {site} is unchanged, not saving it");
+                    return None;
+                }
+                match cache
+                    .save_for_site(&self.cache_dir, key)
                     .await
                     .with_context(|| format!("Failed to save cache for {}", site))
-            });
-        }
-        while let Some(res) = saves.next().await {
-            res?;
-        }
-        Ok(())
+                    .code(ErrorCode::CacheIo)
+                {
+                    Ok(()) => {
+                        cache.dirty = false;
+                        cache.body_dirty = false;
+                        None
+                    }
+                    Err(e) => Some((site.clone(), e)),
+                }
+            })
+            .buffer_unordered(max_concurrent.max(1))
+            .filter_map(async |res| res)
+            .collect()
+            .await
     }
 }
 
+/// What the caches in memory hold, for noticing them growing in long-running processes.
+#[derive(Default)]
+pub struct CacheStats {
+    /// How many sites' caches are in memory.
+    pub caches: usize,
+    /// How many of those have a body.
+    pub bodies: usize,
+    /// How large those bodies are, in all.
+    pub body_bytes: usize,
+    /// How many fetches, entries and links the caches remember, in all.
+    pub remembered: usize,
+}
+
+/// A feed parsed from a site's cache, along with the cached information needed to present it.
+pub struct CachedFeed {
+    pub feed: feed_rs::model::Feed,
+    /// The blake3 hash of the body the feed was parsed from.
+    pub body_hash: [u8; 32],
+    /// See [`SiteCache::resolved_links`].
+    pub resolved_links: HashMap<Box<str>, Box<str>>,
+    /// See [`SiteCache::entries_last_seen`].
+    pub entries_last_seen: HashMap<Box<str>, SystemTime>,
+    /// See [`SiteCache::new_entries`].
+    pub new_entries: HashSet<Box<str>>,
+    /// See [`SiteCache::last_fetch_time`].
+    pub last_fetch_time: Option<SystemTime>,
+}
+
 #[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct SiteCache {
-    /// When the last `retry-after` said to retry, if we've been 429'ed.
+    /// When the last `retry-after` said to retry, if we've been 429'ed or told the site is
+    /// unavailable.
     pub last_retry_after: Option<SystemTime>,
     /// The headers from the most recent successful fetch.
     pub last_headers: Option<HashMap<Box<str>, Box<str>>>,
     /// The body of the most recent successful fetch.
+    ///
+    /// This is kept in its own file next to the cache file, so fetches which find it unchanged
+    /// don't write it out again.
+    #[serde(skip)]
     pub last_body: Option<Box<str>>,
+    /// The blake3 hash of [`last_body`](Self::last_body), which is what the cache file has rather
+    /// than the body itself.
+    body_hash: Option<[u8; 32]>,
     /// The timestamp of the most recent successful fetch.
     pub last_fetch_time: Option<SystemTime>,
+    /// If the most recent fetch attempt failed, when the site started failing.
+    ///
+    /// This is the time of the last successful fetch, or of the first failure if the site has
+    /// never been fetched successfully.
+    pub failing_since: Option<SystemTime>,
+    /// When the most recent failed fetch attempt was, if any have failed.
+    pub last_failure_time: Option<SystemTime>,
+    /// Where entry links ended up after following redirects, for sites which resolve links.
+    ///
+    /// Links which couldn't be resolved map to themselves, so they aren't retried. Links are
+    /// forgotten along with their entries in [`entries_last_seen`](Self::entries_last_seen).
+    pub resolved_links: HashMap<Box<str>, Box<str>>,
+    /// The `content-type` of the most recent successful fetch, if it had one.
+    pub content_type: Option<Box<str>>,
+    /// The format of the body of the most recent successful fetch.
+    pub format: Option<FeedFormat>,
+    /// When each entry, by link, was last in the feed when we fetched it.
+    ///
+    /// Entries are forgotten once they've been gone from the feed for [`LAST_SEEN_RETENTION`].
+    pub entries_last_seen: HashMap<Box<str>, SystemTime>,
+    /// When each entry, by [ID](entry_id), was last in the feed when we fetched it.
+    ///
+    /// This is kept for the same time as [`entries_last_seen`](Self::entries_last_seen).
+    pub seen_entries: HashMap<Box<str>, SystemTime>,
+    /// The entries, by [ID](entry_id), which we hadn't seen before the latest fetch.
+    ///
+    /// Nothing counts as new on the first fetch of a site, since everything would be.
+    pub new_entries: HashSet<Box<str>>,
+    /// The most recent successful fetches, oldest first.
+    ///
+    /// At most [`FETCH_HISTORY_LEN`] fetches are kept, and none older than
+    /// [`LAST_SEEN_RETENTION`].
+    pub fetch_history: VecDeque<FetchRecord>,
+    /// Where the feed permanently redirected to, which is fetched instead from then on.
+    pub redirected_url: Option<Box<str>>,
+    /// The `feed_url` which [`redirected_url`](Self::redirected_url) was redirected from, so the
+    /// redirect is forgotten if the config changes.
+    pub redirected_from: Option<Box<str>>,
+    /// The digest auth challenge the site last gave us, which is answered up front on later
+    /// fetches until the site rejects it.
+    pub digest_challenge: Option<digest_auth::Challenge>,
+    /// The `feed_url` the site had when it was last fetched, so the cache can be found by it when
+    /// switching to [`CacheKey::Url`].
+    pub feed_url: Option<Box<str>>,
+    /// Whether anything changed since the cache was loaded or last saved, so it needs saving.
+    #[serde(skip)]
+    dirty: bool,
+    /// Whether [`last_body`](Self::last_body) changed since it was loaded or last saved, so its
+    /// file needs writing as well.
+    #[serde(skip)]
+    body_dirty: bool,
 }
 impl SiteCache {
-    /// Load the cache entry for the given site.
-    async fn load_for_site(cache_dir: impl AsRef<Path>, config: &SiteConfig) -> Result<Self> {
-        let path = cache_dir
+    /// Where the given site's feed has permanently moved to, if it has.
+    pub fn current_redirect(&self, site: &SiteConfig) -> Option<&str> {
+        self.redirected_url
+            .as_deref()
+            .filter(|_| self.redirected_from.as_deref() == Some(&site.feed_url))
+    }
+
+    /// Record that an attempt to fetch this site failed at `now`.
+    pub fn record_failure(&mut self, now: SystemTime) {
+        if self.failing_since.is_none() {
+            self.failing_since = Some(self.last_fetch_time.unwrap_or(now));
+        }
+        self.last_failure_time = Some(now);
+        self.dirty = true;
+    }
+
+    /// When we last tried to fetch the site, whether or not that worked.
+    pub fn last_attempt_time(&self) -> Option<SystemTime> {
+        self.last_fetch_time.max(self.last_failure_time)
+    }
+
+    /// Record that the cache was changed from outside this module, so it needs saving.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Add a successful fetch to [`Self::fetch_history`], forgetting old ones.
+    fn record_fetch(&mut self, not_modified: bool, changed: bool) {
+        let now = SystemTime::now();
+        self.fetch_history.push_back(FetchRecord {
+            time: now,
+            not_modified,
+            changed,
+        });
+        while self.fetch_history.len() > FETCH_HISTORY_LEN
+            || self
+                .fetch_history
+                .front()
+                .is_some_and(|record| record.time + LAST_SEEN_RETENTION <= now)
+        {
+            self.fetch_history.pop_front();
+        }
+    }
+
+    /// Record that the entries in the cached body are still in the feed, as of `now`.
+    ///
+    /// Entries the site's junk filter drops are skipped, if it [forgets
+    /// them](super::junk::JunkFilter::forgets_dropped). This also forgets everything about entries
+    /// which have been gone for long enough.
+    fn record_seen_entries(
+        &mut self,
+        site: &SiteConfig,
+        limits: &sanitize::Limits,
+        now: SystemTime,
+    ) {
+        let site_name = &site.name;
+        if let Some(feed) = self
+            .last_body
             .as_ref()
-            .join(Self::cache_file_for_name(&config.name));
+            .and_then(|body| feed_rs::parser::parse(std::io::Cursor::new(body.as_bytes())).ok())
+        {
+            let first_fetch = self.seen_entries.is_empty();
+            let mut new_entries = HashSet::new();
+            let feed_title = feed.title.as_ref().map_or(String::new(), |title| {
+                sanitize::normalize_title(&title.content, limits)
+            });
+            for entry in &feed.entries {
+                if site.junk_filter.forgets_dropped() {
+                    let title = entry
+                        .title
+                        .as_ref()
+                        .map(|title| sanitize::normalize_title(&title.content, limits));
+                    if let Some(junk) =
+                        site.junk_filter
+                            .check(title.as_deref(), site_name, &feed_title)
+                    {
+                        log::debug!(
+                            "Not recording entry {:?} from {site_name}, which is junk as {junk}",
+                            entry.id
+                        );
+                        continue;
+                    }
+                }
+                if let Some(link) = entry.links.first() {
+                    self.entries_last_seen
+                        .insert(link.href.as_str().into(), now);
+                }
+                let id = Box::<str>::from(entry_id(entry));
+                if self.seen_entries.insert(id.clone(), now).is_none() && !first_fetch {
+                    new_entries.insert(id);
+                }
+            }
+            self.new_entries = new_entries;
+        }
+        let seen = self.entries_last_seen.len();
+        self.entries_last_seen
+            .retain(|_, &mut last_seen| last_seen + LAST_SEEN_RETENTION > now);
+        self.seen_entries
+            .retain(|_, &mut last_seen| last_seen + LAST_SEEN_RETENTION > now);
+        // A site which stops resolving links doesn't prune them itself, so they'd otherwise be
+        // kept forever.
+        let resolved = self.resolved_links.len();
+        self.resolved_links
+            .retain(|link, _| self.entries_last_seen.contains_key(link));
+        let (seen, resolved) = (
+            seen - self.entries_last_seen.len(),
+            resolved - self.resolved_links.len(),
+        );
+        if seen > 0 || resolved > 0 {
+            log::debug!(
+                "Forgot {seen} entries last seen and {resolved} resolved links for {site_name}"
+            );
+        }
+    }
+
+    /// Load the cache entry stored under the given key.
+    async fn load_for_site(
+        cache_dir: impl AsRef<Path>,
+        key: &str,
+        site_name: &str,
+    ) -> Result<Self> {
+        let path = Self::cache_file_path(cache_dir.as_ref(), key);
         match File::open(&path).await {
             Ok(mut file) => {
                 use tokio::io::AsyncReadExt as _;
-                let postcard_encoded = {
-                    use std::io::Read;
-                    let mut compressed = Vec::new();
-                    file.read_to_end(&mut compressed).await?;
-                    let mut encoded = Vec::new();
-                    lz4_flex::frame::FrameDecoder::new(std::io::Cursor::new(compressed))
-                        .read_to_end(&mut encoded)
-                        .context("Failed to read cache file")?;
-                    encoded
-                };
-                let res = postcard::from_bytes(&postcard_encoded)
-                    .context("Failed to decode cache file")?;
-                Ok(res)
+                let mut compressed = Vec::new();
+                file.read_to_end(&mut compressed)
+                    .await
+                    .context("Failed to read cache entry")
+                    .code(ErrorCode::CacheIo)?;
+                match Self::decode(&compressed) {
+                    Ok(mut res) => {
+                        res.load_body(&path, site_name).await;
+                        Ok(res)
+                    }
+                    Err(e) => {
+                        // Either written by a newer version of jarss, or cut short, so start over
+                        // rather than failing the site forever.
+                        log::warn!(
+                            "Failed to decode cache file for {site_name}, starting with an empty cache: {e:#}"
+                        );
+                        let mut aside = path.clone().into_os_string();
+                        aside.push(".corrupt");
+                        if let Err(e) = tokio::fs::rename(&path, &aside).await {
+                            log::warn!("Failed to move aside cache file for {site_name}: {e}");
+                        }
+                        Ok(Self::default())
+                    }
+                }
             }
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                log::info!("Generating empty cache for new site {}", config.name);
+                log::info!("Generating empty cache for new site {site_name}");
                 Ok(Self::default())
             }
-            Err(e) => Err(anyhow::Error::new(e).context("Failed to read cache entry")),
+            Err(e) => Err(anyhow::Error::new(e).context("Failed to read cache entry"))
+                .code(ErrorCode::CacheIo),
+        }
+    }
+
+    /// Decode the contents of a cache file, migrating them from older layouts.
+    ///
+    /// Unlike loading the cache for a site, this fails if the contents can't be decoded.
+    pub fn decode(compressed: &[u8]) -> Result<Self> {
+        let encoded = Self::decompress(compressed)?;
+        match encoded.split_first() {
+            Some((&CACHE_SCHEMA_VERSION, fields)) => {
+                postcard::from_bytes(fields).context("Failed to decode cache file")
+            }
+            // Files from before the layout had a version start with the first field, which is an
+            // `Option` and so starts with 0 or 1.
+            Some((0 | 1, _)) => match postcard::take_from_bytes::<SiteCacheV0>(&encoded) {
+                Ok((old, [])) => Ok(old.into()),
+                Ok(_) => Err(anyhow::anyhow!(
+                    "Unversioned cache file has more than the first release's fields"
+                ))
+                .code(ErrorCode::CacheCorrupt),
+                Err(e) => {
+                    Err(anyhow::Error::new(e).context("Failed to decode unversioned cache file"))
+                        .code(ErrorCode::CacheCorrupt)
+                }
+            },
+            Some((version, _)) => Err(anyhow::anyhow!(
+                "Cache file has schema version {version}, but only versions up to \
+                 {CACHE_SCHEMA_VERSION} are understood, so it's likely from a newer jarss"
+            ))
+            .code(ErrorCode::CacheCorrupt),
+            None => Err(anyhow::anyhow!("Cache file is empty")).code(ErrorCode::CacheCorrupt),
         }
     }
 
-    /// Save the cache entry for the given site.
-    async fn save_for_site(&self, cache_dir: impl AsRef<Path>, site_name: &str) -> Result<()> {
+    /// Encode the cache as the contents of a cache file.
+    ///
+    /// The body isn't included, see [`Self::body_file_path`].
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded = vec![CACHE_SCHEMA_VERSION];
+        postcard::to_io(self, &mut encoded).context("Error encoding cache")?;
+        Self::compress(&encoded)
+    }
+
+    /// Compress the contents of a cache file.
+    fn compress(encoded: &[u8]) -> Result<Vec<u8>> {
         use std::io::Write as _;
-        use tokio::io::AsyncWriteExt as _;
 
-        let _ = std::fs::create_dir_all(&cache_dir);
-        let path = cache_dir
-            .as_ref()
-            .join(Self::cache_file_for_name(site_name));
-        let encoded = postcard::to_stdvec(self).context("Error writing out cache")?;
-        let compressed = {
-            let mut lz4 = lz4_flex::frame::FrameEncoder::new(Vec::new());
-            lz4.write_all(&encoded)?;
-            lz4.finish()?
+        let mut lz4 = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        lz4.write_all(encoded)?;
+        Ok(lz4.finish()?)
+    }
+
+    /// Read the body from its file next to the cache file at `path`, if the cache has one that
+    /// isn't loaded yet.
+    ///
+    /// If it can't be read, or isn't the body the cache file has the hash of, the body is
+    /// forgotten along with the headers, so the next fetch gets it again in full.
+    async fn load_body(&mut self, path: &Path, site_name: &str) {
+        let Some(hash) = self.body_hash.filter(|_| self.last_body.is_none()) else {
+            return;
         };
-        File::create(&path)
-            .await
-            .context("Error opening cache dir for writing")?
-            .write_all(&compressed)
+        let body_path = Self::body_file_path(path);
+        let body = async { Self::decode_body(&tokio::fs::read(&body_path).await?, &hash) };
+        match body.await {
+            Ok(body) => self.last_body = Some(body.into_boxed_str()),
+            Err(e) => {
+                log::warn!(
+                    "Failed to read the cached body for {site_name}, it'll be fetched again: {e:#}"
+                );
+                self.body_hash = None;
+                self.last_headers = None;
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Check that the body file next to the cache file at `path` is the body this cache has the
+    /// hash of, if it has one that isn't loaded.
+    pub fn check_body(&self, path: &Path) -> Result<()> {
+        let Some(hash) = self.body_hash.filter(|_| self.last_body.is_none()) else {
+            return Ok(());
+        };
+        let compressed = std::fs::read(Self::body_file_path(path))?;
+        Self::decode_body(&compressed, &hash).map(drop)
+    }
+
+    /// Decompress the contents of a body file, checking it against the hash in the cache file.
+    fn decode_body(compressed: &[u8], hash: &[u8; 32]) -> Result<String> {
+        let body = String::from_utf8(Self::decompress(compressed)?)?;
+        anyhow::ensure!(
+            blake3::hash(body.as_bytes()).as_bytes() == hash,
+            "It doesn't match the cache file"
+        );
+        Ok(body)
+    }
+
+    /// Decompress the contents of a cache file into the postcard-encoded [`SiteCache`].
+    fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+        use std::io::Read as _;
+        let mut encoded = Vec::new();
+        lz4_flex::frame::FrameDecoder::new(compressed)
+            .read_to_end(&mut encoded)
+            .context("Failed to read cache file")
+            .code(ErrorCode::CacheCorrupt)?;
+        Ok(encoded)
+    }
+
+    /// Save the cache entry under the given key.
+    async fn save_for_site(&self, cache_dir: impl AsRef<Path>, key: &str) -> Result<()> {
+        let _ = std::fs::create_dir_all(&cache_dir);
+        let path = Self::cache_file_path(cache_dir.as_ref(), key);
+        if self.body_dirty {
+            let body_path = Self::body_file_path(&path);
+            match &self.last_body {
+                Some(body) => {
+                    let compressed =
+                        Self::compress(body.as_bytes()).context("Error writing out cache")?;
+                    tokio::task::spawn_blocking(move || {
+                        replace::write_atomically(&body_path, &compressed)
+                    })
+                    .await
+                    .context("Error writing out cached body")?
+                    .context("Error writing out cached body")?;
+                }
+                None => match tokio::fs::remove_file(&body_path).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(anyhow::Error::new(e).context("Failed to remove cached body"));
+                    }
+                    _ => {}
+                },
+            }
+        }
+        let compressed = self.encode().context("Error writing out cache")?;
+        // Written atomically, so being interrupted can't leave a truncated cache.
+        tokio::task::spawn_blocking(move || replace::write_atomically(&path, &compressed))
             .await
-            .context("Error writing out cache")?;
-        Ok(())
+            .context("Error writing out cache")?
+            .context("Error writing out cache")
+    }
+
+    /// The path of the file the body is kept in, for the cache file at `cache_path`.
+    ///
+    /// The body is written before the cache file, so the cache file never has the hash of a body
+    /// which wasn't written.
+    fn body_file_path(cache_path: &Path) -> PathBuf {
+        cache_path.with_extension("body.lz4")
+    }
+
+    /// The path of the cache file for the given key, in the given cache directory.
+    ///
+    /// # Panics
+    /// If the path would somehow end up outside the cache directory. [`Self::cache_file_for_name`]
+    /// should make that impossible, but site names may come from sources we don't trust, so we
+    /// make sure.
+    fn cache_file_path(cache_dir: &Path, key: &str) -> PathBuf {
+        Self::checked_path(cache_dir, Self::cache_file_for_name(key))
+    }
+
+    /// The path the cache file for the given key had before
+    /// [`Self::cache_file_for_name`] added hashes.
+    fn legacy_cache_file_path(cache_dir: &Path, key: &str) -> PathBuf {
+        Self::checked_path(cache_dir, Self::legacy_cache_file_for_name(key))
+    }
+
+    /// `filename` in the cache directory, making sure it stays there.
+    fn checked_path(cache_dir: &Path, filename: String) -> PathBuf {
+        let mut components = Path::new(&filename).components();
+        assert!(
+            matches!(
+                (components.next(), components.next()),
+                (Some(std::path::Component::Normal(_)), None)
+            ),
+            "Cache file name {filename:?} isn't a plain file name"
+        );
+        cache_dir.join(filename)
     }
 
     /// Turn a feed name into the name of the cache file.
     ///
-    /// The name will be composed entirely of lower-case letters, numbers, and `-`s. Any characters
-    /// which are not one of those, as well as any characters which lack a unique lower-case
-    /// mapping, are excluded.
+    /// The name is a readable slug of the feed name, composed entirely of lower-case letters,
+    /// numbers, and `-`s, followed by a hash of the full name. The hash keeps names distinct even
+    /// when their slugs are the same, like ones which only differ in punctuation or which are made
+    /// of characters the slug leaves out.
+    ///
+    /// Slugs which are too long to be safe as file names on common filesystems are truncated.
+    fn cache_file_for_name(name: &str) -> String {
+        let mut slug = Self::slug(name);
+        let hash = blake3::hash(name.as_bytes()).to_hex();
+        let hash = &hash[..CACHE_FILE_HASH_LEN];
+        if slug.is_empty() {
+            return format!("{hash}.lz4");
+        }
+        let mut end = slug
+            .len()
+            .min(MAX_FILE_STEM_BYTES - CACHE_FILE_HASH_LEN - 1);
+        while !slug.is_char_boundary(end) {
+            end -= 1;
+        }
+        slug.truncate(end);
+        format!("{slug}-{hash}.lz4")
+    }
+
+    /// The name of the cache file for a feed name, as it was before
+    /// [`Self::cache_file_for_name`] added hashes, which could be the same for different names.
+    ///
+    /// Names which are too long to be safe as file names on common filesystems are truncated, with
+    /// a hash of the full name added to keep them distinct, and names which Windows reserves for
+    /// devices get a `-` added.
+    fn legacy_cache_file_for_name(name: &str) -> String {
+        let mut filename = Self::slug(name);
+        if filename.len() > MAX_FILE_STEM_BYTES {
+            let hash = blake3::hash(name.as_bytes()).to_hex();
+            let mut end = MAX_FILE_STEM_BYTES - 17;
+            while !filename.is_char_boundary(end) {
+                end -= 1;
+            }
+            filename = format!("{}-{}", &filename[..end], &hash[..16]);
+        }
+        if WINDOWS_RESERVED_NAMES.contains(&filename.as_str()) {
+            filename.push('-');
+        }
+        filename += ".lz4";
+        filename
+    }
+
+    /// The readable part of a cache file name.
+    ///
+    /// The slug will be composed entirely of lower-case letters, numbers, and `-`s. Any
+    /// characters which are not one of those, as well as any characters which lack a unique
+    /// lower-case mapping, are excluded.
     ///
     /// Yes, this is slightly anglophone-centric, but this is an internal detail users shouldn't
     /// see, so I don't really care.
-    fn cache_file_for_name(name: &str) -> String {
-        let mut filename = name
-            .chars()
+    fn slug(name: &str) -> String {
+        name.chars()
             .filter_map(|c| {
                 if c.is_alphanumeric() {
                     let mut lower_iter = c.to_lowercase();
@@ -280,8 +1490,1123 @@ impl SiteCache {
                     None
                 }
             })
-            .collect::<String>();
-        filename += ".lz4";
-        filename
+            .collect()
+    }
+}
+
+/// The layout of [`SiteCache`] in the unversioned cache files written by the first releases.
+#[derive(serde::Deserialize)]
+struct SiteCacheV0 {
+    last_retry_after: Option<SystemTime>,
+    last_headers: Option<HashMap<Box<str>, Box<str>>>,
+    last_body: Option<Box<str>>,
+    last_fetch_time: Option<SystemTime>,
+}
+impl From<SiteCacheV0> for SiteCache {
+    fn from(old: SiteCacheV0) -> Self {
+        let has_body = old.last_body.is_some();
+        Self {
+            last_retry_after: old.last_retry_after,
+            last_headers: old.last_headers,
+            body_hash: old
+                .last_body
+                .as_ref()
+                .map(|body| *blake3::hash(body.as_bytes()).as_bytes()),
+            last_body: old.last_body,
+            last_fetch_time: old.last_fetch_time,
+            // Saved in the new layout, with the body in its own file, the next time it's saved.
+            dirty: true,
+            body_dirty: has_body,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// The sites of a config with the given `[[sites]]` tables.
+    fn sites(sites: &str) -> Vec<SiteConfig> {
+        toml::from_str::<Config>(&format!("min_fetch_interval = 0\n{sites}"))
+            .unwrap()
+            .sites
+    }
+
+    const BODY: &str = "<rss></rss>";
+
+    fn example_cache() -> SiteCache {
+        SiteCache {
+            last_body: Some(BODY.into()),
+            body_hash: Some(*blake3::hash(BODY.as_bytes()).as_bytes()),
+            last_headers: Some(HashMap::from([("etag".into(), "\"1\"".into())])),
+            last_fetch_time: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            redirected_url: Some("https://example.com/feed".into()),
+            body_dirty: true,
+            ..SiteCache::default()
+        }
+    }
+
+    /// The example cache in the layout of the unversioned files of the first releases.
+    fn v0_fields(last_retry_after: Option<SystemTime>) -> Vec<u8> {
+        #[derive(serde::Serialize)]
+        struct V0<'a> {
+            last_retry_after: Option<SystemTime>,
+            last_headers: Option<HashMap<Box<str>, Box<str>>>,
+            last_body: Option<&'a str>,
+            last_fetch_time: Option<SystemTime>,
+        }
+        let cache = example_cache();
+        postcard::to_stdvec(&V0 {
+            last_retry_after,
+            last_headers: cache.last_headers,
+            last_body: Some(BODY),
+            last_fetch_time: cache.last_fetch_time,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn unversioned_layouts_are_migrated() {
+        for last_retry_after in [None, Some(SystemTime::UNIX_EPOCH)] {
+            let encoded = v0_fields(last_retry_after);
+            let decoded = SiteCache::decode(&SiteCache::compress(&encoded).unwrap()).unwrap();
+            assert_eq!(decoded.last_retry_after, last_retry_after);
+            assert_eq!(decoded.last_body.as_deref(), Some(BODY));
+            assert_eq!(decoded.body_hash, example_cache().body_hash);
+            assert_eq!(decoded.last_headers, example_cache().last_headers);
+            assert!(decoded.dirty && decoded.body_dirty);
+        }
+        // Anything after the first release's fields means it isn't one of its files.
+        let trailing = [v0_fields(None), vec![0]].concat();
+        let e = SiteCache::decode(&SiteCache::compress(&trailing).unwrap()).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::CacheCorrupt);
+    }
+
+    /// A cache file written by the first release, after fetching a feed from a local server.
+    const BASELINE_CACHE: &[u8] = include_bytes!("../testdata/baseline-cache.lz4");
+
+    #[test]
+    fn caches_from_the_first_release_are_migrated() {
+        let decoded = SiteCache::decode(BASELINE_CACHE).unwrap();
+        let headers = decoded.last_headers.as_ref().unwrap();
+        assert_eq!(
+            headers.get("last-modified").map(AsRef::as_ref),
+            Some("Wed, 14 Oct 2026 18:37:54 GMT")
+        );
+        let body = decoded.last_body.as_deref().unwrap();
+        assert!(body.contains("<title>Kept</title>"), "{body}");
+        assert_eq!(
+            decoded.body_hash,
+            Some(*blake3::hash(body.as_bytes()).as_bytes())
+        );
+        assert!(decoded.last_fetch_time.is_some());
+        assert_eq!(decoded.last_retry_after, None);
+        assert!(decoded.dirty && decoded.body_dirty);
+    }
+
+    #[tokio::test]
+    async fn caches_from_the_first_release_are_loaded_from_their_old_name() {
+        let dir = test_dir("first-release");
+        std::fs::write(dir.join("old-site.lz4"), BASELINE_CACHE).unwrap();
+        let sites = sites(
+            r#"
+            [[sites]]
+            name = "Old Site"
+            feed_url = "http://localhost:8765/v0.xml"
+            "#,
+        );
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &sites).unwrap();
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(&sites[0], &guard).await.unwrap();
+        assert!(cache.last_headers.is_some());
+        assert!(cache.last_body.is_some());
+        drop(cache);
+        drop(guard);
+        drop(caches);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sites_with_the_same_name_are_told_apart_by_url() {
+        let settings = r#"
+            min_fetch_interval = 0
+            cache_key = "url"
+            [[sites]]
+            name = "Same"
+            feed_url = "https://a.example/feed"
+            [[sites]]
+            name = "Same"
+            feed_url = "https://b.example/feed"
+            display = false
+            "#;
+        // Feeds are given in the order of the sites, so the shown site has `a`.
+        let feeds = std::sync::atomic::AtomicUsize::new(0);
+        let collected = crate::test_util::collect_cached("same-name", settings, |_| {
+            let title = ["a", "b"][feeds.fetch_add(1, std::sync::atomic::Ordering::Relaxed)];
+            format!(
+                "<rss version=\"2.0\"><channel><title>{title}</title><item><title>{title}</title>\
+                 <link>https://{title}.example/1</link><pubDate>Mon, 01 Jan 2024 00:00:00 GMT\
+                 </pubDate></item></channel></rss>"
+            )
+        })
+        .await;
+        let titles = collected
+            .articles
+            .iter()
+            .map(|article| &*article.title)
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["a"]);
+    }
+
+    #[test]
+    fn caches_from_newer_versions_are_rejected() {
+        let encoded = [vec![CACHE_SCHEMA_VERSION + 1], v0_fields(None)].concat();
+        let e = SiteCache::decode(&SiteCache::compress(&encoded).unwrap()).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::CacheCorrupt);
+        assert!(SiteCache::decode(&SiteCache::compress(&[]).unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn caches_are_saved_and_loaded_in_the_current_layout() {
+        let dir = test_dir("round-trip");
+        example_cache().save_for_site(&dir, "site").await.unwrap();
+        let compressed = std::fs::read(SiteCache::cache_file_path(&dir, "site")).unwrap();
+        assert_eq!(
+            SiteCache::decompress(&compressed).unwrap().first(),
+            Some(&CACHE_SCHEMA_VERSION)
+        );
+        let loaded = SiteCache::load_for_site(&dir, "site", "Site")
+            .await
+            .unwrap();
+        let example = example_cache();
+        assert_eq!(loaded.last_body, example.last_body);
+        assert_eq!(loaded.last_headers, example.last_headers);
+        assert_eq!(loaded.last_fetch_time, example.last_fetch_time);
+        assert_eq!(loaded.redirected_url, example.redirected_url);
+        assert!(!loaded.dirty);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bodies_are_only_written_when_changed() {
+        let dir = test_dir("bodies-written");
+        let body_path = SiteCache::body_file_path(&SiteCache::cache_file_path(&dir, "site"));
+        let mut cache = example_cache();
+        cache.save_for_site(&dir, "site").await.unwrap();
+        assert!(body_path.exists());
+        let loaded = SiteCache::load_for_site(&dir, "site", "Site")
+            .await
+            .unwrap();
+        assert_eq!(loaded.last_body.as_deref(), Some(BODY));
+
+        // Saving a cache whose body is unchanged leaves the body's file alone.
+        std::fs::remove_file(&body_path).unwrap();
+        cache.body_dirty = false;
+        cache.last_fetch_time = Some(SystemTime::UNIX_EPOCH);
+        cache.save_for_site(&dir, "site").await.unwrap();
+        assert!(!body_path.exists());
+
+        // Which loses the body, and the headers so that it's fetched in full again.
+        let loaded = SiteCache::load_for_site(&dir, "site", "Site")
+            .await
+            .unwrap();
+        assert_eq!(loaded.last_fetch_time, Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(loaded.last_body, None);
+        assert_eq!(loaded.body_hash, None);
+        assert!(loaded.last_headers.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bodies_which_dont_match_their_hash_are_dropped() {
+        let dir = test_dir("bodies-mismatched");
+        let cache = example_cache();
+        cache.save_for_site(&dir, "site").await.unwrap();
+        let body_path = SiteCache::body_file_path(&SiteCache::cache_file_path(&dir, "site"));
+        std::fs::write(
+            &body_path,
+            SiteCache::compress(b"<rss>other</rss>").unwrap(),
+        )
+        .unwrap();
+        let loaded = SiteCache::load_for_site(&dir, "site", "Site")
+            .await
+            .unwrap();
+        assert_eq!(loaded.last_body, None);
+        assert!(loaded.last_headers.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Load the cache for `site` from `dir` after replacing its file with `contents`, checking
+    /// that it's loaded as though the site were new and that the file is moved aside.
+    async fn load_corrupt(dir: &Path, contents: &[u8]) {
+        let path = SiteCache::cache_file_path(dir, "site");
+        std::fs::write(&path, contents).unwrap();
+        let loaded = SiteCache::load_for_site(dir, "site", "Site").await.unwrap();
+        assert_eq!(loaded.last_fetch_time, None);
+        assert!(loaded.last_headers.is_none());
+        assert!(!path.exists());
+        let mut aside = path.into_os_string();
+        aside.push(".corrupt");
+        assert_eq!(std::fs::read(aside).unwrap(), contents);
+    }
+
+    #[tokio::test]
+    async fn truncated_cache_files_are_moved_aside() {
+        let dir = test_dir("truncated");
+        example_cache().save_for_site(&dir, "site").await.unwrap();
+        let compressed = std::fs::read(SiteCache::cache_file_path(&dir, "site")).unwrap();
+        load_corrupt(&dir, &compressed[..compressed.len() / 2]).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn undecodable_cache_files_are_moved_aside() {
+        let dir = test_dir("undecodable");
+        // Valid lz4, of a cache of the current version whose first field is neither `None` nor
+        // `Some`.
+        let contents = SiteCache::compress(&[CACHE_SCHEMA_VERSION, 7, 7, 7]).unwrap();
+        load_corrupt(&dir, &contents).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Save a cache for each of `sites` under `cache_key`, fetched from its feed URL, with an entry
+    /// named after the site.
+    async fn save_caches(dir: &Path, cache_key: CacheKey, sites: &[SiteConfig]) {
+        let caches = CacheManager::new(dir.to_owned(), cache_key, sites).unwrap();
+        let guard = caches.cache_guard();
+        for site in sites {
+            let mut cache = caches.get_mut(site, &guard).await.unwrap();
+            cache.feed_url = Some(site.feed_url.clone());
+            cache.seen_entries = [(site.name.clone(), SystemTime::UNIX_EPOCH)].into();
+            cache.mark_dirty();
+        }
+        drop(guard);
+        assert!(caches.save(1).await.is_empty());
+    }
+
+    /// The entries `site`'s cache has seen, loaded under `cache_key`.
+    async fn seen_entries(dir: &Path, cache_key: CacheKey, site: &SiteConfig) -> Vec<Box<str>> {
+        let caches =
+            CacheManager::new(dir.to_owned(), cache_key, std::slice::from_ref(site)).unwrap();
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(site, &guard).await.unwrap();
+        cache.seen_entries.keys().cloned().collect()
+    }
+
+    #[tokio::test]
+    async fn switching_to_url_keys_finds_caches_by_their_feed_url() {
+        let dir = test_dir("url-key-migration");
+        let before = sites(
+            r#"
+            [[sites]]
+            name = "Kept"
+            feed_url = "https://kept.example/feed"
+            [[sites]]
+            name = "Renamed"
+            feed_url = "https://renamed.example/feed"
+            [[sites]]
+            name = "Moved"
+            feed_url = "https://moved.example/feed"
+            "#,
+        );
+        save_caches(&dir, CacheKey::Name, &before).await;
+        // Renamed while switching, and the name of one site given to a different feed.
+        let after = sites(
+            r#"
+            [[sites]]
+            name = "Kept"
+            feed_url = "https://kept.example/feed"
+            [[sites]]
+            name = "Was Renamed"
+            feed_url = "https://renamed.example/feed"
+            [[sites]]
+            name = "Moved"
+            feed_url = "https://elsewhere.example/feed"
+            "#,
+        );
+        assert_eq!(
+            seen_entries(&dir, CacheKey::Url, &after[0]).await,
+            ["Kept".into()]
+        );
+        assert_eq!(
+            seen_entries(&dir, CacheKey::Url, &after[1]).await,
+            ["Renamed".into()]
+        );
+        assert_eq!(
+            seen_entries(&dir, CacheKey::Url, &after[2]).await,
+            Vec::<Box<str>>::new()
+        );
+        assert!(!SiteCache::cache_file_path(&dir, "Kept").exists());
+        assert!(!SiteCache::cache_file_path(&dir, "Renamed").exists());
+        assert!(SiteCache::cache_file_path(&dir, "Moved").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renaming_a_site_after_switching_to_url_keys_keeps_its_cache() {
+        let dir = test_dir("url-key-rename");
+        let before = sites(
+            r#"
+            [[sites]]
+            name = "Before"
+            feed_url = "https://example.com/feed"
+            "#,
+        );
+        save_caches(&dir, CacheKey::Url, &before).await;
+        let after = sites(
+            r#"
+            [[sites]]
+            name = "After"
+            feed_url = "https://example.com/feed"
+            "#,
+        );
+        assert_eq!(
+            seen_entries(&dir, CacheKey::Url, &after[0]).await,
+            ["Before".into()]
+        );
+        // Whereas keyed by name, the renamed site starts over.
+        assert_eq!(
+            seen_entries(&dir, CacheKey::Name, &after[0]).await,
+            Vec::<Box<str>>::new()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn corrupt_and_temporary_files_are_cleaned_up() {
+        let dir = test_dir("leftovers");
+        let sites = sites(
+            r#"
+            [[sites]]
+            name = "Site"
+            feed_url = "https://example.com/feed"
+            "#,
+        );
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &sites).unwrap();
+        let live = caches.cache_path(&sites[0]);
+        let name = |suffix: &str| {
+            let mut name = live.file_name().unwrap().to_owned();
+            name.push(suffix);
+            dir.join(name)
+        };
+        for path in [
+            name(".corrupt"),
+            name(".tmp"),
+            dir.join("gone.lz4.tmp"),
+            dir.join("unrelated.tmp"),
+        ] {
+            std::fs::write(path, "").unwrap();
+        }
+        assert_eq!(
+            caches.orphaned_files(&sites).unwrap(),
+            [dir.join("gone.lz4.tmp"), name(".corrupt"), name(".tmp")]
+        );
+        // A temporary file is kept while it's needed to finish replacing its cache.
+        std::fs::write(name(".replacing"), "").unwrap();
+        assert_eq!(
+            caches.orphaned_files(&sites).unwrap(),
+            [dir.join("gone.lz4.tmp"), name(".corrupt")]
+        );
+        drop(caches);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clean_deletes_exactly_the_caches_no_site_owns() {
+        let dir = test_dir("clean");
+        let cache_dir = dir.join("cache");
+        let config_path = dir.join("jarss.toml");
+        let toml = "min_fetch_interval = 0\n\
+                    [[sites]]\nname = \"Written\"\nfeed_url = \"https://a.example/feed\"\n\
+                    [[sites]]\nname = \"Unwritten\"\nfeed_url = \"https://b.example/feed\"\n\
+                    [[sites]]\nname = \"Disabled\"\nfeed_url = \"https://c.example/feed\"\n\
+                    enabled = false\n";
+        std::fs::write(&config_path, toml).unwrap();
+        let mut all_sites = sites(toml.trim_start_matches("min_fetch_interval = 0\n"));
+        let removed = sites(
+            r#"
+            [[sites]]
+            name = "Removed"
+            feed_url = "https://d.example/feed"
+            "#,
+        );
+        all_sites.extend(removed.iter().cloned());
+        let written = [&all_sites[0], &all_sites[2], &removed[0]].map(Clone::clone);
+        for site in &written {
+            let caches = CacheManager::new(
+                cache_dir.clone(),
+                CacheKey::Name,
+                std::slice::from_ref(site),
+            )
+            .unwrap();
+            let guard = caches.cache_guard();
+            let mut cache = caches.get_mut(site, &guard).await.unwrap();
+            *cache = example_cache();
+            cache.mark_dirty();
+            drop(cache);
+            drop(guard);
+            assert!(caches.save(1).await.is_empty());
+        }
+        let caches = CacheManager::new(cache_dir.clone(), CacheKey::Name, &all_sites).unwrap();
+        let [written, disabled, removed] =
+            [&all_sites[0], &all_sites[2], &all_sites[3]].map(|site| caches.cache_path(site));
+        drop(caches);
+        let leftovers = ["other.lz4.corrupt", "other.lz4.tmp"].map(|name| cache_dir.join(name));
+        let unrelated = cache_dir.join("notes.txt");
+        // What the unwritten site's cache would be migrated from, when it's first loaded.
+        let unmigrated = SiteCache::legacy_cache_file_path(&cache_dir, "Unwritten");
+        for path in leftovers.iter().chain([&unrelated, &unmigrated]) {
+            std::fs::write(path, "").unwrap();
+        }
+        let files = || {
+            let mut files = std::fs::read_dir(&cache_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.file_name().unwrap() != LOCK_FILE_NAME)
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        let clean = async |dry_run: &[&str]| {
+            let paths = [&config_path, &cache_dir].map(|path| path.to_str().unwrap());
+            let args = ["jarss", "--config", paths[0], "--cache", paths[1], "clean"]
+                .into_iter()
+                .chain(dry_run.iter().copied());
+            crate::dispatch(
+                <crate::Args as clap::Parser>::parse_from(args)
+                    .try_into()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        };
+
+        let before = files();
+        assert!(before.contains(&removed) && before.contains(&SiteCache::body_file_path(&removed)));
+        clean(&["--dry-run"]).await;
+        assert_eq!(files(), before);
+        clean(&[]).await;
+        let mut kept = vec![
+            SiteCache::body_file_path(&disabled),
+            disabled,
+            SiteCache::body_file_path(&written),
+            written,
+            unrelated,
+            unmigrated,
+        ];
+        kept.sort();
+        assert_eq!(files(), kept);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// When the given file was last written.
+    fn modified(path: &Path) -> SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_caches_which_changed_are_written() {
+        const BODY: &str = "<rss><channel><title>Feed</title></channel></rss>";
+        let dir = test_dir("dirty");
+        let feeds = crate::test_server::serve_http(|head| {
+            if head.contains("if-none-match: \"1\"") {
+                "HTTP/1.1 304 Not Modified\r\netag: \"1\"\r\nconnection: close\r\n\r\n".to_owned()
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\netag: \"1\"\r\ncontent-type: application/rss+xml\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{BODY}",
+                    BODY.len()
+                )
+            }
+        })
+        .await;
+        let config = toml::from_str::<Config>(&format!(
+            "min_fetch_interval = 0\n[[sites]]\nname = \"Site\"\nfeed_url = \"http://{feeds}/feed\"\n"
+        ))
+        .unwrap();
+        let load = || CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let caches = load();
+        assert!(
+            crate::test_server::fetch_all(&config, &caches)
+                .await
+                .is_empty()
+        );
+        // Leave time for another write to show in the modification time.
+        std::thread::sleep(Duration::from_millis(20));
+        let path = caches.cache_path(&config.sites[0]);
+        let body_path = SiteCache::body_file_path(&path);
+        let (written, body_written) = (modified(&path), modified(&body_path));
+        let guard = caches.cache_guard();
+        let first_fetch = caches
+            .get_mut(&config.sites[0], &guard)
+            .await
+            .unwrap()
+            .last_fetch_time;
+        drop(guard);
+
+        // Loading the caches and saving them again changes nothing, so writes nothing.
+        let caches = load();
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        assert!(!cache.dirty && !cache.body_dirty);
+        drop(cache);
+        drop(guard);
+        assert!(caches.save(1).await.is_empty());
+        assert_eq!(modified(&path), written);
+        assert_eq!(modified(&body_path), body_written);
+
+        // A 304 only changes when the site was fetched, which is still saved for throttling, so
+        // the body isn't written again but the rest of the cache is.
+        let caches = load();
+        let guard = caches.cache_guard();
+        let mut cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        let github = GitHub::new(&config, caches.state()).unwrap();
+        let origins = crate::origins::Origins::load(caches.state());
+        query_site(
+            &reqwest::Client::new(),
+            &config,
+            &github,
+            &origins,
+            &config.sites[0],
+            &mut cache,
+        )
+        .await
+        .unwrap();
+        assert!(cache.dirty && !cache.body_dirty);
+        assert!(cache.last_fetch_time > first_fetch);
+        drop(cache);
+        drop(guard);
+        assert!(caches.save(1).await.is_empty());
+        assert_ne!(modified(&path), written);
+        assert_eq!(modified(&body_path), body_written);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unchanged_bodies_without_validators_are_treated_like_304s() {
+        let dir = test_dir("no-validators");
+        let feeds = crate::test_server::serve_http(|_| {
+            crate::test_server::response("200 OK", "application/rss+xml", "<rss></rss>")
+        })
+        .await;
+        let config = toml::from_str::<Config>(&format!(
+            "min_fetch_interval = 0\n[[sites]]\nname = \"Site\"\nfeed_url = \"http://{feeds}/feed\"\n"
+        ))
+        .unwrap();
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        assert!(
+            crate::test_server::fetch_all(&config, &caches)
+                .await
+                .is_empty()
+        );
+        let body_path = SiteCache::body_file_path(&caches.cache_path(&config.sites[0]));
+        let body_written = modified(&body_path);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(
+            crate::test_server::fetch_all(&config, &caches)
+                .await
+                .is_empty()
+        );
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        let changes = cache
+            .fetch_history
+            .iter()
+            .map(|record| (record.not_modified, record.changed))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, [(false, true), (false, false)]);
+        drop(cache);
+        drop(guard);
+        assert_eq!(modified(&body_path), body_written);
+        drop(caches);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bodies_which_cant_be_used_leave_the_cache_as_it_was() {
+        const SMALL: &str = "<rss><channel><title>Feed</title></channel></rss>";
+        let dir = test_dir("unusable-bodies");
+        let requests = std::sync::atomic::AtomicUsize::new(0);
+        let feeds = crate::test_server::serve_http(move |head| {
+            if head.starts_with("GET /page") {
+                return crate::test_server::response("200 OK", "text/html", "<html></html>");
+            }
+            if requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                format!(
+                    "HTTP/1.1 200 OK\r\netag: \"1\"\r\ncontent-type: application/rss+xml\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{SMALL}",
+                    SMALL.len()
+                )
+            } else {
+                // Without a length, so it's only found to be too large as it's read.
+                format!(
+                    "HTTP/1.1 200 OK\r\netag: \"2\"\r\ncontent-type: application/rss+xml\r\n\
+                     connection: close\r\n\r\n{}",
+                    SMALL.replace("Feed", &"x".repeat(1000))
+                )
+            }
+        })
+        .await;
+        let config = toml::from_str::<Config>(&format!(
+            "min_fetch_interval = 0\nstrict_content_type = true\n[limits]\nmax_body_bytes = 100\n\
+             [[sites]]\nname = \"Feed\"\nfeed_url = \"http://{feeds}/feed\"\n\
+             [[sites]]\nname = \"Page\"\nfeed_url = \"http://{feeds}/page\"\n"
+        ))
+        .unwrap();
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let codes =
+            |errors: Vec<anyhow::Error>| errors.iter().map(ErrorCode::of).collect::<Vec<_>>();
+        assert_eq!(
+            codes(crate::test_server::fetch_all(&config, &caches).await),
+            [ErrorCode::FetchNotAFeed]
+        );
+        assert_eq!(
+            codes(crate::test_server::fetch_all(&config, &caches).await),
+            [ErrorCode::FetchTooLarge, ErrorCode::FetchNotAFeed]
+        );
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        assert_eq!(cache.last_body.as_deref(), Some(SMALL));
+        assert_eq!(
+            cache
+                .last_headers
+                .as_ref()
+                .unwrap()
+                .get("etag")
+                .map(AsRef::as_ref),
+            Some("\"1\"")
+        );
+        drop(cache);
+        let page = caches.get_mut(&config.sites[1], &guard).await.unwrap();
+        assert!(page.last_body.is_none());
+        assert!(page.last_headers.is_none());
+        drop(page);
+        drop(guard);
+        drop(caches);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The cache file names a site name could have, now and before they had hashes.
+    fn cache_file_names(name: &str) -> [String; 2] {
+        [
+            SiteCache::cache_file_for_name(name),
+            SiteCache::legacy_cache_file_for_name(name),
+        ]
+    }
+
+    #[test]
+    fn cache_files_stay_in_the_cache_directory() {
+        let cache_dir = Path::new("/var/cache/jarss");
+        for name in [
+            "../../.ssh/authorized_keys",
+            "..",
+            ".",
+            "/etc/passwd",
+            "..\\..\\Windows\\System32",
+            "C:\\Users\\me",
+            "feed\0name",
+            "~root",
+            "",
+        ] {
+            let [filename, _] = cache_file_names(name);
+            // Old releases named caches with nothing but punctuation `.lz4`, so that's still
+            // looked for, but a name of its own is used from now on.
+            assert!(!filename.starts_with('.'), "{filename:?} from {name:?}");
+            for filename in cache_file_names(name) {
+                assert!(
+                    filename
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-.".contains(c)),
+                    "{filename:?} from {name:?}"
+                );
+                let path = SiteCache::checked_path(cache_dir, filename);
+                assert_eq!(path.parent(), Some(cache_dir), "{name:?}");
+            }
+        }
+        assert_eq!(
+            SiteCache::cache_file_for_name("../../.ssh/authorized_keys"),
+            format!(
+                "sshauthorized-keys-{}.lz4",
+                &blake3::hash(b"../../.ssh/authorized_keys").to_hex()[..CACHE_FILE_HASH_LEN]
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic = "isn't a plain file name"]
+    fn cache_paths_outside_the_cache_directory_are_refused() {
+        SiteCache::checked_path(Path::new("/var/cache/jarss"), "../escaped.lz4".to_owned());
+    }
+
+    #[test]
+    fn cache_files_arent_named_like_windows_devices() {
+        for name in ["CON", "nul", "Com1", "LPT9", "aux", "PRN"] {
+            for filename in cache_file_names(name) {
+                // Windows reserves the names whatever their extension.
+                let stem = filename.split('.').next().unwrap();
+                assert!(
+                    !WINDOWS_RESERVED_NAMES.contains(&stem),
+                    "{filename:?} from {name:?}"
+                );
+            }
+        }
+        assert_eq!(SiteCache::legacy_cache_file_for_name("CON"), "con-.lz4");
+    }
+
+    #[test]
+    fn long_names_are_truncated_and_kept_distinct() {
+        for long in ["a".repeat(300), "é".repeat(300), "语".repeat(300)] {
+            let other = format!("{long}b");
+            for (filename, other_filename) in cache_file_names(&long)
+                .into_iter()
+                .zip(cache_file_names(&other))
+            {
+                let stem = filename.strip_suffix(".lz4").unwrap();
+                assert!(stem.len() <= MAX_FILE_STEM_BYTES, "{filename:?}");
+                assert_ne!(filename, other_filename);
+            }
+        }
+    }
+
+    /// Send a GET of `url` with [`send_following_redirects`], giving where it ended up.
+    async fn get_following_redirects(url: &str) -> Result<(String, bool)> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let request = client.get(url).build().unwrap();
+        let (res, permanent) = send_following_redirects(&client, request).await?;
+        Ok((res.url().to_string(), permanent))
+    }
+
+    #[tokio::test]
+    async fn feeds_are_fetched_through_chains_of_redirects() {
+        let server = crate::test_server::serve_redirect_chains().await;
+        assert_eq!(
+            get_following_redirects(&format!("http://{server}/redirect/3"))
+                .await
+                .unwrap(),
+            (format!("http://{server}/redirect/0"), true)
+        );
+        assert_eq!(
+            get_following_redirects(&format!("http://{server}/redirect/0"))
+                .await
+                .unwrap(),
+            (format!("http://{server}/redirect/0"), false),
+            "no redirects aren't permanent ones"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_stop_at_too_many_redirects() {
+        let server = crate::test_server::serve_redirect_chains().await;
+        assert!(
+            get_following_redirects(&format!("http://{server}/redirect/{MAX_REDIRECTS}"))
+                .await
+                .is_ok()
+        );
+        let e = get_following_redirects(&format!("http://{server}/redirect/{}", MAX_REDIRECTS + 1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Too many redirects, the last was to /redirect/0"
+        );
+    }
+
+    #[tokio::test]
+    async fn permanent_redirects_are_remembered_until_the_feed_url_changes() {
+        let dir = test_dir("redirects");
+        let server = crate::test_server::serve_redirect_chains().await;
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\n\
+             [[sites]]\nname = \"Moved\"\nfeed_url = \"http://{server}/redirect/2\"\n"
+        ))
+        .unwrap();
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let fetched = crate::test_server::fetch_all(&config, &caches).await;
+        assert!(fetched.is_empty(), "{fetched:?}");
+        let site = &config.sites[0];
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(site, &guard).await.unwrap();
+        let moved_to = format!("http://{server}/redirect/0");
+        assert_eq!(cache.current_redirect(site), Some(&*moved_to));
+        let mut moved = site.clone();
+        moved.feed_url = format!("http://{server}/redirect/1").into();
+        assert_eq!(cache.current_redirect(&moved), None);
+        drop(cache);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_gone_for_long_enough_are_forgotten_along_with_their_resolved_links() {
+        let sites = sites("[[sites]]\nname = \"Site\"\nfeed_url = \"https://a.example/feed\"\n");
+        let limits = sanitize::Limits::default();
+        let feed = |link: &str| {
+            format!(
+                "<rss version=\"2.0\"><channel><title>Site</title>\
+                 <item><title>Entry</title><guid>{link}</guid><link>{link}</link></item>\
+                 </channel></rss>"
+            )
+        };
+        let fetched = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut cache = SiteCache {
+            last_body: Some(feed("https://a.example/old").into()),
+            ..SiteCache::default()
+        };
+        cache.record_seen_entries(&sites[0], &limits, fetched);
+        cache.resolved_links.insert(
+            "https://a.example/old".into(),
+            "https://b.example/old".into(),
+        );
+
+        // The entry leaves the feed, and is remembered until it's been gone for long enough.
+        cache.last_body = Some(feed("https://a.example/new").into());
+        let remembered = |cache: &SiteCache| {
+            (
+                cache
+                    .entries_last_seen
+                    .contains_key("https://a.example/old"),
+                cache.seen_entries.contains_key("https://a.example/old"),
+                cache.resolved_links.contains_key("https://a.example/old"),
+            )
+        };
+        cache.record_seen_entries(
+            &sites[0],
+            &limits,
+            fetched + LAST_SEEN_RETENTION - Duration::from_secs(1),
+        );
+        assert_eq!(remembered(&cache), (true, true, true));
+        cache.record_seen_entries(&sites[0], &limits, fetched + LAST_SEEN_RETENTION);
+        assert_eq!(remembered(&cache), (false, false, false));
+        assert!(
+            cache
+                .entries_last_seen
+                .contains_key("https://a.example/new")
+        );
+    }
+
+    #[test]
+    fn junk_entries_are_only_left_unseen_when_not_recorded() {
+        let body = "<rss version=\"2.0\"><channel><title>Site</title>\
+                    <item><title>A real post</title><guid>real</guid></item>\
+                    <item><title>x</title><guid>junk</guid></item></channel></rss>";
+        let limits = sanitize::Limits::default();
+        let seen = |record_filtered: bool| {
+            let config = crate::config_files::to_config(
+                toml::from_str(&format!(
+                    "min_fetch_interval = 0\n\
+                     [[sites]]\nname = \"Site\"\nfeed_url = \"https://a.example/feed\"\n\
+                     junk = {{ min_title_chars = 3, record_filtered = {record_filtered} }}\n"
+                ))
+                .unwrap(),
+                false,
+            )
+            .unwrap();
+            let mut cache = SiteCache {
+                last_body: Some(body.into()),
+                ..SiteCache::default()
+            };
+            cache.record_seen_entries(&config.sites[0], &limits, SystemTime::now());
+            let mut seen = cache.seen_entries.keys().cloned().collect::<Vec<_>>();
+            seen.sort_unstable();
+            seen
+        };
+        assert_eq!(seen(true), [Box::from("junk"), Box::from("real")]);
+        assert_eq!(seen(false), [Box::from("real")]);
+    }
+
+    #[test]
+    fn only_entries_first_seen_in_the_latest_fetch_are_new() {
+        let feed = |ids: &[&str]| {
+            let items = ids
+                .iter()
+                .map(|id| format!("<item><title>{id}</title><guid>{id}</guid></item>"))
+                .collect::<String>();
+            format!("<rss version=\"2.0\"><channel><title>Site</title>{items}</channel></rss>")
+        };
+        let sites = sites("[[sites]]\nname = \"Site\"\nfeed_url = \"https://a.example/feed\"\n");
+        let limits = sanitize::Limits::default();
+        let mut cache = SiteCache::default();
+        let mut fetch = |ids: &[&str]| {
+            cache.last_body = Some(feed(ids).into());
+            cache.record_seen_entries(&sites[0], &limits, SystemTime::now());
+            let mut new = cache.new_entries.iter().map(|id| &**id).collect::<Vec<_>>();
+            new.sort_unstable();
+            new.join(" ")
+        };
+        // Nothing is new on the first fetch, or there'd be nothing but new articles.
+        assert_eq!(fetch(&["a", "b"]), "");
+        assert_eq!(fetch(&["c", "d", "a", "b"]), "c d");
+        // Entries which come back aren't new again.
+        assert_eq!(fetch(&["b", "c"]), "");
+        assert_eq!(fetch(&["e", "a"]), "e");
+    }
+
+    #[tokio::test]
+    async fn json_feeds_are_read_from_caches() {
+        let dir = test_dir("json-feed");
+        let config = crate::config_files::to_config(
+            toml::from_str(
+                "min_fetch_interval = 0\n\
+                 [[sites]]\nname = \"JSON\"\nfeed_url = \"https://json.example/feed.json\"\n",
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let fetched = SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_326_400);
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let guard = caches.cache_guard();
+        let mut cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        let body = include_str!("../testdata/json-feed.json");
+        cache.last_body = Some(body.into());
+        cache.body_hash = Some(*blake3::hash(body.as_bytes()).as_bytes());
+        cache.last_fetch_time = Some(fetched);
+        cache.body_dirty = true;
+        cache.mark_dirty();
+        drop(cache);
+        drop(guard);
+        assert!(caches.save(1).await.is_empty());
+        drop(caches);
+
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let guard = caches.cache_guard();
+        drop(caches.get_mut(&config.sites[0], &guard).await.unwrap());
+        let feeds = futures::StreamExt::collect::<Vec<_>>(caches.feeds(&guard)).await;
+        let [(_, site, Ok(feed))] = &feeds[..] else {
+            panic!("Expected one parsed feed");
+        };
+        assert_eq!(*site, "JSON");
+        assert_eq!(feed.feed.feed_type, feed_rs::model::FeedType::JSON);
+        let titles = feed
+            .feed
+            .entries
+            .iter()
+            .map(|entry| entry.title.as_ref().unwrap().content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["Published", "Modified", "Undated"]);
+        drop(guard);
+
+        // Entries without a publication date still make it to the page.
+        let articles =
+            crate::collect_articles(&config, &caches, &crate::trace::ArticleTrace::new(None))
+                .await
+                .articles;
+        let dated = articles
+            .iter()
+            .map(|article| (&*article.title, article.published.timestamp()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dated,
+            [
+                ("Undated", 1_704_326_400),
+                ("Published", 1_704_240_000),
+                ("Modified", 1_704_153_600),
+            ]
+        );
+        drop(caches);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retry_after_delay_seconds() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(now + Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 0 ", now), Some(now));
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        // 784111777 is Sun, 06 Nov 1994 08:49:37 GMT.
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let now = date - Duration::from_secs(60);
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(date)
+        );
+    }
+
+    #[test]
+    fn retry_after_in_the_past_means_now() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(now)
+        );
+    }
+
+    #[test]
+    fn retry_after_overflowing_the_clock_is_ignored() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        // Too big for a `SystemTime`, and too big for a `u64`.
+        for value in [u64::MAX.to_string(), format!("{}0", u64::MAX)] {
+            assert_eq!(parse_retry_after(&value, now), None, "{value:?}");
+        }
+        // Absurd but representable values are kept, for `doctor` to point out.
+        assert_eq!(
+            parse_retry_after("315360000", now),
+            Some(now + Duration::from_secs(315_360_000))
+        );
+    }
+
+    #[test]
+    fn retry_after_dates_are_taken_against_our_clock() {
+        // 784111777 is Sun, 06 Nov 1994 08:49:37 GMT.
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        // A clock a year behind the server's waits out the whole year.
+        let behind = date - Duration::from_secs(365 * 24 * 60 * 60);
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", behind),
+            Some(date)
+        );
+        // A clock ahead of the server's doesn't wait at all.
+        let ahead = date + Duration::from_secs(60 * 60);
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", ahead),
+            Some(ahead)
+        );
+        // Other time zones are the same instant.
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 00:49:37 -0800", behind),
+            Some(date)
+        );
+    }
+
+    #[test]
+    fn retry_after_garbage_is_ignored() {
+        let now = SystemTime::UNIX_EPOCH;
+        for value in ["", "soon", "-5", "1.5", "Sun, 32 Nov 1994 08:49:37 GMT"] {
+            assert_eq!(parse_retry_after(value, now), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn lookalike_names_are_kept_distinct() {
+        // "Paypal" in Latin letters, and with Cyrillic letters that look the same.
+        let latin = "Paypal";
+        let cyrillic = "\u{420}\u{430}\u{443}\u{440}\u{430}l";
+        assert_ne!(latin, cyrillic);
+        assert_ne!(
+            SiteCache::cache_file_for_name(latin),
+            SiteCache::cache_file_for_name(cyrillic)
+        );
+        // Names which used to share a file, when slugs left out anything but ASCII.
+        let [first, second] = ["Блог Иванова", "Блог Петрова"].map(SiteCache::cache_file_for_name);
+        assert_ne!(first, second);
+        let sites = sites(&format!(
+            "[[sites]]\nname = \"{latin}\"\nfeed_url = \"https://a.example/feed\"\n\
+             [[sites]]\nname = \"{cyrillic}\"\nfeed_url = \"https://b.example/feed\"\n"
+        ));
+        let dir = test_dir("lookalikes");
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &sites).unwrap();
+        assert_ne!(caches.cache_path(&sites[0]), caches.cache_path(&sites[1]));
+        drop(caches);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,232 @@
+use super::{
+    Config, FeedEntryInfo,
+    errors::{ErrorCode, WithCode as _},
+};
+
+use anyhow::Result;
+
+/// A count of articles which fell below the minimum it was expected to have.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Shortfall {
+    /// The site whose `min_expected_entries` this is under, or none for `min_expected_articles`.
+    pub site: Option<Box<str>>,
+    /// How many articles there were.
+    pub count: usize,
+    /// How many there were expected to be at least.
+    pub minimum: usize,
+}
+impl std::fmt::Display for Shortfall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.site {
+            Some(site) => write!(
+                f,
+                "{site} has {} articles, fewer than its min_expected_entries of {}",
+                self.count, self.minimum
+            ),
+            None => write!(
+                f,
+                "There are {} articles, fewer than the min_expected_articles of {}",
+                self.count, self.minimum
+            ),
+        }
+    }
+}
+
+/// Check the articles which will be shown against `min_expected_articles` and each site's
+/// `min_expected_entries`, logging each count which falls short.
+///
+/// This counts the articles left after every filter, limit, and merge of duplicates, so a site's
+/// articles which are shown as another site's copy aren't counted for it. Sites which aren't
+/// displayed are never short.
+pub fn check(config: &Config, articles: &[FeedEntryInfo]) -> Vec<Shortfall> {
+    let mut counts = vec![0; config.sites.len()];
+    for article in articles {
+        if let Some(count) = counts.get_mut(article.site_index) {
+            *count += 1;
+        }
+    }
+    let mut shortfalls = Vec::new();
+    if let Some(minimum) = config.min_expected_articles
+        && articles.len() < minimum
+    {
+        shortfalls.push(Shortfall {
+            site: None,
+            count: articles.len(),
+            minimum,
+        });
+    }
+    for (site, &count) in config.sites.iter().zip(&counts) {
+        if let Some(minimum) = site.min_expected_entries
+            && site.display
+            && count < minimum
+        {
+            shortfalls.push(Shortfall {
+                site: Some(site.name.clone()),
+                count,
+                minimum,
+            });
+        }
+    }
+    for shortfall in &shortfalls {
+        log::error!("{shortfall}, so something may have broken");
+    }
+    shortfalls
+}
+
+/// Fail if there are any `shortfalls` and `refuse_sparse_output` is set, unless `force`, so the
+/// previous outputs are left in place.
+pub fn guard_outputs(config: &Config, shortfalls: &[Shortfall], force: bool) -> Result<()> {
+    if shortfalls.is_empty() || !config.refuse_sparse_output {
+        return Ok(());
+    }
+    if force {
+        log::warn!("Writing the outputs anyway, since `--force` was given");
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "Fewer articles than expected, so the outputs weren't written. Give `--force` to write \
+         them anyway."
+    ))
+    .code(ErrorCode::TooFewArticles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache, config_files, test_server, test_util::test_dir};
+
+    /// The config of the given settings, with sites `a` and `b`, then `hidden` which isn't
+    /// displayed, each expecting at least 2 entries.
+    fn config(settings: &str) -> Config {
+        let sites = ["a", "b", "hidden"]
+            .map(|site| {
+                format!(
+                    "[[sites]]\nname = \"{site}\"\nfeed_url = \"https://{site}.example/feed\"\n\
+                     min_expected_entries = 2\ndisplay = {}\n",
+                    site != "hidden"
+                )
+            })
+            .concat();
+        config_files::to_config(
+            toml::from_str(&format!("min_fetch_interval = 0\n{settings}\n{sites}")).unwrap(),
+            false,
+        )
+        .unwrap()
+    }
+
+    /// A feed from `site` with `count` articles.
+    fn feed(site: &str, count: usize) -> String {
+        let items = (0..count)
+            .map(|i| {
+                format!(
+                    "<item><title>{site} {i}</title><link>https://{site}.example/{i}</link>\
+                     <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item>"
+                )
+            })
+            .collect::<String>();
+        format!("<rss version=\"2.0\"><channel><title>{site}</title>{items}</channel></rss>")
+    }
+
+    /// `count` articles from the site at `site_index`.
+    fn articles(config: &Config, site_index: usize, count: usize) -> Vec<FeedEntryInfo> {
+        let site = &config.sites[site_index].name;
+        let feed = feed_rs::parser::parse(feed(site, count).as_bytes()).unwrap();
+        feed.entries
+            .iter()
+            .map(|entry| {
+                FeedEntryInfo::new(
+                    site_index,
+                    site,
+                    entry,
+                    &Default::default(),
+                    None,
+                    false,
+                    &config.limits,
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn shortfalls(config: &Config, articles: &[FeedEntryInfo]) -> Vec<String> {
+        check(config, articles)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn too_few_articles_in_all_are_found() {
+        let config = config("min_expected_articles = 5");
+        let enough_per_site = [articles(&config, 0, 2), articles(&config, 1, 2)].concat();
+        assert_eq!(
+            shortfalls(&config, &enough_per_site),
+            ["There are 4 articles, fewer than the min_expected_articles of 5"]
+        );
+        let plenty = [articles(&config, 0, 3), articles(&config, 1, 2)].concat();
+        assert!(shortfalls(&config, &plenty).is_empty());
+    }
+
+    #[test]
+    fn too_few_articles_from_a_site_are_found() {
+        let config = config("");
+        let articles = [articles(&config, 0, 1), articles(&config, 1, 2)].concat();
+        // The hidden site has none, but it's never short.
+        assert_eq!(
+            shortfalls(&config, &articles),
+            ["a has 1 articles, fewer than its min_expected_entries of 2"]
+        );
+        assert_eq!(
+            shortfalls(&config, &[]),
+            [
+                "a has 0 articles, fewer than its min_expected_entries of 2",
+                "b has 0 articles, fewer than its min_expected_entries of 2",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sparse_outputs_are_refused_unless_forced() {
+        let dir = test_dir("canary");
+        let addr = test_server::serve_http(|_| {
+            test_server::response("200 OK", "application/rss+xml", &feed("a", 1))
+        })
+        .await;
+        let toml = format!(
+            "min_fetch_interval = 0\nrefuse_sparse_output = true\n\
+             [[sites]]\nname = \"a\"\nfeed_url = \"http://{addr}/feed\"\n\
+             min_expected_entries = 2\n"
+        );
+        let config_path = dir.join("jarss.toml");
+        std::fs::write(&config_path, &toml).unwrap();
+        let config = config_files::to_config(toml::from_str(&toml).unwrap(), false).unwrap();
+        let cache_dir = dir.join("cache");
+        let caches =
+            cache::CacheManager::new(cache_dir.clone(), cache::CacheKey::Name, &config.sites)
+                .unwrap();
+        assert!(test_server::fetch_all(&config, &caches).await.is_empty());
+        drop(caches);
+
+        let out_html = dir.join("out.html");
+        std::fs::write(&out_html, "the last page").unwrap();
+        let render = async |force: &[&str]| {
+            let paths = [&config_path, &cache_dir, &out_html].map(|path| path.to_str().unwrap());
+            let args = ["jarss", "--config", paths[0], "--cache", paths[1], "render"]
+                .into_iter()
+                .chain(force.iter().copied())
+                .chain([paths[2]]);
+            crate::dispatch(
+                <crate::Args as clap::Parser>::parse_from(args)
+                    .try_into()
+                    .unwrap(),
+            )
+            .await
+        };
+        let e = render(&[]).await.unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::TooFewArticles);
+        assert_eq!(std::fs::read_to_string(&out_html).unwrap(), "the last page");
+        render(&["--force"]).await.unwrap();
+        assert_ne!(std::fs::read_to_string(&out_html).unwrap(), "the last page");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use super::{
+    USER_AGENT,
+    errors::{ErrorCode, WithCode as _},
+};
+
+use anyhow::{Context, Result};
+use std::{path::PathBuf, time::Duration};
+
+/// What to do about a site's certificate not being trusted, or there being no certificates to
+/// trust, for error messages to suggest.
+pub const TLS_ADVICE: &str = "Install the system's CA certificates (the `ca-certificates` \
+                              package on most distributions), point `SSL_CERT_FILE` at a bundle \
+                              of them, give a bundle as `http.extra_roots`, or set \
+                              `http.tls_roots = \"webpki\"` with a build of jarss with the \
+                              `webpki-roots` feature.";
+
+/// Which root certificates to trust sites' certificates from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsRoots {
+    /// Those installed on the system.
+    #[default]
+    Native,
+    /// Mozilla's, as bundled into jarss when it's built with the `webpki-roots` feature, for
+    /// systems without any installed.
+    Webpki,
+}
+
+/// Settings for the HTTP client feeds are fetched with, from the `[http]` table.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// How long to wait for each read from a server, in seconds, before giving up on the request.
+    pub timeout_per_call_secs: u64,
+    /// How long a whole request can take, including reading the body, in seconds.
+    pub timeout_global_secs: u64,
+    /// The proxy to send every request through, like `http://proxy.example:3128`.
+    pub proxy: Option<Box<str>>,
+    /// Text to add to the end of our user agent, like an email address for the hosts of feeds to
+    /// contact about abuse.
+    pub user_agent_suffix: Option<Box<str>>,
+    /// Which root certificates to trust.
+    pub tls_roots: TlsRoots,
+    /// A PEM file of more root certificates to trust, along with `tls_roots`, like that of a
+    /// proxy which inspects TLS.
+    pub extra_roots: Option<PathBuf>,
+    /// The certificates read from `extra_roots`, filled in once the config is loaded.
+    #[serde(skip)]
+    pub extra_root_certs: Vec<reqwest::Certificate>,
+}
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_per_call_secs: 20,
+            timeout_global_secs: 40,
+            proxy: None,
+            user_agent_suffix: None,
+            tls_roots: TlsRoots::Native,
+            extra_roots: None,
+            extra_root_certs: Vec::new(),
+        }
+    }
+}
+impl HttpConfig {
+    /// Check that the settings can be used, so mistakes are caught when the config is loaded
+    /// rather than on the first request.
+    pub fn check(&self) -> Result<()> {
+        if let Some(proxy) = &self.proxy {
+            reqwest::Proxy::all(proxy.as_ref())
+                .with_context(|| format!("Invalid `http.proxy` {proxy:?}"))?;
+        }
+        http::HeaderValue::from_str(&self.user_agent())
+            .context("Invalid `http.user_agent_suffix`, it can't be sent in a header")?;
+        if self.tls_roots == TlsRoots::Webpki && !cfg!(feature = "webpki-roots") {
+            anyhow::bail!(
+                "`http.tls_roots = \"webpki\"` needs jarss to be built with the `webpki-roots` \
+                 feature"
+            );
+        }
+        Ok(())
+    }
+
+    /// Read the certificates from `extra_roots`, if it's set.
+    pub fn read_extra_roots(&self) -> Result<Vec<reqwest::Certificate>> {
+        let Some(path) = &self.extra_roots else {
+            return Ok(Vec::new());
+        };
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read `http.extra_roots` {}", path.display()))?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem).with_context(|| {
+            format!(
+                "`http.extra_roots` {} isn't a PEM file of certificates",
+                path.display()
+            )
+        })?;
+        if certs.is_empty() {
+            anyhow::bail!(
+                "`http.extra_roots` {} doesn't have any certificates in it",
+                path.display()
+            );
+        }
+        Ok(certs)
+    }
+
+    /// Check that there are root certificates to trust, so that fetching sites over https
+    /// doesn't fail for every one of them in turn.
+    ///
+    /// The system's certificates can only be looked for where OpenSSL is used, and elsewhere
+    /// they're assumed to be there.
+    pub fn check_roots(&self) -> Result<()> {
+        if self.tls_roots != TlsRoots::Native || !self.extra_root_certs.is_empty() {
+            return Ok(());
+        }
+        #[cfg(not(any(windows, target_vendor = "apple")))]
+        {
+            let probe = openssl_probe::probe();
+            let has_dir = probe.cert_dir.is_some_and(|dir| {
+                std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some())
+            });
+            if probe.cert_file.is_none() && !has_dir {
+                return Err(anyhow::anyhow!(
+                    "There are no root certificates installed to trust sites' certificates \
+                     from, in any of the places OpenSSL looks for them, like /etc/ssl/certs, so \
+                     no site can be fetched over https. {TLS_ADVICE}"
+                ))
+                .code(ErrorCode::FetchTls);
+            }
+        }
+        Ok(())
+    }
+
+    /// The user agent to send, with the suffix if there is one.
+    pub fn user_agent(&self) -> String {
+        match &self.user_agent_suffix {
+            Some(suffix) => format!("{USER_AGENT} {suffix}"),
+            None => USER_AGENT.to_owned(),
+        }
+    }
+
+    /// Start building the client used to fetch feeds.
+    ///
+    /// Redirects aren't followed, so [`cache::query_site`](super::cache::query_site) can tell which
+    /// ones are permanent.
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder()
+            .user_agent(self.user_agent())
+            .redirect(reqwest::redirect::Policy::none())
+            .read_timeout(Duration::from_secs(self.timeout_per_call_secs))
+            .timeout(Duration::from_secs(self.timeout_global_secs));
+        #[cfg(feature = "webpki-roots")]
+        let builder = match self.tls_roots {
+            TlsRoots::Native => builder,
+            TlsRoots::Webpki => builder.use_rustls_tls(),
+        };
+        let builder = self.extra_root_certs.iter().fold(builder, |builder, cert| {
+            builder.add_root_certificate(cert.clone())
+        });
+        match &self.proxy {
+            Some(proxy) => builder.proxy(
+                reqwest::Proxy::all(proxy.as_ref()).expect("Checked when the config was loaded"),
+            ),
+            None => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn mistakes_are_caught_when_checked() {
+        HttpConfig::default().check().unwrap();
+        let proxy = HttpConfig {
+            proxy: Some("not a url".into()),
+            ..Default::default()
+        };
+        assert!(
+            proxy
+                .check()
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid `http.proxy`")
+        );
+        let suffix = HttpConfig {
+            user_agent_suffix: Some("abuse@example.com\nx-injected: 1".into()),
+            ..Default::default()
+        };
+        assert!(
+            suffix
+                .check()
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid `http.user_agent_suffix`")
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_user_agent_suffix() {
+        let server = test_server::serve_http(|head| {
+            let agent = head
+                .lines()
+                .find_map(|line| line.strip_prefix("user-agent: "))
+                .unwrap_or_default();
+            test_server::response("200 OK", "text/plain", agent)
+        })
+        .await;
+        let http = HttpConfig {
+            user_agent_suffix: Some("(abuse@example.com)".into()),
+            ..Default::default()
+        };
+        let agent = http
+            .client_builder()
+            .build()
+            .unwrap()
+            .get(format!("http://{server}/"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(agent, format!("{USER_AGENT} (abuse@example.com)"));
+    }
+
+    /// The code of fetching `url` with a client from `http`, as a site's fetch would be reported.
+    async fn fetch_code(http: &HttpConfig, url: &str) -> ErrorCode {
+        let res = http.client_builder().build().unwrap().get(url).send().await;
+        let e = res
+            .context("Error fetching feed Site from url")
+            .unwrap_err();
+        ErrorCode::of(&e)
+    }
+
+    #[tokio::test]
+    async fn untrusted_certificates_are_tls_errors() {
+        let tls = test_server::serve_untrusted_tls().await;
+        let url = format!("https://localhost:{}/feed", tls.port());
+        assert_eq!(
+            fetch_code(&HttpConfig::default(), &url).await,
+            ErrorCode::FetchTls
+        );
+    }
+
+    #[tokio::test]
+    async fn other_failures_arent_tls_errors() {
+        let http = HttpConfig {
+            timeout_global_secs: 1,
+            ..Default::default()
+        };
+        let closed = test_server::closed_port().await;
+        assert_eq!(
+            fetch_code(&http, &format!("https://localhost:{}/feed", closed.port())).await,
+            ErrorCode::FetchConnect
+        );
+        // A server which never gets as far as sending its certificate.
+        let silent = test_server::serve_nothing().await;
+        assert_eq!(
+            fetch_code(&http, &format!("https://localhost:{}/feed", silent.port())).await,
+            ErrorCode::FetchTimeout
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use super::{Config, auth, filter, junk, notify, post, provenance, safe_mode};
+
+use anyhow::{Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The config files given on the command line, which are merged into one config.
+///
+/// Each file's `include`s are merged in before the file itself, relative to its directory, and
+/// then each file given is merged over the ones before it:
+/// - Settings in later files override those in earlier ones.
+/// - Tables of settings, like `[groups]` or `[limits]`, are merged key by key in the same way.
+/// - `[[sites]]` are concatenated, except that a site with the same `feed_url` as an earlier one
+///   replaces it.
+pub struct ConfigFiles {
+    paths: Vec<PathBuf>,
+    /// Whether the settings of optional features are ignored, for `--safe`.
+    safe: bool,
+}
+impl ConfigFiles {
+    /// The given files, in the order to merge them. There must be at least one.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        assert!(!paths.is_empty(), "No config files given");
+        Self { paths, safe: false }
+    }
+
+    /// Load the config in safe mode if `safe`, ignoring the settings of optional features.
+    pub fn safe_mode(mut self, safe: bool) -> Self {
+        self.safe = safe;
+        self
+    }
+
+    /// The file which changes to the config are written to.
+    ///
+    /// This is the last one given, since that's where the most specific settings go.
+    pub fn editable(&self) -> &Path {
+        self.paths.last().expect("There's at least one config file")
+    }
+
+    /// The files, for messages.
+    pub fn describe(&self) -> String {
+        self.paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// When each of the files was last modified, to tell whether they've changed.
+    ///
+    /// Files which can't be read are `None`, and files they include aren't looked at.
+    pub fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths
+            .iter()
+            .map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Load the merged config.
+    ///
+    /// Disabled sites are left out.
+    pub fn load(&self) -> Result<Config> {
+        self.to_config(self.merged(None)?, false)
+    }
+
+    /// Load the merged config, keeping disabled sites.
+    pub fn load_including_disabled(&self) -> Result<Config> {
+        self.to_config(self.merged(None)?, true)
+    }
+
+    /// Load the merged config as if the [editable](Self::editable) file held `contents`, to check
+    /// that a change to it is valid.
+    pub fn load_with_edit(&self, contents: &str) -> Result<Config> {
+        self.to_config(self.merged(Some(contents))?, false)
+    }
+
+    /// Turn the merged settings into a config, in safe mode if it was asked for.
+    fn to_config(&self, mut merged: toml::Table, keep_disabled: bool) -> Result<Config> {
+        if self.safe {
+            safe_mode::strip(&mut merged);
+        }
+        let mut config = to_config(merged, keep_disabled)?;
+        config.safe_mode = self.safe;
+        Ok(config)
+    }
+
+    /// The settings from `contents`, as if it were the only config file, with its `include`s
+    /// relative to the current directory.
+    ///
+    /// This is for checking a config before it's written anywhere. `name` is what it's called in
+    /// messages.
+    pub fn merged_contents(contents: &str, name: &Path) -> Result<toml::Table> {
+        let mut merged = toml::Table::new();
+        merge_file(&mut merged, name, Some(contents), &mut Vec::new(), None)?;
+        Ok(merged)
+    }
+
+    /// The settings from every file, merged.
+    pub fn merged(&self, edited: Option<&str>) -> Result<toml::Table> {
+        self.merged_tracking(edited, None)
+    }
+
+    /// Load the merged config, keeping disabled sites, along with the settings it was loaded from
+    /// and where each of those was set, for `jarss config show`.
+    ///
+    /// In safe mode, the settings are without those it ignores, but their sources are kept.
+    pub fn load_with_sources(&self) -> Result<(Config, toml::Table, provenance::Sources)> {
+        let mut sources = provenance::Sources::new();
+        let mut merged = self.merged_tracking(None, Some(&mut sources))?;
+        if self.safe {
+            safe_mode::strip(&mut merged);
+        }
+        let mut config = to_config(merged.clone(), true)?;
+        config.safe_mode = self.safe;
+        Ok((config, merged, sources))
+    }
+
+    /// The settings from every file, merged, recording where each was set in `sources` if it's
+    /// given.
+    fn merged_tracking(
+        &self,
+        edited: Option<&str>,
+        mut sources: Option<&mut provenance::Sources>,
+    ) -> Result<toml::Table> {
+        let mut merged = toml::Table::new();
+        for path in &self.paths {
+            let contents = edited.filter(|_| path == self.editable());
+            merge_file(
+                &mut merged,
+                path,
+                contents,
+                &mut Vec::new(),
+                sources.as_deref_mut(),
+            )?;
+        }
+        Ok(merged)
+    }
+}
+
+/// Turn the merged settings into a config, checking the HTTP settings, and compiling each site's
+/// filters and checking its auth and request settings.
+///
+/// Disabled sites are left out unless `keep_disabled`.
+pub fn to_config(merged: toml::Table, keep_disabled: bool) -> Result<Config> {
+    let mut config = toml::Value::Table(merged)
+        .try_into::<Config>()
+        .context("Failed to parse config file")?;
+    config.http.check()?;
+    config.http.extra_root_certs = config.http.read_extra_roots()?;
+    notify::check(&mut config)?;
+    config.sites.retain(|site| keep_disabled || site.enabled);
+    for site in &mut config.sites {
+        site.filter = filter::EntryFilter::new(site)?;
+        site.junk_filter = junk::JunkFilter::new(&config.junk, site)?;
+        auth::check(site)?;
+        post::check(site)?;
+    }
+    Ok(config)
+}
+
+/// Merge the config file at `path`, and those it includes, into `merged`.
+///
+/// If `contents` is given, it's used instead of reading the file. `including` is the chain of
+/// files which included this one, to catch cycles. Where each setting was set is recorded in
+/// `sources`, if it's given.
+fn merge_file(
+    merged: &mut toml::Table,
+    path: &Path,
+    contents: Option<&str>,
+    including: &mut Vec<PathBuf>,
+    mut sources: Option<&mut provenance::Sources>,
+) -> Result<()> {
+    let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    if let Some(start) = including.iter().position(|other| *other == canonical) {
+        let cycle = including[start..]
+            .iter()
+            .chain([&canonical])
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        anyhow::bail!("Config files include each other: {cycle}");
+    }
+    let contents = match contents {
+        Some(contents) => contents.to_owned(),
+        None => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?,
+    };
+    let mut table = toml::de::from_str::<toml::Table>(&contents)
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+    let includes = match table.remove("include") {
+        Some(includes) => includes
+            .try_into::<Vec<PathBuf>>()
+            .with_context(|| format!("`include` in {} isn't a list of paths", path.display()))?,
+        None => Vec::new(),
+    };
+    including.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for include in includes {
+        merge_file(
+            merged,
+            &dir.join(include),
+            None,
+            including,
+            sources.as_deref_mut(),
+        )
+        .with_context(|| format!("Failed to include a file in {}", path.display()))?;
+    }
+    including.pop();
+    let mut file_sources =
+        sources.map(|sources| provenance::FileSources::new(path, &contents, sources));
+    merge_tables(merged, table, "", &mut file_sources);
+    Ok(())
+}
+
+/// Merge the settings in `overlay` over those in `base`, which is the table at `path` in the
+/// merged config, recording where they came from in `sources` if it's given.
+fn merge_tables(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    path: &str,
+    sources: &mut Option<provenance::FileSources>,
+) {
+    for (key, value) in overlay {
+        let key_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Array(sites)), toml::Value::Array(overlay))
+                if path.is_empty() && key == "sites" =>
+            {
+                merge_sites(sites, overlay, sources);
+            }
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay, &key_path, sources);
+            }
+            (_, value) => {
+                if let Some(sources) = sources {
+                    sources.record(&key_path, &key_path, &value);
+                }
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Add the sites in `overlay` to `sites`, replacing any with the same feed URL, and recording
+/// where they came from in `sources` if it's given.
+fn merge_sites(
+    sites: &mut Vec<toml::Value>,
+    overlay: Vec<toml::Value>,
+    sources: &mut Option<provenance::FileSources>,
+) {
+    let feed_url = |site: &toml::Value| {
+        let url = site.get("feed_url")?.as_str()?;
+        Some(super::urls::normalize(url, false))
+    };
+    for (i, site) in overlay.into_iter().enumerate() {
+        let url = feed_url(&site);
+        let index = sites
+            .iter()
+            .position(|other| url.is_some() && feed_url(other) == url);
+        if let Some(sources) = sources {
+            let merged = format!("sites[{}]", index.unwrap_or(sites.len()));
+            sources.record(&format!("sites[{i}]"), &merged, &site);
+        }
+        match index {
+            Some(index) => sites[index] = site,
+            None => sites.push(site),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// Write each of the given files into `dir`.
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (name, contents) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    /// The name and URL of each site.
+    fn sites(config: &Config) -> Vec<(&str, &str)> {
+        config
+            .sites
+            .iter()
+            .map(|site| (&*site.name, &*site.feed_url))
+            .collect()
+    }
+
+    #[test]
+    fn later_settings_take_precedence() {
+        let dir = test_dir("config-precedence");
+        write_files(
+            &dir,
+            &[
+                (
+                    "shared/common.toml",
+                    "min_fetch_interval = 60\nmax_entries_per_site = 5\nmax_link_resolutions = 30\n\
+                     [limits]\nmax_title_bytes = 100\nmax_summary_bytes = 1000\n\
+                     [[sites]]\nname = \"Common\"\nfeed_url = \"https://example.com/common.xml\"\n\
+                     [[sites]]\nname = \"Replaced\"\nfeed_url = \"https://example.com/replaced.xml\"\n",
+                ),
+                (
+                    "base.toml",
+                    "include = [\"shared/common.toml\"]\nmax_entries_per_site = 10\n\
+                     [limits]\nmax_title_bytes = 200\n\
+                     [[sites]]\nname = \"Base\"\nfeed_url = \"https://example.com/base.xml\"\n",
+                ),
+                (
+                    "local.toml",
+                    "max_link_resolutions = 7\n[limits]\nmax_summary_bytes = 2000\n\
+                     [[sites]]\nname = \"Replacement\"\n\
+                     feed_url = \"https://EXAMPLE.com/replaced.xml\"\n",
+                ),
+            ],
+        );
+        let config_files = ConfigFiles::new(vec![dir.join("base.toml"), dir.join("local.toml")]);
+        let config = config_files.load().unwrap();
+        // Files override what they include, and later files override earlier ones.
+        assert_eq!(config.min_fetch_interval, 60);
+        assert_eq!(config.max_entries_per_site, Some(10));
+        assert_eq!(config.max_link_resolutions, 7);
+        // Tables are merged key by key.
+        assert_eq!(config.limits.max_title_bytes, 200);
+        assert_eq!(config.limits.max_summary_bytes, 2000);
+        // Sites are concatenated, with those for the same feed replaced in place.
+        assert_eq!(
+            sites(&config),
+            [
+                ("Common", "https://example.com/common.xml"),
+                ("Replacement", "https://EXAMPLE.com/replaced.xml"),
+                ("Base", "https://example.com/base.xml"),
+            ]
+        );
+        assert_eq!(config_files.editable(), dir.join("local.toml"));
+
+        // Edits are checked as if they were made to the last file.
+        let edited = config_files
+            .load_with_edit("max_entries_per_site = 3\n")
+            .unwrap();
+        assert_eq!(edited.max_entries_per_site, Some(3));
+        assert_eq!(edited.max_link_resolutions, 30);
+        assert_eq!(sites(&edited).len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_includes_are_errors() {
+        let dir = test_dir("config-missing-include");
+        write_files(
+            &dir,
+            &[(
+                "config.toml",
+                "include = [\"missing.toml\"]\nmin_fetch_interval = 0\nsites = []\n",
+            )],
+        );
+        let e = ConfigFiles::new(vec![dir.join("config.toml")])
+            .load()
+            .unwrap_err();
+        let chain = e.chain().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            chain[..2],
+            [
+                format!(
+                    "Failed to include a file in {}",
+                    dir.join("config.toml").display()
+                ),
+                format!(
+                    "Failed to read config file {}",
+                    dir.join("missing.toml").display()
+                ),
+            ]
+        );
+
+        let e = ConfigFiles::new(vec![dir.join("config.toml")])
+            .load_with_edit("include = \"missing.toml\"\n")
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "`include` in {} isn't a list of paths",
+                dir.join("config.toml").display()
+            )
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cyclic_includes_are_errors() {
+        let dir = test_dir("config-cyclic-include");
+        write_files(
+            &dir,
+            &[
+                ("a.toml", "include = [\"sub/b.toml\"]\n"),
+                ("sub/b.toml", "include = [\"../a.toml\"]\n"),
+                ("self.toml", "include = [\"self.toml\"]\n"),
+            ],
+        );
+        // The cycle is reported from where it was first entered.
+        let a = dir.join("a.toml").canonicalize().unwrap();
+        let b = dir.join("sub/b.toml").canonicalize().unwrap();
+        for (path, cycle) in [
+            (
+                dir.join("a.toml"),
+                format!("{} -> {} -> {}", a.display(), b.display(), a.display()),
+            ),
+            (
+                dir.join("sub/b.toml"),
+                format!("{} -> {} -> {}", b.display(), a.display(), b.display()),
+            ),
+            (dir.join("self.toml"), {
+                let path = dir.join("self.toml").canonicalize().unwrap();
+                format!("{} -> {}", path.display(), path.display())
+            }),
+        ] {
+            let e = ConfigFiles::new(vec![path.clone()]).load().unwrap_err();
+            assert_eq!(
+                e.root_cause().to_string(),
+                format!("Config files include each other: {cycle}"),
+                "{}",
+                path.display()
+            );
+        }
+
+        // Including the same file twice, without a cycle, is fine.
+        write_files(
+            &dir,
+            &[
+                ("common.toml", "min_fetch_interval = 0\nsites = []\n"),
+                (
+                    "twice.toml",
+                    "include = [\"common.toml\", \"./common.toml\"]\n",
+                ),
+            ],
+        );
+        ConfigFiles::new(vec![dir.join("twice.toml")])
+            .load()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
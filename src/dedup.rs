@@ -0,0 +1,635 @@
+use super::{Config, FeedEntryInfo, trace::ArticleTrace, urls};
+
+use std::collections::{HashMap, HashSet};
+
+/// What to do with articles that appear on more than one site.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    /// Keep only the canonical copy and discard the rest.
+    Drop,
+    /// Keep the canonical copy, and record the others in its `also_on` list.
+    #[default]
+    Merge,
+}
+
+/// Another site which carried the same article.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct AlsoOn {
+    /// The name of the other site.
+    pub site: Box<str>,
+    /// The link to the article on the other site.
+    pub link: Box<str>,
+    /// The position of the other site in [`Config::sites`].
+    #[serde(skip)]
+    pub site_index: usize,
+}
+
+/// Collapse articles which share a link down to one copy each.
+///
+/// Links are compared [normalized](urls::normalize), so different spellings of one link match.
+///
+/// With [`Config::fuzzy_dedup`], articles from different sites with the same
+/// [normalized](normalize_title) title, published within [`Config::fuzzy_dedup_window`] of the
+/// canonical copy, are collapsed too.
+///
+/// Each canonical copy's [`mentions`](FeedEntryInfo::mentions) counts the sites which carried
+/// it, whichever mode is used and whether or not they're displayed.
+///
+/// The canonical copy is the one published earliest, with ties broken by the order in which the
+/// sites appear in the config, and then by the order of the site's feed. The articles are returned
+/// in that order too, oldest first, and are [sorted](sort_articles) for showing afterwards.
+///
+/// Articles from sites which aren't displayed take part in deduplication like any other, and are
+/// [removed](hide_undisplayed) afterwards.
+pub fn dedup_articles(
+    config: &Config,
+    mut articles: Vec<FeedEntryInfo>,
+    trace: &ArticleTrace,
+) -> Vec<FeedEntryInfo> {
+    articles.sort_by_key(|article| (article.published, article.site_index, article.feed_position));
+
+    let window =
+        chrono::Duration::from_std(config.fuzzy_dedup_window).unwrap_or(chrono::Duration::MAX);
+    let mut canonical_by_link = HashMap::<String, usize>::new();
+    let mut canonical_by_title = HashMap::<String, Vec<usize>>::new();
+    let mut deduped = Vec::<FeedEntryInfo>::with_capacity(articles.len());
+    // The sites which carried each canonical copy, by its index, apart from its own.
+    let mut other_sources = HashSet::<(usize, usize)>::new();
+    for article in articles {
+        let link = urls::normalize(&article.link, config.unify_trailing_slashes);
+        let title = config
+            .fuzzy_dedup
+            .then(|| normalize_title(&article.title))
+            .filter(|title| !title.is_empty());
+        let idx = match canonical_by_link.get(&link) {
+            Some(&idx) => {
+                log::debug!(
+                    "Article {} from {} duplicates one from {}",
+                    article.link,
+                    article.site,
+                    deduped[idx].site,
+                );
+                idx
+            }
+            None => {
+                // The articles are in order of publication, so the latest copy with a matching
+                // title is the one most likely to be in the window.
+                let fuzzy_match = title.as_ref().and_then(|title| {
+                    canonical_by_title
+                        .get(title)?
+                        .iter()
+                        .rev()
+                        .copied()
+                        .find(|&idx| {
+                            let canonical = &deduped[idx];
+                            canonical.site_index != article.site_index
+                                && article.published - canonical.published <= window
+                        })
+                });
+                let Some(idx) = fuzzy_match else {
+                    canonical_by_link.insert(link, deduped.len());
+                    if let Some(title) = title {
+                        canonical_by_title
+                            .entry(title)
+                            .or_default()
+                            .push(deduped.len());
+                    }
+                    deduped.push(article);
+                    continue;
+                };
+                log::info!(
+                    "Article {} from {} has the same title as {} from {}, treating them as the \
+                     same article",
+                    article.link,
+                    article.site,
+                    deduped[idx].link,
+                    deduped[idx].site,
+                );
+                canonical_by_link.insert(link, idx);
+                idx
+            }
+        };
+        let canonical = &mut deduped[idx];
+        if canonical.site_index != article.site_index
+            && other_sources.insert((idx, article.site_index))
+        {
+            canonical.mentions += 1;
+        }
+        if trace.matches(&article) {
+            let fate = match config.dedup_mode {
+                DedupMode::Drop => "excluded as a duplicate of",
+                DedupMode::Merge => "merged into",
+            };
+            trace.event(format_args!("{fate} the copy from {}", canonical.site));
+        }
+        if config.dedup_mode == DedupMode::Merge
+            && canonical.site_index != article.site_index
+            && !canonical
+                .also_on
+                .iter()
+                .any(|other| other.site_index == article.site_index)
+        {
+            canonical.also_on.push(AlsoOn {
+                site: article.site,
+                link: article.link,
+                site_index: article.site_index,
+            });
+        }
+    }
+    deduped
+}
+
+/// Remove the articles of sites which aren't displayed from the [deduplicated](dedup_articles)
+/// `articles`, and those sites from the others' `also_on`.
+///
+/// If a removed article was the canonical copy, then in merge mode the first displayed site which
+/// also carried it takes its place, while in drop mode the article isn't shown at all.
+pub fn hide_undisplayed(
+    config: &Config,
+    articles: Vec<FeedEntryInfo>,
+    trace: &ArticleTrace,
+) -> Vec<FeedEntryInfo> {
+    let displayed =
+        |site_index: usize| config.sites.get(site_index).is_none_or(|site| site.display);
+    articles
+        .into_iter()
+        .filter_map(|mut article| {
+            article.also_on.retain(|other| displayed(other.site_index));
+            if displayed(article.site_index) {
+                return Some(article);
+            }
+            if article.also_on.is_empty() {
+                if trace.matches(&article) {
+                    trace.event(format_args!(
+                        "excluded since {} isn't displayed",
+                        article.site
+                    ));
+                }
+                return None;
+            }
+            let promoted = article.also_on.remove(0);
+            if trace.matches(&article) {
+                trace.event(format_args!(
+                    "shown as from {} since {} isn't displayed",
+                    promoted.site, article.site
+                ));
+            }
+            article.site = promoted.site;
+            article.link = promoted.link;
+            article.original_link = None;
+            article.site_index = promoted.site_index;
+            Some(article)
+        })
+        .collect()
+}
+
+/// Sort the articles newest first.
+///
+/// With [`Config::boost_multi_source`], articles carried by more sites come first among those
+/// published on the same day, in UTC, so they're moved up without leaving their day. When every
+/// article has one source, this is the same order as without it.
+///
+/// Articles published at the same time are sorted by the order in which their sites appear in the
+/// config, and then by the order of their site's feed, so the order is the same every run.
+pub fn sort_articles(config: &Config, articles: &mut [FeedEntryInfo]) {
+    let tie_break = |article: &FeedEntryInfo| (article.site_index, article.feed_position);
+    if config.boost_multi_source {
+        articles.sort_by_key(|article| {
+            (
+                std::cmp::Reverse((article.publish_date, article.mentions, article.published)),
+                tie_break(article),
+            )
+        });
+    } else {
+        articles.sort_by_key(|article| (std::cmp::Reverse(article.published), tie_break(article)));
+    }
+}
+
+/// A title with case, punctuation, and whitespace removed, to compare for
+/// [`Config::fuzzy_dedup`].
+fn normalize_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache, collect_articles, config_files, export, test_util};
+
+    /// A config of the sites named `names`, deduplicating with `mode`.
+    fn config(mode: DedupMode, names: &[&str]) -> Config {
+        let sites = names
+            .iter()
+            .map(|name| {
+                format!("[[sites]]\nname = {name:?}\nfeed_url = \"https://{name}.example/feed\"\n")
+            })
+            .collect::<String>();
+        Config {
+            dedup_mode: mode,
+            ..toml::from_str(&format!("min_fetch_interval = 0\n{sites}")).unwrap()
+        }
+    }
+
+    /// The shared article, as carried by the site at `site_index` whose feed is titled `site`,
+    /// published at `date`.
+    fn shared(site_index: usize, site: &str, date: &str) -> FeedEntryInfo {
+        let published = chrono::DateTime::parse_from_rfc2822(date).unwrap().to_utc();
+        FeedEntryInfo {
+            site: site.into(),
+            published,
+            publish_date: published.date_naive(),
+            id: "shared".into(),
+            site_link: None,
+            mentions: 1,
+            title: "Shared".into(),
+            link: "https://example.com/shared".into(),
+            link_display: "https://example.com/shared".into(),
+            summary: None,
+            content: None,
+            summary_text: None,
+            suspect: false,
+            original_link: None,
+            also_on: Vec::new(),
+            is_new: false,
+            rank: 0,
+            raw: None,
+            site_index,
+            feed_position: 0,
+            last_seen_in_feed: None,
+        }
+    }
+
+    /// The sites of `articles`, with the sites in each one's `also_on`.
+    fn sources(articles: &[FeedEntryInfo]) -> Vec<(&str, Vec<&str>)> {
+        articles
+            .iter()
+            .map(|article| {
+                let also_on = article.also_on.iter().map(|other| &*other.site).collect();
+                (&*article.site, also_on)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_article_on_three_sites_is_shown_once() {
+        // b's copy is the earliest, so it's the canonical one.
+        let articles = || {
+            vec![
+                shared(0, "a", "Mon, 01 Jan 2024 12:00:00 GMT"),
+                shared(1, "b", "Mon, 01 Jan 2024 00:00:00 GMT"),
+                shared(2, "c", "Mon, 01 Jan 2024 12:00:00 GMT"),
+            ]
+        };
+        for (mode, also_on) in [
+            (DedupMode::Merge, vec!["a", "c"]),
+            (DedupMode::Drop, vec![]),
+        ] {
+            let config = config(mode, &["a", "b", "c"]);
+            let articles = dedup_articles(&config, articles(), &ArticleTrace::new(None));
+            assert_eq!(sources(&articles), [("b", also_on)], "{mode:?}");
+            // Whether or not the other copies are listed, they're counted.
+            assert_eq!(articles[0].mentions, 3, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn hidden_canonical_copies_give_way_to_displayed_ones() {
+        // The hidden site's copy is the earliest, so it's the canonical one.
+        for (mode, shown) in [
+            (DedupMode::Merge, vec![("shown", vec![])]),
+            (DedupMode::Drop, vec![]),
+        ] {
+            let mut config = config(mode, &["hidden", "shown"]);
+            config.sites[0].display = false;
+            let trace = ArticleTrace::new(None);
+            let articles = dedup_articles(
+                &config,
+                vec![
+                    shared(0, "hidden", "Mon, 01 Jan 2024 00:00:00 GMT"),
+                    shared(1, "shown", "Mon, 01 Jan 2024 12:00:00 GMT"),
+                ],
+                &trace,
+            );
+            let articles = hide_undisplayed(&config, articles, &trace);
+            assert_eq!(sources(&articles), shown, "{mode:?}");
+            // Sites which aren't displayed still count.
+            assert!(articles.iter().all(|article| article.mentions == 2));
+        }
+    }
+
+    #[tokio::test]
+    async fn hidden_canonical_copies_still_notify() {
+        let feeds = |site: &str| {
+            let date = match site {
+                "hidden" => "Mon, 01 Jan 2024 00:00:00 GMT",
+                _ => "Mon, 01 Jan 2024 12:00:00 GMT",
+            };
+            format!(
+                "<rss version=\"2.0\"><channel><title>{site}</title><item><title>Shared</title>\
+                 <link>https://example.com/shared</link><guid>{site}-1</guid>\
+                 <pubDate>{date}</pubDate></item></channel></rss>"
+            )
+        };
+        for (mode, shown) in [("merge", vec![("shown", vec![])]), ("drop", vec![])] {
+            let settings = format!(
+                r#"
+                min_fetch_interval = 0
+                dedup_mode = "{mode}"
+                [[sites]]
+                name = "hidden"
+                feed_url = "https://hidden.example/feed"
+                display = false
+                [[sites]]
+                name = "shown"
+                feed_url = "https://shown.example/feed"
+                "#
+            );
+            let collected =
+                test_util::collect_cached(&format!("hidden-{mode}"), &settings, feeds).await;
+            assert_eq!(sources(&collected.articles), shown, "{mode}");
+            // Either way, the hidden site's copy is the one notified about.
+            let also_on = if mode == "merge" {
+                vec!["shown"]
+            } else {
+                vec![]
+            };
+            assert_eq!(
+                sources(&collected.new_articles),
+                [("hidden", also_on)],
+                "{mode}"
+            );
+        }
+    }
+
+    #[test]
+    fn sites_whose_feeds_share_a_title_are_each_listed() {
+        // a and c are different sites, whose feeds both call themselves "Blog".
+        let config = config(DedupMode::Merge, &["a", "b", "c"]);
+        let articles = dedup_articles(
+            &config,
+            vec![
+                shared(0, "Blog", "Mon, 01 Jan 2024 12:00:00 GMT"),
+                shared(1, "b", "Mon, 01 Jan 2024 00:00:00 GMT"),
+                shared(2, "Blog", "Mon, 01 Jan 2024 12:00:00 GMT"),
+            ],
+            &ArticleTrace::new(None),
+        );
+        assert_eq!(sources(&articles), [("b", vec!["Blog", "Blog"])]);
+        let also_on = articles[0]
+            .also_on
+            .iter()
+            .map(|other| other.site_index)
+            .collect::<Vec<_>>();
+        assert_eq!(also_on, [0, 2]);
+    }
+
+    /// An article titled `title` at its own link on the site at `site_index` named `site`,
+    /// published at `date`.
+    fn titled(site_index: usize, site: &str, title: &str, date: &str) -> FeedEntryInfo {
+        let link: Box<str> = format!("https://{site}.example/{}", normalize_title(title)).into();
+        FeedEntryInfo {
+            title: title.into(),
+            link: link.clone(),
+            link_display: link,
+            ..shared(site_index, site, date)
+        }
+    }
+
+    /// A config of the sites `a` and `b`, with `fuzzy_dedup` as given and a one-day window.
+    fn fuzzy_config(fuzzy: bool) -> Config {
+        Config {
+            fuzzy_dedup: fuzzy,
+            fuzzy_dedup_window: std::time::Duration::from_secs(24 * 60 * 60),
+            ..config(DedupMode::Merge, &["a", "b"])
+        }
+    }
+
+    #[test]
+    fn near_duplicate_titles_are_merged() {
+        let articles = || {
+            vec![
+                titled(
+                    0,
+                    "a",
+                    "Big News: Rust 2.0!",
+                    "Mon, 01 Jan 2024 00:00:00 GMT",
+                ),
+                titled(
+                    1,
+                    "b",
+                    "big news \u{2014} rust 2.0",
+                    "Mon, 01 Jan 2024 20:00:00 GMT",
+                ),
+            ]
+        };
+        let merged = dedup_articles(&fuzzy_config(true), articles(), &ArticleTrace::new(None));
+        assert_eq!(sources(&merged), [("a", vec!["b"])]);
+        // Without fuzzy_dedup, only matching links are merged.
+        let mut kept = dedup_articles(&fuzzy_config(false), articles(), &ArticleTrace::new(None));
+        kept.sort_by_key(|article| article.site_index);
+        assert_eq!(sources(&kept), [("a", vec![]), ("b", vec![])]);
+    }
+
+    #[test]
+    fn distinct_titles_stay_separate() {
+        let articles = vec![
+            titled(0, "a", "Rust 2.0 released", "Mon, 01 Jan 2024 00:00:00 GMT"),
+            titled(0, "a", "Weekly links", "Mon, 01 Jan 2024 01:00:00 GMT"),
+            titled(0, "a", "???", "Mon, 01 Jan 2024 02:00:00 GMT"),
+            titled(1, "b", "Rust 2.0 delayed", "Mon, 01 Jan 2024 02:00:00 GMT"),
+            // The same title, but too long after to be the same article.
+            titled(1, "b", "Weekly links", "Tue, 09 Jan 2024 01:00:00 GMT"),
+            // Titles with nothing left once normalized never match.
+            titled(1, "b", "!!!", "Mon, 01 Jan 2024 03:00:00 GMT"),
+        ];
+        let articles = dedup_articles(&fuzzy_config(true), articles, &ArticleTrace::new(None));
+        assert_eq!(articles.len(), 6, "{:?}", sources(&articles));
+        assert!(articles.iter().all(|article| article.also_on.is_empty()));
+    }
+
+    /// An article titled `title` from the site at `site_index`, published at `date`, which
+    /// `mentions` sites carried.
+    fn mentioned(
+        config: &crate::Config,
+        site_index: usize,
+        title: &str,
+        date: &str,
+        mentions: usize,
+    ) -> crate::FeedEntryInfo {
+        let feed = format!(
+            "<rss version=\"2.0\"><channel><title>t</title><item><title>{title}</title>\
+             <link>https://example.com/{title}</link><pubDate>{date}</pubDate></item>\
+             </channel></rss>"
+        );
+        let feed = feed_rs::parser::parse(feed.as_bytes()).unwrap();
+        let mut article = crate::FeedEntryInfo::new(
+            site_index,
+            "t",
+            &feed.entries[0],
+            &Default::default(),
+            None,
+            false,
+            &config.limits,
+        )
+        .unwrap();
+        article.mentions = mentions;
+        article
+    }
+
+    /// The titles of the articles once sorted, with `boost_multi_source` as given.
+    fn sorted(boost: bool, articles: &[(usize, &str, &str, usize)]) -> Vec<String> {
+        let config = config_files::to_config(
+            toml::from_str(&format!(
+                "min_fetch_interval = 0\nboost_multi_source = {boost}\n\
+                 [[sites]]\nname = \"a\"\nfeed_url = \"https://a.example/feed\"\n\
+                 [[sites]]\nname = \"b\"\nfeed_url = \"https://b.example/feed\"\n"
+            ))
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let mut articles = articles
+            .iter()
+            .map(|&(site_index, title, date, mentions)| {
+                mentioned(&config, site_index, title, date, mentions)
+            })
+            .collect::<Vec<_>>();
+        super::sort_articles(&config, &mut articles);
+        articles
+            .into_iter()
+            .map(|article| article.title.into())
+            .collect()
+    }
+
+    #[test]
+    fn multi_source_articles_are_boosted_within_their_day() {
+        let articles = [
+            (0, "tuesday-evening", "Tue, 02 Jan 2024 20:00:00 GMT", 1),
+            (
+                0,
+                "tuesday-noon-popular",
+                "Tue, 02 Jan 2024 12:00:00 GMT",
+                2,
+            ),
+            (
+                0,
+                "tuesday-morning-most-popular",
+                "Tue, 02 Jan 2024 06:00:00 GMT",
+                3,
+            ),
+            (0, "monday-late", "Mon, 01 Jan 2024 23:59:59 GMT", 1),
+            // However popular, it doesn't leave its day.
+            (0, "monday-popular", "Mon, 01 Jan 2024 01:00:00 GMT", 5),
+            // Ties go by the order of the sites in the config.
+            (1, "wednesday-b", "Wed, 03 Jan 2024 00:00:00 GMT", 1),
+            (0, "wednesday-a", "Wed, 03 Jan 2024 00:00:00 GMT", 1),
+        ];
+        assert_eq!(
+            sorted(true, &articles),
+            [
+                "wednesday-a",
+                "wednesday-b",
+                "tuesday-morning-most-popular",
+                "tuesday-noon-popular",
+                "tuesday-evening",
+                "monday-popular",
+                "monday-late",
+            ]
+        );
+        assert_eq!(
+            sorted(false, &articles),
+            [
+                "wednesday-a",
+                "wednesday-b",
+                "tuesday-evening",
+                "tuesday-noon-popular",
+                "tuesday-morning-most-popular",
+                "monday-late",
+                "monday-popular",
+            ]
+        );
+        // The same whichever order they were collected in.
+        let mut reversed = articles;
+        reversed.reverse();
+        assert_eq!(sorted(true, &reversed), sorted(true, &articles));
+    }
+
+    #[test]
+    fn boosting_single_source_articles_changes_nothing() {
+        let articles = [
+            (1, "b-new", "Tue, 02 Jan 2024 20:00:00 GMT", 1),
+            (0, "a-old", "Tue, 02 Jan 2024 06:00:00 GMT", 1),
+            (0, "a-new", "Tue, 02 Jan 2024 20:00:00 GMT", 1),
+            (1, "b-older", "Mon, 01 Jan 2024 06:00:00 GMT", 1),
+        ];
+        assert_eq!(
+            sorted(true, &articles),
+            ["a-new", "b-new", "a-old", "b-older"]
+        );
+        assert_eq!(sorted(true, &articles), sorted(false, &articles));
+    }
+
+    /// A feed whose entries were all published at the same time.
+    fn tied_feed(site: &str) -> String {
+        let items = (1..=3)
+            .map(|i| {
+                format!(
+                    "<item><title>{site} {i}</title><link>https://{site}.example/{i}</link>\
+                     <guid>{site}-{i}</guid><pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item>"
+                )
+            })
+            .collect::<String>();
+        format!("<rss version=\"2.0\"><channel><title>{site}</title>{items}</channel></rss>")
+    }
+
+    #[tokio::test]
+    async fn articles_published_at_the_same_time_keep_their_order() {
+        let config = config_files::to_config(
+            toml::from_str(
+                r#"
+                min_fetch_interval = 0
+                [[sites]]
+                name = "a"
+                feed_url = "https://a.example/feed"
+                [[sites]]
+                name = "b"
+                feed_url = "https://b.example/feed"
+                "#,
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let dir = test_util::test_dir("dedup");
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            let caches =
+                cache::CacheManager::new(dir.clone(), cache::CacheKey::Name, &config.sites)
+                    .unwrap();
+            let guard = caches.cache_guard();
+            for site in &config.sites {
+                caches.get_mut(site, &guard).await.unwrap().last_body =
+                    Some(tied_feed(&site.name).into());
+            }
+            drop(guard);
+            let articles = collect_articles(&config, &caches, &ArticleTrace::new(None))
+                .await
+                .articles;
+            outputs.push(export::render_json(&config, &articles, &[]).unwrap());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(outputs[0], outputs[1]);
+        let titles = ["a 1", "a 2", "a 3", "b 1", "b 2", "b 3"];
+        let positions = titles.map(|title| {
+            outputs[0]
+                .find(&format!("\"{title}\""))
+                .unwrap_or_else(|| panic!("{title} is missing"))
+        });
+        assert!(positions.is_sorted(), "{}", outputs[0]);
+    }
+}
@@ -0,0 +1,214 @@
+use super::notify::NotifiedArticle;
+
+/// The most articles listed in a desktop notification of several.
+const MAX_LISTED: usize = 5;
+
+/// The most notifications to wait on clicks of at once.
+///
+/// Each is waited on by a thread until the notification is clicked or closed, which may be never,
+/// so under `jarss watch` they'd pile up without a limit. Notifications past it can't be clicked.
+#[cfg(all(unix, not(target_vendor = "apple")))]
+const MAX_WAITING: usize = 16;
+
+/// How many notifications are being waited on for clicks.
+#[cfg(all(unix, not(target_vendor = "apple")))]
+static WAITING: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// What a desktop notification shows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Payload<'a> {
+    /// The heading of the notification.
+    pub summary: String,
+    /// The text under the heading.
+    pub body: String,
+    /// The link to open when the notification is clicked, if it's of one article.
+    pub link: Option<&'a str>,
+}
+
+/// What to show to notify of `articles`, of which there must be at least one.
+///
+/// One article is shown with its site as the heading and its title below, and opens its link
+/// when clicked. More are counted in the heading and listed below, up to [`MAX_LISTED`] of them.
+pub fn payload(articles: &[NotifiedArticle]) -> Payload<'_> {
+    if let [article] = articles {
+        return Payload {
+            summary: article.site.to_string(),
+            body: article.title.to_string(),
+            link: Some(&article.link),
+        };
+    }
+    let mut lines = articles
+        .iter()
+        .take(MAX_LISTED)
+        .map(|article| format!("{}: {}", article.site, article.title))
+        .collect::<Vec<_>>();
+    if articles.len() > MAX_LISTED {
+        lines.push(format!("and {} more", articles.len() - MAX_LISTED));
+    }
+    Payload {
+        summary: format!("{} new articles", articles.len()),
+        body: lines.join("\n"),
+        link: None,
+    }
+}
+
+/// Show a desktop notification of `articles`.
+///
+/// There being no notification service, as on a desktop-less server, is only warned about, since
+/// a later run may well be somewhere there is one.
+pub async fn show(articles: &[NotifiedArticle]) {
+    let Payload {
+        summary,
+        body,
+        link,
+    } = payload(articles);
+    let link = link
+        .filter(|link| link.starts_with("https://") || link.starts_with("http://"))
+        .map(str::to_owned);
+    let res = tokio::task::spawn_blocking(move || show_blocking(&summary, &body, link)).await;
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::warn!("Couldn't show a desktop notification: {e}"),
+        Err(e) => log::warn!("Couldn't show a desktop notification: {e}"),
+    }
+}
+
+/// Show a desktop notification, waiting for it to be sent.
+///
+/// Only the notification servers of Linux and the BSDs tell us when notifications are clicked, so
+/// elsewhere `link` isn't used.
+fn show_blocking(
+    summary: &str,
+    body: &str,
+    link: Option<String>,
+) -> notify_rust::error::Result<()> {
+    let mut notification = notify_rust::Notification::new();
+    notification.appname("jarss").summary(summary);
+    #[cfg(all(unix, not(target_vendor = "apple")))]
+    {
+        use std::sync::atomic::Ordering;
+
+        // These take markup in the body.
+        notification.body(&escape_markup(body));
+        let link = link.filter(|_| {
+            let counted = WAITING
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |waiting| {
+                    (waiting < MAX_WAITING).then_some(waiting + 1)
+                })
+                .is_ok();
+            if !counted {
+                log::debug!(
+                    "Already waiting on {MAX_WAITING} notifications, so this can't be clicked"
+                );
+            }
+            counted
+        });
+        if link.is_some() {
+            notification.action("default", "Open");
+        }
+        let handle = match notification.show() {
+            Ok(handle) => handle,
+            Err(e) => {
+                if link.is_some() {
+                    WAITING.fetch_sub(1, Ordering::Relaxed);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(link) = link {
+            // Waited for in its own thread, which goes away with jarss if it exits first.
+            std::thread::spawn(move || {
+                handle.wait_for_action(|action| {
+                    if action == "default"
+                        && let Err(e) = std::process::Command::new("xdg-open").arg(&link).spawn()
+                    {
+                        log::warn!("Couldn't open {link}: {e}");
+                    }
+                });
+                WAITING.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    }
+    #[cfg(not(all(unix, not(target_vendor = "apple"))))]
+    {
+        let _ = link;
+        notification.body(body).show()?;
+    }
+    Ok(())
+}
+
+/// Escape the characters which notification servers would take as markup.
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Articles numbered from 1 to `count`, from alternating sites.
+    fn articles(count: usize) -> Vec<NotifiedArticle> {
+        (1..=count)
+            .map(|i| NotifiedArticle {
+                site: if i % 2 == 0 { "Even" } else { "Odd" }.into(),
+                title: format!("Article {i}").into(),
+                link: format!("https://example.com/{i}").into(),
+                id: i.to_string().into(),
+                published: chrono::DateTime::UNIX_EPOCH,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_article_is_shown_with_its_link() {
+        let articles = articles(1);
+        assert_eq!(
+            payload(&articles),
+            Payload {
+                summary: "Odd".to_owned(),
+                body: "Article 1".to_owned(),
+                link: Some("https://example.com/1"),
+            }
+        );
+    }
+
+    #[test]
+    fn several_articles_are_listed_without_a_link() {
+        let articles = articles(MAX_LISTED);
+        assert_eq!(
+            payload(&articles),
+            Payload {
+                summary: "5 new articles".to_owned(),
+                body: "Odd: Article 1\nEven: Article 2\nOdd: Article 3\nEven: Article 4\n\
+                       Odd: Article 5"
+                    .to_owned(),
+                link: None,
+            }
+        );
+    }
+
+    #[test]
+    fn articles_past_the_listed_ones_are_counted() {
+        let articles = articles(MAX_LISTED + 3);
+        let payload = payload(&articles);
+        assert_eq!(payload.summary, "8 new articles");
+        let lines = payload.body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), MAX_LISTED + 1, "{lines:?}");
+        assert_eq!(lines[MAX_LISTED - 1], "Odd: Article 5");
+        assert_eq!(lines[MAX_LISTED], "and 3 more");
+        assert_eq!(payload.link, None);
+    }
+
+    #[cfg(all(unix, not(target_vendor = "apple")))]
+    #[test]
+    fn markup_in_titles_is_escaped() {
+        assert_eq!(
+            escape_markup("<b>Fish & chips</b> &amp; more"),
+            "&lt;b&gt;Fish &amp; chips&lt;/b&gt; &amp;amp; more"
+        );
+        assert_eq!(escape_markup("Plain title"), "Plain title");
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::IsTerminal as _,
+    path::Path,
+};
+
+/// How to print a diff.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum Format {
+    /// For people, grouped by site, and colored when printed to a terminal.
+    #[default]
+    Text,
+    /// As JSON, for scripts.
+    Json,
+}
+
+/// An article, as read from JSON output.
+///
+/// Only the fields needed to tell articles apart and show them are read, so output from other
+/// versions of jarss, with fields added or removed, can still be compared.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+struct Article {
+    site: Box<str>,
+    title: Box<str>,
+    link: Box<str>,
+    /// Missing from output written before articles had IDs, in which case the link is used.
+    #[serde(default)]
+    id: Option<Box<str>>,
+}
+impl Article {
+    /// What identifies the article, across runs.
+    fn key(&self) -> (&str, &str) {
+        (&self.site, self.id.as_deref().unwrap_or(&self.link))
+    }
+}
+
+/// The articles of JSON output, as written with `--out-json`.
+#[derive(serde::Deserialize)]
+struct Output {
+    articles: Vec<Article>,
+}
+
+/// How an article changed between the outputs.
+#[derive(serde::Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+enum Change {
+    Added {
+        #[serde(flatten)]
+        article: Article,
+        /// Where it is in the new output, from 1.
+        to: usize,
+    },
+    Removed {
+        #[serde(flatten)]
+        article: Article,
+        /// Where it was in the old output, from 1.
+        from: usize,
+    },
+    /// In both outputs, but in a different order relative to the other articles in both.
+    Moved {
+        #[serde(flatten)]
+        article: Article,
+        from: usize,
+        to: usize,
+    },
+}
+impl Change {
+    fn article(&self) -> &Article {
+        match self {
+            Self::Added { article, .. }
+            | Self::Removed { article, .. }
+            | Self::Moved { article, .. } => article,
+        }
+    }
+}
+
+/// Print how the articles changed between the JSON outputs at `old` and `new`.
+pub fn diff(old: &Path, new: &Path, format: Format) -> Result<()> {
+    let old = read_output(old)?;
+    let new = read_output(new)?;
+    let changes = compare(&old, &new);
+    match format {
+        Format::Json => {
+            let count = |kind: fn(&Change) -> bool| changes.iter().filter(|c| kind(c)).count();
+            let output = serde_json::json!({
+                "added": count(|change| matches!(change, Change::Added { .. })),
+                "removed": count(|change| matches!(change, Change::Removed { .. })),
+                "moved": count(|change| matches!(change, Change::Moved { .. })),
+                "changes": changes,
+            });
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&output).context("Failed to encode the diff")?
+            );
+        }
+        Format::Text => print_text(&changes, std::io::stdout().is_terminal()),
+    }
+    Ok(())
+}
+
+/// Read the articles from JSON output.
+fn read_output(path: &Path) -> Result<Vec<Article>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let output = serde_json::from_str::<Output>(&contents).with_context(|| {
+        format!(
+            "{} isn't JSON output, as written with `--out-json`",
+            path.display()
+        )
+    })?;
+    Ok(output.articles)
+}
+
+/// Find the articles which were added, removed, or moved, in the order of the site they're from
+/// and then of their position.
+fn compare(old: &[Article], new: &[Article]) -> Vec<Change> {
+    let old_positions = positions(old);
+    let new_positions = positions(new);
+    // Where the articles in both are among each other, so that articles being added or removed
+    // doesn't make the others look moved.
+    let old_order = common_order(old, &new_positions);
+    let new_order = common_order(new, &old_positions);
+
+    let mut changes = Vec::new();
+    for (position, article) in old.iter().enumerate() {
+        match new_positions.get(&article.key()) {
+            None => changes.push(Change::Removed {
+                article: article.clone(),
+                from: position + 1,
+            }),
+            Some(&new_position) if old_order[&article.key()] != new_order[&article.key()] => {
+                changes.push(Change::Moved {
+                    article: article.clone(),
+                    from: position + 1,
+                    to: new_position + 1,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (position, article) in new.iter().enumerate() {
+        if !old_positions.contains_key(&article.key()) {
+            changes.push(Change::Added {
+                article: article.clone(),
+                to: position + 1,
+            });
+        }
+    }
+    changes.sort_by_key(|change| {
+        let position = match change {
+            Change::Added { to, .. } | Change::Moved { to, .. } => *to,
+            Change::Removed { from, .. } => *from,
+        };
+        (change.article().site.clone(), position)
+    });
+    changes
+}
+
+/// The position of each article in `articles` which is also in `other`, among those, by its key.
+fn common_order<'a>(
+    articles: &'a [Article],
+    other: &HashMap<(&str, &str), usize>,
+) -> HashMap<(&'a str, &'a str), usize> {
+    articles
+        .iter()
+        .filter(|article| other.contains_key(&article.key()))
+        .enumerate()
+        .map(|(order, article)| (article.key(), order))
+        .collect()
+}
+
+/// The position of each article in the output, by its key.
+fn positions(articles: &[Article]) -> HashMap<(&str, &str), usize> {
+    articles
+        .iter()
+        .enumerate()
+        .map(|(position, article)| (article.key(), position))
+        .collect()
+}
+
+/// Print the changes grouped by site, followed by how many there were of each kind.
+fn print_text(changes: &[Change], color: bool) {
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text
+        }
+    };
+    let mut by_site = BTreeMap::<&str, Vec<&Change>>::new();
+    for change in changes {
+        by_site
+            .entry(&change.article().site)
+            .or_default()
+            .push(change);
+    }
+    let (mut added, mut removed, mut moved) = (0, 0, 0);
+    for (site, changes) in by_site {
+        println!("{}", paint("1", site.to_owned()));
+        for change in changes {
+            let article = change.article();
+            let line = match change {
+                Change::Added { to, .. } => {
+                    added += 1;
+                    paint(
+                        "32",
+                        format!("  + {} <{}> (at {to})", article.title, article.link),
+                    )
+                }
+                Change::Removed { from, .. } => {
+                    removed += 1;
+                    paint(
+                        "31",
+                        format!("  - {} <{}> (was at {from})", article.title, article.link),
+                    )
+                }
+                Change::Moved { from, to, .. } => {
+                    moved += 1;
+                    paint(
+                        "33",
+                        format!("  ~ {} <{}> ({from} to {to})", article.title, article.link),
+                    )
+                }
+            };
+            println!("{line}");
+        }
+    }
+    println!("{added} added, {removed} removed, {moved} moved");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn article(site: &str, id: Option<&str>, link: &str) -> Article {
+        Article {
+            site: site.into(),
+            title: format!("Title of {link}").into(),
+            link: link.into(),
+            id: id.map(Into::into),
+        }
+    }
+
+    /// Each change, as its kind, the link of its article, and where it was and is.
+    fn summarize(changes: &[Change]) -> Vec<(&str, &str, Option<usize>, Option<usize>)> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Added { article, to } => ("added", &*article.link, None, Some(*to)),
+                Change::Removed { article, from } => ("removed", &*article.link, Some(*from), None),
+                Change::Moved { article, from, to } => {
+                    ("moved", &*article.link, Some(*from), Some(*to))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn articles_are_matched_by_id_rather_than_title_or_link() {
+        let old = [article("a", Some("1"), "https://a.example/old")];
+        let mut new = [article("a", Some("1"), "https://a.example/new")];
+        new[0].title = "A better title".into();
+        assert!(compare(&old, &new).is_empty());
+        // The same ID on another site is another article.
+        let other = [article("b", Some("1"), "https://a.example/old")];
+        assert_eq!(
+            summarize(&compare(&old, &other)),
+            [
+                ("removed", "https://a.example/old", Some(1), None),
+                ("added", "https://a.example/old", None, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_articles_whose_order_among_the_others_changed_are_moved() {
+        let old = ["1", "2", "3", "4"].map(|id| article("a", Some(id), id));
+        let new = ["new", "1", "3", "2"].map(|id| article("a", Some(id), id));
+        // `1` is further down, but only because of what was added before it.
+        assert_eq!(
+            summarize(&compare(&old, &new)),
+            [
+                ("added", "new", None, Some(1)),
+                ("moved", "3", Some(3), Some(3)),
+                ("moved", "2", Some(2), Some(4)),
+                ("removed", "4", Some(4), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn output_without_ids_is_matched_by_link() {
+        let dir = test_util::test_dir("diff");
+        let old = dir.join("old.json");
+        std::fs::write(
+            &old,
+            r#"{"articles": [{"site": "a", "title": "T", "link": "https://a.example/1"}]}"#,
+        )
+        .unwrap();
+        let new = dir.join("new.json");
+        std::fs::write(
+            &new,
+            r#"{"generated_unix": 0, "articles": [
+                {"site": "a", "title": "T", "link": "https://a.example/1", "id": "1"},
+                {"site": "a", "title": "U", "link": "https://a.example/2"}
+            ]}"#,
+        )
+        .unwrap();
+        let page = dir.join("index.html");
+        std::fs::write(&page, "<html></html>").unwrap();
+
+        let old = read_output(&old).unwrap();
+        let new = read_output(&new).unwrap();
+        let error = read_output(&page).unwrap_err().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(error.ends_with("isn't JSON output, as written with `--out-json`"));
+        // The old output's article has no ID, and so isn't the new one with an ID.
+        assert_eq!(
+            summarize(&compare(&old, &new)),
+            [
+                ("removed", "https://a.example/1", Some(1), None),
+                ("added", "https://a.example/1", None, Some(1)),
+                ("added", "https://a.example/2", None, Some(2)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use super::auth::Password;
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The credentials to authenticate to a site with, using HTTP digest auth.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DigestAuth {
+    pub username: Box<str>,
+    #[serde(flatten)]
+    pub password: Password,
+}
+impl DigestAuth {
+    /// Read the password for the site named `site`, to authenticate with.
+    pub fn credentials(&self, site: &str) -> Result<Credentials> {
+        Ok(Credentials {
+            username: self.username.clone(),
+            password: self
+                .password
+                .read(&format!("digest auth password for site {site}"))?,
+        })
+    }
+}
+
+/// A username and password, which are never logged.
+pub struct Credentials {
+    username: Box<str>,
+    password: Box<str>,
+}
+
+/// The hash algorithms digest auth can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+}
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha256 => "SHA-256",
+        }
+    }
+
+    /// The lowercase hex hash of `data`.
+    fn hash(self, data: &str) -> String {
+        use sha2::Digest as _;
+        let bytes = match self {
+            Self::Md5 => md5::Md5::digest(data).to_vec(),
+            Self::Sha256 => sha2::Sha256::digest(data).to_vec(),
+        };
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+
+/// A digest challenge from a server, which is kept to authenticate later requests without being
+/// challenged again.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Challenge {
+    realm: Box<str>,
+    nonce: Box<str>,
+    opaque: Option<Box<str>>,
+    algorithm: Algorithm,
+    /// Whether the server supports `qop=auth`, rather than only the legacy scheme from RFC 2069.
+    qop_auth: bool,
+    /// How many requests we've authenticated with this nonce.
+    nonce_count: u32,
+}
+impl Challenge {
+    /// The best digest challenge among a response's `www-authenticate` headers, if there's one
+    /// we support.
+    ///
+    /// SHA-256 is preferred over MD5.
+    pub fn from_headers(headers: &http::HeaderMap) -> Option<Self> {
+        headers
+            .get_all(http::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("digest"))
+            .filter_map(|(_, params)| {
+                let algorithm = match params.get("algorithm").map(String::as_str) {
+                    None => Algorithm::Md5,
+                    Some(name) if name.eq_ignore_ascii_case("MD5") => Algorithm::Md5,
+                    Some(name) if name.eq_ignore_ascii_case("SHA-256") => Algorithm::Sha256,
+                    // The `-sess` variants and SHA-512-256 aren't supported.
+                    Some(_) => return None,
+                };
+                let qop = params.get("qop");
+                let qop_auth = qop.is_some_and(|qop| {
+                    qop.split(',')
+                        .any(|qop| qop.trim().eq_ignore_ascii_case("auth"))
+                });
+                if qop.is_some() && !qop_auth {
+                    // Only auth-int is offered, which needs the body hashed.
+                    return None;
+                }
+                Some(Self {
+                    realm: params.get("realm")?.as_str().into(),
+                    nonce: params.get("nonce")?.as_str().into(),
+                    opaque: params.get("opaque").map(|opaque| opaque.as_str().into()),
+                    algorithm,
+                    qop_auth,
+                    nonce_count: 0,
+                })
+            })
+            .max_by_key(|challenge| challenge.algorithm == Algorithm::Sha256)
+    }
+
+    /// Add the `authorization` header answering this challenge to `request`.
+    ///
+    /// Fails if the username can't be sent in a header, which [`auth::check`](super::auth::check)
+    /// rules out for configured usernames.
+    pub fn authorize(
+        &mut self,
+        request: &mut reqwest::Request,
+        credentials: &Credentials,
+    ) -> Result<()> {
+        self.nonce_count = self.nonce_count.wrapping_add(1);
+        let url = request.url();
+        let uri = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_owned(),
+        };
+        let header = self.authorization(request.method().as_str(), &uri, credentials, &cnonce());
+        let mut value = http::HeaderValue::from_str(&header)
+            .context("The digest auth username can't be sent in a header")?;
+        value.set_sensitive(true);
+        request
+            .headers_mut()
+            .insert(http::header::AUTHORIZATION, value);
+        Ok(())
+    }
+
+    /// The `authorization` header for a request, per RFC 7616.
+    fn authorization(
+        &self,
+        method: &str,
+        uri: &str,
+        credentials: &Credentials,
+        cnonce: &str,
+    ) -> String {
+        let hash = |data: &str| self.algorithm.hash(data);
+        let ha1 = hash(&format!(
+            "{}:{}:{}",
+            credentials.username, self.realm, credentials.password
+        ));
+        let ha2 = hash(&format!("{method}:{uri}"));
+        let nc = format!("{:08x}", self.nonce_count);
+        let response = if self.qop_auth {
+            hash(&format!("{ha1}:{}:{nc}:{cnonce}:auth:{ha2}", self.nonce))
+        } else {
+            hash(&format!("{ha1}:{}:{ha2}", self.nonce))
+        };
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, \
+             response=\"{response}\"",
+            quote(&credentials.username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            self.algorithm.name(),
+        );
+        if self.qop_auth {
+            header.push_str(&format!(", qop=auth, nc={nc}, cnonce=\"{cnonce}\""));
+        }
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        header
+    }
+}
+
+/// A client nonce, which only has to be unlikely to repeat.
+fn cnonce() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seed = format!("{}:{}", now.as_nanos(), std::process::id());
+    blake3::hash(seed.as_bytes()).to_hex()[..16].to_owned()
+}
+
+/// Escape a value to go in a quoted string.
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Parse the challenges in a `www-authenticate` header, as their schemes and parameters.
+///
+/// Parameter names are lowercased. Challenges using a `token68` rather than parameters, like
+/// some `Negotiate` ones, won't come out right, but they aren't ones we use.
+fn parse_challenges(header: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut challenges = Vec::<(String, HashMap<String, String>)>::new();
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            return challenges;
+        }
+        let token_end = rest
+            .find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let token = &rest[..token_end];
+        rest = rest[token_end..].trim_start();
+        let Some(after_eq) = rest.strip_prefix('=') else {
+            challenges.push((token.to_owned(), HashMap::new()));
+            continue;
+        };
+        rest = after_eq.trim_start();
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+            value
+        } else {
+            let value_end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..value_end].trim().to_owned();
+            rest = &rest[value_end..];
+            value
+        };
+        if let Some((_, params)) = challenges.last_mut() {
+            params.insert(token.to_ascii_lowercase(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The challenges from the example in section 3.9.1 of RFC 7616.
+    fn rfc_7616_challenge(algorithm: &str) -> String {
+        format!(
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+             algorithm={algorithm}, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        )
+    }
+
+    fn rfc_7616_response(headers: &[String]) -> String {
+        let mut header_map = http::HeaderMap::new();
+        for header in headers {
+            header_map.append(
+                http::header::WWW_AUTHENTICATE,
+                http::HeaderValue::from_str(header).unwrap(),
+            );
+        }
+        let mut challenge = Challenge::from_headers(&header_map).unwrap();
+        challenge.nonce_count = 1;
+        let credentials = Credentials {
+            username: "Mufasa".into(),
+            password: "Circle of Life".into(),
+        };
+        challenge.authorization(
+            "GET",
+            "/dir/index.html",
+            &credentials,
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        )
+    }
+
+    #[test]
+    fn rfc_7616_md5() {
+        assert_eq!(
+            rfc_7616_response(&[rfc_7616_challenge("MD5")]),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+             algorithm=MD5, response=\"8ca523f5e9506fed4657c9700eebdbec\", qop=auth, \
+             nc=00000001, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+    }
+
+    #[test]
+    fn rfc_7616_sha256() {
+        // The example offers both, and SHA-256 is preferred.
+        assert_eq!(
+            rfc_7616_response(&[rfc_7616_challenge("SHA-256"), rfc_7616_challenge("MD5")]),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+             algorithm=SHA-256, \
+             response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", \
+             qop=auth, nc=00000001, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+    }
+
+    #[test]
+    fn usernames_which_cant_be_sent_are_errors() {
+        let mut header_map = http::HeaderMap::new();
+        header_map.insert(
+            http::header::WWW_AUTHENTICATE,
+            http::HeaderValue::from_str(&rfc_7616_challenge("MD5")).unwrap(),
+        );
+        let mut challenge = Challenge::from_headers(&header_map).unwrap();
+        let mut request = reqwest::Request::new(
+            http::Method::GET,
+            reqwest::Url::parse("https://example.org/dir/index.html").unwrap(),
+        );
+        let credentials = Credentials {
+            username: "Mufasa\r\nx-injected: yes".into(),
+            password: "Circle of Life".into(),
+        };
+        let e = challenge.authorize(&mut request, &credentials).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "The digest auth username can't be sent in a header"
+        );
+        assert!(request.headers().get(http::header::AUTHORIZATION).is_none());
+    }
+
+    #[test]
+    fn challenges_without_qop_auth_are_skipped() {
+        let header = "Digest realm=\"r\", nonce=\"n\", qop=\"auth-int\"";
+        let mut header_map = http::HeaderMap::new();
+        header_map.insert(
+            http::header::WWW_AUTHENTICATE,
+            http::HeaderValue::from_static(header),
+        );
+        assert!(Challenge::from_headers(&header_map).is_none());
+    }
+}
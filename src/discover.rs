@@ -0,0 +1,356 @@
+use super::{
+    cache::FeedFormat,
+    config_files::ConfigFiles,
+    opml::{self, Outline},
+};
+
+use anyhow::{Context, Result};
+
+/// A feed advertised by an HTML page.
+#[derive(Clone, Debug)]
+pub struct Alternate {
+    /// The absolute URL of the feed.
+    pub url: reqwest::Url,
+    /// The `type` the page gave for the feed.
+    pub mime_type: Box<str>,
+}
+impl Alternate {
+    /// How much we'd rather use this feed than others, with higher being better.
+    fn preference(&self) -> u8 {
+        match self.mime_type.as_ref() {
+            "application/atom+xml" => 2,
+            "application/rss+xml" => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// The MIME types of the feeds we look for.
+const FEED_TYPES: &[&str] = &[
+    "application/atom+xml",
+    "application/rss+xml",
+    "application/feed+json",
+];
+
+/// Find the feeds advertised by `<link rel="alternate">` elements in the given HTML page.
+///
+/// Relative links are resolved against `base`. The best candidate comes first, preferring Atom over
+/// RSS over JSON Feed and otherwise keeping the order of the page.
+pub fn alternates(html: &str, base: &reqwest::Url) -> Vec<Alternate> {
+    let mut alternates = tags(html, "link")
+        .filter_map(|attrs| {
+            let attr = |name: &str| {
+                attrs
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.as_str())
+            };
+            if !attr("rel")?
+                .split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+            {
+                return None;
+            }
+            let mime_type = attr("type")?.trim().to_ascii_lowercase();
+            if !FEED_TYPES.contains(&mime_type.as_str()) {
+                return None;
+            }
+            Some(Alternate {
+                url: base.join(attr("href")?.trim()).ok()?,
+                mime_type: mime_type.into_boxed_str(),
+            })
+        })
+        .collect::<Vec<_>>();
+    alternates.sort_by_key(|alternate| std::cmp::Reverse(alternate.preference()));
+    alternates
+}
+
+/// The `<title>` of the given HTML page, if it has one.
+pub fn title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = decode_entities(&html[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+/// Fetch the page at `url` and print the feeds it advertises, best first.
+///
+/// With `add`, the best one is also added to the config as a site named by the page's title.
+pub async fn discover(config_files: &ConfigFiles, url: &str, add: bool) -> Result<()> {
+    // The config might not exist yet, if this is how it's being set up.
+    let http = config_files
+        .load()
+        .map(|config| config.http)
+        .unwrap_or_default();
+    let client = http
+        .client_builder()
+        .redirect(reqwest::redirect::Policy::limited(10))
+        .build()?;
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to fetch {url}"))?;
+    let base = res.url().clone();
+    let html = res.text().await.context("Failed to read page")?;
+    if !matches!(
+        FeedFormat::detect(&html),
+        FeedFormat::Html | FeedFormat::Unknown
+    ) {
+        anyhow::bail!("{url} is already a feed, so it can be used as a `feed_url` as it is");
+    }
+    let alternates = alternates(&html, &base);
+    let Some(best) = alternates.first() else {
+        anyhow::bail!(
+            "{url} doesn't advertise any feeds, so try guessing at paths like /feed, /rss.xml, \
+             or /atom.xml"
+        );
+    };
+    for alternate in &alternates {
+        println!("{}\t{}", alternate.url, alternate.mime_type);
+    }
+    if add {
+        let feed = Outline {
+            feed_url: best.url.to_string(),
+            name: title(&html).unwrap_or_else(|| base.host_str().unwrap_or(url).to_owned()),
+        };
+        match &opml::add_to_config(config_files, std::slice::from_ref(&feed))?.0[..] {
+            [name] => println!("Added {name} with feed {}", feed.feed_url),
+            _ => println!("{} is already in the config", feed.feed_url),
+        }
+    }
+    Ok(())
+}
+
+/// Iterate over the attributes of each tag with the given name in the HTML.
+///
+/// This is nowhere near a full HTML parser, but it copes with the `<link>` tags in page heads.
+pub fn tags<'a>(html: &'a str, name: &'a str) -> impl Iterator<Item = Vec<(String, String)>> + 'a {
+    let mut rest = html;
+    std::iter::from_fn(move || {
+        loop {
+            let start = rest.find('<')?;
+            rest = &rest[start + 1..];
+            let tag_end = rest.find('>').unwrap_or(rest.len());
+            let tag = &rest[..tag_end];
+            rest = &rest[tag_end..];
+            let Some(attrs) = tag
+                .get(..name.len())
+                .filter(|tag_name| tag_name.eq_ignore_ascii_case(name))
+                .map(|_| &tag[name.len()..])
+                .filter(|attrs| attrs.is_empty() || attrs.starts_with(char::is_whitespace))
+            else {
+                continue;
+            };
+            return Some(parse_attrs(attrs.trim_end_matches('/')));
+        }
+    })
+}
+
+/// Parse the attributes out of the inside of a tag.
+fn parse_attrs(mut attrs: &str) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    loop {
+        attrs = attrs.trim_start();
+        let name_end = attrs
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(attrs.len());
+        if name_end == 0 {
+            return parsed;
+        }
+        let name = attrs[..name_end].to_owned();
+        attrs = attrs[name_end..].trim_start();
+        let Some(after_eq) = attrs.strip_prefix('=') else {
+            parsed.push((name, String::new()));
+            continue;
+        };
+        attrs = after_eq.trim_start();
+        let value = match attrs.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value_end = attrs[1..].find(quote).map_or(attrs.len(), |end| end + 1);
+                let value = &attrs[1..value_end];
+                attrs = attrs.get(value_end + 1..).unwrap_or("");
+                value
+            }
+            _ => {
+                let value_end = attrs.find(char::is_whitespace).unwrap_or(attrs.len());
+                let value = &attrs[..value_end];
+                attrs = &attrs[value_end..];
+                value
+            }
+        };
+        parsed.push((name, decode_entities(value)));
+    }
+}
+
+/// Decode the character references in an attribute value.
+///
+/// Only XML's predefined entities, `&nbsp;`, and numeric references are decoded, anything else is
+/// left as it is.
+pub fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let c = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{A0}'),
+            _ => {
+                let number = reference.strip_prefix('#')?;
+                match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                }
+                .and_then(char::from_u32)
+            }
+        });
+        match (c, reference) {
+            (Some(c), Some(reference)) => {
+                decoded.push(c);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::FeedFormat;
+
+    #[test]
+    fn feeds_are_told_apart_from_the_pages_which_replaced_them() {
+        for (body, format) in [
+            (
+                "<rss version=\"2.0\"><channel></channel></rss>",
+                FeedFormat::Rss,
+            ),
+            (
+                "<feed xmlns=\"http://www.w3.org/2005/Atom\"></feed>",
+                FeedFormat::Atom,
+            ),
+            (
+                r#"{"version": "https://jsonfeed.org/version/1.1", "title": "", "items": []}"#,
+                FeedFormat::JsonFeed,
+            ),
+            ("\n<!DOCTYPE html><html></html>", FeedFormat::Html),
+            ("<HTML><body>Moved</body></HTML>", FeedFormat::Html),
+            ("Service unavailable", FeedFormat::Unknown),
+        ] {
+            assert_eq!(FeedFormat::detect(body), format, "{body}");
+        }
+    }
+
+    #[test]
+    fn advertised_feeds_are_found_best_first() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" type="text/css" href="/style.css">
+            <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+            <LINK REL="Alternate" TYPE="application/atom+xml" HREF='atom.xml'/>
+            <link rel="alternate" type="text/html" hreflang="fr" href="/fr/">
+            <link rel="alternate" type="application/feed+json" href="https://cdn.example/f.json?a=1&amp;b=2">
+            </head></html>"#;
+        let base = reqwest::Url::parse("https://example.com/blog/").unwrap();
+        let found = alternates(html, &base)
+            .into_iter()
+            .map(|alternate| (alternate.url.to_string(), alternate.mime_type))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (
+                    "https://example.com/blog/atom.xml".to_owned(),
+                    "application/atom+xml".into()
+                ),
+                (
+                    "https://example.com/feed.xml".to_owned(),
+                    "application/rss+xml".into()
+                ),
+                (
+                    "https://cdn.example/f.json?a=1&b=2".to_owned(),
+                    "application/feed+json".into()
+                ),
+            ]
+        );
+        assert!(alternates("<p>No feeds here</p>", &base).is_empty());
+    }
+
+    #[test]
+    fn page_titles_are_decoded_and_tidied() {
+        assert_eq!(
+            title("<html><head><TITLE lang=en>\n  Ben &amp; Jo's\n  Blog </TITLE></head></html>"),
+            Some("Ben & Jo's Blog".to_owned())
+        );
+        assert_eq!(title("<title> </title>"), None);
+        assert_eq!(title("<p>Untitled</p>"), None);
+    }
+
+    #[tokio::test]
+    async fn the_best_feed_is_added_once() {
+        let dir = crate::test_util::test_dir("discover-add");
+        let config_path = dir.join("config.toml");
+        std::fs::write(&config_path, "min_fetch_interval = 0\n").unwrap();
+        let config_files = ConfigFiles::new(vec![config_path.clone()]);
+        let server = crate::test_server::serve_http(|head| {
+            if head.starts_with("GET /feed.xml ") {
+                crate::test_server::response(
+                    "200 OK",
+                    "application/rss+xml",
+                    "<rss version=\"2.0\"><channel></channel></rss>",
+                )
+            } else {
+                crate::test_server::response(
+                    "200 OK",
+                    "text/html",
+                    "<html><head><title>Example</title>\
+                     <link rel=\"alternate\" type=\"application/rss+xml\" href=\"/feed.xml\">\
+                     <link rel=\"alternate\" type=\"application/atom+xml\" href=\"atom.xml\">\
+                     </head></html>",
+                )
+            }
+        })
+        .await;
+        let page = format!("http://{server}/blog/");
+        for _ in 0..2 {
+            discover(&config_files, &page, true).await.unwrap();
+        }
+        let sites = config_files
+            .load()
+            .unwrap()
+            .sites
+            .into_iter()
+            .map(|site| (site.name, site.feed_url))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sites,
+            [(
+                "Example".into(),
+                format!("http://{server}/blog/atom.xml").into()
+            )]
+        );
+
+        let e = discover(&config_files, &format!("http://{server}/feed.xml"), false)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("is already a feed"), "{e}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use super::{Config, SiteConfig, cache::SiteCache};
+
+use futures::{
+    FutureExt as _,
+    future::{BoxFuture, Shared},
+};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+/// A failure to resolve a feed's host.
+#[derive(Clone, Debug)]
+pub struct DnsError {
+    host: Box<str>,
+    message: Arc<str>,
+}
+impl std::fmt::Display for DnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to resolve {}: {}", self.host, self.message)
+    }
+}
+impl std::error::Error for DnsError {}
+
+/// Resolves the hosts of all the feeds up front, each at most once.
+///
+/// Each host gets its own client with the resolved addresses filled in, so a site can start
+/// fetching as soon as its own host is resolved, and a host which doesn't resolve fails the sites
+/// using it without waiting on the client's own (potentially much longer) timeout.
+pub struct PreResolver {
+    /// The client for each host, once its addresses are resolved, keyed by host and port.
+    clients: HashMap<(Box<str>, u16), ClientFuture>,
+}
+/// A future giving the client for one host, which any number of sites can wait on.
+type ClientFuture = Shared<BoxFuture<'static, Result<reqwest::Client, DnsError>>>;
+impl PreResolver {
+    /// Start resolving the hosts the given sites' feeds will be fetched from, going by where their
+    /// caches say they've been permanently redirected to.
+    ///
+    /// Each lookup is started straight away, in the background, so the hosts are resolved
+    /// concurrently rather than when each site gets its turn to fetch. Each host's client is made
+    /// with the config's HTTP settings. If pre-resolution is disabled in the config, or requests
+    /// go through a proxy (which resolves the hosts itself), this resolves nothing.
+    pub fn new<'a>(
+        config: &Config,
+        sites: impl IntoIterator<Item = (&'a SiteConfig, &'a SiteCache)>,
+    ) -> Self {
+        let mut clients = HashMap::new();
+        if !config.dns_preresolve || config.http.proxy.is_some() {
+            return Self { clients };
+        }
+        for (site, cache) in sites {
+            let Some((host, port)) = host_of(site, cache) else {
+                continue;
+            };
+            let timeout = config.dns_timeout;
+            let http = config.http.clone();
+            clients.entry((host.clone(), port)).or_insert_with(|| {
+                async move {
+                    let lookup = tokio::time::timeout(
+                        timeout,
+                        tokio::net::lookup_host((host.to_string(), port)),
+                    )
+                    .await;
+                    let addrs = match lookup {
+                        Ok(Ok(addrs)) => addrs.collect::<Vec<SocketAddr>>(),
+                        Ok(Err(e)) => {
+                            return Err(DnsError {
+                                host,
+                                message: e.to_string().into(),
+                            });
+                        }
+                        Err(_) => {
+                            return Err(DnsError {
+                                host,
+                                message: format!("timed out after {timeout:?}").into(),
+                            });
+                        }
+                    };
+                    log::debug!("Resolved {host} to {addrs:?}");
+                    http.client_builder()
+                        .resolve_to_addrs(&host, &addrs)
+                        .build()
+                        .map_err(|e| DnsError {
+                            host,
+                            message: e.to_string().into(),
+                        })
+                }
+                .boxed()
+                .shared()
+            });
+        }
+        for client in clients.values() {
+            tokio::spawn(client.clone());
+        }
+        Self { clients }
+    }
+
+    /// Get the client to fetch the given site with, waiting for its host to be resolved.
+    ///
+    /// Returns `None` for sites whose feed URLs we can't pre-resolve, which should use a normal
+    /// client instead.
+    pub async fn client_for(
+        &self,
+        site: &SiteConfig,
+        cache: &SiteCache,
+    ) -> Option<Result<reqwest::Client, DnsError>> {
+        let client = self.clients.get(&host_of(site, cache)?)?.clone();
+        Some(client.await)
+    }
+}
+
+/// The host and port that fetching the given site's feed will connect to, if known in advance.
+fn host_of(site: &SiteConfig, cache: &SiteCache) -> Option<(Box<str>, u16)> {
+    if site.pre_fetch_command.is_some() {
+        // The command decides which URL is actually fetched.
+        return None;
+    }
+    let url = reqwest::Url::parse(cache.current_redirect(site).unwrap_or(&site.feed_url)).ok()?;
+    // IP addresses don't have anything to resolve.
+    Some((url.domain()?.into(), url.port_or_known_default()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn each_host_is_resolved_once_up_front() {
+        let feeds = test_server::serve_http(|_| {
+            test_server::response("200 OK", "application/rss+xml", "<rss></rss>")
+        })
+        .await;
+        let port = feeds.port();
+        let config: Config = toml::from_str(&format!(
+            r#"
+            min_fetch_interval = 0
+            dns_timeout = "1s"
+            [[sites]]
+            name = "First"
+            feed_url = "http://localhost:{port}/first"
+            [[sites]]
+            name = "Second"
+            feed_url = "http://localhost:{port}/second"
+            [[sites]]
+            name = "Address"
+            feed_url = "http://{feeds}/feed"
+            [[sites]]
+            name = "Command"
+            feed_url = "http://localhost:{port}/feed"
+            pre_fetch_command = ["true"]
+            [[sites]]
+            name = "Missing"
+            feed_url = "http://jarss-test.invalid/feed"
+            "#
+        ))
+        .unwrap();
+        let cache = SiteCache::default();
+        let resolver = PreResolver::new(&config, config.sites.iter().map(|site| (site, &cache)));
+        assert_eq!(resolver.clients.len(), 2);
+        for site in &config.sites[..2] {
+            let client = resolver.client_for(site, &cache).await.unwrap().unwrap();
+            let res = client.get(&*site.feed_url).send().await.unwrap();
+            assert_eq!(res.text().await.unwrap(), "<rss></rss>");
+        }
+        for site in &config.sites[2..4] {
+            assert!(
+                resolver.client_for(site, &cache).await.is_none(),
+                "{}",
+                site.name
+            );
+        }
+        let e = resolver.client_for(&config.sites[4], &cache).await.unwrap();
+        assert!(
+            e.unwrap_err()
+                .to_string()
+                .starts_with("Failed to resolve jarss-test.invalid: "),
+        );
+
+        let config = Config {
+            dns_preresolve: false,
+            ..config
+        };
+        let resolver = PreResolver::new(&config, config.sites.iter().map(|site| (site, &cache)));
+        assert!(
+            resolver
+                .client_for(&config.sites[0], &cache)
+                .await
+                .is_none()
+        );
+
+        // The proxy resolves the hosts itself, so we don't.
+        let config = Config {
+            dns_preresolve: true,
+            http: crate::client::HttpConfig {
+                proxy: Some(format!("http://{feeds}").into()),
+                ..Default::default()
+            },
+            ..config
+        };
+        let resolver = PreResolver::new(&config, config.sites.iter().map(|site| (site, &cache)));
+        assert!(resolver.clients.is_empty());
+    }
+
+    #[tokio::test]
+    async fn redirected_sites_are_resolved_by_where_they_redirected_to() {
+        let feeds = test_server::serve_http(|_| {
+            test_server::response("200 OK", "application/rss+xml", "<rss></rss>")
+        })
+        .await;
+        let config: Config = toml::from_str(
+            r#"
+            min_fetch_interval = 0
+            dns_timeout = "1s"
+            [[sites]]
+            name = "Moved"
+            feed_url = "http://jarss-test.invalid/feed"
+            "#,
+        )
+        .unwrap();
+        let site = &config.sites[0];
+        let moved_to = format!("http://localhost:{}/feed", feeds.port());
+        let mut cache = SiteCache::default();
+        cache.redirected_url = Some(moved_to.as_str().into());
+        cache.redirected_from = Some(site.feed_url.clone());
+        let resolver = PreResolver::new(&config, [(site, &cache)]);
+        let client = resolver.client_for(site, &cache).await.unwrap().unwrap();
+        let res = client.get(&moved_to).send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "<rss></rss>");
+    }
+}
@@ -0,0 +1,1209 @@
+use super::{
+    Config, SiteConfig, auth, cache,
+    config_files::{self, ConfigFiles},
+    post, urls,
+};
+
+use anyhow::{Context, Result};
+use futures::StreamExt as _;
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::ExitCode,
+    time::{Duration, SystemTime},
+};
+
+/// Retry-after times further out than this are assumed to be bogus.
+const MAX_SANE_RETRY_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Server `Date` headers further than this from our clock suggest the clock is wrong.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// The checks of the config, and what it refers to, which we run in order.
+const CHECKS: &[fn(&Environment) -> Vec<Finding>] = &[
+    check_config,
+    check_unknown_keys,
+    check_feed_urls,
+    check_cache_file_collisions,
+    check_template,
+    check_fragment_template,
+    check_output_path,
+];
+
+/// The checks of the caches, which we run after [`CHECKS`] unless checking a candidate config.
+const CACHE_CHECKS: &[fn(&Environment) -> Vec<Finding>] = &[
+    check_cache_dir_writable,
+    check_cache_files,
+    check_retry_after,
+    check_clock,
+];
+
+/// Everything the checks look at.
+struct Environment<'a> {
+    /// Where the config came from, for messages.
+    source: String,
+    /// The settings of the config, merged from its files, or the error from reading them.
+    merged: Result<toml::Table>,
+    /// The parsed config, or the error from parsing it.
+    config: Result<Config>,
+    cache_dir: &'a Path,
+    feed_template: Option<&'a Path>,
+    out_html: Option<&'a Path>,
+}
+impl Environment<'_> {
+    /// The cache manager for the config, which fails if sites share cache files.
+    ///
+    /// This doesn't touch the cache directory.
+    fn cache_manager(&self, config: &Config) -> Result<cache::CacheManager> {
+        cache::CacheManager::new(self.cache_dir.to_owned(), config.cache_key, &config.sites)
+    }
+
+    /// The cache of each site which already has a cache file that can be decoded.
+    fn decodable_caches<'c>(&self, config: &'c Config) -> Vec<(&'c str, cache::SiteCache)> {
+        // Sites sharing cache files are reported by their own check.
+        let Ok(caches) = self.cache_manager(config) else {
+            return Vec::new();
+        };
+        config
+            .sites
+            .iter()
+            .filter_map(|site| {
+                let contents = std::fs::read(caches.cache_path(site)).ok()?;
+                Some((
+                    site.name.as_ref(),
+                    cache::SiteCache::decode(&contents).ok()?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// How bad the result of a check is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The result of a check.
+#[derive(serde::Serialize)]
+struct Finding {
+    #[serde(rename = "severity")]
+    outcome: Outcome,
+    /// What was found.
+    message: String,
+    /// How to fix it, if it's a problem.
+    hint: Option<&'static str>,
+}
+impl Finding {
+    fn pass(message: impl Into<String>) -> Self {
+        Self {
+            outcome: Outcome::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            outcome: Outcome::Warn,
+            message: message.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(message: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            outcome: Outcome::Fail,
+            message: message.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+/// How the sites of a candidate config differ from those of the current one, by name.
+#[derive(Default, serde::Serialize)]
+struct SiteChanges {
+    added: Vec<Box<str>>,
+    removed: Vec<Box<str>>,
+    /// Sites in both whose settings differ.
+    changed: Vec<Box<str>>,
+}
+
+/// Which optional checks to run, and how strictly to judge them.
+pub struct Options {
+    /// Whether to request each site's feed.
+    pub fetch: bool,
+    /// Whether warnings count as failures.
+    pub strict: bool,
+    /// A config to check in place of the config files, which the caches aren't checked against.
+    pub candidate: Option<String>,
+    /// Whether to report how the candidate's sites differ from those of the config files.
+    pub diff_against_current: bool,
+    /// Whether to print the findings as JSON, rather than a line for each.
+    pub json: bool,
+}
+
+/// Run every check, printing the findings.
+///
+/// This fails if any of the checks failed, or with [`Options::strict`], warned.
+pub async fn doctor(
+    config_files: &ConfigFiles,
+    cache_dir: &Path,
+    feed_template: Option<&Path>,
+    out_html: Option<&Path>,
+    options: Options,
+) -> Result<ExitCode> {
+    let (findings, changes) =
+        run_checks(config_files, cache_dir, feed_template, out_html, &options).await;
+    let failed = findings.iter().any(|finding| match finding.outcome {
+        Outcome::Pass => false,
+        Outcome::Warn => options.strict,
+        Outcome::Fail => true,
+    });
+    if options.json {
+        let output = serde_json::json!({
+            "success": !failed,
+            "findings": findings,
+            "site_changes": changes,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).context("Failed to encode the findings")?
+        );
+    } else {
+        for finding in findings {
+            let label = match finding.outcome {
+                Outcome::Pass => "pass",
+                Outcome::Warn => "warn",
+                Outcome::Fail => "FAIL",
+            };
+            println!("[{label}] {}", finding.message);
+            if let Some(hint) = finding.hint {
+                println!("       hint: {hint}");
+            }
+        }
+        if let Some(changes) = changes {
+            let list = |names: &[Box<str>]| {
+                if names.is_empty() {
+                    "none".to_owned()
+                } else {
+                    names.join(", ")
+                }
+            };
+            println!("Compared to the current config:");
+            println!("  added: {}", list(&changes.added));
+            println!("  removed: {}", list(&changes.removed));
+            println!("  changed: {}", list(&changes.changed));
+        }
+    }
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Run every check, giving the findings, and how the sites changed if
+/// [`Options::diff_against_current`] asked for it.
+async fn run_checks(
+    config_files: &ConfigFiles,
+    cache_dir: &Path,
+    feed_template: Option<&Path>,
+    out_html: Option<&Path>,
+    options: &Options,
+) -> (Vec<Finding>, Option<SiteChanges>) {
+    let (source, merged) = match &options.candidate {
+        Some(contents) => (
+            "stdin".to_owned(),
+            ConfigFiles::merged_contents(contents, Path::new("<stdin>")),
+        ),
+        None => (config_files.describe(), config_files.merged(None)),
+    };
+    let env = Environment {
+        source,
+        config: match &merged {
+            Ok(merged) => config_files::to_config(merged.clone(), false),
+            Err(e) => Err(anyhow::anyhow!("{e:#}")),
+        },
+        merged,
+        cache_dir,
+        feed_template,
+        out_html,
+    };
+    let cache_checks = if options.candidate.is_some() {
+        &[][..]
+    } else {
+        CACHE_CHECKS
+    };
+    let mut findings = CHECKS
+        .iter()
+        .chain(cache_checks)
+        .flat_map(|check| check(&env))
+        .collect::<Vec<_>>();
+    if options.fetch {
+        findings.extend(check_feeds(&env).await);
+    }
+    let changes = if options.diff_against_current {
+        match site_changes(config_files, &env) {
+            Ok(changes) => Some(changes),
+            Err(e) => {
+                findings.push(Finding::warn(
+                    format!("Couldn't compare against the current config: {e:#}"),
+                    "Check that the config files given with --config can be loaded",
+                ));
+                None
+            }
+        }
+    } else {
+        None
+    };
+    (findings, changes)
+}
+
+/// How the sites of the config being checked differ from those of `config_files`, including
+/// disabled sites in both.
+fn site_changes(config_files: &ConfigFiles, env: &Environment) -> Result<SiteChanges> {
+    let current = config_files.load_including_disabled()?;
+    let Ok(merged) = &env.merged else {
+        anyhow::bail!("The config being checked can't be read");
+    };
+    let candidate = config_files::to_config(merged.clone(), true)?;
+    let settings = |sites: &[SiteConfig]| {
+        sites
+            .iter()
+            .map(|site| (site.name.clone(), toml::Value::try_from(site).ok()))
+            .collect::<HashMap<_, _>>()
+    };
+    let current_sites = settings(&current.sites);
+    let mut changes = SiteChanges::default();
+    for site in &candidate.sites {
+        match current_sites.get(&site.name) {
+            None => changes.added.push(site.name.clone()),
+            Some(settings) if *settings != toml::Value::try_from(site).ok() => {
+                changes.changed.push(site.name.clone());
+            }
+            Some(_) => {}
+        }
+    }
+    let candidate_sites = settings(&candidate.sites);
+    changes.removed = current
+        .sites
+        .iter()
+        .filter(|site| !candidate_sites.contains_key(&site.name))
+        .map(|site| site.name.clone())
+        .collect();
+    Ok(changes)
+}
+
+fn check_config(env: &Environment) -> Vec<Finding> {
+    let config = match &env.config {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Finding::fail(
+                format!("Config at {} is unusable: {e:#}", env.source),
+                "Fix the config file, or point at a different one with --config",
+            )];
+        }
+    };
+    let mut findings = vec![Finding::pass(format!(
+        "Config at {} parses, with {} sites",
+        env.source,
+        config.sites.len()
+    ))];
+    if config.sites.is_empty() {
+        findings.push(Finding::warn(
+            "Config has no sites",
+            "Add some `[[sites]]` entries to the config",
+        ));
+    }
+    let mut names = HashMap::new();
+    for site in &config.sites {
+        if let Some(first_url) = names.insert(&site.name, &site.feed_url) {
+            findings.push(Finding::fail(
+                format!(
+                    "Site name {} is used for both {first_url} and {}",
+                    site.name, site.feed_url
+                ),
+                "Give each site a unique name",
+            ));
+        }
+    }
+    let mut feed_urls = HashMap::new();
+    for site in &config.sites {
+        // Trailing slashes are ignored even if they aren't elsewhere, since two sites differing
+        // only in one are most likely the same feed.
+        let url = urls::normalize(&site.feed_url, true);
+        if let Some((first_name, first_url)) = feed_urls.insert(url, (&site.name, &site.feed_url)) {
+            findings.push(Finding::warn(
+                format!(
+                    "Sites {first_name} ({first_url}) and {} ({}) have the same feed URL",
+                    site.name, site.feed_url
+                ),
+                "Remove one of the sites, unless they filter the feed differently, or the server \
+                 serves different feeds with and without a trailing slash",
+            ));
+        }
+    }
+    findings
+}
+
+/// Settings which aren't ones we know of, which are otherwise ignored, since they're most likely
+/// typos.
+fn check_unknown_keys(env: &Environment) -> Vec<Finding> {
+    let Ok(merged) = &env.merged else {
+        return Vec::new();
+    };
+    // Disabled sites have to be kept, for the sites to line up with those in the files.
+    let Ok(config) = config_files::to_config(merged.clone(), true) else {
+        return Vec::new();
+    };
+    let Ok(known) = toml::Value::try_from(&config) else {
+        return Vec::new();
+    };
+    let mut unknown = Vec::new();
+    unknown_keys(
+        &toml::Value::Table(merged.clone()),
+        &known,
+        "",
+        &mut unknown,
+    );
+    if unknown.is_empty() {
+        return vec![Finding::pass("Config has no unknown settings")];
+    }
+    unknown
+        .into_iter()
+        .map(|key| {
+            Finding::warn(
+                format!("Config has unknown setting `{key}`, which is ignored"),
+                "Check the setting's spelling, and which table it belongs in",
+            )
+        })
+        .collect()
+}
+
+/// Add the keys in `given` which aren't in `known`, the config as understood, to `unknown`, as
+/// paths under `path`.
+fn unknown_keys(given: &toml::Value, known: &toml::Value, path: &str, unknown: &mut Vec<String>) {
+    match (given, known) {
+        (toml::Value::Table(given), toml::Value::Table(known)) => {
+            for (key, value) in given {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match known.get(key) {
+                    Some(known) => unknown_keys(value, known, &key_path, unknown),
+                    None => unknown.push(key_path),
+                }
+            }
+        }
+        (toml::Value::Array(given), toml::Value::Array(known)) if given.len() == known.len() => {
+            for (index, (given, known)) in given.iter().zip(known).enumerate() {
+                unknown_keys(given, known, &format!("{path}[{index}]"), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_feed_urls(env: &Environment) -> Vec<Finding> {
+    let Ok(config) = &env.config else {
+        return Vec::new();
+    };
+    let mut findings = config
+        .sites
+        .iter()
+        // What these request comes from running the command.
+        .filter(|site| site.pre_fetch_command.is_none())
+        .filter_map(|site| match reqwest::Url::parse(&site.feed_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => None,
+            Ok(url) => Some(Finding::fail(
+                format!(
+                    "Site {} has a `feed_url` with scheme {}, which can't be fetched",
+                    site.name,
+                    url.scheme()
+                ),
+                "Give the feed's http or https URL",
+            )),
+            Err(e) => Some(Finding::fail(
+                format!(
+                    "Site {} has an invalid `feed_url` {}: {e}",
+                    site.name, site.feed_url
+                ),
+                "Give the feed's full URL, like https://example.com/feed.xml",
+            )),
+        })
+        .collect::<Vec<_>>();
+    if findings.is_empty() {
+        findings.push(Finding::pass("Every feed URL is valid"));
+    }
+    findings
+}
+
+fn check_cache_dir_writable(env: &Environment) -> Vec<Finding> {
+    let probe = env.cache_dir.join(".jarss-doctor-probe");
+    let res = std::fs::create_dir_all(env.cache_dir)
+        .and_then(|()| std::fs::write(&probe, b""))
+        .and_then(|()| std::fs::remove_file(&probe));
+    vec![match res {
+        Ok(()) => Finding::pass(format!("Cache dir {} is writable", env.cache_dir.display())),
+        Err(e) => Finding::fail(
+            format!("Cache dir {} isn't writable: {e}", env.cache_dir.display()),
+            "Fix the directory's permissions, or point at a different one with --cache",
+        ),
+    }]
+}
+
+fn check_cache_files(env: &Environment) -> Vec<Finding> {
+    let Ok(config) = &env.config else {
+        return Vec::new();
+    };
+    let Ok(caches) = env.cache_manager(config) else {
+        return Vec::new();
+    };
+    let mut findings = Vec::new();
+    let mut decoded = 0;
+    for site in &config.sites {
+        let path = caches.cache_path(site);
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                findings.push(Finding::fail(
+                    format!(
+                        "Cache file {} for {} is unreadable: {e}",
+                        path.display(),
+                        site.name
+                    ),
+                    "Fix the file's permissions",
+                ));
+                continue;
+            }
+        };
+        match cache::SiteCache::decode(&contents) {
+            Ok(cache) => match cache.check_body(&path) {
+                Ok(()) => decoded += 1,
+                Err(e) => findings.push(Finding::warn(
+                    format!(
+                        "Cache file {} for {} has a body which can't be used: {e:#}",
+                        path.display(),
+                        site.name
+                    ),
+                    "Nothing to do, the feed will be fetched again in full on the next run",
+                )),
+            },
+            Err(e) => findings.push(Finding::fail(
+                format!(
+                    "Cache file {} for {} doesn't decode: {e:#}",
+                    path.display(),
+                    site.name
+                ),
+                "Delete the file, and it will be regenerated on the next run",
+            )),
+        }
+    }
+    findings.push(Finding::pass(format!(
+        "{decoded} cache files decode, along with their bodies"
+    )));
+    findings
+}
+
+fn check_cache_file_collisions(env: &Environment) -> Vec<Finding> {
+    let Ok(config) = &env.config else {
+        return Vec::new();
+    };
+    match env.cache_manager(config) {
+        Ok(_) => vec![Finding::pass("No sites share a cache file")],
+        Err(e) => vec![Finding::fail(
+            format!("{e:#}"),
+            "Rename one of the sites, or set `cache_key = \"url\"`",
+        )],
+    }
+}
+
+fn check_retry_after(env: &Environment) -> Vec<Finding> {
+    let Ok(config) = &env.config else {
+        return Vec::new();
+    };
+    let now = SystemTime::now();
+    let mut findings = env
+        .decodable_caches(config)
+        .into_iter()
+        .filter_map(|(site, cache)| {
+            let wait = cache.last_retry_after?.duration_since(now).ok()?;
+            (wait > MAX_SANE_RETRY_AFTER).then(|| {
+                Finding::warn(
+                    format!(
+                        "{site} won't be fetched for another {}",
+                        humantime::format_duration(Duration::from_secs(wait.as_secs()))
+                    ),
+                    "Delete the site's cache file if you don't want to wait that long",
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    if findings.is_empty() {
+        findings.push(Finding::pass("No absurd retry-after times"));
+    }
+    findings
+}
+
+fn check_clock(env: &Environment) -> Vec<Finding> {
+    let Ok(config) = &env.config else {
+        return Vec::new();
+    };
+    let mut findings = env
+        .decodable_caches(config)
+        .into_iter()
+        .filter_map(|(site, cache)| {
+            let server_date = cache.last_headers.as_ref()?.get("date")?;
+            let server_date =
+                SystemTime::from(chrono::DateTime::parse_from_rfc2822(server_date).ok()?);
+            let fetched = cache.last_fetch_time?;
+            let skew = server_date
+                .duration_since(fetched)
+                .or_else(|_| fetched.duration_since(server_date))
+                .ok()?;
+            (skew > MAX_CLOCK_SKEW).then(|| {
+                Finding::warn(
+                    format!(
+                        "Clock was {} off from {site}'s server",
+                        humantime::format_duration(Duration::from_secs(skew.as_secs()))
+                    ),
+                    "Check that your system clock is synchronized",
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    if findings.is_empty() {
+        findings.push(Finding::pass("Clock agrees with cached server dates"));
+    }
+    findings
+}
+
+fn check_template(env: &Environment) -> Vec<Finding> {
+    let (name, template) = match env.feed_template {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(template) => (path.display().to_string(), template),
+            Err(e) => {
+                return vec![Finding::fail(
+                    format!("Template {} is unreadable: {e}", path.display()),
+                    "Check the path passed to --feed-template",
+                )];
+            }
+        },
+        None => (
+            "The default template".to_owned(),
+            include_str!("../default-render.html.tera").to_owned(),
+        ),
+    };
+    let mut tera = tera::Tera::default();
+    vec![match tera.add_raw_template("output", &template) {
+        Ok(()) => Finding::pass(format!("{name} parses")),
+        Err(e) => Finding::fail(
+            format!("{name} doesn't parse: {:#}", anyhow::Error::new(e)),
+            "Fix the syntax error in the template",
+        ),
+    }]
+}
+
+fn check_fragment_template(env: &Environment) -> Vec<Finding> {
+    let Ok(Config {
+        fragment_output: Some(fragment),
+        ..
+    }) = &env.config
+    else {
+        return Vec::new();
+    };
+    let path = &fragment.template;
+    let template = match std::fs::read_to_string(path) {
+        Ok(template) => template,
+        Err(e) => {
+            return vec![Finding::fail(
+                format!("Fragment template {} is unreadable: {e}", path.display()),
+                "Check `fragment_output.template` in the config",
+            )];
+        }
+    };
+    let mut tera = tera::Tera::default();
+    let mut findings = vec![match tera.add_raw_template("fragment", &template) {
+        Ok(()) => Finding::pass(format!("Fragment template {} parses", path.display())),
+        Err(e) => Finding::fail(
+            format!(
+                "Fragment template {} doesn't parse: {:#}",
+                path.display(),
+                anyhow::Error::new(e)
+            ),
+            "Fix the syntax error in the template",
+        ),
+    }];
+    let dir = fragment
+        .path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if !dir.is_dir() {
+        findings.push(Finding::fail(
+            format!(
+                "Fragment output {} is in a directory which doesn't exist",
+                fragment.path.display()
+            ),
+            "Check `fragment_output.path` in the config, or create its directory",
+        ));
+    }
+    findings
+}
+
+fn check_output_path(env: &Environment) -> Vec<Finding> {
+    let Some(out_html) = env.out_html else {
+        return Vec::new();
+    };
+    let dir = out_html
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let probe = dir.join(".jarss-doctor-probe");
+    let res = std::fs::write(&probe, b"")
+        .and_then(|()| std::fs::remove_file(&probe))
+        .context("Can't write to its directory")
+        .and_then(|()| match std::fs::metadata(out_html) {
+            Ok(metadata) if metadata.permissions().readonly() => {
+                anyhow::bail!("The file is read-only")
+            }
+            _ => Ok(()),
+        });
+    vec![match res {
+        Ok(()) => Finding::pass(format!("Output {} is writable", out_html.display())),
+        Err(e) => Finding::fail(
+            format!("Output {} isn't writable: {e:#}", out_html.display()),
+            "Fix the permissions, or write the output somewhere else",
+        ),
+    }]
+}
+
+/// Request every site's feed, reporting those which can't be reached or don't respond
+/// successfully.
+///
+/// Nothing is read from or written to the caches, so this doesn't affect the next run.
+async fn check_feeds(env: &Environment<'_>) -> Vec<Finding> {
+    let Ok(config) = &env.config else {
+        return Vec::new();
+    };
+    let client = match config
+        .http
+        .client_builder()
+        .redirect(reqwest::redirect::Policy::default())
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Finding::fail(
+                format!("Couldn't make an HTTP client: {e}"),
+                "Check the system's TLS setup",
+            )];
+        }
+    };
+    let mut findings = futures::stream::iter(&config.sites)
+        .map(|site| async {
+            if site.pre_fetch_command.is_some() {
+                // What to request comes from running the command, which we don't do here.
+                return None;
+            }
+            match probe_feed(&client, site).await {
+                Ok(status) if status.is_success() => None,
+                Ok(status) => Some(Finding::warn(
+                    format!("Site {} responded with {status}", site.name),
+                    "Check the site's `feed_url`, and whether the feed has moved",
+                )),
+                Err(e) => Some(Finding::warn(
+                    format!("Site {} is unreachable: {e:#}", site.name),
+                    "Check the site's `feed_url`, and whether its host is up",
+                )),
+            }
+        })
+        .buffered(config.max_concurrent_fetches.max(1))
+        .filter_map(std::future::ready)
+        .collect::<Vec<_>>()
+        .await;
+    if findings.is_empty() {
+        findings.push(Finding::pass("Every feed responds successfully"));
+    }
+    findings
+}
+
+/// Request the given site's feed, giving the status it responded with.
+///
+/// A `HEAD` is sent to GET sites where possible, so the feeds needn't be downloaded.
+async fn probe_feed(client: &reqwest::Client, site: &SiteConfig) -> Result<reqwest::StatusCode> {
+    if site.method == post::Method::Get {
+        let req = auth::apply(site, client.head(site.feed_url.as_ref()))?;
+        let status = req.send().await.context("Error fetching feed")?.status();
+        if !matches!(
+            status,
+            reqwest::StatusCode::METHOD_NOT_ALLOWED | reqwest::StatusCode::NOT_IMPLEMENTED
+        ) {
+            return Ok(status);
+        }
+    }
+    let req = auth::apply(site, post::request(client, site, site.feed_url.as_ref())?)?;
+    Ok(req.send().await.context("Error fetching feed")?.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, test_util::test_dir};
+
+    /// Check `config` with the cache in `cache_dir` and the output at `out_html`.
+    fn environment<'a>(
+        config: &str,
+        cache_dir: &'a Path,
+        out_html: Option<&'a Path>,
+    ) -> Environment<'a> {
+        let merged = toml::from_str::<toml::Table>(config).unwrap();
+        Environment {
+            source: "the test".to_owned(),
+            config: config_files::to_config(merged.clone(), false),
+            merged: Ok(merged),
+            cache_dir,
+            feed_template: None,
+            out_html,
+        }
+    }
+
+    /// The outcome and message of each finding.
+    fn outcomes(findings: &[Finding]) -> Vec<(Outcome, &str)> {
+        findings
+            .iter()
+            .map(|finding| (finding.outcome, finding.message.as_str()))
+            .collect()
+    }
+
+    /// A config with a site for each of `feed_urls`, named after its position.
+    fn config(feed_urls: &[String]) -> String {
+        feed_urls
+            .iter()
+            .enumerate()
+            .map(|(index, feed_url)| {
+                format!("[[sites]]\nname = \"Site {index}\"\nfeed_url = \"{feed_url}\"\n")
+            })
+            .fold("min_fetch_interval = 0\n".to_owned(), |config, site| {
+                config + &site
+            })
+    }
+
+    #[tokio::test]
+    async fn cache_files_which_dont_decode_or_match_their_bodies_are_found() {
+        let dir = test_dir("doctor-caches");
+        let feeds = test_server::serve_http(|head| {
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                &format!("<rss><!-- {path} --></rss>"),
+            )
+        })
+        .await;
+        let config = config(&[0, 1, 2].map(|index| format!("http://{feeds}/{index}")));
+        let env = environment(&config, &dir, None);
+        let caches = env.cache_manager(env.config.as_ref().unwrap()).unwrap();
+        let fetched = test_server::fetch_all(env.config.as_ref().unwrap(), &caches).await;
+        assert!(fetched.is_empty(), "{fetched:?}");
+        let sites = &env.config.as_ref().unwrap().sites;
+        let [good, corrupt, mismatched] = [0, 1, 2].map(|index| caches.cache_path(&sites[index]));
+        drop(caches);
+        std::fs::write(&corrupt, b"not a cache file").unwrap();
+        // The bodies are next to the cache files.
+        let mismatched_body = mismatched.with_extension("body.lz4");
+        assert!(mismatched_body.exists());
+        std::fs::copy(good.with_extension("body.lz4"), &mismatched_body).unwrap();
+
+        let findings = check_cache_files(&env);
+        let corrupt_message = format!(
+            "Cache file {} for Site 1 doesn't decode: Failed to read cache file",
+            corrupt.display()
+        );
+        let mismatched_message = format!(
+            "Cache file {} for Site 2 has a body which can't be used",
+            mismatched.display()
+        );
+        match &outcomes(&findings)[..] {
+            [
+                (Outcome::Fail, corrupt),
+                (Outcome::Warn, mismatched),
+                (Outcome::Pass, "1 cache files decode, along with their bodies"),
+            ] if corrupt.starts_with(&corrupt_message)
+                && mismatched.starts_with(&mismatched_message) => {}
+            findings => panic!("{findings:?}"),
+        }
+
+        // Which the next run fixes.
+        std::fs::remove_file(&corrupt).unwrap();
+        let caches = env.cache_manager(env.config.as_ref().unwrap()).unwrap();
+        test_server::fetch_all(env.config.as_ref().unwrap(), &caches).await;
+        drop(caches);
+        assert_eq!(
+            outcomes(&check_cache_files(&env)),
+            [(
+                Outcome::Pass,
+                "3 cache files decode, along with their bodies"
+            )]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_which_cant_be_written_are_found() {
+        let dir = test_dir("doctor-output");
+        let unwritable_dir = dir.join("missing").join("out.html");
+        let read_only = dir.join("out.html");
+        std::fs::write(&read_only, "").unwrap();
+        let mut permissions = std::fs::metadata(&read_only).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&read_only, permissions).unwrap();
+        let writable = dir.join("new.html");
+        for (path, expected) in [
+            (
+                &unwritable_dir,
+                (
+                    Outcome::Fail,
+                    format!(
+                        "Output {} isn't writable: Can't write to its directory: No such file or \
+                         directory (os error 2)",
+                        unwritable_dir.display()
+                    ),
+                ),
+            ),
+            (
+                &read_only,
+                (
+                    Outcome::Fail,
+                    format!(
+                        "Output {} isn't writable: The file is read-only",
+                        read_only.display()
+                    ),
+                ),
+            ),
+            (
+                &writable,
+                (
+                    Outcome::Pass,
+                    format!("Output {} is writable", writable.display()),
+                ),
+            ),
+        ] {
+            let env = environment("", &dir, Some(path));
+            let findings = check_output_path(&env);
+            assert_eq!(
+                outcomes(&findings),
+                [(expected.0, expected.1.as_str())],
+                "{}",
+                path.display()
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A response with the given status line, extra header and empty feed.
+    fn response_with(status: &str, header: String) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\ncontent-type: application/rss+xml\r\n{header}\r\n\
+             content-length: 11\r\nconnection: close\r\n\r\n<rss></rss>"
+        )
+    }
+
+    #[tokio::test]
+    async fn absurd_retry_afters_are_found() {
+        let dir = test_dir("doctor-retry-after");
+        let far_future = (chrono::Utc::now() + chrono::Duration::days(3650)).to_rfc2822();
+        let mut feed_urls = Vec::new();
+        for retry_after in [
+            "31536000".to_owned(),
+            far_future,
+            // Overflows the clock, so is ignored.
+            u64::MAX.to_string(),
+            // Sane.
+            "120".to_owned(),
+        ] {
+            let feeds = test_server::serve_http(move |_| {
+                response_with(
+                    "503 Service Unavailable",
+                    format!("retry-after: {retry_after}"),
+                )
+            })
+            .await;
+            feed_urls.push(format!("http://{feeds}/"));
+        }
+        let config = config(&feed_urls);
+        let env = environment(&config, &dir, None);
+        let caches = env.cache_manager(env.config.as_ref().unwrap()).unwrap();
+        let fetched = test_server::fetch_all(env.config.as_ref().unwrap(), &caches).await;
+        assert_eq!(fetched.len(), 4, "{fetched:?}");
+        drop(caches);
+
+        let wait = |message: &str, site| {
+            let wait = message.strip_prefix(&format!("{site} won't be fetched for another "))?;
+            humantime::parse_duration(wait).ok()
+        };
+        let days = |days: u64| Duration::from_secs(days * 24 * 60 * 60);
+        match &outcomes(&check_retry_after(&env))[..] {
+            [(Outcome::Warn, seconds), (Outcome::Warn, date)]
+                if wait(seconds, "Site 0").is_some_and(|wait| wait > days(364))
+                    && wait(date, "Site 1").is_some_and(|wait| wait > days(3649)) => {}
+            findings => panic!("{findings:?}"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clock_skew_is_found_from_cached_server_dates() {
+        let dir = test_dir("doctor-clock");
+        let mut feed_urls = Vec::new();
+        for skew in [
+            chrono::Duration::hours(-2),
+            chrono::Duration::hours(3),
+            chrono::Duration::seconds(10),
+        ] {
+            let feeds = test_server::serve_http(move |_| {
+                let date = (chrono::Utc::now() + skew).to_rfc2822();
+                response_with("200 OK", format!("date: {date}"))
+            })
+            .await;
+            feed_urls.push(format!("http://{feeds}/"));
+        }
+        let config = config(&feed_urls);
+        let env = environment(&config, &dir, None);
+        let caches = env.cache_manager(env.config.as_ref().unwrap()).unwrap();
+        let fetched = test_server::fetch_all(env.config.as_ref().unwrap(), &caches).await;
+        assert!(fetched.is_empty(), "{fetched:?}");
+        drop(caches);
+
+        // The server's dates are only to the second.
+        let about = |message: &str, site, hours: u64| {
+            let skew = message
+                .strip_prefix("Clock was ")?
+                .strip_suffix(&format!(" off from {site}'s server"))?;
+            let skew = humantime::parse_duration(skew).ok()?.as_secs();
+            Some(skew.abs_diff(hours * 60 * 60) <= 2)
+        };
+        match &outcomes(&check_clock(&env))[..] {
+            [(Outcome::Warn, behind), (Outcome::Warn, ahead)]
+                if about(behind, "Site 0", 2) == Some(true)
+                    && about(ahead, "Site 1", 3) == Some(true) => {}
+            findings => panic!("{findings:?}"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn templates_which_dont_parse_are_found() {
+        let dir = test_dir("doctor-template");
+        let mut env = environment("", &dir, None);
+        assert_eq!(
+            outcomes(&check_template(&env)),
+            [(Outcome::Pass, "The default template parses")]
+        );
+
+        let broken = dir.join("broken.html.tera");
+        std::fs::write(&broken, "{% for article in articles %}{{ article.title }}").unwrap();
+        env.feed_template = Some(&broken);
+        match &outcomes(&check_template(&env))[..] {
+            [(Outcome::Fail, message)]
+                if message.starts_with(&format!("{} doesn't parse: ", broken.display())) => {}
+            findings => panic!("{findings:?}"),
+        }
+
+        let missing = dir.join("missing.html.tera");
+        env.feed_template = Some(&missing);
+        match &outcomes(&check_template(&env))[..] {
+            [(Outcome::Fail, message)]
+                if message
+                    .starts_with(&format!("Template {} is unreadable", missing.display())) => {}
+            findings => panic!("{findings:?}"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn feeds_which_dont_respond_successfully_are_found() {
+        let dir = test_dir("doctor-fetch");
+        let feeds = test_server::serve_http(|head| {
+            let empty = |status| test_server::response(status, "application/rss+xml", "");
+            match head.split(' ').take(2).collect::<Vec<_>>()[..] {
+                [_, "/gone"] => empty("404 Not Found"),
+                // Servers which don't take a HEAD get the site's usual request.
+                ["HEAD", "/no-head"] => empty("405 Method Not Allowed"),
+                _ => empty("200 OK"),
+            }
+        })
+        .await;
+        let closed = test_server::closed_port().await;
+        let config = config(&[
+            format!("http://{feeds}/ok"),
+            format!("http://{feeds}/gone"),
+            format!("http://{feeds}/no-head"),
+            format!("http://{closed}/feed"),
+        ]);
+        let env = environment(&config, &dir, None);
+        match &outcomes(&check_feeds(&env).await)[..] {
+            [
+                (Outcome::Warn, "Site Site 1 responded with 404 Not Found"),
+                (Outcome::Warn, unreachable),
+            ] if unreachable.starts_with("Site Site 3 is unreachable: ") => {}
+            findings => panic!("{findings:?}"),
+        }
+
+        // Which only fail the checks when they're strict.
+        let config_file = dir.join("jarss.toml");
+        std::fs::write(&config_file, &config).unwrap();
+        let config_files = ConfigFiles::new(vec![config_file]);
+        for strict in [false, true] {
+            let options = Options {
+                fetch: true,
+                strict,
+                candidate: None,
+                diff_against_current: false,
+                json: false,
+            };
+            let exit_code = doctor(&config_files, &dir, None, None, options)
+                .await
+                .unwrap();
+            let expected = if strict {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            };
+            assert_eq!(exit_code, expected, "strict: {strict}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unwritable_cache_dirs_are_found() {
+        let dir = test_dir("doctor-cache-dir");
+        let not_a_dir = dir.join("file");
+        std::fs::write(&not_a_dir, "").unwrap();
+        let env = environment("", &not_a_dir, None);
+        match &outcomes(&check_cache_dir_writable(&env))[..] {
+            [(Outcome::Fail, message)]
+                if message
+                    .starts_with(&format!("Cache dir {} isn't writable", not_a_dir.display())) => {}
+            findings => panic!("{findings:?}"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The findings of checking `candidate` as if it were given on stdin, with the config files
+    /// at `current` and the cache in `dir`, along with how its sites differ from theirs.
+    async fn check_candidate(
+        candidate: &str,
+        current: &Path,
+        dir: &Path,
+    ) -> (Vec<Finding>, Option<SiteChanges>) {
+        let options = Options {
+            fetch: false,
+            strict: false,
+            candidate: Some(candidate.to_owned()),
+            diff_against_current: true,
+            json: true,
+        };
+        let config_files = ConfigFiles::new(vec![current.to_owned()]);
+        run_checks(&config_files, &dir.join("cache"), None, None, &options).await
+    }
+
+    /// The config files of the current config, with sites `Site 0` and `Site 1`.
+    fn current_config(dir: &Path) -> std::path::PathBuf {
+        let current = dir.join("jarss.toml");
+        let feed_urls = ["https://a.example/feed", "https://b.example/feed"];
+        std::fs::write(&current, config(&feed_urls.map(String::from))).unwrap();
+        current
+    }
+
+    #[tokio::test]
+    async fn empty_candidates_are_unusable() {
+        let dir = test_dir("doctor-stdin-empty");
+        let current = current_config(&dir);
+        let (findings, changes) = check_candidate("", &current, &dir).await;
+        match &outcomes(&findings)[..] {
+            [
+                (Outcome::Fail, unusable),
+                (Outcome::Pass, "The default template parses"),
+                (Outcome::Warn, compare),
+            ] if unusable.starts_with(
+                "Config at stdin is unusable: Failed to parse config file: missing field `sites`",
+            ) && compare.starts_with("Couldn't compare against the current config") => {}
+            findings => panic!("{findings:?}"),
+        }
+        assert!(changes.is_none());
+        assert!(!dir.join("cache").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn candidates_with_invalid_urls_fail() {
+        let dir = test_dir("doctor-stdin-url");
+        let current = current_config(&dir);
+        let feed_urls = [
+            "not a url",
+            "https://b.example/feed",
+            "ftp://c.example/feed",
+        ];
+        let (findings, changes) =
+            check_candidate(&config(&feed_urls.map(String::from)), &current, &dir).await;
+        assert_eq!(
+            outcomes(&findings),
+            [
+                (Outcome::Pass, "Config at stdin parses, with 3 sites"),
+                (Outcome::Pass, "Config has no unknown settings"),
+                (
+                    Outcome::Fail,
+                    "Site Site 0 has an invalid `feed_url` not a url: relative URL without a base"
+                ),
+                (
+                    Outcome::Fail,
+                    "Site Site 2 has a `feed_url` with scheme ftp, which can't be fetched"
+                ),
+                (Outcome::Pass, "No sites share a cache file"),
+                (Outcome::Pass, "The default template parses"),
+            ]
+        );
+        let changes = changes.unwrap();
+        assert_eq!(
+            (changes.added, changes.removed, changes.changed),
+            (vec!["Site 2".into()], vec![], vec!["Site 0".into()])
+        );
+        // Checking a candidate doesn't look at the caches, let alone create them.
+        assert!(!dir.join("cache").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn candidates_with_duplicate_sites_fail() {
+        let dir = test_dir("doctor-stdin-duplicate");
+        let current = current_config(&dir);
+        let candidate = config(&["https://a.example/feed".to_owned()])
+            + "[[sites]]\nname = \"Site 0\"\nfeed_url = \"https://d.example/feed\"\n";
+        let (findings, _) = check_candidate(&candidate, &current, &dir).await;
+        match &outcomes(&findings)[..] {
+            [
+                (Outcome::Pass, "Config at stdin parses, with 2 sites"),
+                (
+                    Outcome::Fail,
+                    "Site name Site 0 is used for both https://a.example/feed and \
+                     https://d.example/feed",
+                ),
+                (Outcome::Pass, "Config has no unknown settings"),
+                (Outcome::Pass, "Every feed URL is valid"),
+                (Outcome::Fail, shared),
+                (Outcome::Pass, "The default template parses"),
+            ] if shared.starts_with("Sites Site 0 and Site 0 would share the cache file") => {}
+            findings => panic!("{findings:?}"),
+        }
+        assert!(!dir.join("cache").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
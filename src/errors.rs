@@ -0,0 +1,447 @@
+/// A stable identifier for the kind of an error, for automation to match on.
+///
+/// The messages of errors are for humans and may change between releases, but these codes won't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// A request timed out.
+    FetchTimeout,
+    /// A site's hostname couldn't be resolved.
+    FetchDns,
+    /// We couldn't connect to a site.
+    FetchConnect,
+    /// A site's certificate couldn't be verified, or there are no root certificates to verify
+    /// it with.
+    FetchTls,
+    /// Some other problem with making a request or reading its response.
+    FetchFailed,
+    /// A site responded with a 4xx status.
+    HttpClientError,
+    /// A site responded with 429 Too Many Requests.
+    #[serde(rename = "http_429")]
+    HttpRateLimited,
+    /// A site responded with a 5xx status.
+    HttpServerError,
+    /// A site responded with a status we don't know what to do with.
+    HttpUnexpectedStatus,
+    /// A feed was larger than `max_body_bytes`.
+    FetchTooLarge,
+    /// A site responded with an HTML page rather than a feed, with `strict_content_type`.
+    FetchNotAFeed,
+    /// A feed isn't valid XML, or isn't an RSS or Atom feed.
+    ParseInvalidXml,
+    /// A feed isn't valid JSON, or isn't a JSON Feed.
+    ParseInvalidJson,
+    /// A feed couldn't be parsed for some other reason.
+    ParseFailed,
+    /// A feed's entries are missing things we need.
+    ParseInvalidEntry,
+    /// A cache file couldn't be decoded.
+    CacheCorrupt,
+    /// A cache file couldn't be read or written.
+    CacheIo,
+    /// The template couldn't be parsed or rendered.
+    TemplateError,
+    /// An output file couldn't be written.
+    IoOutput,
+    /// An output was over its enforced size budget.
+    OutputTooLarge,
+    /// There were fewer articles than expected, with `refuse_sparse_output`.
+    TooFewArticles,
+    /// A pre- or post-fetch command failed.
+    HookFailed,
+    /// The config file couldn't be read or parsed.
+    ConfigInvalid,
+    /// Anything else.
+    Other,
+}
+impl ErrorCode {
+    /// Work out the code for the given error.
+    ///
+    /// A code given with [`Coded`] takes precedence, and otherwise the code comes from the
+    /// underlying error.
+    pub fn of(e: &anyhow::Error) -> Self {
+        if let Some(coded) = e.downcast_ref::<Coded>() {
+            return coded.code;
+        }
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<HttpStatusError>() {
+                return if e.0 == http::StatusCode::TOO_MANY_REQUESTS {
+                    Self::HttpRateLimited
+                } else if e.0.is_client_error() {
+                    Self::HttpClientError
+                } else if e.0.is_server_error() {
+                    Self::HttpServerError
+                } else {
+                    Self::HttpUnexpectedStatus
+                };
+            }
+            if cause.is::<super::dns::DnsError>() {
+                return Self::FetchDns;
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                return if e.is_timeout() {
+                    Self::FetchTimeout
+                } else if is_certificate_error(e) {
+                    Self::FetchTls
+                } else if e.is_connect() && is_dns_error(e) {
+                    Self::FetchDns
+                } else if e.is_connect() {
+                    Self::FetchConnect
+                } else {
+                    Self::FetchFailed
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<feed_rs::parser::ParseFeedError>() {
+                use feed_rs::parser::ParseFeedError;
+                return match e {
+                    ParseFeedError::ParseError(_) | ParseFeedError::XmlReader(_) => {
+                        Self::ParseInvalidXml
+                    }
+                    ParseFeedError::JsonSerde(_) | ParseFeedError::JsonUnsupportedVersion(_) => {
+                        Self::ParseInvalidJson
+                    }
+                    ParseFeedError::IoError(_) => Self::ParseFailed,
+                };
+            }
+            if cause.is::<tera::Error>() {
+                return Self::TemplateError;
+            }
+            if cause.is::<postcard::Error>() {
+                return Self::CacheCorrupt;
+            }
+        }
+        Self::Other
+    }
+}
+
+/// Whether the connection error came from failing to resolve the hostname.
+///
+/// reqwest doesn't expose this directly, so we have to go by the message of its cause.
+fn is_dns_error(e: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        if cause.to_string().contains("dns error") {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// Whether the request failed because the site's certificate couldn't be verified.
+///
+/// As with [`is_dns_error`], this goes by the messages of the causes, which are OpenSSL's or
+/// rustls's.
+fn is_certificate_error(e: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        if message.contains("certificate verify failed")
+            || message.contains("invalid peer certificate")
+        {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// A response with a status we treat as an error.
+#[derive(Debug)]
+pub struct HttpStatusError(pub http::StatusCode);
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_client_error() || self.0.is_server_error() {
+            write!(f, "Received error status code {}", self.0)
+        } else {
+            write!(f, "Received unexpected status code {}", self.0)
+        }
+    }
+}
+impl std::error::Error for HttpStatusError {}
+
+/// An error explicitly given a code, where the code can't be worked out from the error itself.
+///
+/// This is invisible in the error's message, which is that of the wrapped error.
+pub struct Coded {
+    code: ErrorCode,
+    error: anyhow::Error,
+}
+impl std::fmt::Debug for Coded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.error, f)
+    }
+}
+impl std::fmt::Display for Coded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.error, f)
+    }
+}
+impl std::error::Error for Coded {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.chain().nth(1)
+    }
+}
+
+/// Give the errors from some fallible operation a code.
+pub trait WithCode<T> {
+    fn code(self, code: ErrorCode) -> anyhow::Result<T>;
+}
+impl<T> WithCode<T> for anyhow::Result<T> {
+    fn code(self, code: ErrorCode) -> anyhow::Result<T> {
+        self.map_err(|error| anyhow::Error::new(Coded { code, error }))
+    }
+}
+
+/// An error as shown in machine-readable output.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ErrorInfo {
+    pub code: ErrorCode,
+    /// The full human-readable message, which may change between releases.
+    pub message: String,
+    /// The innermost cause of the error, as a short human-readable message.
+    pub cause: String,
+}
+impl From<&anyhow::Error> for ErrorInfo {
+    fn from(e: &anyhow::Error) -> Self {
+        Self {
+            code: ErrorCode::of(e),
+            message: format!("{e:#}"),
+            cause: e.root_cause().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, test_util::test_dir};
+    use clap::Parser as _;
+    use std::path::Path;
+
+    /// Define [`ALL_CODES`], checking that it has every code.
+    macro_rules! all_codes {
+        ($($code:ident),* $(,)?) => {
+            const ALL_CODES: &[ErrorCode] = &[$(ErrorCode::$code),*];
+            #[allow(dead_code)]
+            fn check_all_codes_are_listed(code: ErrorCode) {
+                match code {
+                    $(ErrorCode::$code)|* => {}
+                }
+            }
+        };
+    }
+    all_codes!(
+        FetchTimeout,
+        FetchDns,
+        FetchConnect,
+        FetchTls,
+        FetchFailed,
+        HttpClientError,
+        HttpRateLimited,
+        HttpServerError,
+        HttpUnexpectedStatus,
+        FetchTooLarge,
+        FetchNotAFeed,
+        ParseInvalidXml,
+        ParseInvalidJson,
+        ParseFailed,
+        ParseInvalidEntry,
+        CacheCorrupt,
+        CacheIo,
+        TemplateError,
+        IoOutput,
+        OutputTooLarge,
+        TooFewArticles,
+        HookFailed,
+        ConfigInvalid,
+        Other,
+    );
+
+    /// The stable name of `code`.
+    fn name(code: ErrorCode) -> String {
+        serde_json::to_value(code)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    /// A feed with one article, which is `len` bytes or a little more.
+    fn feed(len: usize) -> String {
+        format!(
+            "<rss version=\"2.0\"><channel><title>Feed</title><item><title>Article</title>\
+             <link>https://example.com/1</link><pubDate>Wed, 14 Oct 2026 12:00:00 GMT</pubDate>\
+             <description>{}</description></item></channel></rss>",
+            "x".repeat(len)
+        )
+    }
+
+    /// Serve a feed at `/feed`, and at the other paths, responses which fail in various ways.
+    async fn serve_feeds() -> std::net::SocketAddr {
+        test_server::serve_http(|head| {
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            let (status, content_type, body) = match path {
+                "/feed" => ("200 OK", "application/rss+xml", feed(0)),
+                "/404" => ("404 Not Found", "text/plain", String::new()),
+                "/429" => ("429 Too Many Requests", "text/plain", String::new()),
+                "/500" => ("500 Internal Server Error", "text/plain", String::new()),
+                "/204" => ("204 No Content", "text/plain", String::new()),
+                "/garbage" => return "this isn't HTTP\r\n\r\n".to_owned(),
+                "/large" => ("200 OK", "application/rss+xml", feed(2000)),
+                "/html" => ("200 OK", "text/html", "<html></html>".to_owned()),
+                "/xml" => ("200 OK", "application/rss+xml", "<rss><channel>".to_owned()),
+                "/json" => ("200 OK", "application/feed+json", "{".to_owned()),
+                "/no-link" => (
+                    "200 OK",
+                    "application/rss+xml",
+                    feed(0).replace("<link>https://example.com/1</link>", ""),
+                ),
+                _ => ("404 Not Found", "text/plain", String::new()),
+            };
+            test_server::response(status, content_type, &body)
+        })
+        .await
+    }
+
+    /// Run jarss with `args`, with the config and cache in `dir`, and return the codes of the
+    /// errors in its summary.
+    async fn summarized_codes(dir: &Path, args: &[&str]) -> Vec<String> {
+        let paths = ["jarss.toml", "cache", "summary.json"].map(|file| dir.join(file));
+        let paths = paths.each_ref().map(|path| path.to_str().unwrap());
+        let args = ["jarss", "--config", paths[0], "--cache", paths[1]]
+            .into_iter()
+            .chain(["--summary", paths[2]])
+            .chain(args.iter().copied());
+        let _ = crate::dispatch(crate::Args::parse_from(args).try_into().unwrap()).await;
+        let summary =
+            serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(paths[2]).unwrap())
+                .unwrap();
+        let mut codes = Vec::new();
+        let mut values = vec![&summary];
+        while let Some(value) = values.pop() {
+            match value {
+                serde_json::Value::Object(object) => {
+                    if let Some(serde_json::Value::String(code)) = object.get("code") {
+                        codes.push(code.clone());
+                    }
+                    values.extend(object.values());
+                }
+                serde_json::Value::Array(array) => values.extend(array),
+                _ => {}
+            }
+        }
+        codes
+    }
+
+    #[tokio::test]
+    async fn every_code_comes_from_some_failure() {
+        let feeds = serve_feeds().await;
+        let silent = test_server::serve_nothing().await;
+        let closed = test_server::closed_port().await;
+        let tls = test_server::serve_untrusted_tls().await;
+        let dir = test_dir("codes");
+        let out_html = dir.join("out.html");
+        let out_html = out_html.to_str().unwrap();
+        let unwritable_html = dir.join("missing").join("out.html");
+        let template = dir.join("bad.tera");
+        std::fs::write(&template, "{{ unclosed").unwrap();
+        let site = |name: &str, feed_url: String| {
+            format!("[[sites]]\nname = \"{name}\"\nfeed_url = \"{feed_url}\"\n")
+        };
+        let global = "min_fetch_interval = 0\ndns_timeout = \"1s\"\n";
+        let good_site = site("Good", format!("http://{feeds}/feed"));
+
+        // Each of these sites fails in its own way.
+        let failing_sites = [
+            ("Timeout", format!("http://{silent}/feed")),
+            ("DNS", "http://jarss-test.invalid/feed".to_owned()),
+            ("Connect", format!("http://{closed}/feed")),
+            ("TLS", format!("https://localhost:{}/feed", tls.port())),
+        ]
+        .into_iter()
+        .chain(
+            [
+                "404", "429", "500", "204", "garbage", "large", "html", "xml", "json", "no-link",
+            ]
+            .map(|path| (path, format!("http://{feeds}/{path}"))),
+        )
+        .map(|(name, feed_url)| site(name, feed_url))
+        .collect::<String>()
+            + &site("Hook", format!("http://{feeds}/feed"))
+            + "pre_fetch_command = [\"sh\", \"-c\", \"exit 1\"]\n";
+        // The first run has those sites, and the others each fail as a whole.
+        let runs = [
+            (
+                format!(
+                    "{global}strict_content_type = true\n{good_site}{failing_sites}\
+                     [http]\ntimeout_per_call_secs = 1\ntimeout_global_secs = 1\n\
+                     [limits]\nmax_body_bytes = 1000\n"
+                ),
+                vec![out_html],
+            ),
+            // Without a config file.
+            (String::new(), vec![out_html]),
+            (
+                format!("{global}{good_site}"),
+                vec!["--feed-template", template.to_str().unwrap(), out_html],
+            ),
+            (
+                format!("{global}{good_site}"),
+                vec![unwritable_html.to_str().unwrap()],
+            ),
+            (
+                format!(
+                    "{global}{good_site}[output_budget]\nmax_output_bytes = 10\nenforce = true\n"
+                ),
+                vec![out_html],
+            ),
+            (
+                format!(
+                    "{global}refuse_sparse_output = true\nmin_expected_articles = 2\n{good_site}"
+                ),
+                vec![out_html],
+            ),
+            (format!("{global}{good_site}"), vec![out_html]),
+        ];
+        let mut seen = Vec::new();
+        for (index, (config, args)) in runs.iter().enumerate() {
+            let dir = dir.join(index.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            if !config.is_empty() {
+                std::fs::write(dir.join("jarss.toml"), config).unwrap();
+            }
+            if index == runs.len() - 1 {
+                // A cache directory which can't be made.
+                std::fs::write(dir.join("cache"), "").unwrap();
+            }
+            seen.extend(summarized_codes(&dir, args).await);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Runs never fail these ways: cache files which can't be decoded are set aside, and feeds
+        // are read from memory.
+        let e = crate::cache::SiteCache::decode(b"not a cache file").unwrap_err();
+        seen.push(name(ErrorCode::of(&e)));
+        struct FailingReader;
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("the connection was reset"))
+            }
+        }
+        let e = anyhow::Error::new(feed_rs::parser::parse(FailingReader).unwrap_err());
+        seen.push(name(ErrorCode::of(&e)));
+        // Nor does anything fail in a way we don't know.
+        seen.push(name(ErrorCode::of(&anyhow::anyhow!(
+            "Something unforeseen"
+        ))));
+
+        for &code in ALL_CODES {
+            assert!(
+                seen.contains(&name(code)),
+                "{code:?} wasn't seen in {seen:?}"
+            );
+        }
+    }
+}
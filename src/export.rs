@@ -0,0 +1,221 @@
+use super::{Config, FeedEntryInfo, reading, timestamp};
+
+use anyhow::{Context, Result};
+use std::time::SystemTime;
+
+/// The path which means to write the JSON to stdout instead of a file.
+pub const STDOUT_PATH: &str = "-";
+
+/// Render the articles, in order, as JSON for other tools.
+///
+/// The articles have the same fields templates get, except for their times, which are given as
+/// `published_unix` and `published_rfc3339`, and likewise for `last_seen_in_feed`, as
+/// [`timestamp::insert`] gives them. They're wrapped in an object with when they were generated,
+/// as `generated_unix` and `generated_rfc3339`, and at `sites`, the name of each configured site
+/// along with how many of the articles are from it. Runs in safe mode also have `safe_mode` set,
+/// and with `site_stats` configured, they're given at `site_stats` as they are to templates.
+pub fn render_json(
+    config: &Config,
+    articles: &[FeedEntryInfo],
+    site_stats: &[reading::SiteStats],
+) -> Result<String> {
+    let mut counts = vec![0_usize; config.sites.len()];
+    for article in articles {
+        if let Some(count) = counts.get_mut(article.site_index) {
+            *count += 1;
+        }
+    }
+    let sites = config
+        .sites
+        .iter()
+        .zip(counts)
+        .map(|(site, articles)| serde_json::json!({ "name": site.name, "articles": articles }))
+        .collect::<Vec<_>>();
+
+    let mut output = serde_json::Map::new();
+    timestamp::insert(&mut output, "generated", Some(SystemTime::now()));
+    if config.safe_mode {
+        output.insert("safe_mode".to_owned(), true.into());
+    }
+    output.insert("sites".to_owned(), sites.into());
+    if config.site_stats.is_some() {
+        output.insert(
+            "site_stats".to_owned(),
+            serde_json::to_value(site_stats).context("Failed to encode the site statistics")?,
+        );
+    }
+    let articles = articles
+        .iter()
+        .map(article_json)
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to encode the articles")?;
+    output.insert("articles".to_owned(), articles.into());
+    serde_json::to_string_pretty(&output).context("Failed to encode the JSON output")
+}
+
+/// The fields of `article` in the JSON, with its times replaced by their `_unix` and `_rfc3339`
+/// forms.
+fn article_json(article: &FeedEntryInfo) -> Result<serde_json::Value> {
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(article)? else {
+        unreachable!("Articles are encoded as objects");
+    };
+    fields.remove("published");
+    fields.remove("last_seen_in_feed");
+    timestamp::insert(&mut fields, "published", Some(article.published.into()));
+    timestamp::insert(
+        &mut fields,
+        "last_seen_in_feed",
+        article.last_seen_in_feed.map(Into::into),
+    );
+    Ok(fields.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache, config_files, test_util, trace::ArticleTrace};
+    use serde_json::{Value, json};
+
+    /// The config for the sites `a`, with two articles, and `b`, with none, along with `extra`
+    /// settings.
+    fn config(extra: &str) -> Config {
+        config_files::to_config(
+            toml::from_str(&format!(
+                "min_fetch_interval = 0\n{extra}\n\
+                 [[sites]]\nname = \"a\"\nfeed_url = \"https://a.example/feed\"\n\
+                 [[sites]]\nname = \"b\"\nfeed_url = \"https://b.example/feed\"\n"
+            ))
+            .unwrap(),
+            false,
+        )
+        .unwrap()
+    }
+
+    /// The articles of `config`'s sites, read from caches in `dir`.
+    async fn articles(config: &Config, dir: &std::path::Path) -> Vec<FeedEntryInfo> {
+        let caches =
+            cache::CacheManager::new(dir.to_owned(), cache::CacheKey::Name, &config.sites).unwrap();
+        let guard = caches.cache_guard();
+        for (site, items) in config.sites.iter().zip([
+            "<item><title>Newer</title><link>https://a.example/2</link>\
+             <pubDate>Tue, 02 Jan 2024 00:00:00 GMT</pubDate></item>\
+             <item><title>Older</title><link>https://a.example/1</link>\
+             <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item>",
+            "",
+        ]) {
+            caches.get_mut(site, &guard).await.unwrap().last_body = Some(
+                format!(
+                    "<rss version=\"2.0\"><channel><title>{}</title>{items}</channel></rss>",
+                    site.name
+                )
+                .into(),
+            );
+        }
+        drop(guard);
+        crate::collect_articles(config, &caches, &ArticleTrace::new(None))
+            .await
+            .articles
+    }
+
+    #[tokio::test]
+    async fn articles_are_exported_in_order_with_per_site_counts() {
+        let dir = test_util::test_dir("export");
+        let config = config("");
+        let articles = articles(&config, &dir).await;
+        let before = chrono::Utc::now().timestamp();
+        let output: Value =
+            serde_json::from_str(&render_json(&config, &articles, &[]).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let generated = output["generated_unix"].as_i64().unwrap();
+        assert!((before..=before + 5).contains(&generated), "{output}");
+        let rfc3339 = output["generated_rfc3339"].as_str().unwrap();
+        assert_eq!(
+            chrono::DateTime::parse_from_rfc3339(rfc3339)
+                .unwrap()
+                .timestamp(),
+            generated
+        );
+        assert_eq!(
+            output["sites"],
+            json!([{"name": "a", "articles": 2}, {"name": "b", "articles": 0}])
+        );
+        let articles = output["articles"].as_array().unwrap();
+        let titles = articles
+            .iter()
+            .map(|article| (article["title"].as_str(), article["link"].as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [
+                (Some("Newer"), Some("https://a.example/2")),
+                (Some("Older"), Some("https://a.example/1"))
+            ]
+        );
+        assert!(articles.iter().all(|article| article["site"] == "a"));
+        // Times are given in both forms, like everywhere else, and not in chrono's own.
+        assert_eq!(articles[0]["published_unix"], 1_704_153_600);
+        assert_eq!(articles[0]["published_rfc3339"], "2024-01-02T00:00:00Z");
+        assert!(articles[0].get("published").is_none(), "{output}");
+        assert!(articles[0].get("last_seen_in_feed").is_none(), "{output}");
+        assert!(
+            articles[0].get("last_seen_in_feed_unix").is_some(),
+            "{output}"
+        );
+        assert!(
+            articles[0].get("last_seen_in_feed_rfc3339").is_some(),
+            "{output}"
+        );
+        // Only set when they apply.
+        assert!(output.get("safe_mode").is_none(), "{output}");
+        assert!(output.get("site_stats").is_none(), "{output}");
+    }
+
+    #[tokio::test]
+    async fn safe_mode_and_site_stats_are_exported_when_set() {
+        let dir = test_util::test_dir("export-stats");
+        let mut config = config("[site_stats]\nwindows = [7]");
+        config.safe_mode = true;
+        let articles = articles(&config, &dir).await;
+        let site_stats = [reading::SiteStats {
+            name: "a".into(),
+            windows: vec![reading::WindowStats {
+                days: 7,
+                articles: 2,
+                articles_with_text: 1,
+                words: 460,
+                minutes: 2,
+            }],
+        }];
+        let output: Value =
+            serde_json::from_str(&render_json(&config, &articles, &site_stats).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(output["safe_mode"], true);
+        assert_eq!(
+            output["site_stats"],
+            json!([{
+                "name": "a",
+                "windows": [{
+                    "days": 7,
+                    "articles": 2,
+                    "articles_with_text": 1,
+                    "words": 460,
+                    "minutes": 2,
+                }],
+            }])
+        );
+        assert_eq!(output["articles"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn nothing_to_export_is_still_valid() {
+        let output: Value =
+            serde_json::from_str(&render_json(&config(""), &[], &[]).unwrap()).unwrap();
+        assert_eq!(
+            output["sites"],
+            json!([{"name": "a", "articles": 0}, {"name": "b", "articles": 0}])
+        );
+        assert_eq!(output["articles"], json!([]));
+    }
+}
@@ -0,0 +1,121 @@
+use super::SiteConfig;
+
+use anyhow::{Context, Result};
+
+/// Which of a site's entries to show, from its `include_title_regex`, `exclude_title_regex`, and
+/// `exclude_categories`.
+#[derive(Clone, Debug, Default)]
+pub struct EntryFilter {
+    include_title: Option<regex::Regex>,
+    exclude_title: Option<regex::Regex>,
+    /// Lowercase, to match case-insensitively.
+    exclude_categories: Vec<String>,
+}
+impl EntryFilter {
+    /// Compile the given site's filters.
+    pub fn new(site: &SiteConfig) -> Result<Self> {
+        let compile = |pattern: &Option<Box<str>>, setting: &str| {
+            pattern
+                .as_deref()
+                .map(regex::Regex::new)
+                .transpose()
+                .with_context(|| format!("Invalid `{setting}` for site {}", site.name))
+        };
+        Ok(Self {
+            include_title: compile(&site.include_title_regex, "include_title_regex")?,
+            exclude_title: compile(&site.exclude_title_regex, "exclude_title_regex")?,
+            exclude_categories: site
+                .exclude_categories
+                .iter()
+                .map(|category| category.to_lowercase())
+                .collect(),
+        })
+    }
+
+    /// Whether the given entry passes the filters.
+    ///
+    /// Entries without titles only have to pass the category filter, since they're dropped later
+    /// anyway.
+    pub fn allows(&self, entry: &feed_rs::model::Entry) -> bool {
+        if let Some(title) = &entry.title {
+            if self
+                .include_title
+                .as_ref()
+                .is_some_and(|include| !include.is_match(&title.content))
+            {
+                return false;
+            }
+            if self
+                .exclude_title
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(&title.content))
+            {
+                return false;
+            }
+        }
+        !entry.categories.iter().any(|category| {
+            std::iter::once(&category.term)
+                .chain(&category.label)
+                .any(|name| self.exclude_categories.contains(&name.to_lowercase()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The filter for a site with the given settings.
+    fn filter(settings: &str) -> Result<EntryFilter> {
+        let site: SiteConfig = toml::from_str(&format!(
+            "name = \"Site\"\nfeed_url = \"https://example.com/feed\"\n{settings}"
+        ))
+        .unwrap();
+        EntryFilter::new(&site)
+    }
+
+    /// The titles of the entries in `items` which the filter allows.
+    fn allowed(filter: &EntryFilter, items: &str) -> Vec<String> {
+        let feed = feed_rs::parser::parse(
+            format!("<rss version=\"2.0\"><channel><title>Site</title>{items}</channel></rss>")
+                .as_bytes(),
+        )
+        .unwrap();
+        feed.entries
+            .iter()
+            .filter(|entry| filter.allows(entry))
+            .map(|entry| entry.title.as_ref().unwrap().content.clone())
+            .collect()
+    }
+
+    #[test]
+    fn titles_are_included_and_excluded_by_regex() {
+        let filter =
+            filter("include_title_regex = \"^Release\"\nexclude_title_regex = \"(?i)beta\"")
+                .unwrap();
+        let items = "<item><title>Release 1.0</title></item>\
+                     <item><title>Release 1.1 Beta</title></item>\
+                     <item><title>Roadmap</title></item>";
+        assert_eq!(allowed(&filter, items), ["Release 1.0"]);
+    }
+
+    #[test]
+    fn categories_are_excluded_regardless_of_case() {
+        let filter = filter("exclude_categories = [\"Sponsored\"]").unwrap();
+        let items = "<item><title>Ad</title><category>sponsored</category></item>\
+                     <item><title>Mixed</title><category>News</category>\
+                     <category>SPONSORED</category></item>\
+                     <item><title>News</title><category>News</category></item>\
+                     <item><title>Plain</title></item>";
+        assert_eq!(allowed(&filter, items), ["News", "Plain"]);
+    }
+
+    #[test]
+    fn invalid_patterns_name_the_site_and_setting() {
+        let error = filter("exclude_title_regex = \"(\"").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid `exclude_title_regex` for site Site"
+        );
+    }
+}
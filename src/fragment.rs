@@ -0,0 +1,165 @@
+use super::{
+    FeedEntryInfo,
+    errors::{ErrorCode, WithCode as _},
+    replace,
+    state::StatePaths,
+};
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Where and how to write a fragment of only the articles which are new since the last run.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct FragmentOutput {
+    /// The path to write the fragment to.
+    pub path: PathBuf,
+    /// The path of the [`tera`] template for the fragment.
+    ///
+    /// This takes the new articles at `articles`, like the main template, but should only produce
+    /// the HTML to include in a larger page.
+    pub template: PathBuf,
+}
+
+/// Render the fragment of articles which weren't on the page last time this was done.
+///
+/// `articles` are the articles on the main page. If we don't know what was on the page last time,
+/// they're all new. The fragment is written atomically, so a page including it never sees it
+/// half-written. `tera` must have the fragment's template, as `fragment`.
+pub fn write_fragment(
+    fragment: &FragmentOutput,
+    tera: &tera::Tera,
+    state: &StatePaths,
+    articles: &[FeedEntryInfo],
+) -> Result<()> {
+    let previous = read_previous_render(&state.rendered_articles());
+    let new_articles = articles
+        .iter()
+        .filter(|article| {
+            previous
+                .as_ref()
+                .is_none_or(|previous| !previous.contains(&article.link))
+        })
+        .collect::<Vec<_>>();
+    log::info!(
+        "Writing fragment of {} new articles to {}",
+        new_articles.len(),
+        fragment.path.display()
+    );
+    let mut tera_ctx = tera::Context::new();
+    tera_ctx.insert("articles", &new_articles);
+    let rendered = tera
+        .render("fragment", &tera_ctx)
+        .context("Error rendering fragment template")
+        .code(ErrorCode::TemplateError)?;
+    replace::write_atomically(&fragment.path, rendered.as_bytes())
+        .context("Failed to write fragment")
+        .code(ErrorCode::IoOutput)?;
+
+    let links = articles
+        .iter()
+        .map(|article| &article.link)
+        .collect::<Vec<_>>();
+    let encoded = serde_json::to_vec(&links).context("Failed to encode rendered articles")?;
+    std::fs::create_dir_all(state.dir()).context("Failed to create state directory")?;
+    replace::write_atomically(&state.rendered_articles(), &encoded)
+        .context("Failed to record rendered articles")
+        .code(ErrorCode::CacheIo)
+}
+
+/// Read the links of the articles on the page the last time a fragment was written, from `path`,
+/// if known.
+fn read_previous_render(path: &Path) -> Option<HashSet<Box<str>>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::warn!(
+                "Failed to read {}, treating every article as new: {e}",
+                path.display()
+            );
+            return None;
+        }
+    };
+    match serde_json::from_slice(&contents) {
+        Ok(links) => Some(links),
+        Err(e) => {
+            log::warn!(
+                "Failed to parse {}, treating every article as new: {e}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{strings::RenderConfig, templates::Templates, test_util::test_dir};
+
+    fn article(link: &str) -> FeedEntryInfo {
+        let published = chrono::DateTime::UNIX_EPOCH;
+        FeedEntryInfo {
+            site: "site".into(),
+            published,
+            publish_date: published.date_naive(),
+            id: link.into(),
+            site_link: None,
+            mentions: 1,
+            title: link.into(),
+            link: link.into(),
+            link_display: link.into(),
+            summary: None,
+            content: None,
+            summary_text: None,
+            suspect: false,
+            original_link: None,
+            also_on: Vec::new(),
+            is_new: false,
+            rank: 0,
+            raw: None,
+            site_index: 0,
+            feed_position: 0,
+            last_seen_in_feed: None,
+        }
+    }
+
+    #[test]
+    fn only_articles_missing_from_the_last_fragment_are_new() {
+        let dir = test_dir("fragment-new");
+        let template = dir.join("fragment.tera");
+        std::fs::write(
+            &template,
+            "{% for article in articles %}{{ article.link }};{% endfor %}",
+        )
+        .unwrap();
+        let fragment = FragmentOutput {
+            path: dir.join("fragment.html"),
+            template,
+        };
+        let state = StatePaths::new(dir.join("state"));
+        let mut templates = Templates::new(None).unwrap();
+        let tera = templates
+            .fragment(&fragment.template, &RenderConfig::default())
+            .unwrap();
+        let written = |links: &[&str]| {
+            let articles = links.iter().map(|link| article(link)).collect::<Vec<_>>();
+            write_fragment(&fragment, tera, &state, &articles).unwrap();
+            std::fs::read_to_string(&fragment.path).unwrap()
+        };
+
+        // Without a previous render, everything is new.
+        assert_eq!(written(&["a", "b"]), "a;b;");
+        assert_eq!(written(&["c", "a", "b"]), "c;");
+        // Only the last render counts, so articles which fell off the page are new again.
+        assert_eq!(written(&["c"]), "");
+        assert_eq!(written(&["a", "c"]), "a;");
+
+        std::fs::write(state.rendered_articles(), "garbage").unwrap();
+        assert_eq!(written(&["a"]), "a;");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
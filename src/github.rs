@@ -0,0 +1,395 @@
+use super::{Config, SiteConfig, replace, state::StatePaths};
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// The hosts whose rate limits we keep track of.
+const GITHUB_HOSTS: &[&str] = &["github.com", "api.github.com"];
+
+/// How many requests to leave unused before a rate limit resets.
+///
+/// Several fetches can be in flight at once, so stopping at exactly zero would still run into the
+/// limit.
+const RESERVED_REQUESTS: u64 = 5;
+
+/// What GitHub last told us about how many more requests we can make to a host.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+struct RateLimit {
+    remaining: u64,
+    reset: SystemTime,
+}
+
+/// Fetching for sites whose feeds are on GitHub.
+///
+/// GitHub rate-limits by IP, which is easy to run into with many release feeds, so every site on a
+/// GitHub host shares that host's budget, and we stop fetching from a host before using it up. If
+/// we have a token, release feeds are fetched through the REST API instead, which has much higher
+/// limits.
+pub struct GitHub {
+    token: Option<Box<str>>,
+    /// The latest rate limits for each host, which are saved for the next run.
+    limits: Mutex<HashMap<Box<str>, RateLimit>>,
+    state_path: PathBuf,
+}
+impl GitHub {
+    /// Load what we know about GitHub's rate limits.
+    ///
+    /// The token comes from `GITHUB_TOKEN` if set, or else from the config's `github_token_file`.
+    pub fn new(config: &Config, state: &StatePaths) -> Result<Self> {
+        let token = match (std::env::var("GITHUB_TOKEN"), &config.github_token_file) {
+            (Ok(token), _) => Some(token.into_boxed_str()),
+            (Err(_), Some(path)) => Some(
+                std::fs::read_to_string(path)
+                    .with_context(|| {
+                        format!("Failed to read GitHub token from {}", path.display())
+                    })?
+                    .trim()
+                    .into(),
+            ),
+            (Err(_), None) => None,
+        }
+        .filter(|token| !token.is_empty());
+        let state_path = state.github_rate_limits();
+        let limits = match std::fs::read(&state_path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                log::warn!("Failed to parse {}, ignoring it: {e}", state_path.display());
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                log::warn!("Failed to read {}, ignoring it: {e}", state_path.display());
+                HashMap::new()
+            }
+        };
+        Ok(Self {
+            token,
+            limits: Mutex::new(limits),
+            state_path,
+        })
+    }
+
+    /// Save the latest rate limits for the next run.
+    pub fn save(&self) -> Result<()> {
+        let now = SystemTime::now();
+        let limits = self
+            .limits
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, limit)| limit.reset > now)
+            .map(|(host, limit)| (host.clone(), *limit))
+            .collect::<HashMap<_, _>>();
+        if limits.is_empty() {
+            return match std::fs::remove_file(&self.state_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(anyhow::Error::new(e).context("Failed to remove stale GitHub rate limits"))
+                }
+                _ => Ok(()),
+            };
+        }
+        let encoded = serde_json::to_vec(&limits).context("Failed to encode GitHub rate limits")?;
+        std::fs::create_dir_all(self.state_path.parent().unwrap_or(Path::new("")))
+            .context("Failed to create state directory")?;
+        replace::write_atomically(&self.state_path, &encoded)
+            .context("Failed to save GitHub rate limits")
+    }
+
+    /// The request to fetch the given site's releases through the API with, if the site's feed is
+    /// a GitHub release feed and we have a token.
+    pub fn api_request(
+        &self,
+        agent: &reqwest::Client,
+        site: &SiteConfig,
+    ) -> Option<reqwest::RequestBuilder> {
+        let token = self.token.as_ref()?;
+        let (owner, repo) = releases_repo(&site.feed_url)?;
+        Some(
+            agent
+                .get(format!(
+                    "https://api.github.com/repos/{owner}/{repo}/releases"
+                ))
+                .bearer_auth(token)
+                .header(http::header::ACCEPT, "application/vnd.github+json")
+                .header("x-github-api-version", "2022-11-28"),
+        )
+    }
+
+    /// If fetching the given site now would use up what's left of its host's rate limit, when the
+    /// limit resets.
+    pub fn blocked_until(&self, site: &SiteConfig, now: SystemTime) -> Option<SystemTime> {
+        let host = if self.token.is_some() && releases_repo(&site.feed_url).is_some() {
+            "api.github.com".into()
+        } else {
+            github_host(&reqwest::Url::parse(&site.feed_url).ok()?)?
+        };
+        let limit = *self.limits.lock().unwrap().get(host.as_str())?;
+        (limit.remaining <= RESERVED_REQUESTS && limit.reset > now).then_some(limit.reset)
+    }
+
+    /// Record the rate limit given in a response from `url`, if it's from GitHub.
+    pub fn record_rate_limit(&self, url: &reqwest::Url, headers: &http::HeaderMap) {
+        let Some(host) = github_host(url) else {
+            return;
+        };
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+        let (Some(remaining), Some(reset)) =
+            (header("x-ratelimit-remaining"), header("x-ratelimit-reset"))
+        else {
+            return;
+        };
+        let Some(reset) = SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(reset)) else {
+            log::debug!("Ignoring the rate limit of {host}, which resets too far off");
+            return;
+        };
+        log::debug!("{remaining} requests left to {host} until {reset:?}");
+        self.limits
+            .lock()
+            .unwrap()
+            .insert(host.into_boxed_str(), RateLimit { remaining, reset });
+    }
+}
+
+/// The GitHub host `url` is on, if any.
+fn github_host(url: &reqwest::Url) -> Option<String> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    GITHUB_HOSTS.contains(&host).then(|| host.to_owned())
+}
+
+/// The owner and repository, if the given feed is a GitHub repository's release feed.
+fn releases_repo(feed_url: &str) -> Option<(String, String)> {
+    let url = reqwest::Url::parse(feed_url).ok()?;
+    if github_host(&url)? != "github.com" {
+        return None;
+    }
+    match url.path_segments()?.collect::<Vec<_>>()[..] {
+        [owner, repo, "releases.atom"] if !owner.is_empty() && !repo.is_empty() => {
+            Some((owner.to_owned(), repo.to_owned()))
+        }
+        _ => None,
+    }
+}
+
+/// A release, as returned by the API.
+#[derive(serde::Deserialize)]
+struct Release {
+    tag_name: String,
+    name: Option<String>,
+    html_url: String,
+    body: Option<String>,
+    published_at: Option<String>,
+    created_at: Option<String>,
+    #[serde(default)]
+    draft: bool,
+}
+
+/// Turn the API's list of releases into a JSON Feed, so it goes through the same pipeline as any
+/// other feed.
+pub fn releases_feed(site: &SiteConfig, body: &str) -> Result<String> {
+    let (owner, repo) =
+        releases_repo(&site.feed_url).context("Site isn't a GitHub release feed")?;
+    let releases = serde_json::from_str::<Vec<Release>>(body)
+        .context("Failed to parse releases from the GitHub API")?;
+    let items = releases
+        .into_iter()
+        .filter(|release| !release.draft)
+        .map(|release| {
+            let title = match release.name.filter(|name| !name.trim().is_empty()) {
+                Some(name) if name != release.tag_name => format!("{name} ({})", release.tag_name),
+                _ => release.tag_name,
+            };
+            serde_json::json!({
+                "id": release.html_url,
+                "url": release.html_url,
+                "title": title,
+                "date_published": release.published_at.or(release.created_at),
+                "summary": release.body,
+            })
+        })
+        .collect::<Vec<_>>();
+    let feed = serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": format!("Release notes from {repo}"),
+        "home_page_url": format!("https://github.com/{owner}/{repo}/releases"),
+        "feed_url": site.feed_url,
+        "items": items,
+    });
+    Ok(feed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A site reading `feed_url`.
+    fn site(feed_url: &str) -> SiteConfig {
+        toml::from_str(&format!("name = \"Widget\"\nfeed_url = \"{feed_url}\"")).unwrap()
+    }
+
+    /// A [`GitHub`] with the given token and no rate limits, which doesn't touch the disk.
+    fn github(token: Option<&str>) -> GitHub {
+        GitHub {
+            token: token.map(Into::into),
+            limits: Mutex::new(HashMap::new()),
+            state_path: PathBuf::new(),
+        }
+    }
+
+    #[test]
+    fn release_feeds_are_recognized() {
+        for (feed_url, expected) in [
+            (
+                "https://github.com/example/widget/releases.atom",
+                Some(("example", "widget")),
+            ),
+            (
+                "https://www.GitHub.com/example/widget/releases.atom",
+                Some(("example", "widget")),
+            ),
+            ("https://github.com/example/widget/tags.atom", None),
+            ("https://github.com/example/widget/commits.atom", None),
+            ("https://github.com/example/releases.atom", None),
+            ("https://github.com/example//releases.atom", None),
+            (
+                "https://github.com/example/widget/releases.atom/extra",
+                None,
+            ),
+            ("https://api.github.com/example/widget/releases.atom", None),
+            ("https://gitlab.com/example/widget/releases.atom", None),
+            ("not a url", None),
+        ] {
+            assert_eq!(
+                releases_repo(feed_url),
+                expected.map(|(owner, repo)| (owner.to_owned(), repo.to_owned())),
+                "{feed_url}"
+            );
+        }
+    }
+
+    #[test]
+    fn release_feeds_are_fetched_through_the_api_with_a_token() {
+        let agent = reqwest::Client::new();
+        let releases = site("https://github.com/example/widget/releases.atom");
+        let request = github(Some("secret"))
+            .api_request(&agent, &releases)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://api.github.com/repos/example/widget/releases"
+        );
+        let header = |name| request.headers()[name].to_str().unwrap();
+        assert_eq!(header("authorization"), "Bearer secret");
+        assert_eq!(header("accept"), "application/vnd.github+json");
+        assert_eq!(header("x-github-api-version"), "2022-11-28");
+
+        // Without a token, or for other feeds, the feed itself is fetched.
+        assert!(github(None).api_request(&agent, &releases).is_none());
+        let commits = site("https://github.com/example/widget/commits.atom");
+        assert!(
+            github(Some("secret"))
+                .api_request(&agent, &commits)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rate_limits_hold_back_requests_until_they_reset() {
+        let github = github(None);
+        let url = reqwest::Url::parse("https://github.com/example/widget/releases.atom").unwrap();
+        let releases = site(url.as_str());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let record = |remaining: &str, reset: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert("x-ratelimit-remaining", remaining.parse().unwrap());
+            headers.insert("x-ratelimit-reset", reset.parse().unwrap());
+            github.record_rate_limit(&url, &headers);
+        };
+        record("100", "1700000600");
+        assert_eq!(github.blocked_until(&releases, now), None);
+        record("0", "1700000600");
+        let reset = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_600);
+        assert_eq!(github.blocked_until(&releases, now), Some(reset));
+        // A reset past what the clock can hold is ignored, leaving the last limit.
+        record("0", &u64::MAX.to_string());
+        assert_eq!(github.blocked_until(&releases, now), Some(reset));
+        // Other hosts aren't limited.
+        let other = site("https://example.com/feed.xml");
+        assert_eq!(github.blocked_until(&other, now), None);
+    }
+
+    #[test]
+    fn releases_are_turned_into_a_feed() {
+        let site = site("https://github.com/example/widget/releases.atom");
+        let body = releases_feed(&site, include_str!("../testdata/github-releases.json")).unwrap();
+        let feed = feed_rs::parser::parse(body.as_bytes()).unwrap();
+        assert_eq!(feed.feed_type, feed_rs::model::FeedType::JSON);
+        assert_eq!(feed.title.unwrap().content, "Release notes from widget");
+        // Drafts are left out, names are only shown alongside tags which differ from them, and
+        // releases which were never published are dated by when they were created.
+        let entries = feed
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.title.as_ref().unwrap().content.as_str(),
+                    entry.links[0].href.as_str(),
+                    entry.published.unwrap().to_rfc3339(),
+                    entry
+                        .summary
+                        .as_ref()
+                        .map(|summary| summary.content.as_str()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (
+                    "Spring cleaning (v1.2.0)",
+                    "https://github.com/example/widget/releases/tag/v1.2.0",
+                    "2024-03-02T10:00:00+00:00".to_owned(),
+                    Some("Fixes `<script>` handling & more."),
+                ),
+                (
+                    "v1.1.0",
+                    "https://github.com/example/widget/releases/tag/v1.1.0",
+                    "2024-02-01T08:00:00+00:00".to_owned(),
+                    None,
+                ),
+                (
+                    "v1.0.0",
+                    "https://github.com/example/widget/releases/tag/v1.0.0",
+                    "2024-01-01T00:00:00+00:00".to_owned(),
+                    Some("First release."),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_responses_are_errors() {
+        let releases = site("https://github.com/example/widget/releases.atom");
+        for body in [
+            "",
+            "{\"message\": \"Bad credentials\"}",
+            "[{\"name\": \"v1\"}]",
+        ] {
+            let e = releases_feed(&releases, body).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "Failed to parse releases from the GitHub API",
+                "{body:?}"
+            );
+        }
+        let other = site("https://example.com/feed.xml");
+        let e = releases_feed(&other, "[]").unwrap_err();
+        assert_eq!(e.to_string(), "Site isn't a GitHub release feed");
+    }
+}
@@ -0,0 +1,112 @@
+use super::FeedEntryInfo;
+
+/// How to group the articles given to templates as `grouped_articles`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    /// Group articles published on the same day, newest day first.
+    Date,
+    /// Group articles from the same site, the site with the newest article first.
+    Site,
+}
+
+/// Some of the articles which share a key.
+#[derive(Debug, serde::Serialize)]
+pub struct ArticleGroup<'a> {
+    /// The `publish_date` or site of the articles.
+    pub key: String,
+    /// The articles, newest first.
+    pub articles: Vec<&'a FeedEntryInfo>,
+}
+
+/// Split the articles, which must be sorted newest first, into groups.
+///
+/// Groups are ordered by their newest article, so grouping by date puts the newest day first.
+pub fn group(articles: &[FeedEntryInfo], by: GroupBy) -> Vec<ArticleGroup<'_>> {
+    let mut groups = Vec::<ArticleGroup>::new();
+    for article in articles {
+        let key = match by {
+            GroupBy::Date => article.publish_date.to_string(),
+            GroupBy::Site => article.site.to_string(),
+        };
+        match groups.iter_mut().find(|group| group.key == key) {
+            Some(group) => group.articles.push(article),
+            None => groups.push(ArticleGroup {
+                key,
+                articles: vec![article],
+            }),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An article from `site`, published at `hour` on the given day of January 2024.
+    fn article(site: &str, day: u32, hour: u32) -> FeedEntryInfo {
+        let published = chrono::NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc();
+        let link = format!("https://{site}.example/{day}/{hour}");
+        FeedEntryInfo {
+            site: site.into(),
+            published,
+            publish_date: published.date_naive(),
+            id: link.clone().into(),
+            site_link: None,
+            mentions: 1,
+            title: format!("{day} {hour}").into(),
+            link: link.clone().into(),
+            link_display: link.into(),
+            summary: None,
+            content: None,
+            summary_text: None,
+            suspect: false,
+            original_link: None,
+            also_on: Vec::new(),
+            is_new: false,
+            rank: 0,
+            raw: None,
+            site_index: 0,
+            feed_position: 0,
+            last_seen_in_feed: None,
+        }
+    }
+
+    /// The keys of `groups`, with the titles of the articles in each.
+    fn keys<'a>(groups: &'a [ArticleGroup]) -> Vec<(&'a str, Vec<&'a str>)> {
+        groups
+            .iter()
+            .map(|group| {
+                let titles = group.articles.iter().map(|a| &*a.title).collect();
+                (&*group.key, titles)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_are_ordered_by_their_newest_article() {
+        let articles = [
+            article("b", 2, 12),
+            article("a", 2, 6),
+            article("b", 1, 18),
+            article("a", 1, 0),
+        ];
+        assert_eq!(
+            keys(&group(&articles, GroupBy::Date)),
+            [
+                ("2024-01-02", vec!["2 12", "2 6"]),
+                ("2024-01-01", vec!["1 18", "1 0"]),
+            ]
+        );
+        assert_eq!(
+            keys(&group(&articles, GroupBy::Site)),
+            [("b", vec!["2 12", "1 18"]), ("a", vec!["2 6", "1 0"])]
+        );
+        assert!(group(&[], GroupBy::Site).is_empty());
+    }
+}
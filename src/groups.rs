@@ -0,0 +1,200 @@
+use super::{Config, FeedEntryInfo, trace::ArticleTrace};
+
+use std::{collections::HashMap, time::Duration};
+
+/// Limits on the articles shown from all the sites with a given tag.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct GroupLimits {
+    /// The most articles to show from the group's sites combined.
+    pub max_entries: Option<usize>,
+    /// How old an article from the group can be and still be shown.
+    #[serde(default, with = "optional_human_duration")]
+    pub max_age: Option<Duration>,
+}
+
+/// Drop the articles which the `[groups]` limits exclude, and then apply the global cap.
+///
+/// `articles` must be sorted newest first, so the newest articles are the ones kept. An article
+/// whose site has several tags must be within the limits of every one of those groups, and only
+/// articles which are kept count towards a group's `max_entries`.
+pub fn apply_limits(
+    config: &Config,
+    articles: Vec<FeedEntryInfo>,
+    now: chrono::DateTime<chrono::Utc>,
+    trace: &ArticleTrace,
+) -> Vec<FeedEntryInfo> {
+    let mut counts = HashMap::<&str, usize>::new();
+    let mut kept = Vec::with_capacity(articles.len());
+    for article in articles {
+        let groups = config
+            .sites
+            .get(article.site_index)
+            .map_or(&[][..], |site| &site.tags[..])
+            .iter()
+            .filter_map(|tag| Some((tag.as_ref(), config.groups.get(tag)?)))
+            .collect::<Vec<_>>();
+        let excluded_by = groups.iter().find(|(tag, limits)| {
+            let too_many = limits
+                .max_entries
+                .is_some_and(|max| counts.get(tag).copied().unwrap_or(0) >= max);
+            let too_old = limits.max_age.is_some_and(|max_age| {
+                chrono::Duration::from_std(max_age)
+                    .is_ok_and(|max_age| article.published < now - max_age)
+            });
+            too_many || too_old
+        });
+        if let Some((tag, _)) = excluded_by {
+            log::debug!(
+                "Article {} from {} excluded by the limits of group {tag}",
+                article.link,
+                article.site
+            );
+            if trace.matches(&article) {
+                trace.event(format_args!("excluded by the limits of group {tag}"));
+            }
+            continue;
+        }
+        for (tag, _) in groups {
+            *counts.entry(tag).or_default() += 1;
+        }
+        kept.push(article);
+    }
+    let max_total = config.max_total_entries.unwrap_or(usize::MAX);
+    if kept
+        .iter()
+        .skip(max_total)
+        .any(|article| trace.matches(article))
+    {
+        trace.event(format_args!("excluded by max_total_entries"));
+    }
+    kept.truncate(max_total);
+    kept
+}
+
+/// Like [`crate::human_duration`], but for an optional duration.
+mod optional_human_duration {
+    use serde::Deserialize as _;
+    use std::time::Duration;
+
+    pub fn serialize<S: serde::Serializer>(
+        duration: &Option<Duration>,
+        ser: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => ser.collect_str(&humantime::format_duration(*duration)),
+            None => ser.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        de: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<std::borrow::Cow<str>>::deserialize(de)?
+            .map(|raw| humantime::parse_duration(&raw).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An article from the given site, published on the given day of January 2024.
+    fn article(config: &Config, site_index: usize, day: u32) -> FeedEntryInfo {
+        let site = &config.sites[site_index].name;
+        let feed = format!(
+            "<rss version=\"2.0\"><channel><title>{site}</title><item><title>{day}</title>\
+             <link>https://{site}.example/{day}</link>\
+             <pubDate>{day:02} Jan 2024 00:00:00 GMT</pubDate></item></channel></rss>"
+        );
+        let feed = feed_rs::parser::parse(feed.as_bytes()).unwrap();
+        FeedEntryInfo::new(
+            site_index,
+            site,
+            &feed.entries[0],
+            &HashMap::new(),
+            None,
+            false,
+            &config.limits,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn articles_must_be_within_the_limits_of_every_group_they_overlap() {
+        let config: Config = toml::from_str(
+            r#"
+                min_fetch_interval = 0
+                max_total_entries = 7
+                [groups.a]
+                max_entries = 3
+                [groups.b]
+                max_entries = 2
+                [groups.c]
+                max_entries = 5
+                max_age = "10days"
+                [[sites]]
+                name = "ab"
+                feed_url = "https://ab.example/feed"
+                tags = ["a", "b"]
+                [[sites]]
+                name = "bc"
+                feed_url = "https://bc.example/feed"
+                tags = ["b", "c"]
+                [[sites]]
+                name = "ca"
+                feed_url = "https://ca.example/feed"
+                tags = ["c", "a", "untracked"]
+                [[sites]]
+                name = "c"
+                feed_url = "https://c.example/feed"
+                tags = ["c"]
+                [[sites]]
+                name = "none"
+                feed_url = "https://none.example/feed"
+                "#,
+        )
+        .unwrap();
+        let [ab, bc, ca, c, none] = [0, 1, 2, 3, 4];
+        let articles = [
+            (ab, 31),
+            (bc, 30),
+            (ca, 29),
+            // Group b is full.
+            (ab, 28),
+            (ca, 27),
+            (bc, 26),
+            // And now a.
+            (ca, 25),
+            (none, 24),
+            (c, 23),
+            // Group c has room, but this is too old for it.
+            (c, 21),
+            (bc, 10),
+            (none, 9),
+            // And this only for being past max_total_entries.
+            (none, 8),
+        ]
+        .map(|(site_index, day)| article(&config, site_index, day));
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 2, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        let kept = apply_limits(&config, articles.into(), now, &ArticleTrace::new(None));
+        assert_eq!(
+            kept.iter()
+                .map(|article| &*article.link)
+                .collect::<Vec<_>>(),
+            [
+                "https://ab.example/31",
+                "https://bc.example/30",
+                "https://ca.example/29",
+                "https://ca.example/27",
+                "https://none.example/24",
+                "https://c.example/23",
+                "https://none.example/9",
+            ]
+        );
+    }
+}
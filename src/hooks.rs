@@ -0,0 +1,286 @@
+use super::errors::ErrorInfo;
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, process::Stdio, time::Duration};
+use tokio::io::AsyncWriteExt as _;
+
+/// What a `pre_fetch_command` told us to request.
+pub struct PreFetch {
+    /// The URL to fetch, in place of the configured feed URL.
+    pub url: reqwest::Url,
+    /// Extra headers to send with the request.
+    ///
+    /// These are all marked sensitive, so they're redacted from debug output.
+    pub headers: http::HeaderMap,
+}
+
+/// The JSON form of a `pre_fetch_command`'s output, if it doesn't just print a URL.
+#[derive(serde::Deserialize)]
+struct RawPreFetch {
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+/// What happened when fetching a site, as given to its `post_fetch_command`.
+#[derive(serde::Serialize)]
+pub struct FetchOutcome<'a> {
+    /// The name of the site.
+    pub site: &'a str,
+    /// The configured URL of the site's feed.
+    pub feed_url: &'a str,
+    /// Whether the fetch succeeded.
+    pub success: bool,
+    /// The error, if the fetch failed.
+    pub error: Option<ErrorInfo>,
+}
+
+/// Run a `pre_fetch_command`, and parse what it printed.
+///
+/// The command should print either a URL, or a JSON object with a `url` string and optionally a
+/// `headers` object mapping header names to values.
+pub async fn pre_fetch(command: &[String], timeout: Duration) -> Result<PreFetch> {
+    let output = run(command, None, timeout).await?;
+    let output = std::str::from_utf8(&output)
+        .context("Pre-fetch command printed invalid UTF-8")?
+        .trim();
+    let raw = if output.starts_with('{') {
+        serde_json::from_str(output).context("Pre-fetch command printed invalid JSON")?
+    } else {
+        RawPreFetch {
+            url: output.to_owned(),
+            headers: HashMap::new(),
+        }
+    };
+    let url = reqwest::Url::parse(&raw.url).context("Pre-fetch command printed an invalid URL")?;
+    let headers = raw
+        .headers
+        .into_iter()
+        .map(|(name, value)| {
+            let name = http::HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Pre-fetch command gave invalid header name {name:?}"))?;
+            let mut value = http::HeaderValue::from_str(&value).with_context(|| {
+                format!("Pre-fetch command gave an invalid value for header {name}")
+            })?;
+            value.set_sensitive(true);
+            Ok((name, value))
+        })
+        .collect::<Result<_>>()?;
+    Ok(PreFetch { url, headers })
+}
+
+/// Run a `post_fetch_command`, giving it the outcome of the fetch as JSON on stdin.
+pub async fn post_fetch(
+    command: &[String],
+    timeout: Duration,
+    outcome: &FetchOutcome<'_>,
+) -> Result<()> {
+    let input = serde_json::to_vec(outcome).context("Failed to encode fetch outcome")?;
+    run(command, Some(input), timeout).await?;
+    Ok(())
+}
+
+/// Run a notification channel's `command`, giving it the notification as JSON on stdin.
+pub async fn notify(command: &[String], timeout: Duration, notification: Vec<u8>) -> Result<()> {
+    run(command, Some(notification), timeout).await?;
+    Ok(())
+}
+
+/// Run the given command, returning what it printed to stdout.
+///
+/// The command is killed if it runs for longer than `timeout`, and it's an error for it to exit
+/// unsuccessfully.
+async fn run(command: &[String], stdin: Option<Vec<u8>>, timeout: Duration) -> Result<Vec<u8>> {
+    let (program, args) = command.split_first().context("Hook command is empty")?;
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run hook command {program}"))?;
+    let output = tokio::time::timeout(timeout, async {
+        if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+            child_stdin
+                .write_all(&input)
+                .await
+                .context("Failed to write to hook command")?;
+        }
+        child
+            .wait_with_output()
+            .await
+            .context("Failed waiting for hook command")
+    })
+    .await
+    .with_context(|| format!("Hook command {program} timed out after {timeout:?}"))??;
+    anyhow::ensure!(
+        output.status.success(),
+        "Hook command {program} failed with {}",
+        output.status
+    );
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Config,
+        cache::{CacheKey, CacheManager},
+        errors::ErrorCode,
+        test_server,
+        test_util::test_dir,
+    };
+
+    /// A command running `script` with `sh`.
+    fn sh(script: &str) -> Vec<String> {
+        ["sh", "-c", script].map(str::to_owned).into()
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[tokio::test]
+    async fn pre_fetch_commands_give_urls_and_headers() {
+        let prefetched = pre_fetch(&sh("echo ' https://example.com/signed?sig=1 '"), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(prefetched.url.as_str(), "https://example.com/signed?sig=1");
+        assert!(prefetched.headers.is_empty());
+
+        let prefetched = pre_fetch(
+            &sh(r#"echo '{"url": "https://example.com/feed", "headers": {"x-token": "s3cret"}}'"#),
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(prefetched.url.as_str(), "https://example.com/feed");
+        assert_eq!(prefetched.headers["x-token"], "s3cret");
+    }
+
+    #[tokio::test]
+    async fn secrets_from_pre_fetch_commands_are_redacted() {
+        let prefetched = pre_fetch(
+            &sh(r#"echo '{"url": "https://example.com/feed", "headers": {"x-token": "s3cret"}}'"#),
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        let request = reqwest::Client::new()
+            .get(prefetched.url)
+            .headers(prefetched.headers)
+            .build()
+            .unwrap();
+        let logged = format!("{request:?} {:?}", request.headers());
+        assert!(logged.contains("x-token"), "{logged}");
+        assert!(!logged.contains("s3cret"), "{logged}");
+
+        // Nor are they in the errors about invalid values.
+        let e = pre_fetch(
+            &sh(
+                r#"echo '{"url": "https://example.com/feed", "headers": {"x-token": "s3cret\n"}}'"#,
+            ),
+            TIMEOUT,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(!format!("{e:?}").contains("s3cret"), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn invalid_pre_fetch_output_is_refused() {
+        for (script, error) in [
+            ("echo not a url", "Pre-fetch command printed an invalid URL"),
+            ("echo '{\"url\":'", "Pre-fetch command printed invalid JSON"),
+            (
+                r#"echo '{"url": "https://example.com", "headers": {"bad name": "1"}}'"#,
+                "Pre-fetch command gave invalid header name \"bad name\"",
+            ),
+            ("exit 3", "Hook command sh failed with exit status: 3"),
+        ] {
+            let e = pre_fetch(&sh(script), TIMEOUT).await.err().unwrap();
+            assert_eq!(e.to_string(), error, "{script}");
+        }
+    }
+
+    #[tokio::test]
+    async fn post_fetch_commands_get_the_outcome_on_stdin() {
+        let dir = test_dir("post-fetch");
+        let outcome_path = dir.join("outcome.json");
+        let outcome = FetchOutcome {
+            site: "Site",
+            feed_url: "https://example.com/feed",
+            success: true,
+            error: None,
+        };
+        post_fetch(
+            &sh(&format!("cat > {}", outcome_path.display())),
+            TIMEOUT,
+            &outcome,
+        )
+        .await
+        .unwrap();
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&outcome_path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "site": "Site",
+                "feed_url": "https://example.com/feed",
+                "success": true,
+                "error": null,
+            })
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn commands_are_killed_after_the_timeout() {
+        let started = std::time::Instant::now();
+        let e = pre_fetch(&sh("sleep 30"), Duration::from_millis(100))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), "Hook command sh timed out after 100ms");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn sites_are_fetched_from_where_their_pre_fetch_commands_say() {
+        let dir = test_dir("pre-fetch");
+        let feeds = test_server::serve_http(|head| {
+            if head.contains("/signed") && head.contains("x-token: s3cret") {
+                test_server::response("200 OK", "application/rss+xml", "<rss></rss>")
+            } else {
+                test_server::response("403 Forbidden", "text/plain", "")
+            }
+        })
+        .await;
+        let signed =
+            format!(r#"{{"url": "http://{feeds}/signed", "headers": {{"x-token": "s3cret"}}}}"#);
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\nhook_timeout = \"1s\"\n\
+             [[sites]]\nname = \"Signed\"\nfeed_url = \"http://{feeds}/unsigned\"\n\
+             pre_fetch_command = {:?}\n\
+             [[sites]]\nname = \"Slow\"\nfeed_url = \"http://{feeds}/unsigned\"\n\
+             pre_fetch_command = [\"sleep\", \"30\"]\n",
+            sh(&format!("echo '{signed}'"))
+        ))
+        .unwrap();
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let errors = test_server::fetch_all(&config, &caches).await;
+        // Only the slow site failed.
+        assert_eq!(errors.len(), 1);
+        assert_eq!(ErrorCode::of(&errors[0]), ErrorCode::HookFailed);
+        let guard = caches.cache_guard();
+        let cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        assert_eq!(cache.last_body.as_deref(), Some("<rss></rss>"));
+        drop(cache);
+        drop(guard);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+use super::SiteConfig;
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+/// Settings for dropping junk entries, like housekeeping posts with empty titles or test posts,
+/// from the `[junk]` table or a site's own `[sites.junk]`.
+///
+/// A site's settings take the place of the global ones, except `drop_titles`, which adds to them.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct JunkConfig {
+    /// Drop entries whose titles have fewer characters than this, once normalized.
+    #[serde(default)]
+    pub min_title_chars: Option<usize>,
+    /// Drop entries whose titles, once normalized, are exactly any of these strings, or match any
+    /// of these given as `{ regex = "..." }`.
+    #[serde(default)]
+    pub drop_titles: Vec<TitlePattern>,
+    /// Drop entries whose titles are the site's name, or its feed's title, regardless of case.
+    #[serde(default)]
+    pub drop_site_name_titles: Option<bool>,
+    /// Whether dropped entries are still remembered as having been in the feed, which is the
+    /// default.
+    ///
+    /// That way, they aren't shown as new if the filters are relaxed later. If this is off,
+    /// they're left out of the site's history as if the feed never had them.
+    #[serde(default)]
+    pub record_filtered: Option<bool>,
+}
+
+/// A title to drop entries with.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum TitlePattern {
+    /// Titles which are exactly this.
+    Exact(Box<str>),
+    /// Titles which match this regex.
+    Regex { regex: Box<str> },
+}
+
+/// A site's junk filter, compiled from its [`JunkConfig`] and the global one.
+#[derive(Clone, Debug, Default)]
+pub struct JunkFilter {
+    min_title_chars: usize,
+    exact_titles: HashSet<Box<str>>,
+    title_regexes: Vec<regex::Regex>,
+    drop_site_name_titles: bool,
+    /// The opposite of `record_filtered`, so the default filter drops and forgets nothing.
+    forget_dropped: bool,
+}
+impl JunkFilter {
+    /// Compile the given site's junk filter, with `global` as its defaults.
+    pub fn new(global: &JunkConfig, site: &SiteConfig) -> Result<Self> {
+        let own = &site.junk;
+        let mut exact_titles = HashSet::new();
+        let mut title_regexes = Vec::new();
+        for pattern in global.drop_titles.iter().chain(&own.drop_titles) {
+            match pattern {
+                TitlePattern::Exact(title) => {
+                    exact_titles.insert(title.clone());
+                }
+                TitlePattern::Regex { regex } => {
+                    title_regexes.push(regex::Regex::new(regex).with_context(|| {
+                        format!("Invalid regex in `drop_titles` for site {}", site.name)
+                    })?)
+                }
+            }
+        }
+        Ok(Self {
+            min_title_chars: own.min_title_chars.or(global.min_title_chars).unwrap_or(0),
+            exact_titles,
+            title_regexes,
+            drop_site_name_titles: own
+                .drop_site_name_titles
+                .or(global.drop_site_name_titles)
+                .unwrap_or(false),
+            forget_dropped: !own
+                .record_filtered
+                .or(global.record_filtered)
+                .unwrap_or(true),
+        })
+    }
+
+    /// Why an entry with the given normalized title is junk, if it is.
+    ///
+    /// `feed_title` is the normalized title of the site's feed. Entries without titles are
+    /// treated as having empty ones.
+    pub fn check(&self, title: Option<&str>, site_name: &str, feed_title: &str) -> Option<Junk> {
+        let title = title.unwrap_or("");
+        let chars = title.chars().count();
+        if chars < self.min_title_chars {
+            Some(Junk::Short {
+                chars,
+                min: self.min_title_chars,
+            })
+        } else if self.exact_titles.contains(title)
+            || self.title_regexes.iter().any(|regex| regex.is_match(title))
+        {
+            Some(Junk::DropTitles)
+        } else if self.drop_site_name_titles
+            && (title.to_lowercase() == site_name.to_lowercase()
+                || title.to_lowercase() == feed_title.to_lowercase())
+        {
+            Some(Junk::SiteName)
+        } else {
+            None
+        }
+    }
+
+    /// Whether entries this drops are left out of the site's history.
+    pub fn forgets_dropped(&self) -> bool {
+        self.forget_dropped
+    }
+}
+
+/// Why an entry was dropped as junk.
+#[derive(Clone, Copy, Debug)]
+pub enum Junk {
+    /// Its title was shorter than `min_title_chars`.
+    Short { chars: usize, min: usize },
+    /// Its title was one of `drop_titles`.
+    DropTitles,
+    /// Its title was the site's name, with `drop_site_name_titles`.
+    SiteName,
+}
+impl std::fmt::Display for Junk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Short { chars, min } => write!(
+                f,
+                "its title has {chars} characters, fewer than the {min} of min_title_chars"
+            ),
+            Self::DropTitles => write!(f, "its title is in drop_titles"),
+            Self::SiteName => write!(f, "its title is the site's name"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cache, config_files, test_util, trace::ArticleTrace};
+
+    /// The config with `global` settings and a site `Notes` with `site` settings.
+    fn config(global: &str, site: &str) -> crate::Config {
+        config_files::to_config(
+            toml::from_str(&format!(
+                "min_fetch_interval = 0\n{global}\n\
+                 [[sites]]\nname = \"Notes\"\nfeed_url = \"https://notes.example/feed\"\n{site}"
+            ))
+            .unwrap(),
+            false,
+        )
+        .unwrap()
+    }
+
+    /// Why `Notes` drops an entry titled `title`, from a feed titled `Notes Blog`.
+    fn check(config: &crate::Config, title: Option<&str>) -> Option<String> {
+        let site = &config.sites[0];
+        site.junk_filter
+            .check(title, &site.name, "notes blog")
+            .map(|junk| junk.to_string())
+    }
+
+    #[test]
+    fn nothing_is_junk_by_default() {
+        let config = config("", "");
+        for title in [
+            None,
+            Some(""),
+            Some("notes"),
+            Some("test"),
+            Some("A real post"),
+        ] {
+            assert_eq!(check(&config, title), None, "{title:?}");
+        }
+        assert!(!config.sites[0].junk_filter.forgets_dropped());
+    }
+
+    #[test]
+    fn short_and_dropped_titles_are_junk() {
+        let config = config(
+            "[junk]\nmin_title_chars = 3\n\
+             drop_titles = [\"test\", { regex = \"^(untitled|draft) \\\\d+$\" }]",
+            "",
+        );
+        assert_eq!(
+            check(&config, None).as_deref(),
+            Some("its title has 0 characters, fewer than the 3 of min_title_chars")
+        );
+        assert_eq!(
+            check(&config, Some("ok")).as_deref(),
+            Some("its title has 2 characters, fewer than the 3 of min_title_chars")
+        );
+        // Characters are counted, not bytes.
+        assert_eq!(check(&config, Some("日本語")), None);
+        for title in ["test", "untitled 3", "draft 12"] {
+            assert_eq!(
+                check(&config, Some(title)).as_deref(),
+                Some("its title is in drop_titles"),
+                "{title}"
+            );
+        }
+        // Exact titles must match all of the title, as must anchored regexes.
+        for title in ["test post", "a test", "untitled", "draft 12 notes"] {
+            assert_eq!(check(&config, Some(title)), None, "{title}");
+        }
+    }
+
+    #[test]
+    fn site_name_titles_are_junk_when_asked() {
+        let config = config("[junk]\ndrop_site_name_titles = true", "");
+        for title in ["notes", "NOTES", "Notes Blog"] {
+            assert_eq!(
+                check(&config, Some(title)).as_deref(),
+                Some("its title is the site's name"),
+                "{title}"
+            );
+        }
+        for title in ["Notes on notes", "Blog"] {
+            assert_eq!(check(&config, Some(title)), None, "{title}");
+        }
+    }
+
+    #[test]
+    fn site_settings_replace_the_global_ones_but_add_titles() {
+        let config = config(
+            "[junk]\nmin_title_chars = 5\ndrop_titles = [\"test\"]\n\
+             drop_site_name_titles = true\nrecord_filtered = true",
+            "[sites.junk]\nmin_title_chars = 2\ndrop_titles = [\"ping\"]\n\
+             drop_site_name_titles = false\nrecord_filtered = false",
+        );
+        assert_eq!(check(&config, Some("abc")), None);
+        assert!(check(&config, Some("a")).is_some());
+        assert!(check(&config, Some("test")).is_some());
+        assert!(check(&config, Some("ping")).is_some());
+        assert_eq!(check(&config, Some("Notes")), None);
+        assert!(config.sites[0].junk_filter.forgets_dropped());
+    }
+
+    #[test]
+    fn invalid_regexes_are_rejected() {
+        let e = config_files::to_config(
+            toml::from_str(
+                "min_fetch_interval = 0\n\
+                 [[sites]]\nname = \"Notes\"\nfeed_url = \"https://notes.example/feed\"\n\
+                 junk = { drop_titles = [{ regex = \"(\" }] }",
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invalid regex in `drop_titles` for site Notes"
+        );
+    }
+
+    #[tokio::test]
+    async fn junk_is_left_out_of_the_articles() {
+        let config = config(
+            "search_index = true\n\
+             [junk]\nmin_title_chars = 3\ndrop_titles = [\"test\"]\ndrop_site_name_titles = true",
+            "",
+        );
+        let dir = test_util::test_dir("junk");
+        let caches =
+            cache::CacheManager::new(dir.clone(), cache::CacheKey::Name, &config.sites).unwrap();
+        let items = [
+            "A real post",
+            "",
+            "Test",
+            "  test  ",
+            "Notes",
+            "Notes Blog",
+            "Hi",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            format!(
+                "<item><title>{title}</title><link>https://notes.example/{i}</link>\
+                     <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item>"
+            )
+        })
+        .collect::<String>();
+        let guard = caches.cache_guard();
+        caches
+            .get_mut(&config.sites[0], &guard)
+            .await
+            .unwrap()
+            .last_body = Some(
+            format!(
+                "<rss version=\"2.0\"><channel><title>Notes Blog</title>{items}</channel></rss>"
+            )
+            .into(),
+        );
+        drop(guard);
+        let collected = crate::collect_articles(&config, &caches, &ArticleTrace::new(None)).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        let titles = collected
+            .articles
+            .iter()
+            .map(|article| &*article.title)
+            .collect::<Vec<_>>();
+        // Titles are normalized before matching `drop_titles`, but their case is kept.
+        assert_eq!(titles, ["A real post", "Test"]);
+        // Nor can it be searched for.
+        let mut indexed = collected
+            .search_index
+            .as_ref()
+            .unwrap()
+            .articles()
+            .into_iter()
+            .map(|article| &*article.title)
+            .collect::<Vec<_>>();
+        indexed.sort_unstable();
+        assert_eq!(indexed, ["A real post", "Test"]);
+    }
+}
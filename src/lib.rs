@@ -0,0 +1,2941 @@
+//! An RSS feed reader which generates a static HTML page.
+//!
+//! This is mainly the `jarss` command, but the feeds it reads can also be collected from other
+//! programs, like static site generators, with [`Jarss`]:
+//!
+//! ```no_run
+//! let jarss = jarss::Jarss::builder()
+//!     .config_path("blogroll.toml")
+//!     .cache_dir("target/jarss")
+//!     .offline(true)
+//!     .build()?;
+//! for article in jarss.collect_articles()? {
+//!     println!("{}: {}", article.site, article.title);
+//! }
+//! # Ok::<(), jarss::Error>(())
+//! ```
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use errors::WithCode as _;
+use futures::{FutureExt as _, StreamExt as _};
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::atomic::AtomicUsize,
+    time::{Duration, SystemTime},
+};
+
+mod add;
+mod api;
+mod atom;
+mod auth;
+mod budget;
+mod cache;
+mod canary;
+mod client;
+mod config_files;
+mod dedup;
+#[cfg(feature = "desktop-notify")]
+mod desktop;
+mod diff;
+mod digest_auth;
+mod discover;
+mod dns;
+mod doctor;
+mod errors;
+mod export;
+mod filter;
+mod fragment;
+mod github;
+mod grouping;
+mod groups;
+mod hooks;
+mod junk;
+mod links;
+mod logging;
+mod manifest;
+mod notify;
+mod opml;
+mod origins;
+mod outputs;
+mod picks;
+mod post;
+mod provenance;
+mod reading;
+mod relocate;
+mod replace;
+mod resolve;
+mod safe_mode;
+mod sanitize;
+mod search;
+mod standalone;
+mod state;
+mod status;
+mod strings;
+mod summary;
+mod template_filters;
+mod templates;
+#[cfg(test)]
+mod test_server;
+#[cfg(test)]
+mod test_util;
+mod throttle;
+mod timestamp;
+mod trace;
+mod tune;
+mod url_display;
+mod urls;
+mod watch;
+
+pub use api::{Article, Error, Jarss, JarssBuilder};
+
+/// An RSS feed reader which generates a static HTML page.
+#[derive(Parser)]
+#[command(subcommand_negates_reqs = true)]
+struct Args {
+    /// The path to the config file.
+    ///
+    /// By default, this is a `jarss.toml` file in your config directory. This can be given more
+    /// than once, to merge the files in order:
+    /// - Settings in later files override those in earlier ones.
+    /// - Tables of settings, like `[groups]` or `[limits]`, are merged key by key.
+    /// - `[[sites]]` are concatenated, except that a site with the same `feed_url` as an earlier
+    ///   one replaces it.
+    ///
+    /// A file can also list files to merge in before it with `include = ["other.toml"]`, relative
+    /// to its directory. Changes to the config, like from `tune --apply`, are written to the last
+    /// file given.
+    #[arg(short, long, global = true)]
+    config: Vec<PathBuf>,
+    /// The path to the cache directory.
+    ///
+    /// By default, this is `jarss` in your cache directory.
+    #[arg(long, global = true)]
+    cache: Option<PathBuf>,
+    /// The path to the directory to keep state in, which unlike the caches can't be got back by
+    /// fetching again, like what notifications have been sent.
+    ///
+    /// By default, this is the cache directory if `--cache` is given, and otherwise `jarss` in
+    /// your state directory. State left in the cache directory by earlier versions is moved here.
+    #[arg(long, global = true)]
+    state: Option<PathBuf>,
+    /// A file to write logs to, instead of stderr.
+    ///
+    /// The file is rotated once it reaches `--log-file-max-bytes`.
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// The size at which to rotate the log file.
+    #[arg(long, global = true, default_value_t = 10 * 1024 * 1024)]
+    log_file_max_bytes: u64,
+    /// The number of log files to keep, including the current one.
+    #[arg(long, global = true, default_value_t = 5)]
+    log_file_keep: usize,
+    /// Log what happens to the article with this link or entry ID while collecting articles.
+    ///
+    /// This shows which stage excluded the article, or whether it wasn't in its feed at all.
+    #[arg(long, global = true)]
+    trace_article: Option<String>,
+    #[command(flatten)]
+    run: RunArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// The arguments for generating the page, given to `run` or `render`, or when no subcommand is
+/// given.
+#[derive(clap::Args)]
+struct RunArgs {
+    /// The path to the template to use in generating the feed.
+    ///
+    /// This should be a [`tera`] tempalte which takes a list of articles at `articles`, and
+    /// generates a full HTML document.
+    ///
+    /// By default, this will use a simple HTML template, stored at `default-render.html.tera` in
+    /// the repo. You can use this template as an example in writing your own.
+    ///
+    /// Templates should link to the other files written, like the search index, with
+    /// `output_url(name="search_index")`, which gives working links whether or not
+    /// `render.base_url` is set.
+    #[arg(long)]
+    feed_template: Option<PathBuf>,
+    /// The path to write a JSON manifest of the files produced by this run.
+    ///
+    /// The manifest lists each output with its content hash, size, and whether it changed since
+    /// the manifest from the previous run.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// The path to write a JSON summary of the run, for automation.
+    ///
+    /// The summary gives each error a stable code alongside its message.
+    #[arg(long)]
+    summary: Option<PathBuf>,
+    /// The path to also write the articles to as an Atom feed.
+    #[arg(long)]
+    out_feed: Option<PathBuf>,
+    /// The path to also write the articles to as JSON, for other tools, or `-` for stdout.
+    ///
+    /// The articles have the same fields templates get, with their times as `*_unix` seconds and
+    /// `*_rfc3339` strings, and come with when they were generated and how many are from each
+    /// site.
+    #[arg(long)]
+    out_json: Option<PathBuf>,
+    /// Write the outputs even if there are fewer articles than expected, with
+    /// `refuse_sparse_output`.
+    #[arg(long)]
+    force: bool,
+    /// Run with every optional feature turned off, ignoring their settings, as a fallback for
+    /// when one of them is broken.
+    ///
+    /// Only fetching the feeds, reading them, and rendering the outputs are left, with the
+    /// settings for those. Which settings were ignored is logged, and templates get `safe_mode`.
+    #[arg(long)]
+    safe: bool,
+    /// The path the write the produced HTML page.
+    ///
+    /// This can be left out if `--out-feed` or `--out-json` is given, to only write those.
+    #[arg(required_unless_present_any = ["out_feed", "out_json"])]
+    out_html: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Fetch the feeds which are due, and generate the page.
+    ///
+    /// This is the same as giving no subcommand.
+    Run(RunArgs),
+    /// Generate the page from the cached feeds, without fetching anything.
+    ///
+    /// Fails if a site hasn't been fetched yet.
+    Render(RunArgs),
+    /// Keep running, fetching the feeds which are due and generating the page every `--interval`.
+    ///
+    /// The config is reloaded when its files change, and a run failing is logged without stopping
+    /// the next. The caches are saved before exiting on SIGINT or SIGTERM.
+    Watch {
+        /// How long to wait after each run before starting the next, like `15m`.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// Log how much memory is in use, and what the caches hold, after every this many runs.
+        ///
+        /// Numbers which keep growing over days point to a leak.
+        #[arg(long)]
+        report_every: Option<NonZeroU32>,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Fetch the feeds which are due, without generating anything.
+    ///
+    /// Exits with failure if a fetch fails, and with code 3 if no site was due.
+    Fetch {
+        /// Only fetch the site which was fetched longest ago, of those which are due.
+        ///
+        /// Running this repeatedly fetches each site in turn, for schedulers which only allow a
+        /// short time per run.
+        #[arg(long)]
+        one: bool,
+    },
+    /// Search the cached articles for the given terms, without fetching anything.
+    Search {
+        /// The terms to search for. Articles matching more terms are listed first, and those
+        /// matching in their titles before those matching in their summaries or site names.
+        #[arg(required = true)]
+        terms: Vec<String>,
+    },
+    /// List the configured sites, along with the format their feeds are served in and whether
+    /// they're displayed.
+    List,
+    /// Explain whether the given site would be fetched now, and why, without fetching anything.
+    Explain {
+        /// The name of the site.
+        site: String,
+        /// Print the explanation as JSON.
+        ///
+        /// Times are given as both `*_unix` seconds and `*_rfc3339` strings, except within the
+        /// gates' `inputs`, which are only meant for humans.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how often each site's fetches found something new, and suggest how often to fetch
+    /// them, without fetching anything.
+    ///
+    /// Sites fetched much more often than they change are pointed out.
+    Tune {
+        /// Write the suggested `min_fetch_interval`s of the sites pointed out into the config.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Add the feeds from an OPML file, as exported by other feed readers, to the config.
+    ///
+    /// Feeds whose URLs are already in the config are skipped, and folders are flattened.
+    ImportOpml {
+        /// The OPML file to import.
+        file: PathBuf,
+    },
+    /// Compare two JSON outputs, as written with `--out-json`, listing the articles which were
+    /// added, removed, or moved between them.
+    ///
+    /// Articles are matched by their site and ID, so an article whose title or link changed isn't
+    /// listed as removed and added again.
+    Diff {
+        /// The older output.
+        old: PathBuf,
+        /// The newer output.
+        new: PathBuf,
+        /// How to print the changes.
+        #[arg(long, value_enum, default_value_t)]
+        format: diff::Format,
+    },
+    /// Add a site for the given feed to the config, after fetching it to check that it parses.
+    ///
+    /// The site is named by the feed's title, and the fetch is cached, so the next run doesn't
+    /// fetch it again. Fails if a site already has the feed or the name.
+    Add {
+        /// The URL of the feed.
+        feed_url: String,
+        /// The name to give the site, instead of the feed's title.
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Find the feeds advertised by a web page, like a blog's homepage.
+    ///
+    /// The best feed is listed first, preferring Atom over RSS over JSON Feed.
+    Discover {
+        /// The URL of the page.
+        url: String,
+        /// Add the best feed to the config, named by the page's title.
+        #[arg(long)]
+        add: bool,
+    },
+    /// Fetch the `subscriptions_opml_url` now, and update the sites in the config to match it.
+    SyncSubscriptions {
+        /// Print the changes to the config without making them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// The same as `jarss config show`.
+    #[command(hide = true)]
+    ShowConfig,
+    /// Look at the config.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Delete the cache files of sites which are no longer in the config.
+    ///
+    /// Disabled sites keep their caches, in case they're enabled again.
+    Clean {
+        /// Print the files which would be deleted without deleting them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Manage the cache directory.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Check for common problems with the config, caches, and template, without producing any
+    /// output or changing the caches.
+    ///
+    /// Exits with failure if any check fails, and with `--strict`, if any warns.
+    ///
+    /// With `--stdin`, this checks a candidate config before it's written, like `jarss check
+    /// --stdin < candidate.toml`.
+    #[command(alias = "check")]
+    Doctor {
+        /// The template to check, instead of the default one.
+        #[arg(long)]
+        feed_template: Option<PathBuf>,
+        /// Also request each site's feed, to check that it's reachable and responds successfully.
+        #[arg(long)]
+        fetch: bool,
+        /// Exit with failure if any check warns, not only if one fails.
+        #[arg(long)]
+        strict: bool,
+        /// Check the config read from stdin, instead of the config files, without looking at the
+        /// caches.
+        ///
+        /// Its `include`s are relative to the current directory. This implies `--json`.
+        #[arg(long)]
+        stdin: bool,
+        /// Also report which sites the config from `--stdin` adds, removes, or changes, compared
+        /// to the config files.
+        #[arg(long, requires = "stdin")]
+        diff_against_current: bool,
+        /// Print the findings as JSON, with their severities, for automation.
+        #[arg(long)]
+        json: bool,
+        /// Print the config as merged from the config files, instead of checking anything.
+        ///
+        /// This is the same as `jarss config show`, and with `--json`, `jarss config show --json`.
+        #[arg(long, conflicts_with_all = ["feed_template", "fetch", "strict", "stdin", "out_html"])]
+        show_effective: bool,
+        /// The output path to check is writable.
+        out_html: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Print the config as it's used, with the defaults filled in, and where each setting came
+    /// from: a default, a line of a config file, an environment variable, or `--safe`.
+    ///
+    /// Secrets, like header values and passwords, are redacted, but where they came from is still
+    /// shown. Disabled sites are included.
+    Show {
+        /// Only show the site with this name, including the global settings it falls back to.
+        #[arg(long)]
+        site: Option<String>,
+        /// Print JSON, with each setting as an object of its `value` and `source`, instead of
+        /// TOML with the sources in comments.
+        #[arg(long)]
+        json: bool,
+        /// Show the config as `--safe` would use it, with the settings of optional features
+        /// ignored.
+        #[arg(long)]
+        safe: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum CacheCommand {
+    /// Move everything in the cache directory to another, such as onto a different volume.
+    ///
+    /// Every file is copied and checked before the originals are removed, so the cache is left
+    /// where it was if anything fails. The config isn't changed, so `--cache` must be given with
+    /// the new directory afterwards. Fails if another jarss is using the cache.
+    Move {
+        /// The directory to move the cache to, which must be empty if it exists.
+        new_dir: PathBuf,
+        /// List the files which would be moved, and their total size, without moving them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// [`Args`] but with default values applied.
+struct InferredArgs {
+    /// The config files.
+    config: config_files::ConfigFiles,
+    /// The path to the cache directory.
+    cache: PathBuf,
+    /// Where to keep the state which isn't cache.
+    state: state::StatePaths,
+    /// The link or ID of the article to trace, if any.
+    trace_article: Option<Box<str>>,
+    /// What we were asked to do.
+    command: InferredCommand,
+}
+/// [`RunArgs`] but with the template read.
+struct RunOptions {
+    /// The templates to use in generating the feed.
+    templates: templates::Templates,
+    /// The path to write the manifest of outputs, if requested.
+    manifest: Option<PathBuf>,
+    /// The path to write the run summary, if requested.
+    summary: Option<PathBuf>,
+    /// The path the write the produced HTML page, if any.
+    out_html: Option<PathBuf>,
+    /// The path to write the Atom feed, if any.
+    out_feed: Option<PathBuf>,
+    /// The path to write the JSON output, if any.
+    out_json: Option<PathBuf>,
+    /// Whether to write the outputs even if there are fewer articles than expected.
+    force: bool,
+}
+enum InferredCommand {
+    /// Fetch feeds, if asked to, and generate the page.
+    Run {
+        /// Whether to fetch the feeds first.
+        fetch: bool,
+        options: RunOptions,
+    },
+    /// Fetch feeds and generate the page repeatedly.
+    Watch {
+        /// How long to wait between runs.
+        interval: Duration,
+        /// How many runs to report memory use after, if at all.
+        report_every: Option<NonZeroU32>,
+        options: RunOptions,
+    },
+    /// Fetch the feeds which are due.
+    Fetch { one: bool },
+    /// Search the cached articles.
+    Search { terms: Vec<String> },
+    /// List the configured sites.
+    List,
+    /// Explain whether a site would be fetched.
+    Explain { site: String, json: bool },
+    /// Suggest fetch intervals.
+    Tune { apply: bool },
+    /// Import feeds from OPML.
+    ImportOpml { file: PathBuf },
+    /// Compare two JSON outputs.
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        format: diff::Format,
+    },
+    /// Add a site for a feed.
+    Add {
+        feed_url: String,
+        name: Option<String>,
+    },
+    /// Find the feeds advertised by a page.
+    Discover { url: String, add: bool },
+    /// Sync the sites with the subscriptions OPML.
+    SyncSubscriptions { dry_run: bool },
+    /// Print the effective config, with where each setting came from.
+    ConfigShow { site: Option<String>, json: bool },
+    /// Delete orphaned cache files.
+    Clean { dry_run: bool },
+    /// Move the cache directory.
+    MoveCache { new_dir: PathBuf, dry_run: bool },
+    /// Check for common problems.
+    Doctor {
+        feed_template: Option<PathBuf>,
+        fetch: bool,
+        strict: bool,
+        stdin: bool,
+        diff_against_current: bool,
+        json: bool,
+        out_html: Option<PathBuf>,
+    },
+}
+impl RunArgs {
+    /// Whether any of the arguments were given.
+    fn is_given(&self) -> bool {
+        let Self {
+            feed_template,
+            manifest,
+            summary,
+            out_feed,
+            out_json,
+            force,
+            safe,
+            out_html,
+        } = self;
+        feed_template.is_some()
+            || manifest.is_some()
+            || summary.is_some()
+            || out_feed.is_some()
+            || out_json.is_some()
+            || *force
+            || *safe
+            || out_html.is_some()
+    }
+}
+
+impl RunOptions {
+    /// Read the template the given arguments name.
+    fn new(args: RunArgs) -> Result<Self> {
+        Ok(Self {
+            templates: templates::Templates::new(args.feed_template)?,
+            manifest: args.manifest,
+            summary: args.summary,
+            out_html: args.out_html,
+            out_feed: args.out_feed,
+            out_json: args.out_json,
+            force: args.force,
+        })
+    }
+}
+impl TryFrom<Args> for InferredArgs {
+    type Error = anyhow::Error;
+
+    fn try_from(raw_args: Args) -> Result<Self> {
+        let config = if raw_args.config.is_empty() {
+            vec![default_config_path()?]
+        } else {
+            raw_args.config
+        };
+        let default_cache = raw_args.cache.is_none();
+        let cache = match raw_args.cache {
+            Some(cache) => cache,
+            None => default_cache_dir()?,
+        };
+        let state = state::StatePaths::resolve(raw_args.state, &cache, default_cache);
+        if raw_args.command.is_some() && raw_args.run.is_given() {
+            anyhow::bail!(
+                "Options for generating the page, like `--feed-template`, can't be given with a \
+                 subcommand, except after `run` or `render`"
+            );
+        }
+        let safe = match &raw_args.command {
+            Some(Command::Run(run) | Command::Render(run) | Command::Watch { run, .. }) => run.safe,
+            None => raw_args.run.safe,
+            Some(Command::Config {
+                command: ConfigCommand::Show { safe, .. },
+            }) => *safe,
+            Some(_) => false,
+        };
+        let command = match raw_args.command {
+            Some(Command::Fetch { one }) => InferredCommand::Fetch { one },
+            Some(Command::Search { terms }) => InferredCommand::Search { terms },
+            Some(Command::List) => InferredCommand::List,
+            Some(Command::Explain { site, json }) => InferredCommand::Explain { site, json },
+            Some(Command::Tune { apply }) => InferredCommand::Tune { apply },
+            Some(Command::ImportOpml { file }) => InferredCommand::ImportOpml { file },
+            Some(Command::Diff { old, new, format }) => InferredCommand::Diff { old, new, format },
+            Some(Command::Add { feed_url, name }) => InferredCommand::Add { feed_url, name },
+            Some(Command::Discover { url, add }) => InferredCommand::Discover { url, add },
+            Some(Command::SyncSubscriptions { dry_run }) => {
+                InferredCommand::SyncSubscriptions { dry_run }
+            }
+            Some(Command::ShowConfig) => InferredCommand::ConfigShow {
+                site: None,
+                json: false,
+            },
+            Some(Command::Config {
+                command: ConfigCommand::Show { site, json, .. },
+            }) => InferredCommand::ConfigShow { site, json },
+            Some(Command::Clean { dry_run }) => InferredCommand::Clean { dry_run },
+            Some(Command::Cache {
+                command: CacheCommand::Move { new_dir, dry_run },
+            }) => InferredCommand::MoveCache { new_dir, dry_run },
+            Some(Command::Doctor {
+                show_effective: true,
+                json,
+                ..
+            }) => InferredCommand::ConfigShow { site: None, json },
+            Some(Command::Doctor {
+                feed_template,
+                fetch,
+                strict,
+                stdin,
+                diff_against_current,
+                json,
+                out_html,
+                show_effective: false,
+            }) => InferredCommand::Doctor {
+                feed_template,
+                fetch,
+                strict,
+                stdin,
+                diff_against_current,
+                json,
+                out_html,
+            },
+            Some(Command::Run(run)) => InferredCommand::Run {
+                fetch: true,
+                options: RunOptions::new(run)?,
+            },
+            Some(Command::Render(run)) => InferredCommand::Run {
+                fetch: false,
+                options: RunOptions::new(run)?,
+            },
+            Some(Command::Watch {
+                interval,
+                report_every,
+                run,
+            }) => InferredCommand::Watch {
+                interval,
+                report_every,
+                options: RunOptions::new(run)?,
+            },
+            None => InferredCommand::Run {
+                fetch: true,
+                options: RunOptions::new(raw_args.run)?,
+            },
+        };
+        Ok(InferredArgs {
+            config: config_files::ConfigFiles::new(config).safe_mode(safe),
+            cache,
+            state,
+            trace_article: raw_args.trace_article.map(String::into_boxed_str),
+            command,
+        })
+    }
+}
+
+/// The config file used if none are given: `jarss.toml` in the config directory.
+fn default_config_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("No default config directory on your system")?
+        .join("jarss.toml"))
+}
+
+/// The cache directory used if none is given: `jarss` in the system's cache directory.
+fn default_cache_dir() -> Result<PathBuf> {
+    Ok(dirs::cache_dir()
+        .context("No default cache dir on your system")?
+        .join("jarss"))
+}
+
+/// Run the `jarss` command line, with the arguments the process was given.
+///
+/// This sets up logging, and prints to stdout, so it's only meant for the `jarss` binary. Other
+/// programs should use [`Jarss`] instead.
+#[doc(hidden)]
+#[tokio::main(flavor = "current_thread")]
+pub async fn cli() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+    let dropped_logs = logging::init(
+        args.log_file.clone(),
+        args.log_file_max_bytes,
+        args.log_file_keep,
+        args.trace_article.is_some(),
+    );
+    let res = async { dispatch(args.try_into()?).await }.await;
+    let dropped_logs = dropped_logs.load(std::sync::atomic::Ordering::Relaxed);
+    if dropped_logs > 0 {
+        eprintln!("Failed to write {dropped_logs} lines to the log file");
+    }
+    res
+}
+
+/// Do whatever the command-line arguments asked for.
+async fn dispatch(args: InferredArgs) -> Result<ExitCode> {
+    if let InferredCommand::Doctor {
+        feed_template,
+        fetch,
+        strict,
+        stdin,
+        diff_against_current,
+        json,
+        out_html,
+    } = &args.command
+    {
+        let candidate = if *stdin {
+            Some(std::io::read_to_string(std::io::stdin()).context("Failed to read stdin")?)
+        } else {
+            None
+        };
+        // The doctor reports problems loading the config itself, so it has to come first.
+        return doctor::doctor(
+            &args.config,
+            &args.cache,
+            feed_template.as_deref(),
+            out_html.as_deref(),
+            doctor::Options {
+                fetch: *fetch,
+                strict: *strict,
+                candidate,
+                diff_against_current: *diff_against_current,
+                json: *json || *stdin,
+            },
+        )
+        .await;
+    }
+    if let InferredCommand::ImportOpml { file } = &args.command {
+        // The config might not exist yet, if this is how it's being set up.
+        opml::import(&args.config, file)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let InferredCommand::Diff { old, new, format } = &args.command {
+        // This only reads the outputs, so doesn't need the config.
+        diff::diff(old, new, *format)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let InferredCommand::Discover { url, add } = &args.command {
+        // Likewise, this can be how the config is set up.
+        discover::discover(&args.config, url, *add).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let InferredCommand::ConfigShow { site, json } = &args.command {
+        // This only reads the config files, so doesn't need the cache.
+        let (config, settings, sources) = args
+            .config
+            .load_with_sources()
+            .with_context(|| {
+                format!(
+                    "Couldn't load configuration file at {}",
+                    args.config.describe()
+                )
+            })
+            .code(errors::ErrorCode::ConfigInvalid)?;
+        provenance::show(
+            &config,
+            &settings,
+            &sources,
+            config.safe_mode,
+            site.as_deref(),
+            *json,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let InferredCommand::MoveCache { new_dir, dry_run } = &args.command {
+        // This locks the cache itself, so it can't be opened as usual first.
+        relocate::move_cache(&args.cache, new_dir, *dry_run)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    log::debug!("Keeping state in {}", args.state.dir().display());
+    args.state.migrate_from(&args.cache);
+    if let InferredCommand::Add { feed_url, name } = &args.command {
+        // And so can this.
+        add::add(
+            &args.config,
+            &args.cache,
+            &args.state,
+            feed_url,
+            name.as_deref(),
+        )
+        .await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    log::info!("Loading config from {}", args.config.describe());
+    let mut config = match args
+        .config
+        .load()
+        .with_context(|| {
+            format!(
+                "Couldn't load configuration file at {}",
+                args.config.describe()
+            )
+        })
+        .code(errors::ErrorCode::ConfigInvalid)
+    {
+        Ok(config) => config,
+        Err(e) => {
+            if let InferredCommand::Run { options, .. } | InferredCommand::Watch { options, .. } =
+                &args.command
+                && let Some(summary_path) = &options.summary
+            {
+                let mut summary = summary::RunSummary::new(&Config::default());
+                summary.error = Some((&e).into());
+                summary.write(summary_path)?;
+            }
+            return Err(e);
+        }
+    };
+    let mut caches = cache::CacheManager::new(args.cache, config.cache_key, &config.sites)
+        .code(errors::ErrorCode::ConfigInvalid)?
+        .with_state(args.state);
+    let trace = trace::ArticleTrace::new(args.trace_article);
+
+    match args.command {
+        InferredCommand::Run { fetch, mut options } => {
+            run_and_summarize(
+                &args.config,
+                &mut config,
+                &caches,
+                fetch,
+                &mut options,
+                &trace,
+            )
+            .await
+        }
+        InferredCommand::Watch {
+            interval,
+            report_every,
+            mut options,
+        } => {
+            watch::watch(
+                &args.config,
+                config,
+                &mut caches,
+                interval,
+                report_every,
+                &mut options,
+                &trace,
+            )
+            .await
+        }
+        InferredCommand::Fetch { one } => {
+            let github = github::GitHub::new(&config, caches.state())?;
+            let origins = origins::Origins::load(caches.state());
+            let now = SystemTime::now();
+            let sites = {
+                let guard = caches.cache_guard();
+                let mut site_caches = Vec::new();
+                for site in &config.sites {
+                    let cache = caches
+                        .get_mut(site, &guard)
+                        .await
+                        .with_context(|| format!("Error reading cache for {}", site.name))?;
+                    site_caches.push((site, cache));
+                }
+                let site_caches = site_caches.iter().map(|(site, cache)| (*site, &**cache));
+                if one {
+                    Vec::from_iter(throttle::next_due(
+                        &config,
+                        site_caches,
+                        &github,
+                        &origins,
+                        now,
+                    ))
+                } else {
+                    site_caches
+                        .filter(|(site, cache)| {
+                            throttle::is_due(&config, site, cache, &github, &origins, now)
+                        })
+                        .map(|(site, _)| site)
+                        .collect()
+                }
+            };
+            if sites.is_empty() {
+                log::info!("No sites are due to be fetched");
+                return Ok(ExitCode::from(NOTHING_DUE_EXIT_CODE));
+            }
+            let mut summary = summary::RunSummary::new(&config);
+            let errored =
+                fetch_sites(&config, &caches, &github, &origins, &sites, &mut summary).await?;
+            Ok(if errored {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            })
+        }
+        InferredCommand::Search { terms } => {
+            // Searching works whether or not the page has an index, so this is only saved by
+            // runs which write it.
+            let config = Config {
+                search_index: true,
+                ..config.clone()
+            };
+            let index = collect_articles(&config, &caches, &trace)
+                .await
+                .search_index
+                .unwrap_or_default();
+            for article in search::search(&index, &terms) {
+                println!(
+                    "{} {}: {} <{}>",
+                    article.date, article.site, article.title, article.link
+                );
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        InferredCommand::List => {
+            let guard = caches.cache_guard();
+            for site in &config.sites {
+                let cache = caches
+                    .get_mut(site, &guard)
+                    .await
+                    .with_context(|| format!("Error reading cache for {}", site.name))?;
+                let format = cache
+                    .format
+                    .map_or_else(|| "-".to_owned(), |format| format.to_string());
+                let content_type = cache.content_type.as_deref().unwrap_or("-");
+                let display = if site.display { "shown" } else { "hidden" };
+                println!(
+                    "{}\t{format}\t{content_type}\t{display}\t{}",
+                    site.name,
+                    url_display::display_url(&site.feed_url)
+                );
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        InferredCommand::Explain { site, json } => {
+            let site = config
+                .sites
+                .iter()
+                .find(|config_site| config_site.name.as_ref() == site)
+                .with_context(|| format!("No site named {site} in the config"))?;
+            let guard = caches.cache_guard();
+            let cache = caches
+                .get_mut(site, &guard)
+                .await
+                .with_context(|| format!("Error reading cache for {}", site.name))?;
+            let origins = origins::Origins::load(caches.state());
+            let now = SystemTime::now();
+            let decision = throttle::FetchDecision::new(&config, site, &cache, &origins, now);
+            throttle::explain(site, &decision, now, json);
+            Ok(ExitCode::SUCCESS)
+        }
+        InferredCommand::Tune { apply } => {
+            tune::tune(&config, &caches, &args.config, apply).await?;
+            Ok(ExitCode::SUCCESS)
+        }
+        InferredCommand::SyncSubscriptions { dry_run } => {
+            opml::sync(
+                &config,
+                &args.config,
+                caches.cache_dir(),
+                caches.state(),
+                true,
+                dry_run,
+            )
+            .await?;
+            Ok(ExitCode::SUCCESS)
+        }
+        InferredCommand::Clean { dry_run } => {
+            // Disabled sites are left out of `config`, but they still own their caches, and so
+            // does the subscriptions OPML.
+            let including_disabled = args
+                .config
+                .load_including_disabled()
+                .context("Couldn't load the disabled sites")?;
+            let sites = including_disabled
+                .sites
+                .iter()
+                .cloned()
+                .chain(opml::subscriptions_site(&including_disabled))
+                .collect::<Vec<_>>();
+            let orphans = caches.orphaned_files(&sites)?;
+            if orphans.is_empty() {
+                log::info!("No cache files to delete");
+            }
+            for path in orphans {
+                if dry_run {
+                    println!("Would delete {}", path.display());
+                } else {
+                    std::fs::remove_file(&path)
+                        .with_context(|| format!("Failed to delete {}", path.display()))
+                        .code(errors::ErrorCode::CacheIo)?;
+                    println!("Deleted {}", path.display());
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        InferredCommand::ImportOpml { .. }
+        | InferredCommand::Diff { .. }
+        | InferredCommand::Add { .. }
+        | InferredCommand::Discover { .. }
+        | InferredCommand::MoveCache { .. }
+        | InferredCommand::ConfigShow { .. }
+        | InferredCommand::Doctor { .. } => {
+            unreachable!("Handled above")
+        }
+    }
+}
+
+/// Fetch the feeds if `fetch`, syncing the subscriptions first if there are any, and generate the
+/// outputs `options` asks for, writing the run summary if it asks for one.
+///
+/// `config` is reloaded if syncing the subscriptions changed it.
+async fn run_and_summarize(
+    config_files: &config_files::ConfigFiles,
+    config: &mut Config,
+    caches: &cache::CacheManager,
+    fetch: bool,
+    options: &mut RunOptions,
+    trace: &trace::ArticleTrace,
+) -> Result<ExitCode> {
+    if fetch && config.subscriptions_opml_url.is_some() {
+        match opml::sync(
+            config,
+            config_files,
+            caches.cache_dir(),
+            caches.state(),
+            false,
+            false,
+        )
+        .await
+        {
+            Ok(true) => {
+                *config = config_files
+                    .load()
+                    .context("Couldn't reload configuration after syncing subscriptions")
+                    .code(errors::ErrorCode::ConfigInvalid)?;
+            }
+            Ok(false) => {}
+            // The sites we already have can still be read.
+            Err(e) => log::warn!("Failed to sync subscriptions: {e:?}"),
+        }
+    }
+    let mut summary = summary::RunSummary::new(config);
+    let outputs = OutputPaths {
+        html: options.out_html.as_deref(),
+        feed: options.out_feed.as_deref(),
+        json: options.out_json.as_deref(),
+        manifest: options.manifest.as_deref(),
+        force: options.force,
+    };
+    let res = run(
+        config,
+        caches,
+        fetch,
+        &mut options.templates,
+        &outputs,
+        trace,
+        &mut summary,
+    )
+    .await;
+    if let Some(summary_path) = &options.summary {
+        if let Err(e) = &res {
+            summary.error = Some(e.into());
+        }
+        log::info!("Writing run summary to {}", summary_path.display());
+        summary.write(summary_path)?;
+    }
+    res
+}
+
+/// The exit code of `jarss fetch` when no site was due to be fetched.
+const NOTHING_DUE_EXIT_CODE: u8 = 3;
+
+/// Where a run writes its outputs, and whether it writes them regardless of how few articles
+/// there are.
+struct OutputPaths<'a> {
+    /// The HTML page, if any.
+    html: Option<&'a Path>,
+    /// The Atom feed, if any.
+    feed: Option<&'a Path>,
+    /// The JSON output, if any, which may be [`export::STDOUT_PATH`].
+    json: Option<&'a Path>,
+    /// The manifest of the other outputs, if requested.
+    manifest: Option<&'a Path>,
+    /// Whether to write the outputs even with `refuse_sparse_output` and too few articles.
+    force: bool,
+}
+impl OutputPaths<'_> {
+    /// The directory of the main output, which files written alongside it go in, and which
+    /// paths in the manifest are relative to.
+    fn base_dir(&self) -> &Path {
+        self.html
+            .or(self.feed)
+            .or(self.json_file())
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+    }
+
+    /// The file the JSON output is written to, if it's written to one rather than to stdout.
+    fn json_file(&self) -> Option<&Path> {
+        self.json
+            .filter(|&path| path != Path::new(export::STDOUT_PATH))
+    }
+}
+
+/// Fetch all the feeds if `fetch`, and generate the outputs from the cached feeds.
+///
+/// Without `fetch`, nothing is fetched, and every site must have been fetched before.
+async fn run(
+    config: &Config,
+    caches: &cache::CacheManager,
+    fetch: bool,
+    templates: &mut templates::Templates,
+    output_paths: &OutputPaths<'_>,
+    trace: &trace::ArticleTrace,
+    summary: &mut summary::RunSummary,
+) -> Result<ExitCode> {
+    replace::start_run();
+    // Checked before fetching, so a bad combination of paths fails before doing any work.
+    let planned_outputs = outputs::plan(config, output_paths);
+    outputs::check_collisions(&planned_outputs, output_paths.manifest)
+        .code(errors::ErrorCode::IoOutput)?;
+    let error_update = if fetch {
+        let github = github::GitHub::new(config, caches.state())?;
+        let origins = origins::Origins::load(caches.state());
+        let sites = config.sites.iter().collect::<Vec<_>>();
+        fetch_sites(config, caches, &github, &origins, &sites, summary).await?
+    } else {
+        let guard = caches.cache_guard();
+        for site in &config.sites {
+            let cache = caches
+                .get_mut(site, &guard)
+                .await
+                .with_context(|| format!("Error reading cache for {}", site.name))?;
+            if cache.last_body.is_none() {
+                anyhow::bail!(
+                    "Site {} hasn't been fetched yet, so there's nothing to render for it. Run \
+                     `jarss fetch` first.",
+                    site.name
+                );
+            }
+        }
+        false
+    };
+
+    let CollectedArticles {
+        articles,
+        errors,
+        mut search_index,
+        metadata,
+        random_picks,
+        site_stats,
+        new_articles,
+    } = collect_articles(config, caches, trace).await;
+    for (site_name, e) in errors {
+        if let Some(site_summary) = summary.site_mut(&site_name) {
+            site_summary.parse_error = Some((&e).into());
+        }
+    }
+    summary.shortfalls = canary::check(config, &articles);
+    canary::guard_outputs(config, &summary.shortfalls, output_paths.force)?;
+    let statuses = status::site_statuses(config, caches, SystemTime::now()).await;
+    let site_errors = status::site_errors(config, caches, summary).await;
+
+    let base_dir = output_paths.base_dir();
+    if let Some(out_html) = output_paths.html {
+        log::info!("Generating feed output at {}", out_html.display());
+        let tera = templates.page(&config.render)?;
+        let mut links = links::OutputLinks::new(
+            config.render.base_url.as_deref(),
+            out_html,
+            &planned_outputs,
+        )?;
+        let sites = status::site_infos(config, caches, &metadata, &articles).await;
+        let mut tera_ctx = page_context(
+            config,
+            &articles,
+            &random_picks,
+            &site_stats,
+            &statuses,
+            &sites,
+            &site_errors,
+        );
+        if let Some(search_index) = &search_index {
+            let index = search_index.encode(&articles)?;
+            if config.self_contained {
+                links.replace("search_index", standalone::data_uri(&index));
+            } else {
+                let index_path = base_dir.join(search::INDEX_FILE_NAME);
+                log::info!("Writing search index to {}", index_path.display());
+                search::write_index(&index_path, &index)
+                    .context("Error writing search index")
+                    .code(errors::ErrorCode::IoOutput)?;
+            }
+        }
+        // Kept for templates written before `output_url`.
+        tera_ctx.insert("search_index", &links.get("search_index"));
+        links.register(tera);
+        let page = tera
+            .render("output", &tera_ctx)
+            .context("Error rendering tera template")
+            .code(errors::ErrorCode::TemplateError)?;
+        if config.self_contained {
+            for resource in standalone::external_resources(&page) {
+                log::warn!("Output page isn't self-contained, it loads {resource}");
+            }
+        }
+        if let Some(budget) = &config.output_budget {
+            budget.check(out_html, page.len(), &articles)?;
+        }
+        replace::write_atomically(out_html, page.as_bytes())
+            .context("Failed to write to output file")
+            .code(errors::ErrorCode::IoOutput)?;
+    }
+    if let Some(search_index) = &mut search_index
+        && let Err(e) = search_index.save(caches.cache_dir())
+    {
+        // It's only lost work, the sites are indexed again next run.
+        log::warn!("{e:?}");
+    }
+    if let Some(out_feed) = output_paths.feed {
+        log::info!("Writing Atom feed to {}", out_feed.display());
+        let feed = atom::render_feed(out_feed, &articles).context("Error rendering Atom feed")?;
+        if let Some(budget) = &config.output_budget {
+            budget.check(out_feed, feed.len(), &articles)?;
+        }
+        replace::write_atomically(out_feed, feed.as_bytes())
+            .context("Error writing Atom feed")
+            .code(errors::ErrorCode::IoOutput)?;
+    }
+    if output_paths.json.is_some() {
+        let json = export::render_json(config, &articles, &site_stats)
+            .context("Error rendering JSON output")?;
+        if let Some(out_json) = output_paths.json_file() {
+            log::info!("Writing JSON output to {}", out_json.display());
+            if let Some(budget) = &config.output_budget {
+                budget.check(out_json, json.len(), &articles)?;
+            }
+            replace::write_atomically(out_json, json.as_bytes())
+                .context("Error writing JSON output")
+                .code(errors::ErrorCode::IoOutput)?;
+        } else {
+            // Logs all go to stderr, so this is the only thing on stdout.
+            log::info!("Writing JSON output to stdout");
+            println!("{json}");
+        }
+    }
+    if let Some(fragment_output) = &config.fragment_output {
+        let tera = templates.fragment(&fragment_output.template, &config.render)?;
+        fragment::write_fragment(fragment_output, tera, caches.state(), &articles)
+            .context("Error writing fragment")?;
+    }
+
+    if let Some(manifest_path) = output_paths.manifest {
+        log::info!("Writing manifest to {}", manifest_path.display());
+        let outputs = planned_outputs
+            .iter()
+            .map(|output| output.path.as_path())
+            .collect::<Vec<_>>();
+        manifest::write_manifest(manifest_path, base_dir, &outputs)
+            .context("Error writing manifest")
+            .code(errors::ErrorCode::IoOutput)?;
+    }
+
+    let notify_errors =
+        notify::notify(config, caches.state(), &new_articles, SystemTime::now()).await;
+    for (channel, e) in notify_errors {
+        let e = e.context(format!("Error notifying channel {channel}"));
+        log::error!("{e:?}");
+        summary
+            .notification_errors
+            .push(summary::NotificationError {
+                channel,
+                error: (&e).into(),
+            });
+    }
+
+    status::log_statuses(&statuses);
+    let short_or_unsent = !summary.shortfalls.is_empty() || !summary.notification_errors.is_empty();
+    Ok(if error_update || short_or_unsent {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The context the page's template is rendered with, apart from the search index.
+fn page_context(
+    config: &Config,
+    articles: &[FeedEntryInfo],
+    random_picks: &[FeedEntryInfo],
+    site_stats: &[reading::SiteStats],
+    statuses: &[status::SiteStatus],
+    sites: &[status::SiteInfo],
+    site_errors: &[status::SiteError],
+) -> tera::Context {
+    let mut tera_ctx = tera::Context::new();
+    tera_ctx.insert("articles", articles);
+    if let Some(group_by) = config.group_by {
+        tera_ctx.insert("grouped_articles", &grouping::group(articles, group_by));
+    }
+    tera_ctx.insert("random_picks", random_picks);
+    if config.site_stats.is_some() {
+        tera_ctx.insert("site_stats", site_stats);
+    }
+    tera_ctx.insert("site_status", statuses);
+    tera_ctx.insert("sites", sites);
+    tera_ctx.insert("errors", site_errors);
+    tera_ctx.insert("strings", &strings::strings(&config.render));
+    tera_ctx.insert("include_summaries", &config.include_summaries);
+    tera_ctx.insert("safe_mode", &config.safe_mode);
+    tera_ctx
+}
+
+/// Fetch the given sites, and save their caches.
+///
+/// Sites which can't be fetched yet are skipped. Errors are logged and recorded in the summary,
+/// and whether there were any is returned.
+async fn fetch_sites<'a>(
+    config: &Config,
+    caches: &cache::CacheManager,
+    github: &github::GitHub,
+    origins: &origins::Origins,
+    sites: &[&'a SiteConfig],
+    summary: &mut summary::RunSummary,
+) -> Result<bool> {
+    let mut errored = false;
+    if sites.iter().any(|site| site.feed_url.starts_with("https:")) {
+        config.http.check_roots()?;
+    }
+    let http_client = config.http.client_builder().build()?;
+    let fetch_guard = caches.cache_guard();
+    let (pre_resolver, by_origin) = {
+        let mut loaded = Vec::new();
+        for site in sites {
+            // A cache which fails to load is reported when its site is fetched.
+            loaded.push((*site, caches.get_mut(site, &fetch_guard).await.ok()));
+        }
+        let pre_resolver = dns::PreResolver::new(
+            config,
+            loaded
+                .iter()
+                .filter_map(|(site, cache)| Some((*site, &**cache.as_ref()?))),
+        );
+        let by_origin =
+            origins::by_origin(loaded.iter().map(|(site, cache)| (*site, cache.as_deref())));
+        (pre_resolver, by_origin)
+    };
+    let resolve_client = config
+        .http
+        .client_builder()
+        .timeout(Duration::from_secs(20))
+        .build()?;
+    let resolve_budget = AtomicUsize::new(config.max_link_resolutions);
+
+    let fetch = |site: &'a SiteConfig| {
+        async {
+            let mut cache = caches
+                .get_mut(site, &fetch_guard)
+                .await
+                .with_context(|| format!("Error reading cache for {}", site.name))?;
+            let mut res = if throttle::is_throttled(config, site, &cache, origins) {
+                Ok(())
+            } else if let Some(reset) = github.blocked_until(site, SystemTime::now()) {
+                log::warn!(
+                    "Not fetching {} because GitHub's rate limit is nearly used up, it resets at {}",
+                    site.name,
+                    throttle::describe_time(Some(reset))
+                );
+                Ok(())
+            } else {
+                match pre_resolver.client_for(site, &cache).await.transpose() {
+                    Ok(client) => {
+                        let client = client.as_ref().unwrap_or(&http_client);
+                        cache::query_site(client, config, github, origins, site, &mut cache)
+                            .await
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            };
+            if let Some(command) = &site.post_fetch_command {
+                let outcome = hooks::FetchOutcome {
+                    site: &site.name,
+                    feed_url: &site.feed_url,
+                    success: res.is_ok(),
+                    error: res.as_ref().err().map(Into::into),
+                };
+                if let Err(e) = hooks::post_fetch(command, config.hook_timeout, &outcome)
+                    .await
+                    .context("Error running post-fetch command")
+                    .code(errors::ErrorCode::HookFailed)
+                {
+                    res = res.and(Err(e));
+                }
+            }
+            if res.is_err() {
+                cache.record_failure(SystemTime::now());
+            }
+            res.context(format!(
+                "Error fetching feed {} from url {}",
+                site.name, site.feed_url
+            ))?;
+            if site.resolve_links {
+                resolve::resolve_links(&resolve_client, site, &mut cache, &resolve_budget)
+                    .await;
+            }
+            anyhow::Ok(())
+        }
+        .map(move |res| (site, res))
+    };
+    // Each origin's sites are fetched one after another, see `origins::by_origin`, and only take
+    // up one of the concurrent fetches between them.
+    let mut fetches = futures::stream::iter(by_origin)
+        .map(|sites| Box::pin(futures::stream::iter(sites).then(fetch)))
+        .flatten_unordered(config.max_concurrent_fetches.max(1));
+    // Certificates failing to verify is usually down to our setup rather than the sites, so it's
+    // reported once for all of them.
+    let mut untrusted = Vec::new();
+    while let Some((site, res)) = fetches.next().await {
+        if let Err(e) = res {
+            if errors::ErrorCode::of(&e) == errors::ErrorCode::FetchTls {
+                log::debug!("{e:?}");
+                untrusted.push((&*site.name, format!("{:#}", e.root_cause())));
+            } else {
+                log::error!("{:?}", e);
+            }
+            errored = true;
+            if let Some(site_summary) = summary.site_mut(&site.name) {
+                site_summary.fetch_error = Some((&e).into());
+            }
+        }
+    }
+    drop(fetches);
+    drop(fetch_guard);
+    if let Some((_, cause)) = untrusted.first() {
+        let names = untrusted
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::error!(
+            "Failed to fetch {} sites because their certificates couldn't be verified: {names}. \
+             The first failed with: {cause}. If every site fails like this, the trusted root \
+             certificates are likely missing or wrong. {}",
+            untrusted.len(),
+            client::TLS_ADVICE
+        );
+    }
+    if let Err(e) = github.save() {
+        log::warn!("{e:?}");
+    }
+    if let Err(e) = origins.save() {
+        log::warn!("{e:?}");
+    }
+    for (site_name, e) in caches.save(config.max_concurrent_saves).await {
+        log::error!("{:?}", e);
+        errored = true;
+        if let Some(site_summary) = summary.site_mut(&site_name) {
+            site_summary.save_error = Some((&e).into());
+        }
+    }
+    Ok(errored)
+}
+
+/// The articles collected from the cached feeds.
+struct CollectedArticles {
+    /// The articles, newest first.
+    articles: Vec<FeedEntryInfo>,
+    /// The errors which made us skip sites, by site name.
+    errors: Vec<(Box<str>, anyhow::Error)>,
+    /// Every article which can be searched, with [`Config::search_index`].
+    search_index: Option<search::StoredIndex>,
+    /// What each site's feed says about the site, in the order of [`Config::sites`].
+    metadata: Vec<status::FeedMetadata>,
+    /// The older articles picked at random, with [`Config::random_picks`].
+    random_picks: Vec<FeedEntryInfo>,
+    /// The statistics of each displayed site, with [`Config::site_stats`].
+    site_stats: Vec<reading::SiteStats>,
+    /// The articles which are new since the last fetch of their sites, including those of sites
+    /// which aren't displayed, for notifications.
+    new_articles: Vec<FeedEntryInfo>,
+}
+
+/// Parse the cached feeds and grab the most recent articles, newest first.
+///
+/// Errors with individual sites are logged and returned, and those sites skipped. What happens to
+/// the article being traced, if any, is logged at each stage.
+async fn collect_articles(
+    config: &Config,
+    caches: &cache::CacheManager,
+    trace: &trace::ArticleTrace,
+) -> CollectedArticles {
+    let mut errors = Vec::new();
+    // Make sure every site's cache is loaded, even if we didn't fetch it.
+    let load_guard = caches.cache_guard();
+    for site in &config.sites {
+        if let Err(e) = caches.get_mut(site, &load_guard).await {
+            let e = e.context(format!("Error reading cache for {}", site.name));
+            log::error!("{e:?}");
+            errors.push((site.name.clone(), e));
+        }
+    }
+    drop(load_guard);
+
+    let mut articles = Vec::new();
+    // Everything the random picks could be, if there are to be any.
+    let mut archive = Vec::new();
+    // And what every entry adds to the site statistics, if they're wanted.
+    let mut readings = Vec::new();
+    let mut search_index = config
+        .search_index
+        .then(|| search::StoredIndex::load(caches.cache_dir()));
+    // Sites are found by their caches' keys rather than their names, which needn't be unique.
+    let site_indices = config
+        .sites
+        .iter()
+        .enumerate()
+        .map(|(index, site)| (caches.storage_key(site), index))
+        .collect::<HashMap<_, _>>();
+    let mut metadata = vec![status::FeedMetadata::default(); config.sites.len()];
+    let feed_guard = caches.cache_guard();
+    let mut feeds = std::pin::pin!(caches.feeds(&feed_guard));
+    while let Some((cache_key, site_name, feed)) = feeds.next().await {
+        let cache::CachedFeed {
+            mut feed,
+            body_hash,
+            resolved_links,
+            entries_last_seen,
+            new_entries,
+            last_fetch_time,
+        } = match feed {
+            Ok(feed) => feed,
+            Err(e) => {
+                let e = e.context(format!("Error reading feed from {site_name}"));
+                log::error!("{e:?}");
+                errors.push((site_name.into(), e));
+                continue;
+            }
+        };
+        let site_index = site_indices.get(cache_key).copied().unwrap_or(usize::MAX);
+        if let Some(site) = config.sites.get(site_index) {
+            metadata[site_index] = status::FeedMetadata::of(site, &feed);
+        }
+        let site_link = metadata
+            .get(site_index)
+            .and_then(|metadata| metadata.link.clone());
+        let (max_age_days, max_age_setting) = match config
+            .sites
+            .get(site_index)
+            .and_then(|site| site.max_age_days)
+        {
+            Some(max_age_days) => (max_age_days, "the site's max_age_days"),
+            None => (config.max_age_days.unwrap_or(0), "max_age_days"),
+        };
+        let cutoff = (max_age_days > 0)
+            .then(|| chrono::Utc::now().checked_sub_days(chrono::Days::new(max_age_days)))
+            .flatten();
+        let feed_title = feed.title.as_mut().map_or(site_name, |title| {
+            title.sanitize();
+            &title.content
+        });
+        let feed_title = sanitize::limit(
+            &sanitize::normalize_title(feed_title, &config.limits),
+            config.limits.max_title_bytes,
+            &config.limits,
+            &format!("feed title from {site_name}"),
+        )
+        .text;
+        for entry in &mut feed.entries {
+            // Done before anything compares titles, so the filters and dedup see what's shown.
+            if let Some(title) = &mut entry.title {
+                title.content = sanitize::normalize_title(&title.content, &config.limits);
+            }
+            if entry.published.is_none() && entry.updated.is_none() && cutoff.is_some() {
+                // There's no telling how old it is, so it can't be shown.
+                log::debug!(
+                    "Dropping entry {:?} from {site_name}, which has no date to check against \
+                     {max_age_setting}",
+                    entry.id
+                );
+            } else if entry.published.is_none()
+                && entry.updated.is_none()
+                && let Some(fetched) = last_fetch_time
+            {
+                log::warn!(
+                    "Entry {:?} from {site_name} has no date, using when the feed was fetched",
+                    entry.id
+                );
+                entry.updated = Some(fetched.into());
+            }
+        }
+        // A stable sort, so entries published at the same time stay in the order of the feed, and
+        // are shown in the same order every run.
+        feed.entries
+            .sort_by_key(|entry| std::cmp::Reverse(entry.published.or(entry.updated)));
+        let (max_entries, max_entries_setting) = match config
+            .sites
+            .get(site_index)
+            .and_then(|site| site.max_entries)
+        {
+            Some(max_entries) => (max_entries, "the site's max_entries"),
+            None => (
+                config.max_entries_per_site.unwrap_or(usize::MAX),
+                "max_entries_per_site",
+            ),
+        };
+        let site_config = config.sites.get(site_index);
+        let filter = site_config.map(|site| &site.filter);
+        let junk = |entry: &feed_rs::model::Entry| {
+            site_config.and_then(|site| {
+                site.junk_filter.check(
+                    entry.title.as_ref().map(|title| title.content.as_str()),
+                    &site.name,
+                    &feed_title,
+                )
+            })
+        };
+        let recent = |entry: &feed_rs::model::Entry| {
+            cutoff.is_none_or(|cutoff| {
+                entry
+                    .published
+                    .or(entry.updated)
+                    .is_some_and(|date| date >= cutoff)
+            })
+        };
+        let allowed = |entry: &feed_rs::model::Entry| {
+            recent(entry)
+                && junk(entry).is_none()
+                && filter.is_none_or(|filter| filter.allows(entry))
+        };
+        match feed
+            .entries
+            .iter()
+            .position(|entry| trace.matches_entry(entry))
+        {
+            Some(position) => {
+                let entry = &feed.entries[position];
+                trace.found(
+                    site_name,
+                    entry
+                        .links
+                        .first()
+                        .and_then(|link| entries_last_seen.get(link.href.as_str()))
+                        .copied(),
+                );
+                // Its position among the entries left after filtering.
+                let position = feed.entries[..position]
+                    .iter()
+                    .filter(|entry| allowed(entry))
+                    .count();
+                if !recent(entry) {
+                    trace.event(format_args!(
+                        "excluded by {max_age_setting}, as it's more than {max_age_days} days \
+                         old or has no date"
+                    ));
+                } else if let Some(junk) = junk(entry) {
+                    trace.event(format_args!(
+                        "excluded as junk by the filters of {site_name}, as {junk}"
+                    ));
+                } else if !allowed(entry) {
+                    trace.event(format_args!(
+                        "excluded by the title and category filters of {site_name}"
+                    ));
+                } else if position >= max_entries {
+                    trace.event(format_args!(
+                        "excluded by {max_entries_setting}, as it's entry {} of {site_name}",
+                        position + 1
+                    ));
+                }
+            }
+            None => trace.check_history(site_name, &entries_last_seen),
+        }
+        let junk_entries = feed
+            .entries
+            .iter()
+            .filter(|entry| recent(entry) && junk(entry).is_some())
+            .count();
+        if junk_entries > 0 {
+            log::debug!("Dropped {junk_entries} junk entries from {site_name}");
+        }
+        if picks::wanted(config) && site_config.is_some_and(|site| site.display) {
+            // However old they are, since that's the point, but they must get through the filters.
+            archive.extend(
+                feed.entries
+                    .iter()
+                    .filter(|entry| {
+                        junk(entry).is_none() && filter.is_none_or(|filter| filter.allows(entry))
+                    })
+                    .filter_map(|entry| {
+                        let mut info = FeedEntryInfo::new(
+                            site_index,
+                            &feed_title,
+                            entry,
+                            &resolved_links,
+                            None,
+                            false,
+                            &config.limits,
+                        )
+                        .ok()?;
+                        info.site_link = site_link.clone();
+                        Some(info)
+                    }),
+            );
+        }
+        if let Some(index) = &mut search_index
+            && let Some(site) = config.sites.get(site_index).filter(|site| site.display)
+        {
+            let fingerprint = search::fingerprint(
+                site,
+                &config.limits,
+                &config.junk,
+                &body_hash,
+                &resolved_links,
+            );
+            if index.is_current(site_name, &fingerprint) {
+                index.forget_old(site_name, &entries_last_seen);
+            } else {
+                // Like the random picks, however old they are, as long as they get through the
+                // filters.
+                let indexed = feed
+                    .entries
+                    .iter()
+                    .filter(|entry| {
+                        junk(entry).is_none() && filter.is_none_or(|filter| filter.allows(entry))
+                    })
+                    .filter_map(|entry| {
+                        let feed_link = &entry.links.first()?.href;
+                        let info = FeedEntryInfo::new(
+                            site_index,
+                            &feed_title,
+                            entry,
+                            &resolved_links,
+                            None,
+                            false,
+                            &config.limits,
+                        )
+                        .ok()?;
+                        Some(search::StoredArticle::new(&info, feed_link))
+                    })
+                    .collect();
+                index.update(site_name, &fingerprint, indexed, &entries_last_seen);
+            }
+        }
+        if config.site_stats.is_some() && site_config.is_some_and(|site| site.display) {
+            readings.extend(
+                feed.entries
+                    .iter()
+                    .filter(|entry| {
+                        junk(entry).is_none() && filter.is_none_or(|filter| filter.allows(entry))
+                    })
+                    .filter_map(|entry| {
+                        let text = entry
+                            .content
+                            .as_ref()
+                            .and_then(|content| content.body.as_deref())
+                            .or(entry
+                                .summary
+                                .as_ref()
+                                .map(|summary| summary.content.as_str()));
+                        Some(reading::EntryReading {
+                            site_index,
+                            published: entry.published.or(entry.updated)?,
+                            words: text.map(sanitize::word_count),
+                        })
+                    }),
+            );
+        }
+        feed.entries.retain(|entry| allowed(entry));
+        let newest_entries = match feed
+            .entries
+            .iter()
+            .take(max_entries)
+            .enumerate()
+            .map(|(feed_position, entry)| {
+                FeedEntryInfo::new(
+                    site_index,
+                    &feed_title,
+                    entry,
+                    &resolved_links,
+                    entry
+                        .links
+                        .first()
+                        .and_then(|link| entries_last_seen.get(link.href.as_str()))
+                        .copied(),
+                    new_entries.contains(cache::entry_id(entry)),
+                    &config.limits,
+                )
+                .map(|mut info| {
+                    info.site_link = site_link.clone();
+                    info.feed_position = feed_position;
+                    (info, entry)
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .code(errors::ErrorCode::ParseInvalidEntry)
+        {
+            Ok(entries) => entries
+                .into_iter()
+                .filter(|(entry, _)| {
+                    let size = serde_json::to_vec(entry).map_or(usize::MAX, |json| json.len());
+                    if size > config.limits.max_entry_bytes {
+                        log::warn!(
+                            "Dropping entry {:?} from {site_name}, which is {size} bytes",
+                            entry.link
+                        );
+                        if trace.matches(entry) {
+                            trace.event(format_args!(
+                                "excluded by max_entry_bytes, as it's {size} bytes"
+                            ));
+                        }
+                    }
+                    size <= config.limits.max_entry_bytes
+                })
+                // Only added once the entry's size is checked, since the whole parsed entry is
+                // much bigger than what we take from it.
+                .map(|(mut info, entry)| {
+                    if config.render.expose_raw_entries {
+                        info.raw = sanitize::neutralized_json(entry);
+                    }
+                    info
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                let e = e.context(format!("Error parsing entries in field from {site_name}"));
+                if feed.entries.iter().any(|entry| trace.matches_entry(entry)) {
+                    trace.event(format_args!("excluded along with its whole site: {e:#}"));
+                }
+                log::error!("{e:?}");
+                errors.push((site_name.into(), e));
+                continue;
+            }
+        };
+        articles.extend_from_slice(&newest_entries);
+    }
+    if let Some(index) = &mut search_index {
+        index.retain_sites(|name| {
+            config
+                .sites
+                .iter()
+                .any(|site| site.display && *site.name == *name)
+        });
+    }
+    let articles = dedup::dedup_articles(config, articles, trace);
+    // Sites which aren't displayed still notify, so the new articles are kept before they're left
+    // out, along with those the page's limits leave out.
+    let new_articles = articles
+        .iter()
+        .filter(|article| article.is_new)
+        .cloned()
+        .collect();
+    let mut articles = dedup::hide_undisplayed(config, articles, trace);
+    dedup::sort_articles(config, &mut articles);
+    let mut articles = groups::apply_limits(config, articles, chrono::Utc::now(), trace);
+    for (index, article) in articles.iter_mut().enumerate() {
+        article.rank = index + 1;
+    }
+    trace.finish(articles.iter().any(|article| trace.matches(article)));
+    let random_picks = match &config.random_picks {
+        Some(picks) => picks::pick(picks, archive, &articles, chrono::Utc::now()),
+        None => Vec::new(),
+    };
+    let site_stats = match &config.site_stats {
+        Some(settings) => reading::site_stats(
+            settings,
+            &config.sites,
+            &readings,
+            &chrono::Local,
+            chrono::Utc::now(),
+        ),
+        None => Vec::new(),
+    };
+    CollectedArticles {
+        articles,
+        errors,
+        search_index,
+        metadata,
+        random_picks,
+        site_stats,
+        new_articles,
+    }
+}
+
+/// An article, as given to templates.
+///
+/// Apart from [`summary`](Self::summary) and [`content`](Self::content), none of the strings here
+/// are sanitized HTML, so templates must not mark them `safe`. Tera escapes them when rendering,
+/// and any tera syntax is broken up by [`sanitize`] in case a template renders them again.
+#[derive(Clone, Debug, serde::Serialize)]
+struct FeedEntryInfo {
+    /// The title of the site, as given by its feed. Plain text.
+    site: Box<str>,
+    /// The site's homepage, if its feed gives one.
+    site_link: Option<Box<str>>,
+    published: chrono::DateTime<chrono::Utc>,
+    publish_date: chrono::NaiveDate,
+    /// The title of the article. Plain text.
+    title: Box<str>,
+    /// The ID of the article in its feed, or its link in the feed if it has none. Plain text.
+    ///
+    /// This stays the same as long as the feed carries the article, so it's what tells articles
+    /// apart between runs.
+    id: Box<str>,
+    /// The summary of the article, if the feed gives one, as sanitized HTML.
+    summary: Option<Box<str>>,
+    /// The content of the article, if the feed gives it, as sanitized HTML.
+    content: Option<Box<str>>,
+    /// The start of the summary, or else the content, as plain text. Truncated to
+    /// [`summary_text_chars`](sanitize::Limits::summary_text_chars).
+    summary_text: Option<Box<str>>,
+    /// Whether the title or summary looked like garbage and were replaced with a placeholder.
+    suspect: bool,
+    /// The link to the article.
+    link: Box<str>,
+    /// The link to the article as it should be shown, with unicode left readable.
+    ///
+    /// This is for visible text only, links should go to [`link`](Self::link).
+    link_display: Box<str>,
+    /// The last time we saw this article in its site's feed, when fetching it.
+    last_seen_in_feed: Option<chrono::DateTime<chrono::Utc>>,
+    /// The link given by the feed, if it redirected elsewhere and [`link`](Self::link) is where
+    /// it ended up.
+    original_link: Option<Box<str>>,
+    /// Other sites which carried this same article.
+    also_on: Vec<dedup::AlsoOn>,
+    /// How many of the configured sites carried this article, including its own.
+    ///
+    /// Sites which aren't displayed are counted too, so this can be more than one more than the
+    /// length of [`also_on`](Self::also_on).
+    mentions: usize,
+    /// Whether the article first showed up in its site's feed in the latest fetch of it.
+    is_new: bool,
+    /// The article's position among those shown, from 1, once they're all sorted and limited.
+    ///
+    /// This is the same in every output from a run.
+    rank: usize,
+    /// The entry as parsed from the feed, with
+    /// [`expose_raw_entries`](strings::RenderConfig::expose_raw_entries), as a
+    /// [`feed_rs::model::Entry`] serialized to JSON with tera syntax broken up in its strings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<serde_json::Value>,
+    /// The position of the site this came from in [`Config::sites`].
+    #[serde(skip)]
+    site_index: usize,
+    /// The position of this among its site's entries, newest first, with those published at the
+    /// same time in the order of the feed.
+    ///
+    /// This breaks ties between articles published at the same time, so they're sorted the same
+    /// way every run.
+    #[serde(skip)]
+    feed_position: usize,
+}
+impl FeedEntryInfo {
+    fn new(
+        site_index: usize,
+        site_name: &str,
+        entry: &feed_rs::model::Entry,
+        resolved_links: &HashMap<Box<str>, Box<str>>,
+        last_seen_in_feed: Option<SystemTime>,
+        is_new: bool,
+        limits: &sanitize::Limits,
+    ) -> Result<Self> {
+        let published = entry
+            .published
+            .or(entry.updated)
+            .context("Entry missing published time")?;
+        let feed_link = entry
+            .links
+            .first()
+            .context("Entry missing link")?
+            .href
+            .as_str();
+        let (link, original_link) = match resolved_links.get(feed_link) {
+            Some(resolved) if resolved.as_ref() != feed_link => (
+                sanitize::neutralize_link(resolved),
+                Some(sanitize::neutralize_link(feed_link)),
+            ),
+            _ => (sanitize::neutralize_link(feed_link), None),
+        };
+        let mut title = entry.title.clone().context("Entry missing title")?;
+        title.sanitize();
+        let title = sanitize::limit(
+            &title.content,
+            limits.max_title_bytes,
+            limits,
+            &format!("title from {site_name}"),
+        );
+        let summary = entry.summary.as_ref().and_then(|summary| {
+            let limited = sanitize::limit(
+                &summary.content,
+                limits.max_summary_bytes,
+                limits,
+                &format!("summary from {site_name}"),
+            );
+            let html = sanitize::to_safe_html(&limited.text, summary.content_type.as_str())?;
+            Some((html, limited.suspect))
+        });
+        let content = entry.content.as_ref().and_then(|content| {
+            let limited = sanitize::limit(
+                content.body.as_deref()?,
+                limits.max_content_bytes,
+                limits,
+                &format!("content from {site_name}"),
+            );
+            sanitize::to_safe_html(&limited.text, content.content_type.as_str())
+        });
+        let summary_text = summary
+            .as_ref()
+            .map(|(html, _)| html)
+            .or(content.as_ref())
+            .map(|html| sanitize::to_plain_text(html, limits))
+            .filter(|text| !text.is_empty());
+        Ok(Self {
+            site: sanitize::neutralize_template_syntax(site_name),
+            site_link: None,
+            published,
+            publish_date: published.date_naive(),
+            title: sanitize::neutralize_template_syntax(&title.text),
+            id: sanitize::neutralize_template_syntax(cache::entry_id(entry)),
+            suspect: title.suspect || summary.as_ref().is_some_and(|(_, suspect)| *suspect),
+            summary: summary.map(|(html, _)| sanitize::neutralize_template_syntax(&html)),
+            content: content.map(|html| sanitize::neutralize_template_syntax(&html)),
+            summary_text: summary_text.map(|text| sanitize::neutralize_template_syntax(&text)),
+            link_display: url_display::display_url(&link),
+            link,
+            last_seen_in_feed: last_seen_in_feed.map(Into::into),
+            original_link,
+            also_on: Vec::new(),
+            mentions: 1,
+            is_new,
+            // Assigned once every article is collected.
+            rank: 0,
+            raw: None,
+            site_index,
+            // Assigned once the entry's position is known.
+            feed_position: 0,
+        })
+    }
+}
+
+/// The configuration file schema.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+struct Config {
+    /// The list of sites being used.
+    sites: Vec<SiteConfig>,
+    /// The minimum interval between fetches of the same site, in seconds.
+    min_fetch_interval: u64,
+    /// The maximum amount of entries from a given site.
+    max_entries_per_site: Option<usize>,
+    /// The maximum total amount of entries to display.
+    max_total_entries: Option<usize>,
+    /// The most days old an article can be to be shown, if there's a limit.
+    ///
+    /// Articles without a date aren't shown when there's a limit, since there's no telling how
+    /// old they are.
+    #[serde(default)]
+    max_age_days: Option<u64>,
+    /// How to drop junk entries, like housekeeping posts with empty titles, from every site.
+    #[serde(default)]
+    junk: junk::JunkConfig,
+    /// How to group the articles given to templates as `grouped_articles`, if at all.
+    ///
+    /// Articles are grouped after the limits on how many to show are applied.
+    #[serde(default)]
+    group_by: Option<grouping::GroupBy>,
+    /// Limits on the articles from all the sites with a given tag, keyed by the tag.
+    #[serde(default)]
+    groups: HashMap<Box<str>, groups::GroupLimits>,
+    /// What to do with articles which appear on more than one site.
+    #[serde(default)]
+    dedup_mode: dedup::DedupMode,
+    /// Whether to also treat articles from different sites as the same if their titles match,
+    /// ignoring case, punctuation, and whitespace, and they were published within
+    /// `fuzzy_dedup_window` of each other.
+    ///
+    /// This catches articles cross-posted under different links, but can merge unrelated articles
+    /// which happen to share a title.
+    #[serde(default)]
+    fuzzy_dedup: bool,
+    /// Whether articles carried by more than one site are moved up among the articles published
+    /// on the same day, with those carried by the most sites first.
+    ///
+    /// This doesn't move any article past one from a different day.
+    #[serde(default)]
+    boost_multi_source: bool,
+    /// How far apart articles with matching titles can be published and still be treated as the
+    /// same, with `fuzzy_dedup`.
+    #[serde(default = "default_fuzzy_dedup_window", with = "human_duration")]
+    fuzzy_dedup_window: Duration,
+    /// Whether caches are identified by site name or feed URL.
+    #[serde(default)]
+    cache_key: cache::CacheKey,
+    /// Whether URLs which differ only in a trailing slash, like `https://example.com/feed` and
+    /// `https://example.com/feed/`, are taken to be the same.
+    ///
+    /// This is off by default, since some servers serve different things at each.
+    #[serde(default)]
+    unify_trailing_slashes: bool,
+    /// Whether to write a search index of the articles alongside the output page, with their
+    /// titles, sites, dates, links, and the starts of their summaries.
+    ///
+    /// This covers every article of the displayed sites which their caches remember, not only
+    /// those on the page. It's kept in the cache directory, and only the sites whose feeds or
+    /// settings changed are indexed again each run.
+    #[serde(default)]
+    search_index: bool,
+    /// Whether the default template shows the start of each article's summary.
+    #[serde(default)]
+    include_summaries: bool,
+    /// Whether the output page should work on its own, without loading anything else.
+    ///
+    /// The search index, if any, is embedded in the page, and we warn about anything the page
+    /// would still load from elsewhere.
+    #[serde(default)]
+    self_contained: bool,
+    /// How the page is rendered.
+    #[serde(default)]
+    render: strings::RenderConfig,
+    /// The channels to notify of new articles, each of which is sent the new articles its
+    /// selector matches, on its own schedule.
+    #[serde(default)]
+    notifications: Vec<notify::Channel>,
+    /// How to pick older articles at random for templates, as `random_picks`, if at all.
+    #[serde(default)]
+    random_picks: Option<picks::RandomPicks>,
+    /// How to count the articles and estimated reading time of each site, for templates as
+    /// `site_stats`, if at all.
+    ///
+    /// These count every entry still in the sites' cached feeds which gets through their filters,
+    /// not only those shown.
+    #[serde(default)]
+    site_stats: Option<reading::SiteStatsConfig>,
+    /// A limit on the size of the page and Atom feed, if any.
+    #[serde(default)]
+    output_budget: Option<budget::OutputBudget>,
+    /// The fewest articles a run is expected to show, so that something breaking and leaving the
+    /// page nearly empty is noticed.
+    ///
+    /// A run showing fewer logs an error and exits with a failure, after writing its outputs
+    /// unless `refuse_sparse_output` is set.
+    #[serde(default)]
+    min_expected_articles: Option<usize>,
+    /// Whether a run with fewer articles than `min_expected_articles`, or than a site's
+    /// `min_expected_entries`, leaves the previous outputs in place, unless given `--force`.
+    #[serde(default)]
+    refuse_sparse_output: bool,
+    /// Where to also write a fragment of only the articles which weren't on the last page.
+    #[serde(default)]
+    fragment_output: Option<fragment::FragmentOutput>,
+    /// How long a site can keep failing before the page warns about it.
+    #[serde(default = "default_warn_after", with = "human_duration")]
+    warn_after: Duration,
+    /// How long a site can keep failing before the page alerts about it.
+    #[serde(default = "default_alert_after", with = "human_duration")]
+    alert_after: Duration,
+    /// The maximum number of requests to make per run in resolving entry links.
+    #[serde(default = "default_max_link_resolutions")]
+    max_link_resolutions: usize,
+    /// The most feeds to fetch at once.
+    ///
+    /// Feeds on the same origin are fetched one after another, and only count once.
+    #[serde(default = "default_max_concurrent_fetches")]
+    max_concurrent_fetches: usize,
+    /// The most caches to save at once at the end of a fetch.
+    #[serde(default = "default_max_concurrent_saves")]
+    max_concurrent_saves: usize,
+    /// Whether to resolve the hosts of all the feeds at the start of a run.
+    ///
+    /// This lets DNS failures be reported as such, and stops a slow resolver holding up the
+    /// fetches from other hosts. It's skipped when `http.proxy` is set, since the proxy is what
+    /// resolves the hosts then.
+    #[serde(default = "default_dns_preresolve")]
+    dns_preresolve: bool,
+    /// How long to wait for each host to resolve, when pre-resolving.
+    #[serde(default = "default_dns_timeout", with = "human_duration")]
+    dns_timeout: Duration,
+    /// A file holding a GitHub token, to fetch GitHub release feeds through the API with.
+    ///
+    /// The `GITHUB_TOKEN` environment variable takes precedence over this.
+    #[serde(default)]
+    github_token_file: Option<PathBuf>,
+    /// An OPML file listing the feeds to read, which the sites are kept in sync with.
+    ///
+    /// New feeds in it are added as sites, and what happens to sites whose feeds aren't in it is
+    /// set by `subscriptions_vanished`. It's fetched at the start of runs, at most once every
+    /// `subscriptions_sync_interval`.
+    #[serde(default)]
+    subscriptions_opml_url: Option<Box<str>>,
+    /// The least time between fetches of `subscriptions_opml_url`.
+    #[serde(
+        default = "default_subscriptions_sync_interval",
+        with = "human_duration"
+    )]
+    subscriptions_sync_interval: Duration,
+    /// What to do with sites whose feeds are no longer in `subscriptions_opml_url`.
+    #[serde(default)]
+    subscriptions_vanished: opml::VanishedAction,
+    /// How long pre- and post-fetch commands may run before they're killed.
+    #[serde(default = "default_hook_timeout", with = "human_duration")]
+    hook_timeout: Duration,
+    /// Limits on the size and content of feeds and their entries.
+    #[serde(default)]
+    limits: sanitize::Limits,
+    /// How requests are made: their timeouts, any proxy, and the user agent.
+    #[serde(default)]
+    http: client::HttpConfig,
+    /// Whether a feed served as an HTML page fails its fetch, rather than only being warned about.
+    #[serde(default)]
+    strict_content_type: bool,
+    /// Whether this config was loaded in safe mode, with the settings of optional features
+    /// ignored.
+    #[serde(skip)]
+    safe_mode: bool,
+}
+
+fn default_dns_preresolve() -> bool {
+    true
+}
+
+fn default_dns_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_display() -> bool {
+    true
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_fuzzy_dedup_window() -> Duration {
+    Duration::from_secs(48 * 60 * 60)
+}
+
+fn default_subscriptions_sync_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_hook_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_concurrent_fetches() -> usize {
+    8
+}
+
+fn default_max_concurrent_saves() -> usize {
+    16
+}
+
+fn default_max_link_resolutions() -> usize {
+    20
+}
+
+fn default_warn_after() -> Duration {
+    Duration::from_secs(3 * 24 * 60 * 60)
+}
+
+fn default_alert_after() -> Duration {
+    Duration::from_secs(14 * 24 * 60 * 60)
+}
+
+/// (De)serialize a [`Duration`] as a human-readable string, like `"3d"` or `"1h 30m"`.
+mod human_duration {
+    use serde::Deserialize as _;
+    use std::time::Duration;
+
+    pub fn serialize<S: serde::Serializer>(duration: &Duration, ser: S) -> Result<S::Ok, S::Error> {
+        ser.collect_str(&humantime::format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Duration, D::Error> {
+        let raw = std::borrow::Cow::<str>::deserialize(de)?;
+        humantime::parse_duration(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct SiteConfig {
+    /// The name of the site.
+    name: Box<str>,
+    /// The URL of the feed to read.
+    feed_url: Box<str>,
+    /// Whether the site's articles are shown in the output.
+    ///
+    /// Sites which aren't displayed are still fetched, and their articles can still be the
+    /// canonical copies when deduplicating.
+    #[serde(default = "default_display")]
+    display: bool,
+    /// Whether the site is read at all.
+    ///
+    /// Disabled sites are left out as if they weren't in the config, but keep their settings.
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    /// The tags of the site, which place it in the corresponding `[groups]`.
+    #[serde(default)]
+    tags: Vec<Box<str>>,
+    /// The minimum number of seconds between fetches of this site, in place of the global
+    /// `min_fetch_interval`.
+    ///
+    /// `0` means the site is fetched on every run.
+    min_fetch_interval: Option<u64>,
+    /// The most articles to show from this site, in place of the global `max_entries_per_site`.
+    ///
+    /// This counts only the articles left after the filters below.
+    max_entries: Option<usize>,
+    /// The fewest articles this site is expected to have shown, like the global
+    /// `min_expected_articles`.
+    #[serde(default)]
+    min_expected_entries: Option<usize>,
+    /// The most days old an article from this site can be to be shown, in place of the global
+    /// `max_age_days`.
+    ///
+    /// `0` means there's no limit for this site.
+    #[serde(default)]
+    max_age_days: Option<u64>,
+    /// Only show articles whose titles match this regex.
+    #[serde(default)]
+    include_title_regex: Option<Box<str>>,
+    /// Don't show articles whose titles match this regex.
+    #[serde(default)]
+    exclude_title_regex: Option<Box<str>>,
+    /// Don't show articles in any of these categories, regardless of case.
+    #[serde(default)]
+    exclude_categories: Vec<Box<str>>,
+    /// The compiled filters from the settings above, filled in once the config is loaded.
+    #[serde(skip)]
+    filter: filter::EntryFilter,
+    /// How to drop junk entries from this site, in place of the global `[junk]` settings.
+    #[serde(default)]
+    junk: junk::JunkConfig,
+    /// The compiled junk filter, from `junk` and the global settings, filled in once the config
+    /// is loaded.
+    #[serde(skip)]
+    junk_filter: junk::JunkFilter,
+    /// Whether to follow redirects on entry links, so the page links to their final location.
+    #[serde(default)]
+    resolve_links: bool,
+    /// A command to run before each fetch, to determine what to request.
+    ///
+    /// The command should print either the URL to fetch, or a JSON object with the `url` and a
+    /// `headers` object of extra headers to send.
+    pre_fetch_command: Option<Vec<String>>,
+    /// A command to run after each fetch, which is given the outcome as JSON on stdin.
+    post_fetch_command: Option<Vec<String>>,
+    /// The username and password file to authenticate with, if the site asks for HTTP digest
+    /// auth.
+    digest_auth: Option<digest_auth::DigestAuth>,
+    /// The username and password to authenticate with using HTTP basic auth, which are sent with
+    /// every request.
+    #[serde(default)]
+    basic_auth: Option<auth::BasicAuth>,
+    /// Extra headers to send with every request, like `Authorization = "Bearer ..."`.
+    ///
+    /// These are sent to the site's origin only, and not after redirects to elsewhere.
+    #[serde(default)]
+    headers: HashMap<Box<str>, Box<str>>,
+    /// Extra headers to send with every request, by the environment variable holding each one's
+    /// value, so tokens needn't be in the config.
+    #[serde(default)]
+    header_env: HashMap<Box<str>, Box<str>>,
+    /// The HTTP method to fetch the feed with.
+    #[serde(default)]
+    method: post::Method,
+    /// The body to send with `method = "POST"`, either as a string or as a table to send as JSON.
+    #[serde(default)]
+    body: Option<post::Body>,
+    /// An environment variable holding the body to send, in place of `body`, so secrets needn't
+    /// be in the config.
+    #[serde(default)]
+    body_env: Option<Box<str>>,
+    /// The `content-type` of the body, which must be given along with it.
+    #[serde(default)]
+    content_type: Option<Box<str>>,
+}
+
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_DESCRIBE"),
+    ") <",
+    env!("CARGO_PKG_REPOSITORY"),
+    "> RSS Feed Reader"
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Args, Config, InferredArgs, InferredCommand, OutputPaths, cache, summary,
+        templates::Templates, test_server, test_util::test_dir, trace::ArticleTrace,
+    };
+    use clap::Parser as _;
+    use std::{
+        path::Path,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+        },
+    };
+
+    /// A feed with one article titled `title`.
+    fn feed(title: &str) -> String {
+        format!(
+            "<rss version=\"2.0\"><channel><title>{title}</title><item><title>{title}</title>\
+             <link>https://example.com/{title}</link>\
+             <pubDate>Wed, 14 Oct 2026 12:00:00 GMT</pubDate></item></channel></rss>"
+        )
+    }
+
+    /// Run jarss with the config and cache in `dir`, rendering to `out_html` with `template` if
+    /// it's given.
+    async fn run(dir: &Path, out_html: &Path, template: Option<&Path>) {
+        let paths = [&dir.join("jarss.toml"), &dir.join("cache"), out_html]
+            .map(|path| path.to_str().unwrap().to_owned());
+        let template = template.map(|path| path.to_str().unwrap().to_owned());
+        let args = ["jarss", "--config", &paths[0], "--cache", &paths[1], "run"]
+            .into_iter()
+            .chain(
+                template
+                    .as_deref()
+                    .map(|template| ["--feed-template", template])
+                    .into_iter()
+                    .flatten(),
+            )
+            .chain([paths[2].as_str()]);
+        crate::dispatch(
+            <crate::Args as clap::Parser>::parse_from(args)
+                .try_into()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_so_many_feeds_are_fetched_at_once() {
+        let dir = test_dir("fetch-limit");
+        let (in_flight, most_in_flight) =
+            (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        // Each site is on its own origin, since an origin's sites are fetched one at a time.
+        let mut sites = String::new();
+        for i in 0..5 {
+            let feed = test_server::serve_http_after({
+                let (in_flight, most_in_flight) =
+                    (Arc::clone(&in_flight), Arc::clone(&most_in_flight));
+                move |_| {
+                    let (in_flight, most_in_flight) =
+                        (Arc::clone(&in_flight), Arc::clone(&most_in_flight));
+                    async move {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_in_flight.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        test_server::response(
+                            "200 OK",
+                            "application/rss+xml",
+                            "<rss version=\"2.0\"><channel><title>Feed</title></channel></rss>",
+                        )
+                    }
+                }
+            })
+            .await;
+            sites += &format!("[[sites]]\nname = \"{i}\"\nfeed_url = \"http://{feed}/feed\"\n");
+        }
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\nmax_concurrent_fetches = 2\n{sites}"
+        ))
+        .unwrap();
+        let caches =
+            cache::CacheManager::new(dir.join("cache"), cache::CacheKey::Name, &config.sites)
+                .unwrap();
+        crate::run(
+            &config,
+            &caches,
+            true,
+            &mut Templates::with_page("{{ articles | length }}"),
+            &OutputPaths {
+                html: Some(&dir.join("index.html")),
+                feed: None,
+                json: None,
+                manifest: None,
+                force: false,
+            },
+            &ArticleTrace::new(None),
+            &mut summary::RunSummary::new(&config),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn sites_can_override_how_many_entries_are_shown() {
+        let dir = test_dir("max-entries");
+        let feeds = test_server::serve_http(|head| {
+            let site = head
+                .split(' ')
+                .nth(1)
+                .unwrap_or_default()
+                .trim_start_matches('/');
+            let items = (1..=3)
+                .map(|day| {
+                    format!(
+                        "<item><title>{day}</title><link>https://{site}.example/{day}</link>\
+                         <pubDate>0{day} Jan 2024 00:00:00 GMT</pubDate></item>"
+                    )
+                })
+                .collect::<String>();
+            test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                &format!(
+                    "<rss version=\"2.0\"><channel><title>{site}</title>{items}</channel></rss>"
+                ),
+            )
+        })
+        .await;
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\nmax_entries_per_site = 2\n\
+             [[sites]]\nname = \"Default\"\nfeed_url = \"http://{feeds}/default\"\n\
+             [[sites]]\nname = \"Fewer\"\nfeed_url = \"http://{feeds}/fewer\"\nmax_entries = 1\n\
+             [[sites]]\nname = \"More\"\nfeed_url = \"http://{feeds}/more\"\nmax_entries = 3\n"
+        ))
+        .unwrap();
+        let caches =
+            cache::CacheManager::new(dir.join("cache"), cache::CacheKey::Name, &config.sites)
+                .unwrap();
+        crate::run(
+            &config,
+            &caches,
+            true,
+            &mut Templates::with_page(
+                "{% for article in articles %}{{ article.link }}\n{% endfor %}",
+            ),
+            &OutputPaths {
+                html: Some(&dir.join("index.html")),
+                feed: None,
+                json: None,
+                manifest: None,
+                force: false,
+            },
+            &ArticleTrace::new(None),
+            &mut summary::RunSummary::new(&config),
+        )
+        .await
+        .unwrap();
+        let page = std::fs::read_to_string(dir.join("index.html")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut links = page.lines().collect::<Vec<_>>();
+        links.sort_unstable();
+        assert_eq!(
+            links,
+            [
+                "https://default.example/2",
+                "https://default.example/3",
+                "https://fewer.example/3",
+                "https://more.example/1",
+                "https://more.example/2",
+                "https://more.example/3",
+            ]
+        );
+    }
+
+    #[test]
+    fn page_options_are_only_taken_with_run_or_render() {
+        let infer = |args: &[&str]| {
+            let args = ["jarss", "--config", "jarss.toml", "--cache", "cache"]
+                .iter()
+                .chain(args);
+            InferredArgs::try_from(Args::try_parse_from(args).unwrap())
+        };
+        let e = infer(&["--summary", "summary.json", "fetch"])
+            .err()
+            .unwrap();
+        assert!(
+            e.to_string().contains("can't be given with a subcommand"),
+            "{e}"
+        );
+        for (args, fetches) in [
+            (&["run", "out.html"][..], true),
+            (&["render", "out.html"], false),
+            (&["out.html"], true),
+        ] {
+            let Ok(InferredArgs {
+                command: InferredCommand::Run { fetch, options },
+                ..
+            }) = infer(args)
+            else {
+                panic!("{args:?} doesn't generate the page");
+            };
+            assert_eq!(fetch, fetches, "{args:?}");
+            assert_eq!(
+                options.out_html.as_deref(),
+                Some("out.html".as_ref()),
+                "{args:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn articles_older_than_max_age_days_are_dropped() {
+        let recent = (chrono::Utc::now() - chrono::Days::new(1)).to_rfc2822();
+        let collected = crate::test_util::collect_cached(
+            "max-age",
+            "min_fetch_interval = 0\nmax_age_days = 7\nsearch_index = true\n\
+             [[sites]]\nname = \"limited\"\nfeed_url = \"https://limited.example/feed\"\n\
+             [[sites]]\nname = \"unlimited\"\nfeed_url = \"https://unlimited.example/feed\"\n\
+             max_age_days = 0\n",
+            |site| {
+                // Only with a limit, as without one it'd be dated by when the feed was fetched,
+                // which these caches don't have.
+                let undated = if site == "limited" {
+                    "<item><title>Undated</title><link>https://limited.example/undated</link></item>"
+                } else {
+                    ""
+                };
+                format!(
+                    "<rss version=\"2.0\"><channel><title>{site}</title>\
+                     <item><title>{site} recent</title><link>https://{site}.example/recent</link>\
+                     <pubDate>{recent}</pubDate></item>\
+                     <item><title>{site} old</title><link>https://{site}.example/old</link>\
+                     <pubDate>Sat, 01 Jan 2000 00:00:00 GMT</pubDate></item>\
+                     {undated}\
+                     </channel></rss>"
+                )
+            },
+        )
+        .await;
+        assert!(collected.errors.is_empty());
+        let mut links = collected
+            .articles
+            .iter()
+            .map(|article| &*article.link)
+            .collect::<Vec<_>>();
+        links.sort_unstable();
+        // Undated entries can't be checked against a limit, so they're dropped.
+        assert_eq!(
+            links,
+            [
+                "https://limited.example/recent",
+                "https://unlimited.example/old",
+                "https://unlimited.example/recent",
+            ]
+        );
+        // They can still be searched for.
+        let mut indexed = collected
+            .search_index
+            .as_ref()
+            .unwrap()
+            .articles()
+            .into_iter()
+            .map(|article| &*article.link)
+            .collect::<Vec<_>>();
+        indexed.sort_unstable();
+        assert_eq!(
+            indexed,
+            [
+                "https://limited.example/old",
+                "https://limited.example/recent",
+                "https://unlimited.example/old",
+                "https://unlimited.example/recent",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rendering_uses_only_the_caches() {
+        let dir = test_dir("render");
+        let requests = Arc::new(AtomicUsize::new(0));
+        let feeds = test_server::serve_http({
+            let requests = Arc::clone(&requests);
+            move |_| {
+                requests.fetch_add(1, Ordering::SeqCst);
+                test_server::response("500 Internal Server Error", "text/plain", "")
+            }
+        })
+        .await;
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\n[[sites]]\nname = \"Site\"\nfeed_url = \"http://{feeds}/feed\"\n"
+        ))
+        .unwrap();
+        let caches =
+            cache::CacheManager::new(dir.join("cache"), cache::CacheKey::Name, &config.sites)
+                .unwrap();
+        let render = async |caches: &cache::CacheManager| {
+            crate::run(
+                &config,
+                caches,
+                false,
+                &mut Templates::with_page(
+                    "{% for article in articles %}{{ article.title }}{% endfor %}",
+                ),
+                &OutputPaths {
+                    html: Some(&dir.join("index.html")),
+                    feed: None,
+                    json: None,
+                    manifest: None,
+                    force: false,
+                },
+                &ArticleTrace::new(None),
+                &mut summary::RunSummary::new(&config),
+            )
+            .await
+        };
+        let e = render(&caches).await.unwrap_err();
+        assert!(e.to_string().contains("hasn't been fetched yet"), "{e}");
+
+        let guard = caches.cache_guard();
+        caches
+            .get_mut(&config.sites[0], &guard)
+            .await
+            .unwrap()
+            .last_body = Some(
+            "<rss version=\"2.0\"><channel><title>Site</title><item><title>Cached</title>\
+             <link>https://example.com/1</link><pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>\
+             </item></channel></rss>"
+                .into(),
+        );
+        drop(guard);
+        render(&caches).await.unwrap();
+        let page = std::fs::read_to_string(dir.join("index.html")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(page, "Cached");
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn sites_waiting_on_their_origin_leave_room_for_other_origins() {
+        use futures::FutureExt as _;
+
+        let feed = |title: &str| {
+            format!(
+                "<rss version=\"2.0\"><channel><title>{title}</title><item><title>{title}</title>\
+                 <link>https://example.com/{title}</link>\
+                 <pubDate>Wed, 14 Oct 2026 12:00:00 GMT</pubDate></item></channel></rss>"
+            )
+        };
+        let dir = test_dir("fetch-by-origin");
+        // The busy origin only answers once the other one has been asked, which happens while
+        // its first site is being fetched only if its other sites aren't taking up the fetches.
+        let (ask, asked) = futures::channel::oneshot::channel::<()>();
+        let ask = std::sync::Mutex::new(Some(ask));
+        let asked = asked.shared();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let waited = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let busy = test_server::serve_http_after({
+            let waited = Arc::clone(&waited);
+            move |head| {
+                let (asked, in_flight, waited) =
+                    (asked.clone(), Arc::clone(&in_flight), Arc::clone(&waited));
+                async move {
+                    let overlapping = in_flight.fetch_add(1, Ordering::SeqCst) > 0;
+                    let seen = tokio::time::timeout(std::time::Duration::from_secs(5), asked).await;
+                    waited.lock().unwrap().push((seen.is_ok(), overlapping));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let path = head.split(' ').nth(1).unwrap_or_default();
+                    test_server::response("200 OK", "application/rss+xml", &feed(&path[1..]))
+                }
+            }
+        })
+        .await;
+        let other = test_server::serve_http(move |_| {
+            if let Some(ask) = ask.lock().unwrap().take() {
+                let _ = ask.send(());
+            }
+            test_server::response("200 OK", "application/rss+xml", &feed("other"))
+        })
+        .await;
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\nmax_concurrent_fetches = 2\n\
+             [[sites]]\nname = \"a\"\nfeed_url = \"http://{busy}/a\"\n\
+             [[sites]]\nname = \"b\"\nfeed_url = \"http://{busy}/b\"\n\
+             [[sites]]\nname = \"c\"\nfeed_url = \"http://{busy}/c\"\n\
+             [[sites]]\nname = \"other\"\nfeed_url = \"http://{other}/feed\"\n"
+        ))
+        .unwrap();
+        let caches =
+            cache::CacheManager::new(dir.join("cache"), cache::CacheKey::Name, &config.sites)
+                .unwrap();
+        let github = crate::github::GitHub::new(&config, caches.state()).unwrap();
+        let origins = crate::origins::Origins::load(caches.state());
+        let sites = config.sites.iter().collect::<Vec<_>>();
+        let mut summary = summary::RunSummary::new(&config);
+        let errored = crate::fetch_sites(&config, &caches, &github, &origins, &sites, &mut summary)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!errored);
+        // Every site on the busy origin was fetched after the other origin was, and one at a time.
+        assert_eq!(*waited.lock().unwrap(), [(true, false); 3]);
+    }
+
+    #[tokio::test]
+    async fn fetch_and_parse_errors_are_shown_on_the_page() {
+        let dir = test_dir("page-errors");
+        let failing = Arc::new(AtomicBool::new(false));
+        let fail = Arc::clone(&failing);
+        let feeds = test_server::serve_http(move |head| {
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            match path {
+                "/flaky" if fail.load(Ordering::Relaxed) => {
+                    test_server::response("500 Internal Server Error", "text/plain", "")
+                }
+                "/broken" => test_server::response(
+                    "200 OK",
+                    "application/rss+xml",
+                    "<rss version=\"2.0\"><channel>",
+                ),
+                path => test_server::response("200 OK", "application/rss+xml", &feed(&path[1..])),
+            }
+        })
+        .await;
+        let closed = test_server::closed_port().await;
+        std::fs::write(
+            dir.join("jarss.toml"),
+            format!(
+                "min_fetch_interval = 0\n\
+                 [[sites]]\nname = \"Good\"\nfeed_url = \"http://{feeds}/good\"\n\
+                 [[sites]]\nname = \"Flaky\"\nfeed_url = \"http://{feeds}/flaky\"\n\
+                 [[sites]]\nname = \"Broken\"\nfeed_url = \"http://{feeds}/broken\"\n\
+                 [[sites]]\nname = \"Unreachable\"\nfeed_url = \"http://{closed}/feed\"\n"
+            ),
+        )
+        .unwrap();
+        let out_html = dir.join("out.html");
+        run(&dir, &out_html, None).await;
+        failing.store(true, Ordering::Relaxed);
+
+        // With the default template.
+        run(&dir, &out_html, None).await;
+        let page = std::fs::read_to_string(&out_html).unwrap();
+        let today = chrono::Utc::now().format("%Y-%m-%d");
+        let problems = page
+            .split("<details class=\"feed-errors\">")
+            .nth(1)
+            .and_then(|rest| rest.split("</details>").next())
+            .unwrap_or_else(|| panic!("No errors shown: {page}"));
+        let problems = problems
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("<li>"))
+            .collect::<Vec<_>>();
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(
+            problems[0].starts_with("<li>Flaky: Received error status code 500")
+                && problems[0].ends_with(&format!("(last fetched {today})</li>")),
+            "{problems:?}"
+        );
+        assert!(
+            problems[1].starts_with("<li>Broken: ")
+                && problems[1].ends_with(&format!("(last fetched {today})</li>")),
+            "{problems:?}"
+        );
+        assert!(
+            problems[2].starts_with("<li>Unreachable: ")
+                && problems[2].ends_with("(never fetched)</li>"),
+            "{problems:?}"
+        );
+        // Sites which failed to fetch still have their cached articles shown.
+        assert!(page.contains("https://example.com/good"), "{page}");
+        assert!(page.contains("https://example.com/flaky"), "{page}");
+
+        // And with a template of our own, which gets the errors' codes too.
+        let template = dir.join("errors.html.tera");
+        std::fs::write(
+            &template,
+            "{% for error in errors %}{{ error.name }} {{ error.code }} \
+             {{ error.last_fetch_time is string }}\n{% endfor %}",
+        )
+        .unwrap();
+        run(&dir, &out_html, Some(&template)).await;
+        assert_eq!(
+            std::fs::read_to_string(&out_html).unwrap(),
+            "Flaky http_server_error true\nBroken parse_invalid_xml true\n\
+             Unreachable fetch_connect false\n"
+        );
+
+        // Once the sites work again, nothing is shown.
+        failing.store(false, Ordering::Relaxed);
+        std::fs::write(
+            dir.join("jarss.toml"),
+            format!(
+                "min_fetch_interval = 0\n\
+                 [[sites]]\nname = \"Good\"\nfeed_url = \"http://{feeds}/good\"\n\
+                 [[sites]]\nname = \"Flaky\"\nfeed_url = \"http://{feeds}/flaky\"\n"
+            ),
+        )
+        .unwrap();
+        run(&dir, &out_html, None).await;
+        let page = std::fs::read_to_string(&out_html).unwrap();
+        assert!(!page.contains("feed-errors"), "{page}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ranks_count_the_shown_articles_and_stay_the_same_between_runs() {
+        // Each site has four articles of its own, on days of its own, and one they all share.
+        let feeds = |site: &str| {
+            let first_day = match site {
+                "a" => 1,
+                "b" => 6,
+                _ => 11,
+            };
+            let items = (first_day..first_day + 4)
+                .map(|day| {
+                    format!(
+                        "<item><title>{site} {day}</title><link>https://{site}.example/{day}</link>\
+                         <pubDate>{day:02} Jan 2024 00:00:00 GMT</pubDate></item>"
+                    )
+                })
+                .collect::<String>();
+            format!(
+                "<rss version=\"2.0\"><channel><title>{site}</title>{items}<item><title>Shared\
+                 </title><link>https://example.com/shared</link>\
+                 <pubDate>20 Jan 2024 00:00:00 GMT</pubDate></item></channel></rss>"
+            )
+        };
+        let settings = r#"
+            min_fetch_interval = 0
+            dedup_mode = "merge"
+            [groups.small]
+            max_entries = 2
+            [[sites]]
+            name = "a"
+            feed_url = "https://a.example/feed"
+            tags = ["small"]
+            [[sites]]
+            name = "b"
+            feed_url = "https://b.example/feed"
+            [[sites]]
+            name = "c"
+            feed_url = "https://c.example/feed"
+        "#;
+        let ranked = |articles: &[crate::FeedEntryInfo]| {
+            articles
+                .iter()
+                .map(|article| (article.rank, article.link.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let first = crate::test_util::collect_cached("ranks", settings, feeds).await;
+        let first = ranked(&first.articles);
+        // The shared article is shown once, as a's copy, which leaves room in a's group for only
+        // one of its own.
+        assert_eq!(first.len(), 1 + 1 + 4 + 4, "{first:?}");
+        let ranks = first.iter().map(|(rank, _)| *rank).collect::<Vec<_>>();
+        assert_eq!(ranks, (1..=first.len()).collect::<Vec<_>>());
+        assert_eq!(first[0].1, "https://example.com/shared");
+
+        let second = crate::test_util::collect_cached("ranks", settings, feeds).await;
+        assert_eq!(ranked(&second.articles), first);
+    }
+}
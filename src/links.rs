@@ -0,0 +1,257 @@
+use super::outputs::PlannedOutput;
+
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Component, Path},
+};
+use tera::Value;
+
+/// The URLs of the files a run writes, as linked to from the page.
+///
+/// Templates get these from `output_url(name="...")`, rather than building paths themselves, so
+/// links between outputs work both when the page is opened from disk and when it's served from
+/// under `render.base_url`.
+pub struct OutputLinks {
+    urls: BTreeMap<&'static str, String>,
+}
+impl OutputLinks {
+    /// Work out the links from the page at `page` to each of the `planned` outputs.
+    ///
+    /// With a `base_url`, which is where the page's directory is served from, links are absolute
+    /// URLs under it. Otherwise, they're relative to the page's directory.
+    pub fn new(base_url: Option<&str>, page: &Path, planned: &[PlannedOutput]) -> Result<Self> {
+        let base_url = base_url
+            .map(|base_url| {
+                // Without the slash, the last segment would be replaced rather than added to.
+                let base_url = if base_url.ends_with('/') {
+                    base_url.to_owned()
+                } else {
+                    format!("{base_url}/")
+                };
+                reqwest::Url::parse(&base_url)
+                    .with_context(|| format!("Invalid `render.base_url` {base_url:?}"))
+            })
+            .transpose()?;
+        let page_dir = page
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut urls = BTreeMap::new();
+        for output in planned {
+            let relative = relative_url(page_dir, &output.path)
+                .with_context(|| format!("Failed to find a link to {}", output.path.display()))?;
+            let url = match &base_url {
+                Some(base_url) => base_url
+                    .join(&relative)
+                    .with_context(|| {
+                        format!("Failed to find a link to {relative} under {base_url}")
+                    })?
+                    .into(),
+                None => relative,
+            };
+            urls.insert(output.name, url);
+        }
+        Ok(Self { urls })
+    }
+
+    /// Link to `name` with `url` instead, such as for an output embedded in the page.
+    pub fn replace(&mut self, name: &'static str, url: String) {
+        self.urls.insert(name, url);
+    }
+
+    /// The link to the output `name`, if that output is written.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.urls.get(name).map(String::as_str)
+    }
+
+    /// Register the `output_url(name)` function on `tera`, giving these links.
+    ///
+    /// Asking for an output which isn't written is an error, so a template can't silently link to
+    /// nothing.
+    pub fn register(self, tera: &mut tera::Tera) {
+        tera.register_function("output_url", move |args: &HashMap<String, Value>| {
+            let name = args
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| tera::Error::msg("`output_url` needs the `name` of an output"))?;
+            self.get(name).map(Value::from).ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "`output_url` got {name:?}, which isn't written by this run. The outputs \
+                     written are: {}",
+                    self.urls.keys().copied().collect::<Vec<_>>().join(", ")
+                ))
+            })
+        });
+    }
+}
+
+/// The relative URL of `target` from the directory `from`, with its segments percent-encoded.
+fn relative_url(from: &Path, target: &Path) -> Result<String> {
+    let from = std::path::absolute(from)?;
+    let target = std::path::absolute(target)?;
+    let from = from.components().collect::<Vec<_>>();
+    let target = target.components().collect::<Vec<_>>();
+    if from.first() != target.first() && matches!(target.first(), Some(Component::Prefix(_))) {
+        // On another drive, so there's no relative path, only a `file:` URL.
+        return reqwest::Url::from_file_path(target.iter().collect::<std::path::PathBuf>())
+            .map(String::from)
+            .map_err(|()| anyhow::anyhow!("Can't make a URL of the path"));
+    }
+    let common = from
+        .iter()
+        .zip(&target)
+        .take_while(|(from, target)| from == target)
+        .count();
+    let mut segments = vec![String::from(".."); from.len() - common];
+    for component in &target[common..] {
+        segments.push(encode_segment(&component.as_os_str().to_string_lossy()));
+    }
+    Ok(segments.join("/"))
+}
+
+/// Percent-encode everything in a path segment other than the characters always safe in URLs.
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(char::from(byte));
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outputs named after their paths, which are given relative to the working directory.
+    fn planned(paths: &[&'static str]) -> Vec<PlannedOutput> {
+        paths
+            .iter()
+            .map(|path| PlannedOutput {
+                name: path,
+                path: path.into(),
+                source: "test",
+            })
+            .collect()
+    }
+
+    /// The link to each of `paths` from `page`.
+    fn links(base_url: Option<&str>, page: &str, paths: &[&'static str]) -> Vec<String> {
+        let links = OutputLinks::new(base_url, Path::new(page), &planned(paths)).unwrap();
+        paths
+            .iter()
+            .map(|path| links.get(path).unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn links_are_relative_to_the_page_without_a_base_url() {
+        let paths = [
+            "out/search.json",
+            "out/feeds/feed.xml",
+            "data/a b.json",
+            "out/site/index.html",
+        ];
+        assert_eq!(
+            links(None, "out/index.html", &paths),
+            [
+                "search.json",
+                "feeds/feed.xml",
+                "../data/a%20b.json",
+                "site/index.html"
+            ]
+        );
+        assert_eq!(
+            links(None, "out/site/index.html", &paths),
+            [
+                "../search.json",
+                "../feeds/feed.xml",
+                "../../data/a%20b.json",
+                "index.html"
+            ]
+        );
+        assert_eq!(
+            links(None, "out/site/deeper/index.html", &["out/feeds/feed.xml"]),
+            ["../../feeds/feed.xml"]
+        );
+        // A page with no directory is in the working directory.
+        assert_eq!(
+            links(None, "index.html", &["feed.xml", "out/feed.xml"]),
+            ["feed.xml", "out/feed.xml"]
+        );
+    }
+
+    #[test]
+    fn links_are_under_the_base_url_with_or_without_its_slash() {
+        let paths = ["out/search.json", "out/feeds/feed.xml", "data/a b.json"];
+        let expected = [
+            "https://example.com/news/search.json",
+            "https://example.com/news/feeds/feed.xml",
+            "https://example.com/data/a%20b.json",
+        ];
+        assert_eq!(
+            links(Some("https://example.com/news"), "out/index.html", &paths),
+            expected
+        );
+        assert_eq!(
+            links(Some("https://example.com/news/"), "out/index.html", &paths),
+            expected
+        );
+        assert_eq!(
+            links(Some("https://example.com"), "out/site/index.html", &paths),
+            [
+                "https://example.com/search.json",
+                "https://example.com/feeds/feed.xml",
+                "https://example.com/data/a%20b.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_base_urls_are_errors() {
+        let Err(e) = OutputLinks::new(
+            Some("example.com/news"),
+            Path::new("out/index.html"),
+            &planned(&["out/feed.xml"]),
+        ) else {
+            panic!("A base URL without a scheme was accepted");
+        };
+        assert_eq!(
+            e.to_string(),
+            "Invalid `render.base_url` \"example.com/news/\""
+        );
+    }
+
+    #[test]
+    fn templates_can_only_link_to_written_outputs() {
+        let mut links = OutputLinks::new(
+            None,
+            Path::new("out/index.html"),
+            &planned(&["out/feed.xml", "out/search.json"]),
+        )
+        .unwrap();
+        links.replace("out/search.json", "data:application/json,[]".to_owned());
+        let mut tera = tera::Tera::default();
+        tera.add_raw_template(
+            "linked",
+            "{{ output_url(name=\"out/feed.xml\") }} {{ output_url(name=\"out/search.json\") }}",
+        )
+        .unwrap();
+        tera.add_raw_template("unwritten", "{{ output_url(name=\"opml\") }}")
+            .unwrap();
+        links.register(&mut tera);
+        let context = tera::Context::new();
+        assert_eq!(
+            tera.render("linked", &context).unwrap(),
+            "feed.xml data:application/json,[]"
+        );
+        let e = anyhow::Error::from(tera.render("unwritten", &context).unwrap_err());
+        let e = format!("{e:#}");
+        assert!(e.contains("`output_url` got \"opml\""), "{e}");
+        assert!(e.contains("out/feed.xml, out/search.json"), "{e}");
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Set up logging, to stderr or to a rotating log file.
+///
+/// If `tracing_article` is set, the article trace is logged whatever `RUST_LOG` says. Returns a
+/// count of the log lines which couldn't be written, to report at shutdown.
+pub fn init(
+    log_file: Option<PathBuf>,
+    max_bytes: u64,
+    keep: usize,
+    tracing_article: bool,
+) -> Arc<AtomicU64> {
+    let dropped = Arc::new(AtomicU64::new(0));
+    let mut builder = env_logger::Builder::from_default_env();
+    if tracing_article {
+        builder.filter_module("jarss::trace", log::LevelFilter::Info);
+    }
+    if let Some(path) = log_file {
+        builder.target(env_logger::Target::Pipe(Box::new(RotatingFile::new(
+            path,
+            max_bytes,
+            keep,
+            Arc::clone(&dropped),
+        ))));
+    }
+    builder.init();
+    dropped
+}
+
+/// A log file which is rotated once it grows past a size limit.
+///
+/// The current file is at `path`, and older ones are at `path.1`, `path.2`, and so on, with the
+/// oldest deleted so there are at most `keep` files in total. Failing to write never returns an
+/// error, since there's nowhere to report it that wouldn't itself be logging. Instead, the line is
+/// dropped and counted.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    /// The current file, if we were able to open it.
+    file: Option<File>,
+    /// How many bytes are in the current file.
+    size: u64,
+    /// How many writes we've had to drop.
+    dropped: Arc<AtomicU64>,
+}
+impl RotatingFile {
+    fn new(path: PathBuf, max_bytes: u64, keep: usize, dropped: Arc<AtomicU64>) -> Self {
+        let mut this = Self {
+            path,
+            max_bytes,
+            keep: keep.max(1),
+            file: None,
+            size: 0,
+            dropped,
+        };
+        this.open();
+        this
+    }
+
+    /// Open the file at [`Self::path`] for appending.
+    fn open(&mut self) {
+        self.file = File::options()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .ok();
+        self.size = self
+            .file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map_or(0, |metadata| metadata.len());
+    }
+
+    /// The path of the `n`th oldest rotated file.
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    /// Move each file along one step, and start a new current file.
+    fn rotate(&mut self) {
+        self.file = None;
+        if self.keep > 1 {
+            let _ = std::fs::remove_file(self.rotated_path(self.keep - 1));
+            for n in (1..self.keep - 1).rev() {
+                let _ = std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+            }
+            let _ = std::fs::rename(&self.path, self.rotated_path(1));
+        } else {
+            let _ = std::fs::remove_file(&self.path);
+        }
+        self.open();
+    }
+}
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate();
+        }
+        match self.file.as_mut().map(|file| file.write_all(buf)) {
+            Some(Ok(())) => self.size += buf.len() as u64,
+            _ => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                // Maybe whatever was wrong has cleared up by the next line.
+                self.open();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.flush();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory for the calling test, which is empty.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jarss-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The contents of each log file in `dir`, by name.
+    fn log_files(dir: &std::path::Path) -> Vec<(String, String)> {
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.file_name().into_string().unwrap(),
+                    std::fs::read_to_string(entry.path()).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// Owned names and contents, to compare [`log_files`] with.
+    fn files<const N: usize>(files: [(&str, &str); N]) -> Vec<(String, String)> {
+        files
+            .map(|(name, contents)| (name.to_owned(), contents.to_owned()))
+            .into()
+    }
+
+    #[test]
+    fn files_are_rotated_once_the_next_line_would_go_past_the_limit() {
+        let dir = test_dir("log-rotate");
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut log = RotatingFile::new(dir.join("log"), 8, 3, Arc::clone(&dropped));
+        log.write_all(b"1234\n").unwrap();
+        // Exactly at the limit.
+        log.write_all(b"56\n").unwrap();
+        log.flush().unwrap();
+        assert_eq!(log_files(&dir), files([("log", "1234\n56\n")]));
+        log.write_all(b"8\n").unwrap();
+        assert_eq!(
+            log_files(&dir),
+            files([("log", "8\n"), ("log.1", "1234\n56\n")])
+        );
+        // Lines over the limit are still written, on their own.
+        log.write_all(b"much too long\n").unwrap();
+        log.write_all(b"9\n").unwrap();
+        assert_eq!(
+            log_files(&dir),
+            files([
+                ("log", "9\n"),
+                ("log.1", "much too long\n"),
+                ("log.2", "8\n")
+            ])
+        );
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+
+        // And a file left by an earlier run is appended to, up to the limit.
+        drop(log);
+        let mut log = RotatingFile::new(dir.join("log"), 8, 3, Arc::clone(&dropped));
+        log.write_all(b"10\n").unwrap();
+        assert_eq!(log_files(&dir)[0], ("log".to_owned(), "9\n10\n".to_owned()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_newest_files_are_kept() {
+        let dir = test_dir("log-keep");
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut log = RotatingFile::new(dir.join("log"), 2, 3, Arc::clone(&dropped));
+        for line in ["1\n", "2\n", "3\n", "4\n", "5\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(
+            log_files(&dir),
+            files([("log", "5\n"), ("log.1", "4\n"), ("log.2", "3\n")])
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Keeping one file just starts it again.
+        let dir = test_dir("log-keep-one");
+        let mut log = RotatingFile::new(dir.join("log"), 2, 1, Arc::clone(&dropped));
+        for line in ["1\n", "2\n", "3\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(log_files(&dir), files([("log", "3\n")]));
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lines_which_cant_be_written_are_counted() {
+        let dir = test_dir("log-dropped");
+        let dropped = Arc::new(AtomicU64::new(0));
+        // A directory, which can't be opened as a log file.
+        let mut log = RotatingFile::new(dir.clone(), 100, 2, Arc::clone(&dropped));
+        log.write_all(b"lost\n").unwrap();
+        log.write_all(b"also lost\n").unwrap();
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,265 +1,3 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use futures::StreamExt as _;
-use std::{
-    fs::File,
-    path::{Path, PathBuf},
-    process::ExitCode,
-    time::Duration,
-};
-
-mod cache;
-
-#[derive(Parser)]
-struct Args {
-    /// The path to the config file.
-    ///
-    /// By default, this is a `jarss.toml` file in your config directory.
-    #[arg(short, long)]
-    config: Option<PathBuf>,
-    /// The path to the cache directory.
-    ///
-    /// By default, this is `jarss` in your cache directory.
-    #[arg(long)]
-    cache: Option<PathBuf>,
-    /// The path to the template to use in generating the feed.
-    ///
-    /// This should be a [`tera`] tempalte which takes a list of articles at `articles`, and
-    /// generates a full HTML document.
-    ///
-    /// By default, this will use a simple HTML template, stored at `default-render.html.tera` in
-    /// the repo. You can use this template as an example in writing your own.
-    #[arg(long)]
-    feed_template: Option<PathBuf>,
-    /// The path the write the produced HTML page.
-    out_html: PathBuf,
+fn main() -> anyhow::Result<std::process::ExitCode> {
+    jarss::cli()
 }
-
-/// [`Args`] but with default values applied.
-struct InferredArgs {
-    /// The path to the config file.
-    config: PathBuf,
-    /// The path to the cache directory.
-    cache: PathBuf,
-    /// The template to use in generating the feed.
-    feed_template: Box<str>,
-    /// The path the write the produced HTML page.
-    out_html: PathBuf,
-}
-impl TryFrom<Args> for InferredArgs {
-    type Error = anyhow::Error;
-
-    fn try_from(raw_args: Args) -> Result<Self> {
-        let config = match raw_args.config {
-            Some(config) => config,
-            None => dirs::config_dir()
-                .context("No default config directory on your system")?
-                .join("jarss.toml"),
-        };
-        let cache = match raw_args.cache {
-            Some(cache) => cache,
-            None => dirs::cache_dir()
-                .context("No default cache dir on your system")?
-                .join("jarss"),
-        };
-        let feed_template = raw_args
-            .feed_template
-            .map_or_else(
-                || Ok(include_str!("../default-render.html.tera").to_owned()),
-                std::fs::read_to_string,
-            )
-            .context("Error reading feed template from file")?
-            .into_boxed_str();
-        Ok(InferredArgs {
-            config,
-            cache,
-            feed_template,
-            out_html: raw_args.out_html,
-        })
-    }
-}
-
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<ExitCode> {
-    env_logger::init();
-    let args: InferredArgs = Args::parse().try_into()?;
-    log::info!("Loading config from {}", args.config.display());
-    let config = load_config(&args.config).await.with_context(|| {
-        format!(
-            "Couldn't load configuraion file at {}",
-            args.config.display()
-        )
-    })?;
-    let caches = cache::CacheManager::new(args.cache);
-
-    let mut error_update = false;
-
-    // Fetch the feeds to check for updates
-    let http_client = reqwest::Client::builder()
-        .user_agent(USER_AGENT)
-        .read_timeout(Duration::from_secs(20))
-        .timeout(Duration::from_secs(40))
-        .build()?;
-
-    let fetch_guard = caches.cache_guard();
-    let mut fetches = futures::stream::FuturesUnordered::new();
-    for site in &config.sites {
-        fetches.push(async {
-            let mut cache = caches
-                .get_mut(site, &fetch_guard)
-                .await
-                .with_context(|| format!("Error reading cache for {}", site.name))?;
-            cache::query_site(&http_client, &config, site, &mut cache)
-                .await
-                .context(format!(
-                    "Error fetching feed {} from url {}",
-                    site.name, site.feed_url
-                ))?;
-            anyhow::Ok(())
-        });
-    }
-    while let Some(res) = fetches.next().await {
-        if let Err(e) = res {
-            log::error!("{:?}", e);
-            error_update = true;
-        }
-    }
-    drop(fetches);
-    drop(fetch_guard);
-    caches.save().await.context("Error saving caches")?;
-
-    // Parse the articles and grab the most recent ones
-    let mut articles = Vec::new();
-    let feed_guard = caches.cache_guard();
-    let mut feeds = std::pin::pin!(caches.feeds(&feed_guard));
-    while let Some((site_name, feed)) = feeds.next().await {
-        let mut feed = match feed {
-            Ok(feed) => feed,
-            Err(e) => {
-                log::error!(
-                    "{:?}",
-                    e.context(format!("Error reading feed from {site_name}"))
-                );
-                continue;
-            }
-        };
-        feed.entries
-            .sort_unstable_by_key(|entry| std::cmp::Reverse(entry.published.or(entry.updated)));
-        let feed_title = feed.title.as_mut().map_or(site_name, |title| {
-            title.sanitize();
-            &title.content
-        });
-        let newest_entries = match feed
-            .entries
-            .iter()
-            .take(config.max_entries_per_site.unwrap_or(usize::MAX))
-            .map(|entry| FeedEntryInfo::new(feed_title, entry))
-            .collect::<Result<Vec<FeedEntryInfo>>>()
-        {
-            Ok(entries) => entries,
-            Err(e) => {
-                log::error!(
-                    "{:?}",
-                    e.context(format!("Error parsing entries in field from {site_name}"))
-                );
-                continue;
-            }
-        };
-        articles.extend_from_slice(&newest_entries);
-    }
-    articles.sort_unstable_by_key(|article| std::cmp::Reverse(article.published));
-
-    // Generate HTML output
-    log::info!("Generating feed output at {}", args.out_html.display());
-    let mut tera = tera::Tera::default();
-    tera.add_raw_template("output", &args.feed_template)
-        .context("Error parsing tera template")?;
-    let mut tera_ctx = tera::Context::new();
-    tera_ctx.insert("articles", &articles);
-    tera.render_to(
-        "output",
-        &tera_ctx,
-        File::create(&args.out_html).context("Failed to open output file")?,
-    )
-    .context("Failed to write to output file")?;
-
-    Ok(if error_update {
-        ExitCode::FAILURE
-    } else {
-        ExitCode::SUCCESS
-    })
-}
-
-#[derive(Clone, Debug, serde::Serialize)]
-struct FeedEntryInfo {
-    site: Box<str>,
-    published: chrono::DateTime<chrono::Utc>,
-    publish_date: chrono::NaiveDate,
-    title: Box<str>,
-    link: Box<str>,
-}
-impl FeedEntryInfo {
-    fn new(site_name: &str, entry: &feed_rs::model::Entry) -> Result<Self> {
-        let published = entry
-            .published
-            .or(entry.updated)
-            .context("Entry missing published time")?;
-        Ok(Self {
-            site: site_name.to_owned().into_boxed_str(),
-            published,
-            publish_date: published.date_naive(),
-            title: {
-                let mut title = entry.title.clone().context("Entry missing title")?;
-                title.sanitize();
-                title.content.into_boxed_str()
-            },
-            link: entry
-                .links
-                .first()
-                .context("Entry missing link")?
-                .href
-                .clone()
-                .into_boxed_str(),
-        })
-    }
-}
-
-/// The configuration file schema.
-#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
-struct Config {
-    /// The list of sites being used.
-    sites: Vec<SiteConfig>,
-    /// The minimum interval between fetches of the same site, in seconds.
-    min_fetch_interval: u64,
-    /// The maximum amount of entries from a given site.
-    max_entries_per_site: Option<usize>,
-    /// The maximum total amount of entries to display.
-    max_total_entries: Option<usize>,
-}
-
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-struct SiteConfig {
-    /// The name of the site.
-    name: Box<str>,
-    /// The URL of the feed to read.
-    feed_url: Box<str>,
-}
-
-/// Load the config from the given path.
-async fn load_config(path: impl AsRef<Path>) -> Result<Config> {
-    let contents = tokio::fs::read_to_string(path)
-        .await
-        .context("Failed to read config file")?;
-    toml::de::from_str(&contents).context("Failed to parse config file")
-}
-
-const USER_AGENT: &str = concat!(
-    env!("CARGO_PKG_NAME"),
-    "/",
-    env!("CARGO_PKG_VERSION"),
-    " (",
-    env!("GIT_DESCRIBE"),
-    ") <",
-    env!("CARGO_PKG_REPOSITORY"),
-    "> RSS Feed Reader"
-);
@@ -0,0 +1,149 @@
+use super::replace::write_atomically;
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, path::Path};
+
+/// The version of the manifest format, bumped whenever the schema changes incompatibly.
+const SCHEMA_VERSION: u32 = 1;
+
+/// A listing of every file written by a run, for external tools to consume.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    /// The value of [`SCHEMA_VERSION`] when this manifest was written.
+    schema_version: u32,
+    /// The files that were written.
+    files: Vec<ManifestEntry>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct ManifestEntry {
+    /// The path of the file, relative to the directory of the main output.
+    path: Box<str>,
+    /// The hex-encoded blake3 hash of the file's contents.
+    hash: Box<str>,
+    /// The size of the file, in bytes.
+    size: u64,
+    /// Whether the contents differ from those listed in the previous manifest.
+    changed: bool,
+}
+
+/// Write a manifest of the given outputs to `manifest_path`.
+///
+/// `base_dir` is the directory which paths in the manifest are relative to. Any existing manifest
+/// at `manifest_path` is read first, to determine which files changed since the last run.
+pub fn write_manifest(manifest_path: &Path, base_dir: &Path, outputs: &[&Path]) -> Result<()> {
+    let previous_hashes = read_previous_hashes(manifest_path);
+    let files = outputs
+        .iter()
+        .map(|output| {
+            let contents = std::fs::read(output)
+                .with_context(|| format!("Failed to read output {}", output.display()))?;
+            let path = relative_path(base_dir, output);
+            let hash = blake3::hash(&contents)
+                .to_hex()
+                .to_string()
+                .into_boxed_str();
+            let changed = previous_hashes.get(&path) != Some(&hash);
+            Ok(ManifestEntry {
+                path,
+                hash,
+                size: contents.len() as u64,
+                changed,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let manifest = Manifest {
+        schema_version: SCHEMA_VERSION,
+        files,
+    };
+    let encoded = serde_json::to_vec_pretty(&manifest).context("Failed to encode manifest")?;
+    write_atomically(manifest_path, &encoded).context("Failed to write manifest")
+}
+
+/// Read the hashes listed in the manifest from the previous run, if there is a usable one.
+fn read_previous_hashes(manifest_path: &Path) -> HashMap<Box<str>, Box<str>> {
+    let contents = match std::fs::read(manifest_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+        Err(e) => {
+            log::warn!("Couldn't read previous manifest, treating all outputs as changed: {e}");
+            return HashMap::new();
+        }
+    };
+    match serde_json::from_slice::<Manifest>(&contents) {
+        Ok(manifest) if manifest.schema_version == SCHEMA_VERSION => manifest
+            .files
+            .into_iter()
+            .map(|entry| (entry.path, entry.hash))
+            .collect(),
+        Ok(manifest) => {
+            log::info!(
+                "Previous manifest has schema version {}, treating all outputs as changed",
+                manifest.schema_version
+            );
+            HashMap::new()
+        }
+        Err(e) => {
+            log::warn!("Couldn't parse previous manifest, treating all outputs as changed: {e}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Express `path` relative to `base_dir`, falling back to the path as given if it isn't inside.
+fn relative_path(base_dir: &Path, path: &Path) -> Box<str> {
+    path.strip_prefix(base_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// Whether each file in the manifest at `path` changed, by its path.
+    fn changed(path: &Path) -> Vec<(String, bool)> {
+        let manifest: Manifest = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        manifest
+            .files
+            .into_iter()
+            .map(|entry| (entry.path.into(), entry.changed))
+            .collect()
+    }
+
+    #[test]
+    fn outputs_are_only_changed_when_their_contents_change() {
+        let dir = test_dir("manifest");
+        let manifest_path = dir.join("manifest.json");
+        let page = dir.join("out.html");
+        let feed = dir.join("out.xml");
+        std::fs::write(&page, "page").unwrap();
+        std::fs::write(&feed, "feed").unwrap();
+        write_manifest(&manifest_path, &dir, &[&page, &feed]).unwrap();
+        assert_eq!(
+            changed(&manifest_path),
+            [("out.html".to_owned(), true), ("out.xml".to_owned(), true)]
+        );
+
+        // The page is written again as it was, and the feed isn't.
+        std::fs::write(&page, "page").unwrap();
+        std::fs::write(&feed, "new feed").unwrap();
+        write_manifest(&manifest_path, &dir, &[&page, &feed]).unwrap();
+        assert_eq!(
+            changed(&manifest_path),
+            [("out.html".to_owned(), false), ("out.xml".to_owned(), true)]
+        );
+
+        write_manifest(&manifest_path, &dir, &[&page, &feed]).unwrap();
+        assert_eq!(
+            changed(&manifest_path),
+            [
+                ("out.html".to_owned(), false),
+                ("out.xml".to_owned(), false)
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
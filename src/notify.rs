@@ -0,0 +1,813 @@
+#[cfg(feature = "desktop-notify")]
+use super::desktop;
+use super::{
+    Config, FeedEntryInfo, SiteConfig,
+    errors::{ErrorCode, WithCode as _},
+    hooks, replace,
+    state::StatePaths,
+};
+
+use anyhow::{Context, Result};
+use chrono::TimeZone;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// How long an article which was routed to a channel is remembered, so it isn't sent again.
+///
+/// This is longer than entries are remembered in their sites' caches, so an article can't be
+/// forgotten here while it can still come up as new.
+const ROUTED_RETENTION: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+/// A channel to send notifications of new articles to, from `[[notifications]]`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Channel {
+    /// The name of the channel, which its state is kept under.
+    pub name: Box<str>,
+    /// How the notifications are sent.
+    #[serde(flatten)]
+    pub transport: Transport,
+    /// When the notifications are sent.
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Only notify of articles from these sites, or from sites with any of `tags`.
+    ///
+    /// With neither this nor `tags`, articles from every site are notified of.
+    #[serde(default)]
+    pub sites: Vec<Box<str>>,
+    /// Only notify of articles from sites with any of these tags, or from any of `sites`.
+    #[serde(default)]
+    pub tags: Vec<Box<str>>,
+    /// Only notify of articles whose titles match this regex.
+    #[serde(default)]
+    pub title_regex: Option<Box<str>>,
+    /// The most articles to send in each run, if there's a limit. The rest wait for later runs.
+    #[serde(default)]
+    pub max_per_run: Option<usize>,
+    /// A span of local time when nothing is sent, like `"22:00-07:00"`. What would have been
+    /// sent waits for the first run after.
+    #[serde(default)]
+    pub quiet_hours: Option<Box<str>>,
+    /// `title_regex`, compiled once the config is loaded.
+    #[serde(skip)]
+    title_filter: Option<regex::Regex>,
+}
+impl Channel {
+    /// Whether an article from `site` with `title` goes to this channel.
+    pub fn selects(&self, site: &SiteConfig, title: &str) -> bool {
+        let by_site = (self.sites.is_empty() && self.tags.is_empty())
+            || self.sites.contains(&site.name)
+            || site.tags.iter().any(|tag| self.tags.contains(tag));
+        by_site
+            && self
+                .title_filter
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(title))
+    }
+
+    /// The start and end of `quiet_hours`, if it's set.
+    fn quiet_hours(&self) -> Result<Option<(chrono::NaiveTime, chrono::NaiveTime)>> {
+        let Some(quiet_hours) = &self.quiet_hours else {
+            return Ok(None);
+        };
+        let invalid =
+            || format!("Invalid `quiet_hours` {quiet_hours:?}, it should be like \"22:00-07:00\"");
+        let (start, end) = quiet_hours.split_once('-').with_context(invalid)?;
+        let parse = |time: &str| chrono::NaiveTime::parse_from_str(time.trim(), "%H:%M");
+        Ok(Some((
+            parse(start).with_context(invalid)?,
+            parse(end).with_context(invalid)?,
+        )))
+    }
+}
+
+/// How a channel's notifications are sent.
+///
+/// Each notification sent to a command or webhook is JSON with the channel's `name` and the
+/// `articles` in it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    /// Run this command, with the notification on stdin. It's killed after `hook_timeout`.
+    Command(Vec<String>),
+    /// POST the notification to this URL.
+    Webhook(Box<str>),
+    /// One of the transports built into jarss, given as `type = "..."`.
+    #[serde(rename = "type")]
+    Builtin(Builtin),
+}
+
+/// The transports built into jarss, by their `type`.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Builtin {
+    /// Show desktop notifications, which needs jarss to be built with the `desktop-notify`
+    /// feature.
+    ///
+    /// Where the desktop can tell us, clicking the notification of an article opens it.
+    Desktop,
+}
+
+/// When a channel's notifications are sent.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    /// One notification for each new article, as soon as it's seen.
+    #[default]
+    Immediate,
+    /// One notification for each run with new articles, of all of them.
+    PerRun,
+    /// One notification a day, of the articles since the last, sent by the first run after this
+    /// local time of day, like `"08:00"`.
+    Daily(Box<str>),
+}
+impl Schedule {
+    /// The time of day of a daily schedule.
+    fn daily_at(&self) -> Result<Option<chrono::NaiveTime>> {
+        let Self::Daily(at) = self else {
+            return Ok(None);
+        };
+        chrono::NaiveTime::parse_from_str(at, "%H:%M")
+            .or_else(|_| chrono::NaiveTime::parse_from_str(at, "%H:%M:%S"))
+            .with_context(|| format!("Invalid daily time {at:?}, it should be like \"08:00\""))
+            .map(Some)
+    }
+}
+
+/// Check the channels of `config` against its sites, so mistakes are caught when the config is
+/// loaded, and compile their selectors.
+///
+/// This must be given every site, including disabled ones, so that naming a disabled site isn't
+/// an error.
+pub fn check(config: &mut Config) -> Result<()> {
+    let mut names = HashSet::new();
+    for channel in &mut config.notifications {
+        let name = channel.name.clone();
+        let context = || format!("Invalid notification channel {name:?}");
+        if !names.insert(channel.name.clone()) {
+            return Err(anyhow::anyhow!(
+                "There's more than one channel with this name"
+            ))
+            .with_context(context);
+        }
+        match &channel.transport {
+            Transport::Command(command) if command.is_empty() => {
+                return Err(anyhow::anyhow!("`command` is empty")).with_context(context);
+            }
+            Transport::Command(_) => {}
+            Transport::Webhook(url) => {
+                reqwest::Url::parse(url)
+                    .context("Invalid `webhook` URL")
+                    .with_context(context)?;
+            }
+            Transport::Builtin(Builtin::Desktop) => {
+                if !cfg!(feature = "desktop-notify") {
+                    return Err(anyhow::anyhow!(
+                        "`type = \"desktop\"` needs jarss to be built with the \
+                         `desktop-notify` feature"
+                    ))
+                    .with_context(context);
+                }
+            }
+        }
+        channel.schedule.daily_at().with_context(context)?;
+        channel.quiet_hours().with_context(context)?;
+        for site in &channel.sites {
+            if !config.sites.iter().any(|other| other.name == *site) {
+                return Err(anyhow::anyhow!("There's no site named {site:?}"))
+                    .with_context(context);
+            }
+        }
+        for tag in &channel.tags {
+            if !config.sites.iter().any(|site| site.tags.contains(tag)) {
+                return Err(anyhow::anyhow!("No site has the tag {tag:?}")).with_context(context);
+            }
+        }
+        channel.title_filter = channel
+            .title_regex
+            .as_deref()
+            .map(regex::Regex::new)
+            .transpose()
+            .context("Invalid `title_regex`")
+            .with_context(context)?;
+    }
+    Ok(())
+}
+
+/// An article, as sent in notifications and kept until it's sent.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct NotifiedArticle {
+    pub site: Box<str>,
+    pub title: Box<str>,
+    pub link: Box<str>,
+    pub id: Box<str>,
+    pub published: chrono::DateTime<chrono::Utc>,
+}
+
+/// A notification, as sent to a channel.
+#[derive(serde::Serialize)]
+struct Notification<'a> {
+    channel: &'a str,
+    articles: &'a [NotifiedArticle],
+}
+
+/// What we've done for a channel so far.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+struct ChannelState {
+    /// The articles routed to the channel, by site and then ID, and when they were.
+    routed: HashMap<Box<str>, HashMap<Box<str>, SystemTime>>,
+    /// The articles routed to the channel which haven't been sent yet.
+    pending: Vec<NotifiedArticle>,
+    /// When the channel's pending articles were last sent, or when it started waiting for its
+    /// first daily notification.
+    last_sent: Option<SystemTime>,
+}
+
+/// Route the new articles among `articles` to every channel which selects them, and send each
+/// channel what its schedule says is due as of `now`.
+///
+/// Articles are only routed to each channel once. Channels which fail to send keep their
+/// articles, to try again on the next run, and the errors are returned, by channel name.
+pub async fn notify(
+    config: &Config,
+    state: &StatePaths,
+    articles: &[FeedEntryInfo],
+    now: SystemTime,
+) -> Vec<(Box<str>, anyhow::Error)> {
+    if config.notifications.is_empty() {
+        return Vec::new();
+    }
+    let state_path = state.notifications();
+    let mut states = load_state(&state_path);
+    states.retain(|name, _| {
+        config
+            .notifications
+            .iter()
+            .any(|channel| channel.name == *name)
+    });
+    let new_articles = articles
+        .iter()
+        .filter(|article| article.is_new)
+        .filter_map(|article| Some((config.sites.get(article.site_index)?, article)))
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    let client = config.http.client_builder().build();
+    for channel in &config.notifications {
+        let state = states.entry(channel.name.clone()).or_default();
+        route(channel, state, &new_articles, now);
+        let due = match channel.schedule.daily_at() {
+            Ok(Some(at)) => daily_due(
+                &chrono::Local,
+                state.last_sent.get_or_insert(now).to_owned().into(),
+                at,
+                now.into(),
+            ),
+            Ok(None) => true,
+            Err(e) => {
+                errors.push((channel.name.clone(), e));
+                continue;
+            }
+        };
+        if !due {
+            continue;
+        }
+        if state.pending.is_empty() {
+            state.last_sent = Some(now);
+            continue;
+        }
+        if let Ok(Some((start, end))) = channel.quiet_hours()
+            && in_quiet_hours(&chrono::Local, start, end, now.into())
+        {
+            log::debug!(
+                "Not notifying channel {} during its quiet hours",
+                channel.name
+            );
+            continue;
+        }
+        let allowed = &state.pending[..channel
+            .max_per_run
+            .map_or(state.pending.len(), |max| max.min(state.pending.len()))];
+        let batches = match channel.schedule {
+            Schedule::Immediate => allowed.chunks(1).collect::<Vec<_>>(),
+            Schedule::PerRun | Schedule::Daily(_) => vec![allowed],
+        };
+        let mut sent = 0;
+        for batch in batches {
+            let notification = Notification {
+                channel: &channel.name,
+                articles: batch,
+            };
+            let res = match &client {
+                Ok(client) => send(config, client, channel, &notification).await,
+                Err(e) => Err(anyhow::anyhow!("Failed to build HTTP client: {e}")),
+            };
+            if let Err(e) = res {
+                errors.push((channel.name.clone(), e));
+                break;
+            }
+            sent += batch.len();
+        }
+        if sent > 0 {
+            log::info!("Sent {sent} new articles to channel {}", channel.name);
+        }
+        state.pending.drain(..sent);
+        if state.pending.is_empty() {
+            state.last_sent = Some(now);
+        }
+    }
+    if let Err(e) = save_state(&state_path, &states, now) {
+        log::warn!("{e:?}");
+    }
+    errors
+}
+
+/// Add the articles in `new_articles` which `channel` selects, and which haven't been routed to
+/// it before, to its pending articles.
+fn route(
+    channel: &Channel,
+    state: &mut ChannelState,
+    new_articles: &[(&SiteConfig, &FeedEntryInfo)],
+    now: SystemTime,
+) {
+    for (site, article) in new_articles {
+        if !channel.selects(site, &article.title) {
+            continue;
+        }
+        let routed = state.routed.entry(site.name.clone()).or_default();
+        if routed.insert(article.id.clone(), now).is_none() {
+            state.pending.push(NotifiedArticle {
+                site: article.site.clone(),
+                title: article.title.clone(),
+                link: article.link.clone(),
+                id: article.id.clone(),
+                published: article.published,
+            });
+        }
+    }
+}
+
+/// Whether a daily notification at `at` is due as of `now`, if the previous one was sent at
+/// `last_sent`, with times of day in the time zone `tz`.
+///
+/// It's due once `now` is past the most recent time it's been `at`, if `last_sent` was before
+/// then, so it's sent at most once a day however many days were missed.
+pub fn daily_due<Tz: TimeZone>(
+    tz: &Tz,
+    last_sent: chrono::DateTime<chrono::Utc>,
+    at: chrono::NaiveTime,
+    now: chrono::DateTime<chrono::Utc>,
+) -> bool {
+    let local_now = now.with_timezone(tz);
+    let boundary = |date: chrono::NaiveDate| {
+        // On days when clocks change, `at` may come twice, in which case the first is used, or not
+        // at all, in which case it's as soon as the clocks have gone forward.
+        let at = date.and_time(at);
+        tz.from_local_datetime(&at)
+            .earliest()
+            .or_else(|| {
+                tz.from_local_datetime(&(at + chrono::Duration::hours(1)))
+                    .earliest()
+            })
+            .map(|boundary| boundary.with_timezone(&chrono::Utc))
+    };
+    let today = local_now.date_naive();
+    let latest = match boundary(today) {
+        Some(boundary) if boundary <= now => Some(boundary),
+        _ => today.pred_opt().and_then(boundary),
+    };
+    latest.is_some_and(|latest| last_sent < latest && latest <= now)
+}
+
+/// Whether `now` is within the quiet hours from `start` to `end`, as times of day in the time zone
+/// `tz`.
+///
+/// Quiet hours which end earlier in the day than they start run over midnight.
+pub fn in_quiet_hours<Tz: TimeZone>(
+    tz: &Tz,
+    start: chrono::NaiveTime,
+    end: chrono::NaiveTime,
+    now: chrono::DateTime<chrono::Utc>,
+) -> bool {
+    let time = now.with_timezone(tz).time();
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+/// Send a notification to `channel`.
+async fn send(
+    config: &Config,
+    client: &reqwest::Client,
+    channel: &Channel,
+    notification: &Notification<'_>,
+) -> Result<()> {
+    let body = serde_json::to_vec(notification).context("Failed to encode notification")?;
+    match &channel.transport {
+        Transport::Command(command) => hooks::notify(command, config.hook_timeout, body)
+            .await
+            .context("Error running notification command")
+            .code(ErrorCode::HookFailed),
+        Transport::Webhook(url) => {
+            client
+                .post(url.as_ref())
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .with_context(|| format!("Failed to send notification to {url}"))?;
+            Ok(())
+        }
+        #[cfg(feature = "desktop-notify")]
+        Transport::Builtin(Builtin::Desktop) => {
+            desktop::show(notification.articles).await;
+            Ok(())
+        }
+        #[cfg(not(feature = "desktop-notify"))]
+        Transport::Builtin(Builtin::Desktop) => unreachable!("Checked when the config was loaded"),
+    }
+}
+
+/// Load the state of the channels, starting afresh if it can't be read.
+fn load_state(path: &Path) -> HashMap<Box<str>, ChannelState> {
+    match std::fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+            log::warn!("Failed to parse {}, ignoring it: {e}", path.display());
+            HashMap::new()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            log::warn!("Failed to read {}, ignoring it: {e}", path.display());
+            HashMap::new()
+        }
+    }
+}
+
+/// Save the state of the channels, forgetting articles routed longer than [`ROUTED_RETENTION`]
+/// ago.
+fn save_state(
+    path: &Path,
+    states: &HashMap<Box<str>, ChannelState>,
+    now: SystemTime,
+) -> Result<()> {
+    let mut states = states.clone();
+    for state in states.values_mut() {
+        for routed in state.routed.values_mut() {
+            routed.retain(|_, &mut at| {
+                !now.duration_since(at)
+                    .is_ok_and(|age| age >= ROUTED_RETENTION)
+            });
+        }
+        state.routed.retain(|_, routed| !routed.is_empty());
+    }
+    let encoded = serde_json::to_vec(&states).context("Failed to encode notification state")?;
+    std::fs::create_dir_all(path.parent().unwrap_or(Path::new("")))
+        .context("Failed to create state directory")?;
+    replace::write_atomically(path, &encoded).context("Failed to save notification state")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+    /// A time zone an hour ahead of UTC, which goes forward another hour from 01:00 UTC on
+    /// 2024-03-31 until 01:00 UTC on 2024-10-27, like central Europe.
+    #[derive(Clone, Copy)]
+    struct SummerTime;
+    impl SummerTime {
+        fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+            let start = utc_time("2024-03-31 01:00").naive_utc();
+            let end = utc_time("2024-10-27 01:00").naive_utc();
+            let hours = if (start..end).contains(utc) { 2 } else { 1 };
+            FixedOffset::east_opt(hours * 60 * 60).unwrap()
+        }
+    }
+    impl TimeZone for SummerTime {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Self
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            // Whichever of the two offsets would give back this local time, earliest first.
+            let offsets = [2, 1]
+                .map(|hours| FixedOffset::east_opt(hours * 60 * 60).unwrap())
+                .into_iter()
+                .filter(|&offset| Self::offset_at(&(*local - offset)) == offset)
+                .collect::<Vec<_>>();
+            match offsets[..] {
+                [] => MappedLocalTime::None,
+                [offset] => MappedLocalTime::Single(offset),
+                [earliest, latest] => MappedLocalTime::Ambiguous(earliest, latest),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            Self::offset_at(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    fn utc_time(time: &str) -> chrono::DateTime<Utc> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn time_of_day(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn daily_is_due_once_past_the_time() {
+        let at = time_of_day("08:00");
+        let last_sent = utc_time("2024-05-01 08:00");
+        for (now, due) in [
+            ("2024-05-01 23:59", false),
+            ("2024-05-02 07:59", false),
+            ("2024-05-02 08:00", true),
+            ("2024-05-02 12:00", true),
+            // However many days were missed, it's sent once.
+            ("2024-05-09 12:00", true),
+        ] {
+            assert_eq!(daily_due(&Utc, last_sent, at, utc_time(now)), due, "{now}");
+        }
+        assert!(!daily_due(
+            &Utc,
+            utc_time("2024-05-09 12:00"),
+            at,
+            utc_time("2024-05-09 13:00")
+        ));
+    }
+
+    #[test]
+    fn daily_goes_by_the_local_time_of_day() {
+        let tz = FixedOffset::east_opt(10 * 60 * 60).unwrap();
+        let at = time_of_day("08:00");
+        // 08:00 at UTC+10 is 22:00 UTC the day before.
+        let last_sent = utc_time("2024-05-01 21:00");
+        assert!(!daily_due(&tz, last_sent, at, utc_time("2024-05-01 21:59")));
+        assert!(daily_due(&tz, last_sent, at, utc_time("2024-05-01 22:00")));
+    }
+
+    #[test]
+    fn daily_times_skipped_by_clocks_going_forward_are_sent_after() {
+        // 02:30 doesn't happen on 2024-03-31, when the clocks go from 02:00 to 03:00.
+        let at = time_of_day("02:30");
+        let last_sent = utc_time("2024-03-30 01:30");
+        assert!(!daily_due(
+            &SummerTime,
+            last_sent,
+            at,
+            utc_time("2024-03-31 00:59")
+        ));
+        // 03:30 in summer time.
+        assert!(daily_due(
+            &SummerTime,
+            last_sent,
+            at,
+            utc_time("2024-03-31 01:30")
+        ));
+    }
+
+    #[test]
+    fn daily_times_repeated_by_clocks_going_back_are_sent_once() {
+        // 02:30 happens twice on 2024-10-27, when the clocks go from 03:00 back to 02:00.
+        let at = time_of_day("02:30");
+        let first = utc_time("2024-10-27 00:30");
+        let second = utc_time("2024-10-27 01:30");
+        assert!(daily_due(
+            &SummerTime,
+            utc_time("2024-10-26 00:30"),
+            at,
+            first
+        ));
+        assert!(!daily_due(&SummerTime, first, at, second));
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let tz = FixedOffset::east_opt(-5 * 60 * 60).unwrap();
+        let (start, end) = (time_of_day("22:00"), time_of_day("07:00"));
+        for (now, quiet) in [
+            // 21:59 local time.
+            ("2024-05-02 02:59", false),
+            ("2024-05-02 03:00", true),
+            // Midnight.
+            ("2024-05-02 05:00", true),
+            ("2024-05-02 11:59", true),
+            ("2024-05-02 12:00", false),
+        ] {
+            assert_eq!(
+                in_quiet_hours(&tz, start, end, utc_time(now)),
+                quiet,
+                "{now}"
+            );
+        }
+        let (start, end) = (time_of_day("12:00"), time_of_day("13:00"));
+        assert!(in_quiet_hours(
+            &Utc,
+            start,
+            end,
+            utc_time("2024-05-02 12:00")
+        ));
+        assert!(!in_quiet_hours(
+            &Utc,
+            start,
+            end,
+            utc_time("2024-05-02 13:00")
+        ));
+    }
+
+    #[test]
+    fn quiet_hours_follow_clock_changes() {
+        let (start, end) = (time_of_day("22:00"), time_of_day("07:00"));
+        // 07:30 in winter time, but 06:30 the day before, in summer time.
+        assert!(!in_quiet_hours(
+            &SummerTime,
+            start,
+            end,
+            utc_time("2024-10-27 06:30")
+        ));
+        assert!(in_quiet_hours(
+            &SummerTime,
+            start,
+            end,
+            utc_time("2024-10-26 04:30")
+        ));
+        // Both of the times it's 02:30.
+        assert!(in_quiet_hours(
+            &SummerTime,
+            start,
+            end,
+            utc_time("2024-10-27 00:30")
+        ));
+        assert!(in_quiet_hours(
+            &SummerTime,
+            start,
+            end,
+            utc_time("2024-10-27 01:30")
+        ));
+    }
+
+    #[test]
+    fn articles_are_routed_to_every_channel_that_selects_them_once() {
+        let config = crate::config_files::to_config(
+            toml::from_str(
+                r#"
+                min_fetch_interval = 0
+                [[sites]]
+                name = "a"
+                feed_url = "https://a.example/feed"
+                tags = ["news"]
+                [[notifications]]
+                name = "by-site"
+                command = ["true"]
+                sites = ["a"]
+                [[notifications]]
+                name = "by-tag"
+                command = ["true"]
+                tags = ["news"]
+                [[notifications]]
+                name = "by-title"
+                command = ["true"]
+                title_regex = "^Nothing"
+                "#,
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let feed = feed_rs::parser::parse(
+            &b"<rss version=\"2.0\"><channel><title>a</title><item><title>Hello</title>\
+               <link>https://a.example/1</link><guid>1</guid>\
+               <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item></channel></rss>"[..],
+        )
+        .unwrap();
+        let article = crate::FeedEntryInfo::new(
+            0,
+            "a",
+            &feed.entries[0],
+            &HashMap::new(),
+            None,
+            true,
+            &config.limits,
+        )
+        .unwrap();
+        let new_articles = [(&config.sites[0], &article)];
+        let now = SystemTime::now();
+        let mut states = config
+            .notifications
+            .iter()
+            .map(|_| ChannelState::default())
+            .collect::<Vec<_>>();
+        for _ in 0..2 {
+            for (channel, state) in config.notifications.iter().zip(&mut states) {
+                route(channel, state, &new_articles, now);
+            }
+        }
+        let pending = states
+            .iter()
+            .map(|state| {
+                state
+                    .pending
+                    .iter()
+                    .map(|article| &*article.title)
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(pending, [vec!["Hello"], vec!["Hello"], vec![]]);
+    }
+
+    #[tokio::test]
+    async fn at_most_max_per_run_articles_are_sent_each_run() {
+        let dir = crate::test_util::test_dir("notify-max-per-run");
+        let sent = dir.join("sent");
+        let config = crate::config_files::to_config(
+            toml::from_str(&format!(
+                r#"
+                min_fetch_interval = 0
+                [[sites]]
+                name = "a"
+                feed_url = "https://a.example/feed"
+                [[notifications]]
+                name = "capped"
+                command = ["sh", "-c", "cat >> \"$0\" && echo >> \"$0\"", {sent:?}]
+                schedule = "per_run"
+                max_per_run = 2
+                "#
+            ))
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let items = (1..=3)
+            .map(|i| {
+                format!(
+                    "<item><title>{i}</title><link>https://a.example/{i}</link><guid>{i}</guid>\
+                     <pubDate>Mon, 01 Jan 2024 00:00:0{i} GMT</pubDate></item>"
+                )
+            })
+            .collect::<String>();
+        let feed = feed_rs::parser::parse(
+            format!("<rss version=\"2.0\"><channel><title>a</title>{items}</channel></rss>")
+                .as_bytes(),
+        )
+        .unwrap();
+        let articles = feed
+            .entries
+            .iter()
+            .map(|entry| {
+                crate::FeedEntryInfo::new(
+                    0,
+                    "a",
+                    entry,
+                    &HashMap::new(),
+                    None,
+                    true,
+                    &config.limits,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let state = StatePaths::new(dir.join("state"));
+        for _ in 0..3 {
+            let errors = notify(&config, &state, &articles, SystemTime::now()).await;
+            assert!(errors.is_empty(), "{errors:?}");
+        }
+        // The third run has nothing left to send, as everything was routed already.
+        let notifications = std::fs::read_to_string(&sent)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let notification = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                notification["articles"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|article| article["title"].as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(notifications, [vec!["1", "2"], vec!["3"]]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,711 @@
+use super::{
+    Config, SiteConfig,
+    cache::{self, CacheManager},
+    config_files::ConfigFiles,
+    discover,
+    github::GitHub,
+    origins::Origins,
+    post, replace,
+    state::StatePaths,
+    throttle,
+};
+
+use anyhow::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// What a new config starts with, before the imported sites.
+///
+/// `min_fetch_interval` is the only setting without a default, so it has to be given.
+const NEW_CONFIG: &str = "min_fetch_interval = 3600\n";
+
+/// The name the subscriptions OPML is cached under, as if it were a site.
+const SUBSCRIPTIONS_CACHE_NAME: &str = "jarss subscriptions OPML";
+
+/// A feed listed in an OPML file, or found some other way.
+pub struct Outline {
+    pub feed_url: String,
+    /// The name to give the feed's site, if it's added.
+    pub name: String,
+}
+
+/// The feeds listed in an OPML file, with nested outlines flattened.
+///
+/// Each `<outline>` with an `xmlUrl` is a feed, named by its `title` or `text`.
+fn outlines(opml: &str) -> Vec<Outline> {
+    discover::tags(opml, "outline")
+        .filter_map(|attrs| {
+            let attr = |name: &str| {
+                attrs
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.trim())
+                    .filter(|value| !value.is_empty())
+            };
+            // Outlines without feeds are folders.
+            let feed_url = attr("xmlUrl")?;
+            Some(Outline {
+                feed_url: feed_url.to_owned(),
+                name: attr("title")
+                    .or(attr("text"))
+                    .unwrap_or(feed_url)
+                    .to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// The `[[sites]]` of a config file, and the names already taken by them.
+struct Sites<'a> {
+    sites: &'a mut toml_edit::ArrayOfTables,
+    names: HashSet<String>,
+}
+impl<'a> Sites<'a> {
+    fn of(doc: &'a mut toml_edit::DocumentMut) -> Result<Self> {
+        let sites = doc
+            .entry("sites")
+            .or_insert_with(|| toml_edit::ArrayOfTables::new().into())
+            .as_array_of_tables_mut()
+            .context("`sites` in the config file isn't a list of [[sites]]")?;
+        let names = sites
+            .iter()
+            .filter_map(|site| site_attr(site, "name"))
+            .collect();
+        Ok(Self { sites, names })
+    }
+
+    /// Add a site for the given feed, returning the name it was given.
+    fn add(&mut self, outline: &Outline) -> String {
+        let base_name = &outline.name;
+        // Sites with the same name would share a cache.
+        let name = std::iter::once(base_name.to_owned())
+            .chain((2..).map(|n| format!("{base_name} ({n})")))
+            .find(|name| !self.names.contains(name))
+            .expect("Some name is unused");
+        self.names.insert(name.clone());
+        let mut site = toml_edit::Table::new();
+        site["name"] = toml_edit::value(&name);
+        site["feed_url"] = toml_edit::value(&outline.feed_url);
+        self.sites.push(site);
+        name
+    }
+}
+
+/// A string setting of a site in the config file.
+fn site_attr(site: &toml_edit::Table, name: &str) -> Option<String> {
+    site.get(name)
+        .and_then(toml_edit::Item::as_str)
+        .map(str::to_owned)
+}
+
+/// Read the config file to edit, or what a new one starts with if it doesn't exist.
+pub fn read_config(config_path: &Path) -> Result<toml_edit::DocumentMut> {
+    let contents = match std::fs::read_to_string(config_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::info!("Creating a new config at {}", config_path.display());
+            NEW_CONFIG.to_owned()
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to read config file")),
+    };
+    contents
+        .parse::<toml_edit::DocumentMut>()
+        .context("Failed to parse config file")
+}
+
+/// Add the feeds in the OPML file at `opml_path` to the [editable](ConfigFiles::editable) config
+/// file.
+///
+/// Each `<outline>` with an `xmlUrl` becomes a site, named by its `title` or `text`, and nested
+/// outlines are flattened.
+pub fn import(config_files: &ConfigFiles, opml_path: &Path) -> Result<()> {
+    let opml = std::fs::read_to_string(opml_path).context("Failed to read OPML file")?;
+    let (added, skipped) = add_to_config(config_files, &outlines(&opml))?;
+    println!(
+        "Added {} feeds, skipped {skipped} already in the config",
+        added.len()
+    );
+    Ok(())
+}
+
+/// Add sites for the given feeds to the [editable](ConfigFiles::editable) config file.
+///
+/// Feeds which are already in it, by URL, are skipped. The rest of the config file is kept as it
+/// was, and nothing is written if the result isn't a valid config. Returns the names of the sites
+/// added, and how many feeds were skipped.
+pub fn add_to_config(
+    config_files: &ConfigFiles,
+    feeds: &[Outline],
+) -> Result<(Vec<String>, usize)> {
+    let config_path = config_files.editable();
+    let mut doc = read_config(config_path)?;
+    let mut sites = Sites::of(&mut doc)?;
+    let mut urls = sites
+        .sites
+        .iter()
+        .filter_map(|site| site_attr(site, "feed_url"))
+        .map(|url| normalize_url(&url))
+        .collect::<HashSet<_>>();
+    let (mut added, mut skipped) = (Vec::new(), 0);
+    for outline in feeds {
+        if !urls.insert(normalize_url(&outline.feed_url)) {
+            log::debug!(
+                "Skipping {}, which is already in the config",
+                outline.feed_url
+            );
+            skipped += 1;
+            continue;
+        }
+        added.push(sites.add(outline));
+    }
+    let updated = doc.to_string();
+    config_files
+        .load_with_edit(&updated)
+        .context("Config with the new feeds would be invalid")?;
+    if !added.is_empty() {
+        replace::write_atomically(config_path, updated.as_bytes())
+            .context("Failed to write config")?;
+    }
+    Ok((added, skipped))
+}
+
+/// What to do with sites whose feeds are no longer in the subscriptions OPML.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VanishedAction {
+    /// Set `enabled = false` on the site, keeping its settings in case it comes back.
+    #[default]
+    Disable,
+    /// Remove the site from the config.
+    Remove,
+}
+
+/// How syncing with the subscriptions OPML changed the sites in the config.
+#[derive(Default)]
+struct SyncChanges {
+    added: Vec<String>,
+    enabled: Vec<String>,
+    disabled: Vec<String>,
+    removed: Vec<String>,
+}
+impl SyncChanges {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.enabled.is_empty()
+            && self.disabled.is_empty()
+            && self.removed.is_empty()
+    }
+
+    /// Each change, described for a person.
+    fn describe(&self) -> impl Iterator<Item = String> + '_ {
+        self.added
+            .iter()
+            .map(|name| format!("Added {name}"))
+            .chain(self.enabled.iter().map(|name| format!("Re-enabled {name}")))
+            .chain(self.disabled.iter().map(|name| format!("Disabled {name}")))
+            .chain(self.removed.iter().map(|name| format!("Removed {name}")))
+    }
+}
+
+/// Make the sites in the config match the feeds in the subscriptions OPML.
+///
+/// Sites are matched to feeds by URL, so any settings given for a site in the config are kept. New
+/// feeds get sites with the default settings, unless they're already sites `elsewhere`, and sites
+/// whose feeds are gone are handled according to `vanished`.
+fn sync_sites(
+    doc: &mut toml_edit::DocumentMut,
+    feeds: &[Outline],
+    elsewhere: &HashSet<String>,
+    vanished: VanishedAction,
+) -> Result<SyncChanges> {
+    let mut sites = Sites::of(doc)?;
+    let mut wanted = feeds
+        .iter()
+        .map(|outline| (normalize_url(&outline.feed_url), outline))
+        .collect::<HashMap<_, _>>();
+    let mut changes = SyncChanges::default();
+    let mut to_remove = Vec::new();
+    for (i, site) in sites.sites.iter_mut().enumerate() {
+        let name = site_attr(site, "name").unwrap_or_default();
+        let url = site_attr(site, "feed_url").map(|url| normalize_url(&url));
+        let disabled = site.get("enabled").and_then(toml_edit::Item::as_bool) == Some(false);
+        if url.is_some_and(|url| wanted.remove(&url).is_some()) {
+            if disabled {
+                site.remove("enabled");
+                changes.enabled.push(name);
+            }
+            continue;
+        }
+        match vanished {
+            VanishedAction::Disable if !disabled => {
+                site["enabled"] = toml_edit::value(false);
+                changes.disabled.push(name);
+            }
+            VanishedAction::Disable => {}
+            VanishedAction::Remove => {
+                to_remove.push(i);
+                changes.removed.push(name);
+            }
+        }
+    }
+    for i in to_remove.into_iter().rev() {
+        sites.sites.remove(i);
+    }
+    // Keep the order the OPML lists new feeds in.
+    for outline in feeds {
+        let url = normalize_url(&outline.feed_url);
+        if wanted.remove(&url).is_some() && !elsewhere.contains(&url) {
+            changes.added.push(sites.add(outline));
+        }
+    }
+    Ok(changes)
+}
+
+/// The site the subscriptions OPML is fetched and cached as, if `subscriptions_opml_url` is set.
+///
+/// It's never displayed, and it owns a cache file like any other site.
+pub fn subscriptions_site(config: &Config) -> Option<SiteConfig> {
+    let url = config.subscriptions_opml_url.as_ref()?;
+    Some(SiteConfig {
+        name: SUBSCRIPTIONS_CACHE_NAME.into(),
+        feed_url: url.clone(),
+        display: false,
+        enabled: true,
+        tags: Vec::new(),
+        min_fetch_interval: Some(config.subscriptions_sync_interval.as_secs()),
+        max_entries: None,
+        min_expected_entries: None,
+        max_age_days: None,
+        include_title_regex: None,
+        exclude_title_regex: None,
+        exclude_categories: Vec::new(),
+        filter: Default::default(),
+        junk: Default::default(),
+        junk_filter: Default::default(),
+        resolve_links: false,
+        pre_fetch_command: None,
+        post_fetch_command: None,
+        digest_auth: None,
+        basic_auth: None,
+        headers: HashMap::new(),
+        header_env: HashMap::new(),
+        method: post::Method::Get,
+        body: None,
+        body_env: None,
+        content_type: None,
+    })
+}
+
+/// Fetch the subscriptions OPML and update the sites in the [editable](ConfigFiles::editable)
+/// config file to match it.
+///
+/// Feeds which are already sites in the other config files are left to them.
+///
+/// The OPML is fetched like a feed, cached under its own name, and only as often as
+/// `subscriptions_sync_interval` allows unless `force` is set. Its cache is kept apart from the
+/// sites', so it isn't read as a feed. The changes are logged, or just printed without saving
+/// anything if `dry_run` is set. Returns whether the config file was changed.
+pub async fn sync(
+    config: &Config,
+    config_files: &ConfigFiles,
+    cache_dir: &Path,
+    state: &StatePaths,
+    force: bool,
+    dry_run: bool,
+) -> Result<bool> {
+    let site = subscriptions_site(config).context("No `subscriptions_opml_url` in the config")?;
+    let caches = CacheManager::new(
+        cache_dir.to_owned(),
+        config.cache_key,
+        std::slice::from_ref(&site),
+    )?;
+    let guard = caches.cache_guard();
+    let mut cache = caches
+        .get_mut(&site, &guard)
+        .await
+        .context("Error reading subscriptions cache")?;
+    let origins = Origins::load(state);
+    if !force && throttle::is_throttled(config, &site, &cache, &origins) {
+        log::debug!("Not syncing subscriptions yet");
+        return Ok(false);
+    }
+    let client = config.http.client_builder().build()?;
+    let github = GitHub::new(config, state)?;
+    let fetched = cache::query_site(&client, config, &github, &origins, &site, &mut cache).await;
+    if fetched.is_err() {
+        cache.record_failure(std::time::SystemTime::now());
+    }
+    let opml = cache.last_body.clone();
+    drop(cache);
+    if !dry_run {
+        for (name, e) in caches.save(config.max_concurrent_saves).await {
+            log::warn!("Failed to save cache for {name}: {e:?}");
+        }
+        if let Err(e) = origins.save() {
+            log::warn!("{e:?}");
+        }
+    }
+    fetched.context("Failed to fetch subscriptions")?;
+    let opml = opml.context("Subscriptions OPML has never been fetched")?;
+    let feeds = outlines(&opml);
+    if feeds.is_empty() {
+        // More likely a broken export than someone unsubscribing from everything.
+        anyhow::bail!("Subscriptions OPML lists no feeds, not syncing");
+    }
+
+    let config_path = config_files.editable();
+    let mut doc = read_config(config_path)?;
+    let elsewhere = config
+        .sites
+        .iter()
+        .map(|site| normalize_url(&site.feed_url))
+        .collect();
+    let changes = sync_sites(&mut doc, &feeds, &elsewhere, config.subscriptions_vanished)?;
+    if dry_run {
+        for change in changes.describe() {
+            println!("{change}");
+        }
+        if changes.is_empty() {
+            println!("Config is in sync with the subscriptions");
+        }
+        return Ok(false);
+    }
+    if changes.is_empty() {
+        log::debug!("Config is in sync with the subscriptions");
+        return Ok(false);
+    }
+    let updated = doc.to_string();
+    config_files
+        .load_with_edit(&updated)
+        .context("Config synced with the subscriptions would be invalid")?;
+    replace::write_atomically(config_path, updated.as_bytes()).context("Failed to write config")?;
+    for change in changes.describe() {
+        log::info!("Subscriptions: {change}");
+    }
+    Ok(true)
+}
+
+/// The form of a feed URL to compare, so trivially different spellings of it match.
+pub fn normalize_url(url: &str) -> String {
+    super::urls::normalize(url, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// The name and URL of each site in the config at `path`.
+    fn sites(path: &Path) -> Vec<(String, String)> {
+        ConfigFiles::new(vec![path.to_owned()])
+            .load()
+            .unwrap()
+            .sites
+            .iter()
+            .map(|site| (site.name.to_string(), site.feed_url.to_string()))
+            .collect()
+    }
+
+    /// The sites in a new config with the feeds in `opml` imported.
+    fn imported(dir: &Path, opml: &str) -> Vec<(String, String)> {
+        let (config_path, opml_path) = (dir.join("config.toml"), dir.join("feeds.opml"));
+        let _ = std::fs::remove_file(&config_path);
+        std::fs::write(&opml_path, opml).unwrap();
+        import(&ConfigFiles::new(vec![config_path.clone()]), &opml_path).unwrap();
+        sites(&config_path)
+    }
+
+    fn owned<const N: usize>(sites: [(&str, &str); N]) -> [(String, String); N] {
+        sites.map(|(name, url)| (name.to_owned(), url.to_owned()))
+    }
+
+    const SUBSCRIPTIONS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="News" title="News">
+      <outline type="rss" text="Example" title="Example News" xmlUrl="https://example.com/feed.xml" htmlUrl="https://example.com/"/>
+      <outline type="rss" text="Only text" xmlUrl="https://example.org/rss"/>
+    </outline>
+    <outline type="rss" XMLURL="https://example.net/atom.xml" TITLE="Shouting &amp; caps"/>
+    <outline type="rss" xmlUrl="https://example.com/untitled.xml"/>
+  </body>
+</opml>
+"#;
+
+    #[test]
+    fn imported_feeds_become_sites() {
+        let dir = test_dir("opml-import");
+        assert_eq!(
+            imported(&dir, SUBSCRIPTIONS),
+            owned([
+                ("Example News", "https://example.com/feed.xml"),
+                ("Only text", "https://example.org/rss"),
+                ("Shouting & caps", "https://example.net/atom.xml"),
+                (
+                    "https://example.com/untitled.xml",
+                    "https://example.com/untitled.xml"
+                ),
+            ])
+        );
+
+        // Importing them again changes nothing.
+        let config_path = dir.join("config.toml");
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        import(
+            &ConfigFiles::new(vec![config_path.clone()]),
+            &dir.join("feeds.opml"),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), contents);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imports_keep_the_rest_of_the_config() {
+        let dir = test_dir("opml-import-existing");
+        let (config_path, opml_path) = (dir.join("config.toml"), dir.join("feeds.opml"));
+        std::fs::write(
+            &config_path,
+            "# Checked hourly.\nmin_fetch_interval = 3600\n\n\
+             [[sites]]\nname = \"Example News\"\nfeed_url = \"https://EXAMPLE.com/feed.xml\"\n\
+             max_entries = 3\n",
+        )
+        .unwrap();
+        // One feed already there, spelled differently, and a new one with a name which is taken.
+        std::fs::write(
+            &opml_path,
+            "<opml><body>\
+             <outline text=\"Example\" xmlUrl=\"https://example.com/feed.xml\"/>\
+             <outline text=\"Example News\" xmlUrl=\"https://example.com/other.xml\"/>\
+             </body></opml>",
+        )
+        .unwrap();
+        import(&ConfigFiles::new(vec![config_path.clone()]), &opml_path).unwrap();
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        assert!(
+            contents.starts_with(
+                "# Checked hourly.\nmin_fetch_interval = 3600\n\n[[sites]]\nname = \"Example \
+                 News\"\nfeed_url = \"https://EXAMPLE.com/feed.xml\"\nmax_entries = 3\n"
+            ),
+            "{contents}"
+        );
+        assert_eq!(
+            sites(&config_path)[1..],
+            owned([("Example News (2)", "https://example.com/other.xml")])
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_outlines_are_skipped_or_read_as_well_as_possible() {
+        let dir = test_dir("opml-import-malformed");
+        let opml = r#"<opml><body>
+            <outline text="Folder without a feed">
+            <outline text="Blank" xmlUrl="  "/>
+            <outline text='Single quotes' xmlUrl='https://example.com/single.xml'/>
+            <outline text=Unquoted xmlUrl=https://example.com/unquoted.xml />
+            <outlines text="Not an outline" xmlUrl="https://example.com/not.xml"/>
+            <outline text="  " title="" xmlUrl="https://example.com/nameless.xml"/>
+            <outline text="Entities &#233;&#x301; &lt;b&gt;" xmlUrl="https://example.com/?a=1&amp;b=2"/>
+            </outline>
+            <outline text="Cut off" xmlUrl="https://example.com/cut.xml"
+        "#;
+        assert_eq!(
+            imported(&dir, opml),
+            owned([
+                ("Single quotes", "https://example.com/single.xml"),
+                ("Unquoted", "https://example.com/unquoted.xml"),
+                (
+                    "https://example.com/nameless.xml",
+                    "https://example.com/nameless.xml"
+                ),
+                ("Entities é\u{301} <b>", "https://example.com/?a=1&b=2"),
+                ("Cut off", "https://example.com/cut.xml"),
+            ])
+        );
+        let (config_path, opml_path) = (dir.join("config.toml"), dir.join("feeds.opml"));
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        for not_opml in ["", "not xml at all", "<rss><channel></channel></rss>"] {
+            std::fs::write(&opml_path, not_opml).unwrap();
+            import(&ConfigFiles::new(vec![config_path.clone()]), &opml_path).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&config_path).unwrap(),
+                contents,
+                "{not_opml:?}"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nothing_is_written_if_the_import_would_break_the_config() {
+        let dir = test_dir("opml-import-invalid");
+        let (config_path, opml_path) = (dir.join("config.toml"), dir.join("feeds.opml"));
+        std::fs::write(&opml_path, SUBSCRIPTIONS).unwrap();
+        // Not a valid config, since it's missing `min_fetch_interval`.
+        std::fs::write(&config_path, "# Unfinished\n").unwrap();
+        let e = import(&ConfigFiles::new(vec![config_path.clone()]), &opml_path).unwrap_err();
+        assert_eq!(e.to_string(), "Config with the new feeds would be invalid");
+        assert_eq!(
+            std::fs::read_to_string(&config_path).unwrap(),
+            "# Unfinished\n"
+        );
+
+        std::fs::write(&config_path, "min_fetch_interval = 0\nsites = 3\n").unwrap();
+        let e = import(&ConfigFiles::new(vec![config_path.clone()]), &opml_path).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "`sites` in the config file isn't a list of [[sites]]"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Feeds with the given names and URLs.
+    fn feeds(feeds: &[(&str, &str)]) -> Vec<Outline> {
+        feeds
+            .iter()
+            .map(|&(name, feed_url)| Outline {
+                feed_url: feed_url.to_owned(),
+                name: name.to_owned(),
+            })
+            .collect()
+    }
+
+    /// Each change [`SyncChanges::describe`]s.
+    fn described(changes: &SyncChanges) -> Vec<String> {
+        changes.describe().collect()
+    }
+
+    #[test]
+    fn syncing_makes_the_sites_match_the_subscriptions() {
+        let config = "min_fetch_interval = 0\n\n\
+                      [[sites]]\nname = \"Kept\"\nfeed_url = \"https://example.com/kept.xml\"\n\
+                      max_entries = 3\n\n\
+                      [[sites]]\nname = \"Back\"\nfeed_url = \"https://example.com/back.xml\"\n\
+                      enabled = false\n\n\
+                      [[sites]]\nname = \"Gone\"\nfeed_url = \"https://example.com/gone.xml\"\n";
+        let subscriptions = feeds(&[
+            ("New", "https://example.com/new.xml"),
+            ("Renamed", "https://EXAMPLE.com/kept.xml"),
+            ("Back", "https://example.com/back.xml"),
+            ("Elsewhere", "https://example.com/elsewhere.xml"),
+        ]);
+        let elsewhere = HashSet::from([normalize_url("https://example.com/elsewhere.xml")]);
+        for (vanished, gone) in [
+            (VanishedAction::Disable, "Disabled Gone"),
+            (VanishedAction::Remove, "Removed Gone"),
+        ] {
+            let mut doc = config.parse::<toml_edit::DocumentMut>().unwrap();
+            let changes = sync_sites(&mut doc, &subscriptions, &elsewhere, vanished).unwrap();
+            assert_eq!(
+                described(&changes),
+                ["Added New", "Re-enabled Back", gone],
+                "{vanished:?}"
+            );
+            let synced =
+                crate::config_files::to_config(toml::from_str(&doc.to_string()).unwrap(), false)
+                    .unwrap();
+            let sites = synced
+                .sites
+                .iter()
+                .map(|site| (&*site.name, site.max_entries))
+                .collect::<Vec<_>>();
+            // The settings of sites which were kept are too.
+            assert_eq!(
+                sites,
+                [("Kept", Some(3)), ("Back", None), ("New", None)],
+                "{vanished:?}"
+            );
+
+            // And syncing again changes nothing.
+            let synced = doc.to_string();
+            let changes = sync_sites(&mut doc, &subscriptions, &elsewhere, vanished).unwrap();
+            assert!(changes.is_empty(), "{:?}", described(&changes));
+            assert_eq!(doc.to_string(), synced);
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_fetched_and_synced_into_the_config() {
+        let dir = test_dir("opml-sync");
+        let opml = std::sync::Arc::new(std::sync::Mutex::new(SUBSCRIPTIONS.to_owned()));
+        let served = std::sync::Arc::clone(&opml);
+        let server = crate::test_server::serve_http(move |_| {
+            crate::test_server::response("200 OK", "text/x-opml", &served.lock().unwrap())
+        })
+        .await;
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "min_fetch_interval = 0\nsubscriptions_opml_url = \"http://{server}/subscriptions.opml\"\n\n\
+                 [[sites]]\nname = \"Old\"\nfeed_url = \"https://example.com/old.xml\"\n"
+            ),
+        )
+        .unwrap();
+        let config_files = ConfigFiles::new(vec![config_path.clone()]);
+        let (cache_dir, state) = (dir.join("cache"), StatePaths::new(dir.join("state")));
+        let config = config_files.load().unwrap();
+        // Dry runs only print what they would change.
+        assert!(
+            !sync(&config, &config_files, &cache_dir, &state, true, true)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            sites(&config_path),
+            [("Old".to_owned(), "https://example.com/old.xml".to_owned())]
+        );
+
+        assert!(
+            sync(&config, &config_files, &cache_dir, &state, true, false)
+                .await
+                .unwrap()
+        );
+        // Only enabled sites are loaded, so the old one is left out.
+        let expected = dir.join("expected");
+        std::fs::create_dir(&expected).unwrap();
+        assert_eq!(sites(&config_path), imported(&expected, SUBSCRIPTIONS));
+
+        // Once in sync, it stays that way.
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        let config = config_files.load().unwrap();
+        assert!(
+            !sync(&config, &config_files, &cache_dir, &state, true, false)
+                .await
+                .unwrap()
+        );
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), contents);
+        // Unless forced, it isn't fetched again within `subscriptions_sync_interval`.
+        *opml.lock().unwrap() = "<opml><body></body></opml>".to_owned();
+        assert!(
+            !sync(&config, &config_files, &cache_dir, &state, false, false)
+                .await
+                .unwrap()
+        );
+
+        // A list without any feeds is taken to be broken, rather than unsubscribing from all.
+        let e = sync(&config, &config_files, &cache_dir, &state, true, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Subscriptions OPML lists no feeds, not syncing"
+        );
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), contents);
+
+        // The subscriptions own their cache, so cleaning up the cache directory keeps it.
+        let subscriptions = subscriptions_site(&config).unwrap();
+        let mut owners = config.sites.clone();
+        let caches = CacheManager::new(cache_dir.clone(), config.cache_key, &owners).unwrap();
+        let cache_path = caches.cache_path(&subscriptions);
+        let orphans = caches.orphaned_files(&owners).unwrap();
+        assert!(orphans.contains(&cache_path), "{orphans:?}");
+        owners.push(subscriptions);
+        assert!(caches.orphaned_files(&owners).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use super::{Config, OutputPaths, search};
+
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// A file a run will write.
+pub struct PlannedOutput {
+    /// What templates call the file, to link to it with `output_url`.
+    pub name: &'static str,
+    pub path: PathBuf,
+    /// What the file is, and where its path comes from, for messages.
+    pub source: &'static str,
+}
+
+/// The files a run writes, in the order they're written, apart from the manifest.
+///
+/// Outputs are listed in the manifest in this order.
+pub fn plan(config: &Config, output_paths: &OutputPaths<'_>) -> Vec<PlannedOutput> {
+    let mut planned = Vec::new();
+    if let Some(html) = output_paths.html {
+        planned.push(PlannedOutput {
+            name: "page",
+            path: html.to_owned(),
+            source: "the page (OUT_HTML)",
+        });
+        if config.search_index && !config.self_contained {
+            planned.push(PlannedOutput {
+                name: "search_index",
+                path: output_paths.base_dir().join(search::INDEX_FILE_NAME),
+                source: "the search index (written next to the page with `search_index`)",
+            });
+        }
+    }
+    if let Some(feed) = output_paths.feed {
+        planned.push(PlannedOutput {
+            name: "feed",
+            path: feed.to_owned(),
+            source: "the Atom feed (--out-feed)",
+        });
+    }
+    if let Some(json) = output_paths.json_file() {
+        planned.push(PlannedOutput {
+            name: "json",
+            path: json.to_owned(),
+            source: "the JSON output (--out-json)",
+        });
+    }
+    if let Some(fragment) = &config.fragment_output {
+        planned.push(PlannedOutput {
+            name: "fragment",
+            path: fragment.path.clone(),
+            source: "the fragment (`fragment_output.path`)",
+        });
+    }
+    planned
+}
+
+/// Check that no two of the outputs would be written to the same file, so none of them is
+/// overwritten by another.
+///
+/// The manifest at `manifest`, if any, is checked along with the other outputs, and paths are
+/// compared as [`find_collision`] does.
+pub fn check_collisions(planned: &[PlannedOutput], manifest: Option<&Path>) -> Result<()> {
+    let manifest = manifest.map(|path| PlannedOutput {
+        name: "manifest",
+        path: path.to_owned(),
+        source: "the manifest (--manifest)",
+    });
+    let outputs = planned.iter().chain(&manifest).collect::<Vec<_>>();
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if let Some((first, second)) = find_collision(&outputs, &cwd) {
+        anyhow::bail!(
+            "Both {} and {} would be written to {}, so one would overwrite the other. Give one of \
+             them a different path or directory.",
+            first.source,
+            second.source,
+            second.path.display()
+        );
+    }
+    Ok(())
+}
+
+/// The first two of `outputs` which would be written to the same file, if any do, with relative
+/// paths taken to be in `cwd`.
+///
+/// Paths are compared ignoring case, since the filesystems of macOS and Windows do, and ignoring
+/// `.` components. Symlinks aren't resolved, so `..` components are kept as they are.
+fn find_collision<'a>(
+    outputs: &[&'a PlannedOutput],
+    cwd: &Path,
+) -> Option<(&'a PlannedOutput, &'a PlannedOutput)> {
+    let mut seen = HashMap::new();
+    for &output in outputs {
+        let key = cwd
+            .join(&output.path)
+            .components()
+            .filter(|component| *component != std::path::Component::CurDir)
+            .collect::<PathBuf>()
+            .to_string_lossy()
+            .to_lowercase();
+        if let Some(other) = seen.insert(key, output) {
+            return Some((other, output));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &'static str, path: &str) -> PlannedOutput {
+        PlannedOutput {
+            name,
+            path: path.into(),
+            source: name,
+        }
+    }
+
+    fn collision(paths: &[&str]) -> Option<(&'static str, &'static str)> {
+        const NAMES: [&str; 4] = ["page", "feed", "json", "fragment"];
+        let outputs = paths
+            .iter()
+            .zip(NAMES)
+            .map(|(path, name)| output(name, path))
+            .collect::<Vec<_>>();
+        let outputs = outputs.iter().collect::<Vec<_>>();
+        find_collision(&outputs, Path::new("/home/me/site"))
+            .map(|(first, second)| (first.source, second.source))
+    }
+
+    #[test]
+    fn distinct_paths_dont_collide() {
+        assert_eq!(
+            collision(&["index.html", "feed.xml", "out/index.html"]),
+            None
+        );
+        assert_eq!(collision(&["/srv/index.html", "index.html"]), None);
+        // Symlinks could make `..` anywhere, so it isn't taken to cancel anything out.
+        assert_eq!(collision(&["out/../index.html", "index.html"]), None);
+    }
+
+    #[test]
+    fn the_same_path_collides() {
+        assert_eq!(
+            collision(&["index.html", "feed.xml", "index.html"]),
+            Some(("page", "json"))
+        );
+    }
+
+    #[test]
+    fn paths_differing_in_case_collide() {
+        assert_eq!(
+            collision(&["Index.HTML", "index.html"]),
+            Some(("page", "feed"))
+        );
+        assert_eq!(collision(&["ÉTÉ.xml", "été.xml"]), Some(("page", "feed")));
+    }
+
+    #[test]
+    fn relative_and_absolute_paths_to_the_same_file_collide() {
+        assert_eq!(
+            collision(&["/home/me/site/out.json", "./out.json"]),
+            Some(("page", "feed"))
+        );
+        assert_eq!(collision(&["a/./b.xml", "a/b.xml"]), Some(("page", "feed")));
+    }
+
+    #[test]
+    fn the_manifest_is_checked_too() {
+        let planned = [output("page", "index.html")];
+        assert!(check_collisions(&planned, Some(Path::new("index.html"))).is_err());
+        assert!(check_collisions(&planned, Some(Path::new("manifest.json"))).is_ok());
+    }
+}
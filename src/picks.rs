@@ -0,0 +1,183 @@
+use super::{Config, FeedEntryInfo};
+
+use chrono::Datelike as _;
+use std::collections::{HashMap, HashSet};
+
+/// Settings for picking a few older articles at random, for a "from the archives" section.
+///
+/// The picks are given to templates as `random_picks`. They stay the same through a day, in local
+/// time, and change the next.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct RandomPicks {
+    /// How many articles to pick. Fewer are picked if there aren't enough to pick from.
+    pub count: usize,
+    /// The fewest days old an article can be to be picked.
+    #[serde(default = "default_min_age_days")]
+    pub min_age_days: u64,
+    /// The most articles to pick from each site.
+    #[serde(default = "default_max_per_site")]
+    pub max_per_site: usize,
+}
+
+fn default_min_age_days() -> u64 {
+    30
+}
+
+fn default_max_per_site() -> usize {
+    1
+}
+
+/// Pick the articles for `random_picks` from `pool`, with the seed for the local date of `now`.
+///
+/// `pool` should be every entry in the cached feeds of the displayed sites which the title,
+/// category, and junk filters let through. Entries which are already being shown, in `shown`, or
+/// which are newer than `min_age_days`, aren't picked.
+pub fn pick(
+    picks: &RandomPicks,
+    mut pool: Vec<FeedEntryInfo>,
+    shown: &[FeedEntryInfo],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Vec<FeedEntryInfo> {
+    let shown_ids = shown
+        .iter()
+        .map(|article| (article.site_index, &*article.id))
+        .collect::<HashSet<_>>();
+    let shown_links = shown
+        .iter()
+        .map(|article| &*article.link)
+        .collect::<HashSet<_>>();
+    let newest = now
+        .checked_sub_days(chrono::Days::new(picks.min_age_days))
+        .unwrap_or(now);
+    pool.retain(|entry| {
+        entry.published <= newest
+            && !shown_ids.contains(&(entry.site_index, &*entry.id))
+            && !shown_links.contains(&*entry.link)
+    });
+    // The feeds are read in whatever order their caches load, so the pool is put in an order of
+    // its own for the same seed to give the same picks.
+    pool.sort_by(|a, b| (a.site_index, &a.id).cmp(&(b.site_index, &b.id)));
+    // Articles on more than one site are only in the pool once, from the first of them.
+    let mut links = HashSet::new();
+    pool.retain(|entry| links.insert(entry.link.clone()));
+    let picked = sample(
+        &pool,
+        |entry| entry.site_index,
+        picks.count,
+        picks.max_per_site,
+        seed(now.with_timezone(&chrono::Local).date_naive()),
+    );
+    picked.into_iter().cloned().collect()
+}
+
+/// The seed to pick with on `date`, which is the same all day and different every day.
+pub fn seed(date: chrono::NaiveDate) -> u64 {
+    let mut state = date.num_days_from_ce() as u64;
+    split_mix(&mut state)
+}
+
+/// Pick up to `count` of `items` at random with `seed`, taking at most `max_per_site` with the
+/// same `site`.
+///
+/// The same items and seed always give the same picks, in the same order. This never fails, and
+/// picks all it can when there aren't enough items.
+pub fn sample<T>(
+    items: &[T],
+    site: impl Fn(&T) -> usize,
+    count: usize,
+    max_per_site: usize,
+    seed: u64,
+) -> Vec<&T> {
+    let mut state = seed;
+    let mut order = (0..items.len()).collect::<Vec<_>>();
+    // A Fisher-Yates shuffle.
+    for i in (1..order.len()).rev() {
+        let j = (split_mix(&mut state) % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    let mut per_site = HashMap::<usize, usize>::new();
+    let mut picked = Vec::new();
+    for index in order {
+        if picked.len() >= count {
+            break;
+        }
+        let taken = per_site.entry(site(&items[index])).or_default();
+        if *taken < max_per_site {
+            *taken += 1;
+            picked.push(&items[index]);
+        }
+    }
+    picked
+}
+
+/// The next number from a SplitMix64 generator, which is plenty random for picking articles.
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Whether `config` asks for random picks, and so whether the pool for them should be collected.
+pub fn wanted(config: &Config) -> bool {
+    config
+        .random_picks
+        .as_ref()
+        .is_some_and(|picks| picks.count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> chrono::NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn seeds_are_the_same_all_day_and_different_every_day() {
+        // Pinned, so the picks for a day don't change between versions.
+        assert_eq!(seed(date("2024-01-01")), seed(date("2024-01-01")));
+        assert_eq!(seed(date("2024-01-01")), 0xb8a0_7cae_04a5_1ced);
+        let start = date("2020-01-01");
+        let seeds = (0..3650)
+            .map(|days| seed(start + chrono::Days::new(days)))
+            .collect::<HashSet<_>>();
+        assert_eq!(seeds.len(), 3650);
+    }
+
+    #[test]
+    fn samples_are_deterministic() {
+        let items = (0..20).collect::<Vec<usize>>();
+        let picks = sample(&items, |&item| item, 5, 1, 42);
+        assert_eq!(picks, sample(&items, |&item| item, 5, 1, 42));
+        assert_eq!(picks.len(), 5);
+        // Some other seed in the next few picks differently.
+        assert!((43..53).any(|seed| sample(&items, |&item| item, 5, 1, seed) != picks));
+    }
+
+    #[test]
+    fn samples_take_at_most_max_per_site() {
+        // Four sites, with five items each.
+        let items = (0..20).collect::<Vec<usize>>();
+        for seed in 0..100 {
+            let picks = sample(&items, |&item| item % 4, 10, 2, seed);
+            assert_eq!(picks.len(), 8, "seed {seed}");
+            for site in 0..4 {
+                let from_site = picks.iter().filter(|&&&item| item % 4 == site).count();
+                assert_eq!(from_site, 2, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn samples_take_what_there_is() {
+        let items = [1, 2, 3];
+        let mut picks = sample(&items, |&item| item, 10, 1, 7);
+        picks.sort();
+        assert_eq!(picks, [&1, &2, &3]);
+        assert!(sample(&items, |&item| item, 0, 1, 7).is_empty());
+        assert!(sample(&[] as &[usize], |&item| item, 5, 1, 7).is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+use super::SiteConfig;
+
+use anyhow::{Context, Result};
+
+/// The HTTP method to fetch a site's feed with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    #[default]
+    Get,
+    /// For feeds which are only served in response to a POST, like reports selected by a JSON
+    /// body.
+    ///
+    /// Conditional requests aren't made for these, so a changed feed is only noticed by its body
+    /// changing.
+    Post,
+}
+
+/// The body of a site's POST requests, as given in the config.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Body {
+    /// Sent as is.
+    Text(Box<str>),
+    /// Sent as JSON.
+    Json(toml::Table),
+}
+
+/// Check that the given site's request settings make sense, so mistakes are caught when the
+/// config is loaded rather than on every fetch.
+pub fn check(site: &SiteConfig) -> Result<()> {
+    let has_body = site.body.is_some() || site.body_env.is_some();
+    match site.method {
+        Method::Get if has_body || site.content_type.is_some() => anyhow::bail!(
+            "Site {} has a `body` or `content_type`, which are only sent with `method = \"POST\"`",
+            site.name
+        ),
+        Method::Get => {}
+        Method::Post if site.body.is_some() && site.body_env.is_some() => anyhow::bail!(
+            "Site {} has both `body` and `body_env`, only one can be used",
+            site.name
+        ),
+        Method::Post if has_body && site.content_type.is_none() => anyhow::bail!(
+            "Site {} has a `body` but no `content_type` to send it with",
+            site.name
+        ),
+        Method::Post => {}
+    }
+    if let Some(content_type) = &site.content_type {
+        http::HeaderValue::from_str(content_type)
+            .with_context(|| format!("Invalid `content_type` for site {}", site.name))?;
+    }
+    Ok(())
+}
+
+/// Start a request for the given site's feed at `url`, with its method and any body.
+pub fn request(
+    agent: &reqwest::Client,
+    site: &SiteConfig,
+    url: impl reqwest::IntoUrl,
+) -> Result<reqwest::RequestBuilder> {
+    if site.method == Method::Get {
+        return Ok(agent.get(url));
+    }
+    let body = match (&site.body, &site.body_env) {
+        (Some(Body::Text(body)), _) => Some(body.to_string()),
+        (Some(Body::Json(body)), _) => Some(
+            serde_json::to_string(body)
+                .with_context(|| format!("Failed to encode the body for site {}", site.name))?,
+        ),
+        (None, Some(var)) => Some(std::env::var(var.as_ref()).with_context(|| {
+            format!("Failed to read the body for site {} from ${var}", site.name)
+        })?),
+        (None, None) => None,
+    };
+    let req = agent.post(url);
+    Ok(match (body, &site.content_type) {
+        (Some(body), Some(content_type)) => req
+            .header(http::header::CONTENT_TYPE, content_type.as_ref())
+            .body(body),
+        (Some(body), None) => req.body(body),
+        (None, _) => req,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_server, test_util::test_dir};
+
+    /// A site reading `http://example.com/feed` with the given extra settings.
+    fn site(settings: &str) -> SiteConfig {
+        toml::from_str(&format!(
+            "name = \"Site\"\nfeed_url = \"http://example.com/feed\"\n{settings}"
+        ))
+        .unwrap()
+    }
+
+    /// The method, `content-type` and body of the request for `site`.
+    fn sent(site: &SiteConfig) -> Result<(String, Option<String>, Option<String>)> {
+        let req = request(&reqwest::Client::new(), site, "http://example.com/feed")?
+            .build()
+            .unwrap();
+        Ok((
+            req.method().to_string(),
+            req.headers()
+                .get(http::header::CONTENT_TYPE)
+                .map(|value| value.to_str().unwrap().to_owned()),
+            req.body()
+                .and_then(reqwest::Body::as_bytes)
+                .map(|body| String::from_utf8(body.to_vec()).unwrap()),
+        ))
+    }
+
+    #[test]
+    fn requests_are_sent_with_the_method_and_body() {
+        assert_eq!(sent(&site("")).unwrap(), ("GET".to_owned(), None, None));
+        assert_eq!(
+            sent(&site("method = \"POST\"")).unwrap(),
+            ("POST".to_owned(), None, None)
+        );
+        assert_eq!(
+            sent(&site(
+                "method = \"POST\"\nbody = \"q=rust\"\n\
+                 content_type = \"application/x-www-form-urlencoded\""
+            ))
+            .unwrap(),
+            (
+                "POST".to_owned(),
+                Some("application/x-www-form-urlencoded".to_owned()),
+                Some("q=rust".to_owned())
+            )
+        );
+        assert_eq!(
+            sent(&site(
+                "method = \"POST\"\ncontent_type = \"application/json\"\n\
+                 body = { report = \"weekly\", limit = 10 }"
+            ))
+            .unwrap(),
+            (
+                "POST".to_owned(),
+                Some("application/json".to_owned()),
+                Some("{\"limit\":10,\"report\":\"weekly\"}".to_owned())
+            )
+        );
+        // `PATH` is set for every process, so stands in for a variable holding a secret.
+        assert_eq!(
+            sent(&site(
+                "method = \"POST\"\nbody_env = \"PATH\"\ncontent_type = \"text/plain\""
+            ))
+            .unwrap(),
+            (
+                "POST".to_owned(),
+                Some("text/plain".to_owned()),
+                Some(std::env::var("PATH").unwrap())
+            )
+        );
+    }
+
+    #[test]
+    fn missing_body_variables_are_errors() {
+        let e = sent(&site(
+            "method = \"POST\"\nbody_env = \"JARSS_TEST_UNSET_VARIABLE\"\n\
+             content_type = \"text/plain\"",
+        ))
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Failed to read the body for site Site from $JARSS_TEST_UNSET_VARIABLE"
+        );
+    }
+
+    #[test]
+    fn inconsistent_request_settings_are_rejected() {
+        for (settings, expected) in [
+            (
+                "body = \"q\"\ncontent_type = \"text/plain\"",
+                "Site Site has a `body` or `content_type`, which are only sent with \
+                 `method = \"POST\"`",
+            ),
+            (
+                "content_type = \"text/plain\"",
+                "Site Site has a `body` or `content_type`, which are only sent with \
+                 `method = \"POST\"`",
+            ),
+            (
+                "method = \"POST\"\nbody = \"q\"\nbody_env = \"BODY\"\n\
+                 content_type = \"text/plain\"",
+                "Site Site has both `body` and `body_env`, only one can be used",
+            ),
+            (
+                "method = \"POST\"\nbody = \"q\"",
+                "Site Site has a `body` but no `content_type` to send it with",
+            ),
+            (
+                "method = \"POST\"\nbody = \"q\"\ncontent_type = \"text/plain\\n\"",
+                "Invalid `content_type` for site Site",
+            ),
+        ] {
+            let e = check(&site(settings)).unwrap_err();
+            assert_eq!(e.to_string(), expected, "{settings:?}");
+        }
+        for settings in [
+            "",
+            "method = \"GET\"",
+            "method = \"POST\"",
+            "method = \"POST\"\nbody = \"q\"\ncontent_type = \"text/plain\"",
+        ] {
+            check(&site(settings)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn posted_feeds_are_fetched_without_conditional_requests() {
+        let dir = test_dir("post");
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = std::sync::Arc::clone(&requests);
+        let feeds = test_server::serve_http(move |head| {
+            seen.lock().unwrap().push(head.to_owned());
+            if !head.starts_with("POST ") {
+                return test_server::response("405 Method Not Allowed", "text/plain", "");
+            }
+            let response = test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                "<rss version=\"2.0\"><channel><title>Report</title></channel></rss>",
+            );
+            response.replacen("\r\n", "\r\netag: \"report\"\r\n", 1)
+        })
+        .await;
+        let config = crate::config_files::to_config(
+            toml::from_str(&format!(
+                "min_fetch_interval = 0\n\
+                 [[sites]]\nname = \"Posted\"\nfeed_url = \"http://{feeds}/report\"\n\
+                 method = \"POST\"\ncontent_type = \"application/json\"\n\
+                 body = {{ report = \"weekly\" }}\n\
+                 [[sites]]\nname = \"Got\"\nfeed_url = \"http://{feeds}/get\"\n"
+            ))
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let caches = crate::cache::CacheManager::new(
+            dir.join("cache"),
+            crate::cache::CacheKey::Url,
+            &config.sites,
+        )
+        .unwrap();
+        // The site which isn't POSTed to fails.
+        let errors = test_server::fetch_all(&config, &caches).await;
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            ["Received error status code 405 Method Not Allowed"]
+        );
+        test_server::fetch_all(&config, &caches).await;
+
+        let requests = requests.lock().unwrap();
+        let posts = requests
+            .iter()
+            .filter(|head| head.starts_with("POST "))
+            .collect::<Vec<_>>();
+        assert_eq!(posts.len(), 2, "{requests:?}");
+        for head in posts {
+            let head = head.to_ascii_lowercase();
+            assert!(head.contains("content-type: application/json"), "{head}");
+            assert!(!head.contains("if-none-match"), "{head}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
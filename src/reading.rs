@@ -0,0 +1,244 @@
+use super::SiteConfig;
+
+use chrono::TimeZone;
+
+/// Settings for the per-site reading statistics given to templates as `site_stats`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SiteStatsConfig {
+    /// The windows to count over, in days, like `[7, 30]`.
+    ///
+    /// Each window is whole days in local time, ending with today, so the 7-day window starts at
+    /// midnight six days ago. The default template sorts the sites by reading time in the first.
+    #[serde(default = "default_windows")]
+    pub windows: Vec<u32>,
+    /// How many words take a minute to read, for the estimated reading times.
+    #[serde(default = "default_words_per_minute")]
+    pub words_per_minute: u32,
+}
+
+fn default_windows() -> Vec<u32> {
+    vec![7, 30]
+}
+
+fn default_words_per_minute() -> u32 {
+    230
+}
+
+/// What an entry adds to its site's statistics.
+#[derive(Clone, Debug)]
+pub struct EntryReading {
+    /// The index of the entry's site in [`Config::sites`](super::Config::sites).
+    pub site_index: usize,
+    /// When the entry was published, or else last updated.
+    pub published: chrono::DateTime<chrono::Utc>,
+    /// How many words the entry's content, or else its summary, has, if it has either.
+    pub words: Option<usize>,
+}
+
+/// A displayed site's statistics, as given to templates in `site_stats`.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SiteStats {
+    /// The name of the site.
+    pub name: Box<str>,
+    /// The statistics over each of the configured windows, in the order they're configured.
+    pub windows: Vec<WindowStats>,
+}
+
+/// A site's statistics over one window.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct WindowStats {
+    /// How many days the window is.
+    pub days: u32,
+    /// How many articles were published in the window.
+    pub articles: usize,
+    /// How many of those had a summary or content to estimate the reading time from.
+    pub articles_with_text: usize,
+    /// How many words those had, all together.
+    pub words: usize,
+    /// The estimated minutes to read those, rounded up.
+    pub minutes: u64,
+}
+
+/// Compute the statistics of each displayed site from `readings`, as of `now`, with the windows
+/// made of days in the time zone `tz`.
+///
+/// Entries published after `now` aren't counted in any window.
+pub fn site_stats<Tz: TimeZone>(
+    settings: &SiteStatsConfig,
+    sites: &[SiteConfig],
+    readings: &[EntryReading],
+    tz: &Tz,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Vec<SiteStats> {
+    let today = now.with_timezone(tz).date_naive();
+    let starts = settings
+        .windows
+        .iter()
+        .map(|&days| {
+            let first_day = today
+                .checked_sub_days(chrono::Days::new(days.saturating_sub(1).into()))
+                .unwrap_or(chrono::NaiveDate::MIN);
+            // On days when clocks change, midnight may come twice, or not at all.
+            tz.from_local_datetime(&first_day.and_time(chrono::NaiveTime::MIN))
+                .earliest()
+                .map_or_else(
+                    || first_day.and_time(chrono::NaiveTime::MIN).and_utc(),
+                    |start| start.with_timezone(&chrono::Utc),
+                )
+        })
+        .collect::<Vec<_>>();
+    let mut stats = sites
+        .iter()
+        .map(|site| SiteStats {
+            name: site.name.clone(),
+            windows: settings
+                .windows
+                .iter()
+                .map(|&days| WindowStats {
+                    days,
+                    articles: 0,
+                    articles_with_text: 0,
+                    words: 0,
+                    minutes: 0,
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+    for reading in readings.iter().filter(|reading| reading.published <= now) {
+        let Some(site_stats) = stats.get_mut(reading.site_index) else {
+            continue;
+        };
+        for (window, start) in site_stats.windows.iter_mut().zip(&starts) {
+            if reading.published >= *start {
+                window.articles += 1;
+                if let Some(words) = reading.words {
+                    window.articles_with_text += 1;
+                    window.words += words;
+                }
+            }
+        }
+    }
+    let words_per_minute = u64::from(settings.words_per_minute.max(1));
+    for window in stats.iter_mut().flat_map(|site| &mut site.windows) {
+        window.minutes = (window.words as u64).div_ceil(words_per_minute);
+    }
+    stats
+        .into_iter()
+        .zip(sites)
+        .filter(|(_, site)| site.display)
+        .map(|(stats, _)| stats)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_files;
+
+    /// Sites `a` and `b`, then `hidden` which isn't displayed.
+    fn sites() -> Vec<SiteConfig> {
+        let config = ["a", "b", "hidden"]
+            .map(|site| {
+                format!(
+                    "[[sites]]\nname = \"{site}\"\nfeed_url = \"https://{site}.example/feed\"\n\
+                     display = {}\n",
+                    site != "hidden"
+                )
+            })
+            .concat();
+        config_files::to_config(
+            toml::from_str(&format!("min_fetch_interval = 0\n{config}")).unwrap(),
+            false,
+        )
+        .unwrap()
+        .sites
+    }
+
+    /// 10 in the morning of the 15th in UTC+10, which is midnight UTC.
+    fn now() -> chrono::DateTime<chrono::Utc> {
+        "2024-03-15T00:00:00Z".parse().unwrap()
+    }
+
+    fn tz() -> chrono::FixedOffset {
+        chrono::FixedOffset::east_opt(10 * 60 * 60).unwrap()
+    }
+
+    fn reading(site_index: usize, published: &str, words: Option<usize>) -> EntryReading {
+        EntryReading {
+            site_index,
+            published: published.parse().unwrap(),
+            words,
+        }
+    }
+
+    /// The articles and minutes of each window of each site.
+    fn counts(stats: &[SiteStats]) -> Vec<(&str, Vec<(usize, u64)>)> {
+        stats
+            .iter()
+            .map(|site| {
+                let windows = site.windows.iter().map(|w| (w.articles, w.minutes));
+                (&*site.name, windows.collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn windows_start_at_local_midnight_and_end_now() {
+        let settings = SiteStatsConfig {
+            windows: vec![7, 30],
+            words_per_minute: 100,
+        };
+        let readings = [
+            // The first instant of the 7-day window, midnight of the 9th in UTC+10.
+            reading(0, "2024-03-08T14:00:00Z", Some(100)),
+            // Just before it, so only in the 30-day window.
+            reading(0, "2024-03-08T13:59:59Z", Some(1)),
+            // The first instant of the 30-day window.
+            reading(0, "2024-02-14T14:00:00Z", None),
+            reading(0, "2024-02-14T13:59:59Z", Some(1000)),
+            // Now is in every window, but a second later isn't.
+            reading(1, "2024-03-15T00:00:00Z", Some(101)),
+            reading(1, "2024-03-15T00:00:01Z", Some(1000)),
+            reading(2, "2024-03-15T00:00:00Z", Some(1000)),
+        ];
+        let stats = site_stats(&settings, &sites(), &readings, &tz(), now());
+        assert_eq!(
+            counts(&stats),
+            [("a", vec![(1, 1), (3, 2)]), ("b", vec![(1, 2), (1, 2)])]
+        );
+        assert_eq!(stats[0].windows[1].articles_with_text, 2);
+        assert_eq!(stats[0].windows[1].words, 101);
+
+        // In UTC, the windows start 10 hours later.
+        let stats = site_stats(&settings, &sites(), &readings, &chrono::Utc, now());
+        assert_eq!(
+            counts(&stats),
+            [("a", vec![(0, 0), (2, 2)]), ("b", vec![(1, 2), (1, 2)])]
+        );
+    }
+
+    #[test]
+    fn empty_windows_have_nothing_in_them() {
+        let settings = SiteStatsConfig {
+            windows: vec![1, 7],
+            words_per_minute: 230,
+        };
+        let readings = [reading(0, "2024-03-13T00:00:00Z", Some(10))];
+        let stats = site_stats(&settings, &sites(), &readings, &tz(), now());
+        assert_eq!(
+            counts(&stats),
+            [("a", vec![(0, 0), (1, 1)]), ("b", vec![(0, 0), (0, 0)])]
+        );
+        let stats = site_stats(&settings, &sites(), &[], &tz(), now());
+        assert_eq!(
+            counts(&stats),
+            [("a", vec![(0, 0), (0, 0)]), ("b", vec![(0, 0), (0, 0)])]
+        );
+        let no_windows = SiteStatsConfig {
+            windows: Vec::new(),
+            ..settings
+        };
+        let stats = site_stats(&no_windows, &sites(), &readings, &tz(), now());
+        assert_eq!(counts(&stats), [("a", vec![]), ("b", vec![])]);
+    }
+}
@@ -0,0 +1,326 @@
+use super::{
+    cache,
+    errors::{ErrorCode, WithCode as _},
+};
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// A file in the cache directory, to be moved.
+struct CacheFile {
+    /// The path of the file, relative to the cache directory.
+    relative: PathBuf,
+    /// The size of the file, in bytes.
+    size: u64,
+}
+
+/// Move everything in the cache directory `from` to `to`, or only list what would be moved if
+/// `dry_run`.
+///
+/// Every file is copied and checked against its original before any original is removed, so if
+/// anything fails, `from` is left as it was and the copies made so far are removed. `to` must not
+/// already have any files in it. Fails if another jarss is using `from`.
+pub fn move_cache(from: &Path, to: &Path, dry_run: bool) -> Result<()> {
+    let from_canonical = std::fs::canonicalize(from)
+        .with_context(|| format!("Cache directory {} can't be read", from.display()))
+        .code(ErrorCode::CacheIo)?;
+    if canonicalize_nonexistent(to).starts_with(&from_canonical) {
+        anyhow::bail!(
+            "{} is within the cache directory {}, so the cache can't be moved there",
+            to.display(),
+            from.display()
+        );
+    }
+    let lock = cache::lock_exclusive(from)?;
+    let mut files = Vec::new();
+    list_files(from, Path::new(""), &mut files)?;
+    let total = files.iter().map(|file| file.size).sum::<u64>();
+    if dry_run {
+        for file in &files {
+            println!(
+                "Would move {} ({} bytes)",
+                from.join(&file.relative).display(),
+                file.size
+            );
+        }
+        println!(
+            "Would move {} files, {total} bytes in all, to {}",
+            files.len(),
+            to.display()
+        );
+        return Ok(());
+    }
+    if std::fs::read_dir(to).is_ok_and(|mut entries| entries.next().is_some()) {
+        anyhow::bail!(
+            "{} already has files in it, so the cache isn't moved there in case they'd be \
+             overwritten",
+            to.display()
+        );
+    }
+
+    copy_cache(from, to, &files, &mut |path, contents| {
+        std::fs::write(path, contents)
+    })?;
+    for file in &files {
+        let path = from.join(&file.relative);
+        std::fs::remove_file(&path)
+            .with_context(|| {
+                format!(
+                    "The cache was copied to {}, but {} couldn't be removed",
+                    to.display(),
+                    path.display()
+                )
+            })
+            .code(ErrorCode::CacheIo)?;
+    }
+    drop(lock);
+    let _ = std::fs::remove_file(from.join(cache::LOCK_FILE_NAME));
+    remove_empty_dirs(from);
+    println!(
+        "Moved {} files, {total} bytes in all, to {}",
+        files.len(),
+        to.display()
+    );
+    println!(
+        "Give `--cache {}` to jarss from now on, or `.cache_dir(...)` to the library's \
+         `JarssBuilder`, to use it",
+        to.display()
+    );
+    Ok(())
+}
+
+/// `path` made absolute, with symlinks resolved, even if it doesn't exist yet.
+///
+/// The deepest of its ancestors which exists is resolved, and the rest appended as given.
+fn canonicalize_nonexistent(path: &Path) -> PathBuf {
+    let mut rest = Vec::new();
+    let mut ancestor = path;
+    loop {
+        if let Ok(canonical) = std::fs::canonicalize(ancestor) {
+            return rest
+                .into_iter()
+                .rev()
+                .fold(canonical, |path, part| path.join(part));
+        }
+        match (ancestor.parent(), ancestor.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                ancestor = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+            }
+            _ => return path.to_owned(),
+        }
+    }
+}
+
+/// Add the files in `dir`, which is `relative` within the cache directory, and its
+/// subdirectories, to `files`.
+///
+/// The lock file isn't listed, since it's only there while the cache is in use.
+fn list_files(dir: &Path, relative: &Path, files: &mut Vec<CacheFile>) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to list {}", dir.display()))
+        .code(ErrorCode::CacheIo)?;
+    let mut entries = entries
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to list {}", dir.display()))
+        .code(ErrorCode::CacheIo)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let relative = relative.join(entry.file_name());
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Failed to read {}", entry.path().display()))
+            .code(ErrorCode::CacheIo)?;
+        if metadata.is_dir() {
+            list_files(&entry.path(), &relative, files)?;
+        } else if relative != Path::new(cache::LOCK_FILE_NAME) {
+            files.push(CacheFile {
+                relative,
+                size: metadata.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Writes a file's contents, which is [`std::fs::write`] except in tests.
+type Write<'a> = dyn FnMut(&Path, &[u8]) -> std::io::Result<()> + 'a;
+
+/// Copy `files` from the cache directory `from` to `to` with [`copy_files`], removing the copies
+/// if any of them fails.
+fn copy_cache(from: &Path, to: &Path, files: &[CacheFile], write: &mut Write<'_>) -> Result<()> {
+    let mut copied = Vec::new();
+    let Err(e) = copy_files(from, to, files, write, &mut copied) else {
+        return Ok(());
+    };
+    // Only the copies are removed, so nothing is lost whatever got us here.
+    for path in copied.iter().rev() {
+        if let Err(e) = std::fs::remove_file(path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            log::warn!("Failed to remove the copy at {}: {e}", path.display());
+        }
+    }
+    Err(e.context(format!(
+        "Failed to copy the cache to {}, so it's been left in {}",
+        to.display(),
+        from.display()
+    )))
+    .code(ErrorCode::CacheIo)
+}
+
+/// Copy `files` from the cache directory `from` to `to`, writing them with `write`, and checking
+/// that each copy's contents match its original.
+///
+/// Each copy is added to `copied` once it's been written, so they can be removed if a later one
+/// fails.
+fn copy_files(
+    from: &Path,
+    to: &Path,
+    files: &[CacheFile],
+    write: &mut Write<'_>,
+    copied: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for file in files {
+        let source = from.join(&file.relative);
+        let destination = to.join(&file.relative);
+        log::info!("Copying {} to {}", source.display(), destination.display());
+        std::fs::create_dir_all(destination.parent().unwrap_or(to)).with_context(|| {
+            format!(
+                "Failed to create the directory for {}",
+                destination.display()
+            )
+        })?;
+        let original = std::fs::read(&source)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        // Recorded first, so even a partly written copy is cleaned up.
+        copied.push(destination.clone());
+        write(&destination, &original)
+            .with_context(|| format!("Failed to write {}", destination.display()))?;
+        let copy = std::fs::read(&destination)
+            .with_context(|| format!("Failed to read back {}", destination.display()))?;
+        if blake3::hash(&copy) != blake3::hash(&original) {
+            anyhow::bail!(
+                "The copy at {} doesn't match {}",
+                destination.display(),
+                source.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Remove `dir` and the directories in it, if they're empty, as the cache directory is once
+/// everything's been moved out of it.
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    if let Err(e) = std::fs::remove_dir(dir) {
+        log::warn!(
+            "Left {} in place, as it couldn't be removed: {e}",
+            dir.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// A cache directory with a few files in it, and an empty directory to move it to, which are
+    /// only used by the calling test.
+    fn test_dirs(name: &str) -> (PathBuf, PathBuf, Vec<CacheFile>) {
+        let dir = test_dir(name);
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::create_dir_all(from.join("nested")).unwrap();
+        for (path, contents) in [("a.lz4", "a"), ("b.lz4", "b"), ("nested/c.json", "c")] {
+            std::fs::write(from.join(path), contents).unwrap();
+        }
+        let mut files = Vec::new();
+        list_files(&from, Path::new(""), &mut files).unwrap();
+        (from, to, files)
+    }
+
+    /// The paths and contents of the files in `dir`.
+    fn contents(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = Vec::new();
+        if dir.exists() {
+            list_files(dir, Path::new(""), &mut files).unwrap();
+        }
+        files
+            .into_iter()
+            .map(|file| {
+                let contents = std::fs::read(dir.join(&file.relative)).unwrap();
+                (file.relative, contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failing_partway_leaves_the_cache_untouched() {
+        let (from, to, files) = test_dirs("relocate-fails");
+        let before = contents(&from);
+        let mut writes = 0;
+        let e = copy_cache(&from, &to, &files, &mut |path, contents| {
+            writes += 1;
+            if writes < 2 {
+                return std::fs::write(path, contents);
+            }
+            // Fail partway through writing the second copy.
+            std::fs::write(path, &contents[..contents.len() / 2])?;
+            Err(std::io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::CacheIo);
+        assert_eq!(contents(&from), before);
+        assert_eq!(contents(&to), []);
+        std::fs::remove_dir_all(from.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn copies_which_dont_match_are_removed() {
+        let (from, to, files) = test_dirs("relocate-mismatch");
+        let before = contents(&from);
+        copy_cache(&from, &to, &files, &mut |path, _| {
+            std::fs::write(path, "wrong")
+        })
+        .unwrap_err();
+        assert_eq!(contents(&from), before);
+        assert_eq!(contents(&to), []);
+        std::fs::remove_dir_all(from.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn moving_moves_everything() {
+        let (from, to, _) = test_dirs("relocate-moves");
+        let before = contents(&from);
+        move_cache(&from, &to, false).unwrap();
+        assert_eq!(contents(&to), before);
+        assert!(!from.exists());
+        std::fs::remove_dir_all(to.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn caches_in_use_arent_moved() {
+        let (from, to, _) = test_dirs("relocate-in-use");
+        let before = contents(&from);
+        let caches = cache::CacheManager::new(from.clone(), cache::CacheKey::Name, &[]).unwrap();
+        let e = move_cache(&from, &to, false).unwrap_err();
+        assert!(e.to_string().starts_with("Another jarss"), "{e:?}");
+        assert!(!to.exists());
+        drop(caches);
+        move_cache(&from, &to, false).unwrap();
+        assert_eq!(contents(&to), before);
+        std::fs::remove_dir_all(to.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    io::{ErrorKind, Write as _},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether we've had to replace a file in two steps this run, so it's only logged once.
+static REPLACING_IN_TWO_STEPS: AtomicBool = AtomicBool::new(false);
+
+/// Start a new run, so having to replace files in two steps is logged again if it happens.
+pub fn start_run() {
+    REPLACING_IN_TWO_STEPS.store(false, Ordering::Relaxed);
+}
+
+/// Renames a file, which is [`std::fs::rename`] except in tests.
+type Rename = dyn Fn(&Path, &Path) -> std::io::Result<()>;
+
+/// Write `contents` to `path` such that readers see either the old or the new contents in full.
+///
+/// The new contents reach the disk before they replace the old, so a crash or power loss doesn't
+/// leave a truncated file behind.
+///
+/// On filesystems which can't rename over an existing file, like some network mounts, the old
+/// file is removed first, so readers may briefly see no file at all. A marker file records that
+/// this is in progress, so it's finished the next time `path` is written if it was interrupted.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    replace(
+        path,
+        contents,
+        &|from, to| std::fs::rename(from, to),
+        &REPLACING_IN_TWO_STEPS,
+    )
+}
+
+/// [`write_atomically`], renaming with `rename`.
+///
+/// `replacing_in_two_steps` is set once files have had to be replaced in two steps, so it's only
+/// logged once.
+fn replace(
+    path: &Path,
+    contents: &[u8],
+    rename: &Rename,
+    replacing_in_two_steps: &AtomicBool,
+) -> std::io::Result<()> {
+    let temp_path = sibling(path, ".tmp");
+    let marker_path = sibling(path, ".replacing");
+    finish_interrupted_replace(path, &temp_path, &marker_path, rename)?;
+    write_synced(&temp_path, contents)?;
+    match rename(&temp_path, path) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::AlreadyExists | ErrorKind::CrossesDevices
+            ) =>
+        {
+            if !replacing_in_two_steps.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "Couldn't replace {} by renaming over it ({e}), replacing files in two steps instead",
+                    path.display()
+                );
+            }
+            // The marker says the temporary file is complete, so it's safe to finish with. It has
+            // to be on the disk before the old file is removed, and the new file in its place
+            // before the marker is.
+            write_synced(&marker_path, b"")?;
+            sync_dir_of(path)?;
+            remove_if_exists(path)?;
+            move_file(&temp_path, path, rename)?;
+            sync_dir_of(path)?;
+            std::fs::remove_file(&marker_path)
+        }
+        res => res,
+    }
+}
+
+/// Write `contents` to a new file at `path`, waiting for them to reach the disk.
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Wait for the files added to and removed from the directory `path` is in to reach the disk.
+///
+/// Only Unix can open directories to do this. Elsewhere, the filesystem has to take care of it.
+fn sync_dir_of(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Whether a two-step replacement of `path` was interrupted, so its temporary file is needed to
+/// finish it.
+pub fn is_replacing(path: &Path) -> bool {
+    sibling(path, ".replacing").exists()
+}
+
+/// The path of a file alongside `path`, with `suffix` added to its name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// If a two-step replacement of `path` was interrupted, finish it.
+fn finish_interrupted_replace(
+    path: &Path,
+    temp_path: &Path,
+    marker_path: &Path,
+    rename: &Rename,
+) -> std::io::Result<()> {
+    if !marker_path.try_exists()? {
+        return Ok(());
+    }
+    if temp_path.try_exists()? {
+        log::warn!("Finishing an interrupted replacement of {}", path.display());
+        remove_if_exists(path)?;
+        move_file(temp_path, path, rename)?;
+        sync_dir_of(path)?;
+    }
+    std::fs::remove_file(marker_path)
+}
+
+/// Move a file, copying it if it has to go to another filesystem.
+fn move_file(from: &Path, to: &Path, rename: &Rename) -> std::io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            std::fs::copy(from, to)?;
+            std::fs::File::open(to)?.sync_all()?;
+            std::fs::remove_file(from)
+        }
+        res => res,
+    }
+}
+
+/// Remove a file, if there is one.
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// Renames like a filesystem which can't rename over an existing file.
+    fn rename_without_replacing(from: &Path, to: &Path) -> std::io::Result<()> {
+        if to.exists() {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        std::fs::rename(from, to)
+    }
+
+    #[test]
+    fn files_are_replaced_in_two_steps_when_renaming_over_them_fails() {
+        let dir = test_dir("two-steps");
+        let path = dir.join("out.html");
+        std::fs::write(&path, "old").unwrap();
+        let replacing_in_two_steps = AtomicBool::new(false);
+        replace(
+            &path,
+            b"new",
+            &rename_without_replacing,
+            &replacing_in_two_steps,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(replacing_in_two_steps.load(Ordering::Relaxed));
+        assert!(!sibling(&path, ".tmp").exists());
+        assert!(!sibling(&path, ".replacing").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn other_rename_failures_leave_the_old_file() {
+        let dir = test_dir("rename-fails");
+        let path = dir.join("out.html");
+        std::fs::write(&path, "old").unwrap();
+        let replacing_in_two_steps = AtomicBool::new(false);
+        let e = replace(
+            &path,
+            b"new",
+            &|_, _| Err(ErrorKind::PermissionDenied.into()),
+            &replacing_in_two_steps,
+        )
+        .unwrap_err();
+        assert!(!replacing_in_two_steps.load(Ordering::Relaxed));
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        assert!(!sibling(&path, ".replacing").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_interrupted_two_step_replace_is_finished_by_the_next_write() {
+        let dir = test_dir("interrupted");
+        let path = dir.join("out.html");
+        // As left by a crash after the old file was removed, before the new one was moved in.
+        std::fs::write(sibling(&path, ".tmp"), "interrupted").unwrap();
+        std::fs::write(sibling(&path, ".replacing"), "").unwrap();
+        finish_interrupted_replace(
+            &path,
+            &sibling(&path, ".tmp"),
+            &sibling(&path, ".replacing"),
+            &rename_without_replacing,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "interrupted");
+        assert!(!sibling(&path, ".replacing").exists());
+
+        // And by a crash before the old file was removed.
+        std::fs::write(sibling(&path, ".tmp"), "interrupted again").unwrap();
+        std::fs::write(sibling(&path, ".replacing"), "").unwrap();
+        replace(
+            &path,
+            b"new",
+            &rename_without_replacing,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!sibling(&path, ".replacing").exists());
+        assert!(!sibling(&path, ".tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_synced_wherever_they_are() {
+        let dir = test_dir("synced");
+        let path = dir.join("out.html");
+        write_synced(&path, b"synced").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "synced");
+        sync_dir_of(&path).unwrap();
+        // Paths without a directory are in the working directory.
+        sync_dir_of(Path::new("out.html")).unwrap();
+        // Replacing a file leaves no temporary files behind.
+        write_atomically(&path, b"replaced").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "replaced");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+use super::{SiteConfig, cache::SiteCache};
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The most redirects we'll follow from a single link.
+const MAX_REDIRECTS: usize = 5;
+
+/// The result of trying to resolve one link.
+enum Resolution {
+    /// The link ends up at the given URL (which may be the link itself).
+    Resolved(Box<str>),
+    /// The link can't be usefully resolved, so the original should be kept.
+    Unresolvable,
+    /// Something went wrong which may not happen next time, so try again later.
+    Retry,
+    /// We've used up the requests we're allowed this run.
+    OutOfBudget,
+}
+
+/// Resolve the links of any entries in the cached feed which we haven't resolved yet.
+///
+/// `client` must not follow redirects itself. Each request made is taken from `budget`, and once it
+/// hits zero no further links are resolved. Links which are no longer in the feed are forgotten.
+pub async fn resolve_links(
+    client: &reqwest::Client,
+    site: &SiteConfig,
+    cache: &mut SiteCache,
+    budget: &AtomicUsize,
+) {
+    let Some(body) = cache.last_body.as_ref() else {
+        return;
+    };
+    let feed = match feed_rs::parser::parse(std::io::Cursor::new(body.as_bytes())) {
+        Ok(feed) => feed,
+        // This gets reported properly when collecting articles.
+        Err(_) => return,
+    };
+    let mut resolved_links = HashMap::new();
+    let mut out_of_budget = false;
+    for link in feed
+        .entries
+        .iter()
+        .filter_map(|entry| Some(entry.links.first()?.href.as_str()))
+    {
+        if let Some((original, resolved)) = cache.resolved_links.get_key_value(link) {
+            resolved_links.insert(original.clone(), resolved.clone());
+            continue;
+        }
+        if out_of_budget {
+            continue;
+        }
+        match resolve(client, link, budget).await {
+            Resolution::Resolved(resolved) => {
+                if resolved.as_ref() != link {
+                    log::debug!("Resolved {link} from {} to {resolved}", site.name);
+                }
+                resolved_links.insert(link.into(), resolved);
+            }
+            Resolution::Unresolvable => {
+                resolved_links.insert(link.into(), link.into());
+            }
+            Resolution::Retry => {}
+            Resolution::OutOfBudget => {
+                log::info!("Out of link resolutions for this run, skipping the rest");
+                out_of_budget = true;
+            }
+        }
+    }
+    if resolved_links != cache.resolved_links {
+        cache.resolved_links = resolved_links;
+        cache.mark_dirty();
+    }
+}
+
+/// Follow the redirects from `link` to find where it ends up.
+async fn resolve(client: &reqwest::Client, link: &str, budget: &AtomicUsize) -> Resolution {
+    let Ok(mut url) = reqwest::Url::parse(link) else {
+        return Resolution::Unresolvable;
+    };
+    let mut seen = HashSet::new();
+    for _ in 0..=MAX_REDIRECTS {
+        if !seen.insert(url.clone()) {
+            log::warn!("Redirect loop resolving {link}, keeping it as-is");
+            return Resolution::Unresolvable;
+        }
+        if budget
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .is_err()
+        {
+            return Resolution::OutOfBudget;
+        }
+        let res = match client.head(url.clone()).send().await {
+            Ok(res) => res,
+            Err(e) => {
+                log::warn!("Error resolving {link}: {e}");
+                return Resolution::Retry;
+            }
+        };
+        if !res.status().is_redirection() {
+            return if res.status().is_success() {
+                Resolution::Resolved(url.as_str().into())
+            } else {
+                Resolution::Unresolvable
+            };
+        }
+        let Some(next) = res
+            .headers()
+            .get(http::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| url.join(location).ok())
+        else {
+            return Resolution::Unresolvable;
+        };
+        if !matches!(next.scheme(), "http" | "https") {
+            log::warn!("{link} redirects to a non-HTTP URL, keeping it as-is");
+            return Resolution::Unresolvable;
+        }
+        url = next;
+    }
+    log::warn!("Too many redirects resolving {link}, keeping it as-is");
+    Resolution::Unresolvable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    /// Where `link` resolves to, and how many requests that took.
+    async fn resolve_counting(link: &str) -> (Option<Box<str>>, usize) {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let budget = AtomicUsize::new(100);
+        let resolved = match resolve(&client, link, &budget).await {
+            Resolution::Resolved(resolved) => Some(resolved),
+            Resolution::Unresolvable => None,
+            Resolution::Retry | Resolution::OutOfBudget => panic!("{link} wasn't resolved"),
+        };
+        (resolved, 100 - budget.into_inner())
+    }
+
+    #[tokio::test]
+    async fn links_are_resolved_through_chains_of_redirects() {
+        let server = test_server::serve_redirect_chains().await;
+        assert_eq!(
+            resolve_counting(&format!("http://{server}/redirect/3")).await,
+            (Some(format!("http://{server}/redirect/0").into()), 4)
+        );
+    }
+
+    #[tokio::test]
+    async fn links_are_kept_when_they_redirect_too_many_times() {
+        let server = test_server::serve_redirect_chains().await;
+        assert_eq!(
+            resolve_counting(&format!("http://{server}/redirect/{MAX_REDIRECTS}")).await,
+            (
+                Some(format!("http://{server}/redirect/0").into()),
+                MAX_REDIRECTS + 1
+            )
+        );
+        // Which gives up without following the last of them.
+        assert_eq!(
+            resolve_counting(&format!("http://{server}/redirect/{}", MAX_REDIRECTS + 1)).await,
+            (None, MAX_REDIRECTS + 1)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+
+/// The top-level settings safe mode keeps, which are those of fetching the feeds, reading them,
+/// and rendering the page.
+///
+/// Every other setting belongs to an optional feature, and is ignored in safe mode. This lists
+/// what's kept rather than what's ignored, so the settings of new features are ignored without
+/// needing to be added anywhere.
+const CORE_SETTINGS: &[&str] = &[
+    "sites",
+    "min_fetch_interval",
+    "max_entries_per_site",
+    "max_total_entries",
+    "max_age_days",
+    "junk",
+    "dedup_mode",
+    "cache_key",
+    "include_summaries",
+    "self_contained",
+    "render",
+    "warn_after",
+    "alert_after",
+    "max_concurrent_fetches",
+    "max_concurrent_saves",
+    "dns_preresolve",
+    "dns_timeout",
+    "github_token_file",
+    "hook_timeout",
+    "limits",
+    "http",
+    "strict_content_type",
+];
+
+/// The settings of each site which safe mode keeps, like [`CORE_SETTINGS`].
+const CORE_SITE_SETTINGS: &[&str] = &[
+    "name",
+    "feed_url",
+    "display",
+    "enabled",
+    "min_fetch_interval",
+    "max_entries",
+    "max_age_days",
+    "include_title_regex",
+    "exclude_title_regex",
+    "exclude_categories",
+    "junk",
+    "pre_fetch_command",
+    "digest_auth",
+    "basic_auth",
+    "headers",
+    "header_env",
+    "method",
+    "body",
+    "body_env",
+    "content_type",
+];
+
+/// Remove the settings safe mode ignores from the `merged` config, so the optional features they
+/// turn on are left at their defaults, and log which were ignored.
+pub fn strip(merged: &mut toml::Table) {
+    let mut ignored = BTreeSet::new();
+    merged.retain(|key, _| {
+        let keep = CORE_SETTINGS.contains(&key);
+        if !keep {
+            ignored.insert(key.to_owned());
+        }
+        keep
+    });
+    if let Some(toml::Value::Array(sites)) = merged.get_mut("sites") {
+        for site in sites.iter_mut().filter_map(toml::Value::as_table_mut) {
+            site.retain(|key, _| {
+                let keep = CORE_SITE_SETTINGS.contains(&key);
+                if !keep {
+                    ignored.insert(format!("sites.{key}"));
+                }
+                keep
+            });
+        }
+    }
+    if ignored.is_empty() {
+        log::info!("Running in safe mode, which ignores none of the settings in this config");
+    } else {
+        log::warn!(
+            "Running in safe mode, so these settings are ignored: {}",
+            ignored.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The name of every setting in a table, going by what `value` serializes to.
+    fn keys_of(value: impl serde::Serialize) -> BTreeSet<String> {
+        match serde_json::to_value(value).unwrap() {
+            serde_json::Value::Object(fields) => fields.keys().cloned().collect(),
+            value => panic!("Expected settings, got {value}"),
+        }
+    }
+
+    #[test]
+    fn only_core_settings_are_kept() {
+        let config = crate::config_files::to_config(
+            toml::from_str(
+                r#"
+                min_fetch_interval = 0
+                [[sites]]
+                name = "a"
+                feed_url = "https://a.example/feed"
+                "#,
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let settings = keys_of(&config);
+        let site_settings = keys_of(&config.sites[0]);
+        // Every kept setting has to exist, so a typo can't keep a setting by accident.
+        for core in CORE_SETTINGS {
+            assert!(settings.contains(*core), "{core} isn't a setting");
+        }
+        for core in CORE_SITE_SETTINGS {
+            assert!(
+                site_settings.contains(*core),
+                "sites.{core} isn't a setting"
+            );
+        }
+
+        // A config setting everything, whatever to.
+        let set_everything = |keys: &BTreeSet<String>| {
+            keys.iter()
+                .map(|key| (key.clone(), toml::Value::Boolean(true)))
+                .collect::<toml::Table>()
+        };
+        let mut merged = set_everything(&settings);
+        merged.insert(
+            "sites".into(),
+            toml::Value::Array(vec![toml::Value::Table(set_everything(&site_settings))]),
+        );
+        strip(&mut merged);
+
+        let kept = merged.keys().map(String::as_str).collect::<BTreeSet<_>>();
+        assert_eq!(kept, CORE_SETTINGS.iter().copied().collect());
+        let toml::Value::Array(sites) = &merged["sites"] else {
+            panic!("The sites weren't kept");
+        };
+        let kept = sites[0]
+            .as_table()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(kept, CORE_SITE_SETTINGS.iter().copied().collect());
+    }
+}
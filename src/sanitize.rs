@@ -0,0 +1,561 @@
+use super::{discover, strings::RenderConfig, template_filters};
+
+/// A [`tera::Tera`] to render pages from feed-provided values with, with our
+/// [filters](template_filters::register) showing `render`'s text.
+///
+/// Tera only escapes values by default in templates whose names end in `.html`, so this escapes
+/// them in every template. Unlike tera's own escaping, `/` is left alone, so links stay readable.
+pub fn tera(render: &RenderConfig) -> tera::Tera {
+    let mut tera = tera::Tera::default();
+    tera.autoescape_on(vec![""]);
+    tera.set_escape_fn(escape_html);
+    template_filters::register(&mut tera, render);
+    tera
+}
+
+/// Escape the characters which are special in HTML text and attribute values.
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Break up any tera delimiters in a feed-provided string.
+///
+/// Tera escapes HTML in values, but it doesn't stop values from containing template syntax, which
+/// would be dangerous if any template ever rendered a value as a template itself. A zero-width
+/// space is inserted between the braces of `{{`, `{%`, and `{#`, so the text looks the same but
+/// can't be parsed as a delimiter.
+pub fn neutralize_template_syntax(text: &str) -> Box<str> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '{'
+            && chars
+                .peek()
+                .is_some_and(|next| matches!(next, '{' | '%' | '#'))
+        {
+            out.push('\u{200B}');
+        }
+    }
+    out.into_boxed_str()
+}
+
+/// A feed-provided value as JSON, with [`neutralize_template_syntax`] applied to every string in
+/// it.
+///
+/// This gives `None` if the value can't be serialized, which is only for values which no feed
+/// could give.
+pub fn neutralized_json(value: &impl serde::Serialize) -> Option<serde_json::Value> {
+    fn neutralize(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => {
+                if text.contains('{') {
+                    *text = neutralize_template_syntax(text).into();
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(neutralize),
+            serde_json::Value::Object(values) => values.values_mut().for_each(neutralize),
+            serde_json::Value::Null | serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {
+            }
+        }
+    }
+    let mut value = serde_json::to_value(value).ok()?;
+    neutralize(&mut value);
+    Some(value)
+}
+
+/// Percent-encode any braces in a feed-provided link.
+///
+/// This is the equivalent of [`neutralize_template_syntax`] for URLs, where inserting characters
+/// would change where the link goes.
+pub fn neutralize_link(link: &str) -> Box<str> {
+    if !link.contains(['{', '}']) {
+        return link.into();
+    }
+    link.replace('{', "%7B")
+        .replace('}', "%7D")
+        .into_boxed_str()
+}
+
+/// Limits on the feed-provided strings we'll accept, so one broken or malicious feed can't bloat
+/// the page.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// The largest feed we'll download, in bytes. Fetches of larger ones fail.
+    pub max_body_bytes: usize,
+    /// The longest title we'll keep, in bytes. Longer ones are truncated.
+    pub max_title_bytes: usize,
+    /// The longest summary we'll keep, in bytes. Longer ones are truncated.
+    pub max_summary_bytes: usize,
+    /// The longest content we'll keep, in bytes. Longer content is truncated.
+    pub max_content_bytes: usize,
+    /// The most characters of plain text to give in an article's `summary_text`.
+    pub summary_text_chars: usize,
+    /// The largest an entry can be once serialized, in bytes. Larger entries are dropped.
+    pub max_entry_bytes: usize,
+    /// The fraction of a string's characters which can be non-printable before we consider it
+    /// garbage and replace it.
+    pub max_non_printable_ratio: f64,
+    /// Whether non-breaking and ideographic spaces in titles and text are collapsed like any other
+    /// whitespace, rather than kept as they are.
+    pub collapse_nonbreaking_spaces: bool,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 5 * 1024 * 1024,
+            max_title_bytes: 1024,
+            max_summary_bytes: 16 * 1024,
+            max_content_bytes: 32 * 1024,
+            summary_text_chars: 300,
+            max_entry_bytes: 64 * 1024,
+            max_non_printable_ratio: 0.1,
+            collapse_nonbreaking_spaces: false,
+        }
+    }
+}
+
+/// What's shown in place of a string which looks like garbage.
+pub const GARBAGE_PLACEHOLDER: &str = "[unreadable]";
+
+/// A feed-provided string, after applying [`Limits`] to it.
+pub struct Limited {
+    pub text: Box<str>,
+    /// Whether the string looked like garbage, and so was replaced with [`GARBAGE_PLACEHOLDER`].
+    pub suspect: bool,
+}
+
+/// Apply the limits to a feed-provided string, logging a warning if it had to be changed.
+///
+/// `field` names the string in the warning.
+pub fn limit(text: &str, max_bytes: usize, limits: &Limits, field: &str) -> Limited {
+    if is_garbage(text, limits.max_non_printable_ratio) {
+        log::warn!("Replacing {field} which looks like garbage");
+        return Limited {
+            text: GARBAGE_PLACEHOLDER.into(),
+            suspect: true,
+        };
+    }
+    if text.len() <= max_bytes {
+        return Limited {
+            text: text.into(),
+            suspect: false,
+        };
+    }
+    log::warn!(
+        "Truncating {field}, which is {} bytes, to {max_bytes} bytes",
+        text.len()
+    );
+    // Leave room for the ellipsis, so the result still fits in the limit.
+    let mut end = max_bytes.saturating_sub('…'.len_utf8());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Limited {
+        text: format!("{}…", &text[..end]).into_boxed_str(),
+        suspect: false,
+    }
+}
+
+/// Normalize feed-provided text, so it's shown and compared the same however the feed encoded it.
+///
+/// The text is put in Unicode's NFC form, and then:
+/// - Control characters, and invisible ones like soft hyphens and zero-width spaces, are removed.
+///   Zero-width joiners are kept, since emoji sequences depend on them.
+/// - Runs of whitespace, including line breaks like `\r\n`, become single spaces, and whitespace
+///   at either end is trimmed. Non-breaking and ideographic spaces are only included with
+///   [`Limits::collapse_nonbreaking_spaces`].
+pub fn normalize_text(text: &str, limits: &Limits) -> String {
+    let text = icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(text);
+    let mut normalized = String::with_capacity(text.len());
+    let mut space_pending = false;
+    for c in text.chars() {
+        let is_space = if is_nonbreaking_space(c) {
+            limits.collapse_nonbreaking_spaces
+        } else {
+            c.is_whitespace()
+        };
+        if is_space {
+            space_pending = !normalized.is_empty();
+        } else if !is_invisible(c) {
+            if space_pending {
+                normalized.push(' ');
+                space_pending = false;
+            }
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+/// [Normalize](normalize_text) a feed-provided title, unless it looks like garbage.
+///
+/// Normalizing removes the control characters which garbage is recognized by, so titles which
+/// look like garbage are left as they are, for [`limit`] to replace later.
+pub fn normalize_title(title: &str, limits: &Limits) -> String {
+    if is_garbage(title, limits.max_non_printable_ratio) {
+        title.to_owned()
+    } else {
+        normalize_text(title, limits)
+    }
+}
+
+/// Whether `c` is a space which text shouldn't be broken at.
+fn is_nonbreaking_space(c: char) -> bool {
+    matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}' | '\u{3000}')
+}
+
+/// Whether `c` isn't whitespace but doesn't show up, so can be removed from text.
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            // Soft hyphen, zero-width space, word joiner, and byte order mark.
+            '\u{ad}' | '\u{200b}' | '\u{2060}' | '\u{feff}'
+        )
+}
+
+/// Sanitize feed-provided text of the given MIME type into HTML which is safe to include in a page.
+///
+/// HTML is stripped of scripts, event handlers, and anything else which could run code or load
+/// things unexpectedly. Plain text is escaped, and anything else (such as base64-encoded media)
+/// gives `None`.
+pub fn to_safe_html(text: &str, content_type: &str) -> Option<String> {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    if essence.eq_ignore_ascii_case("text/html")
+        || essence.eq_ignore_ascii_case("application/xhtml+xml")
+    {
+        Some(ammonia::clean(text))
+    } else if essence.eq_ignore_ascii_case("text/plain") {
+        Some(ammonia::clean_text(text))
+    } else {
+        None
+    }
+}
+
+/// The text of an HTML fragment, without any markup, [normalized](normalize_text) and truncated
+/// to [`Limits::summary_text_chars`] characters.
+pub fn to_plain_text(html: &str, limits: &Limits) -> String {
+    let max_chars = limits.summary_text_chars;
+    let stripped = ammonia::Builder::empty().clean(html).to_string();
+    let text = normalize_text(&discover::decode_entities(&stripped), limits);
+    match text.char_indices().nth(max_chars.saturating_sub(1)) {
+        Some((end, _)) if text.chars().count() > max_chars => format!("{}…", &text[..end]),
+        _ => text,
+    }
+}
+
+/// How many words the text of an HTML fragment has, without any markup.
+pub fn word_count(html: &str) -> usize {
+    ammonia::Builder::empty()
+        .clean(html)
+        .to_string()
+        .split_whitespace()
+        .count()
+}
+
+/// Whether too much of the text is made up of control characters or replacement characters (which
+/// are what invalid UTF-8 decodes to).
+fn is_garbage(text: &str, max_non_printable_ratio: f64) -> bool {
+    let (total, non_printable) = text.chars().fold((0usize, 0usize), |(total, bad), c| {
+        let is_bad = (c.is_control() && !c.is_whitespace()) || c == char::REPLACEMENT_CHARACTER;
+        (total + 1, bad + usize::from(is_bad))
+    });
+    total > 0 && non_printable as f64 > total as f64 * max_non_printable_ratio
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_titles_are_still_caught_after_normalizing() {
+        let limits = Limits::default();
+        let garbage = "\u{1}\u{2}\u{3}abc\u{fffd}\u{fffd}";
+        let normalized = normalize_title(garbage, &limits);
+        let limited = limit(&normalized, limits.max_title_bytes, &limits, "title");
+        assert!(limited.suspect);
+        assert_eq!(&*limited.text, GARBAGE_PLACEHOLDER);
+    }
+
+    #[test]
+    fn normal_titles_are_normalized() {
+        let limits = Limits::default();
+        let normalized = normalize_title("  A\r\n title\u{ad} ", &limits);
+        assert_eq!(normalized, "A title");
+        assert!(!limit(&normalized, limits.max_title_bytes, &limits, "title").suspect);
+    }
+
+    #[test]
+    fn links_with_braces_are_percent_encoded() {
+        assert_eq!(
+            &*neutralize_link("https://example.com/{{ 7 * 7 }}?q={%x%}#{#y#}"),
+            "https://example.com/%7B%7B 7 * 7 %7D%7D?q=%7B%x%%7D#%7B#y#%7D"
+        );
+        assert_eq!(
+            &*neutralize_link("https://example.com/}{"),
+            "https://example.com/%7D%7B"
+        );
+        // Links without braces are left exactly as they are, percent-encoding and all.
+        for link in ["https://example.com/a%20b?c=d&e#f", "", "/relative"] {
+            assert_eq!(&*neutralize_link(link), link);
+        }
+    }
+
+    #[test]
+    fn template_syntax_is_broken_up_but_looks_the_same() {
+        for (text, expected) in [
+            ("{{ x }}", "{\u{200B}{ x }}"),
+            ("{% if x %}", "{\u{200B}% if x %}"),
+            ("{# x #}", "{\u{200B}# x #}"),
+            ("{{{", "{\u{200B}{\u{200B}{"),
+            ("{ x } {} }}", "{ x } {} }}"),
+        ] {
+            let neutralized = neutralize_template_syntax(text);
+            assert_eq!(&*neutralized, expected, "{text:?}");
+            assert_eq!(neutralized.replace('\u{200B}', ""), text);
+        }
+    }
+
+    #[tokio::test]
+    async fn template_syntax_from_feeds_renders_inertly() {
+        let config: crate::Config = toml::from_str(
+            "min_fetch_interval = 0\n\
+             [[sites]]\nname = \"Site\"\nfeed_url = \"https://example.com/feed\"\n",
+        )
+        .unwrap();
+        let dir = crate::test_util::test_dir("sanitize-templates");
+        let caches = crate::cache::CacheManager::new(
+            dir.clone(),
+            crate::cache::CacheKey::Name,
+            &config.sites,
+        )
+        .unwrap();
+        let guard = caches.cache_guard();
+        caches
+            .get_mut(&config.sites[0], &guard)
+            .await
+            .unwrap()
+            .last_body = Some(
+            "<rss version=\"2.0\"><channel><title>{% raw %}</title><item>\
+             <title>{{ 7 * 7 }} {% set x = 1 %}</title>\
+             <guid>{# id #}</guid>\
+             <link>https://example.com/{{ 7 * 7 }}?q={%x%}</link>\
+             <description><![CDATA[<p>{# hidden #}{%- for i in [1, 2] -%}x{%- endfor -%}</p>]]>\
+             </description>\
+             <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item></channel></rss>"
+                .into(),
+        );
+        drop(guard);
+        let articles =
+            crate::collect_articles(&config, &caches, &crate::trace::ArticleTrace::new(None))
+                .await
+                .articles;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut tera = tera(&config.render);
+        tera.add_raw_template(
+            "output",
+            "{% for article in articles %}{{ article.site }}|{{ article.title }}|{{ article.id }}|\
+             {{ article.link }}|{{ article.link_display }}|{{ article.summary | safe }}|\
+             {{ article.summary_text }}{% endfor %}",
+        )
+        .unwrap();
+        let mut context = tera::Context::new();
+        context.insert("articles", &articles);
+        let page = tera.render("output", &context).unwrap();
+        for delimiter in ["{{", "{%", "{#"] {
+            assert!(!page.contains(delimiter), "{delimiter} in {page}");
+        }
+        assert!(
+            page.contains("https://example.com/%7B%7B%207%20*%207%20%7D%7D?q=%7B%x%%7D"),
+            "{page}"
+        );
+        // The text reads as it was given.
+        let visible = page.replace('\u{200B}', "");
+        assert!(
+            visible.starts_with("{% raw %}|{{ 7 * 7 }} {% set x = 1 %}|{# id #}|"),
+            "{visible}"
+        );
+        assert!(
+            visible.contains("<p>{# hidden #}{%- for i in [1, 2] -%}x{%- endfor -%}</p>"),
+            "{visible}"
+        );
+        // Even rendering the page as a template itself only gives back the page.
+        assert_eq!(
+            tera::Tera::one_off(&page, &tera::Context::new(), false).unwrap(),
+            page
+        );
+    }
+
+    #[test]
+    fn nonbreaking_spaces_are_only_collapsed_when_asked() {
+        let text = "\u{a0}10\u{a0}km \u{202f}\u{3000}away\u{2007}";
+        let limits = Limits::default();
+        assert_eq!(
+            normalize_text(text, &limits),
+            "\u{a0}10\u{a0}km \u{202f}\u{3000}away\u{2007}"
+        );
+        let limits = Limits {
+            collapse_nonbreaking_spaces: true,
+            ..Limits::default()
+        };
+        assert_eq!(normalize_text(text, &limits), "10 km away");
+    }
+
+    #[test]
+    fn emoji_sequences_survive() {
+        let limits = Limits::default();
+        for emoji in [
+            // Family, joined by zero-width joiners.
+            "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}",
+            // Rainbow flag, with a variation selector.
+            "\u{1f3f3}\u{fe0f}\u{200d}\u{1f308}",
+            // Thumbs up with a skin tone.
+            "\u{1f44d}\u{1f3fd}",
+        ] {
+            let text = format!("New {emoji} post");
+            assert_eq!(normalize_text(&text, &limits), text, "{emoji}");
+        }
+    }
+
+    #[test]
+    fn invisible_characters_are_removed() {
+        let limits = Limits::default();
+        assert_eq!(
+            normalize_text("\u{feff}zero\u{200b}width and\u{2060} sh\u{ad}y", &limits),
+            "zerowidth and shy"
+        );
+        // Only spaces between visible characters are kept.
+        assert_eq!(
+            normalize_text("\u{200b} \u{feff} text \u{200b} ", &limits),
+            "text"
+        );
+        assert_eq!(
+            normalize_text("line\r\nbreaks\rand\ttabs\n\n", &limits),
+            "line breaks and tabs"
+        );
+    }
+
+    #[test]
+    fn decomposed_text_is_composed() {
+        let limits = Limits::default();
+        // "café" and "Ångström", with their accents as combining characters.
+        assert_eq!(
+            normalize_text("cafe\u{301} A\u{30a}ngstro\u{308}m", &limits),
+            "caf\u{e9} \u{c5}ngstr\u{f6}m"
+        );
+        assert_eq!(
+            normalize_text("caf\u{e9}", &limits),
+            normalize_text("cafe\u{301}", &limits)
+        );
+    }
+
+    /// Collect the only item of an RSS feed with the given title and HTML description.
+    fn article(title: &str, description: &str, limits: &Limits) -> crate::FeedEntryInfo {
+        let feed = format!(
+            "<rss version=\"2.0\"><channel><title>Site</title><item><title>{title}</title>\
+             <description><![CDATA[{description}]]></description>\
+             <link>https://example.com/1</link><pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>\
+             </item></channel></rss>"
+        );
+        let feed = feed_rs::parser::parse(feed.as_bytes()).unwrap();
+        crate::FeedEntryInfo::new(
+            0,
+            "Site",
+            &feed.entries[0],
+            &Default::default(),
+            None,
+            false,
+            limits,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn huge_titles_and_summaries_are_truncated_on_character_boundaries() {
+        let limits = Limits::default();
+        // Two bytes a character, so the limits fall in the middle of some.
+        let article = article(
+            &"é".repeat(1 << 20),
+            &format!("<p>{}</p>", "ü".repeat(1 << 20)),
+            &limits,
+        );
+        assert!(article.title.len() <= limits.max_title_bytes);
+        assert!(article.title.ends_with("é…"));
+        let summary = article.summary.as_deref().unwrap();
+        assert!(summary.len() <= limits.max_summary_bytes + "</p>".len());
+        assert!(summary.starts_with("<p>ü"));
+        let summary_text = article.summary_text.as_deref().unwrap();
+        assert_eq!(summary_text.chars().count(), limits.summary_text_chars);
+        assert!(!article.suspect);
+        assert!(serde_json::to_vec(&article).unwrap().len() <= limits.max_entry_bytes);
+    }
+
+    #[test]
+    fn deeply_nested_and_malformed_html_is_made_safe() {
+        // Sanitizing HTML nested this deeply would take minutes, so this is only quick because
+        // summaries are truncated first.
+        let nested = format!("<p>deep{}", "<div><b>".repeat(100_000));
+        let limits = Limits::default();
+        let nested = article("Nested", &nested, &limits);
+        // Closing every tag more than doubles it, but it's still small enough to keep.
+        assert!(serde_json::to_vec(&nested).unwrap().len() <= limits.max_entry_bytes);
+        assert!(nested.summary_text.unwrap().starts_with("deep"));
+
+        let malformed = "<p>Hello <b>world<script>alert(1)</script><img src=x onerror=alert(1)>\
+                         <a href='javascript:alert(1)'>link<style>* {}</style><iframe src=x>";
+        let article = article("Malformed", malformed, &limits);
+        let summary = article.summary.as_deref().unwrap();
+        for unsafe_html in [
+            "script",
+            "onerror",
+            "javascript:",
+            "style",
+            "iframe",
+            "alert",
+        ] {
+            assert!(!summary.contains(unsafe_html), "{summary}");
+        }
+        assert_eq!(article.summary_text.as_deref().unwrap(), "Hello worldlink");
+        assert_eq!(word_count(malformed), 2);
+    }
+
+    #[test]
+    fn control_characters_are_removed_or_make_text_garbage() {
+        let limits = Limits::default();
+        assert_eq!(
+            normalize_text("a\u{0}b\u{1b}[0m\u{7}c\u{85}d\u{7f}", &limits),
+            "ab[0mc d"
+        );
+        let article = article(
+            "Binary &#xFFFD;&#xFFFD;&#xFFFD;&#xFFFD;",
+            &"\u{fffd}".repeat(100),
+            &limits,
+        );
+        assert_eq!(&*article.title, GARBAGE_PLACEHOLDER);
+        assert_eq!(article.summary.as_deref(), Some(GARBAGE_PLACEHOLDER));
+        assert!(article.suspect);
+        // And escape sequences in titles are only removed.
+        let limited = limit(
+            &normalize_title("In \u{1b}[31mred\u{1b}[0m and more text", &limits),
+            limits.max_title_bytes,
+            &limits,
+            "title",
+        );
+        assert_eq!(&*limited.text, "In [31mred[0m and more text");
+        assert!(!limited.suspect);
+    }
+}
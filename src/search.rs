@@ -0,0 +1,497 @@
+use super::{FeedEntryInfo, SiteConfig, junk, replace, sanitize, timestamp};
+
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::SystemTime,
+};
+
+/// The name of the search index file, written next to the output page.
+pub const INDEX_FILE_NAME: &str = "search-index.json";
+
+/// The name of the file in the cache directory the [`StoredIndex`] is kept in between runs.
+const STORED_INDEX_FILE_NAME: &str = "search-index.json";
+
+/// An article, as listed in the search index.
+#[derive(Clone, Debug, serde::Serialize)]
+struct IndexEntry<'a> {
+    title: &'a str,
+    site: &'a str,
+    date: chrono::NaiveDate,
+    /// When it was published, as `published_unix` and `published_rfc3339`.
+    #[serde(flatten)]
+    published: serde_json::Map<String, serde_json::Value>,
+    link: &'a str,
+    /// Its position on the page, if it's on the page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rank: Option<usize>,
+    /// The start of its summary as plain text, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a str>,
+}
+
+/// The articles which can be searched, kept in the cache directory between runs.
+///
+/// This has every article of each displayed site which gets through the site's filters, however
+/// old, and not only those on the page. Articles stay after they leave their feed for as long as
+/// their site's cache remembers them, see
+/// [`SiteCache::entries_last_seen`](super::cache::SiteCache::entries_last_seen), so the index is
+/// bounded by that. Only the sites whose feeds or settings changed are indexed again.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct StoredIndex {
+    /// The indexed articles of each site, by the site's name.
+    sites: BTreeMap<Box<str>, SiteIndex>,
+    /// Whether anything changed since the index was loaded, so it needs saving.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// The indexed articles of one site.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct SiteIndex {
+    /// A hash of the feed and the site's settings the articles were indexed from.
+    fingerprint: Box<str>,
+    /// The articles, those still in the feed first.
+    articles: Vec<StoredArticle>,
+}
+
+/// An article in the [`StoredIndex`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StoredArticle {
+    pub title: Box<str>,
+    /// The name of its site, as shown.
+    pub site: Box<str>,
+    pub published: chrono::DateTime<chrono::Utc>,
+    /// The day it was published on, as shown.
+    pub date: chrono::NaiveDate,
+    pub link: Box<str>,
+    /// The start of its summary as plain text, if it has one.
+    pub summary: Option<Box<str>>,
+    /// Its link as given in the feed, which its site's cache remembers it by.
+    feed_link: Box<str>,
+}
+impl StoredArticle {
+    /// The article to index for `article`, which has the link `feed_link` in its feed.
+    pub fn new(article: &FeedEntryInfo, feed_link: &str) -> Self {
+        Self {
+            title: article.title.clone(),
+            site: article.site.clone(),
+            published: article.published,
+            date: article.publish_date,
+            link: article.link.clone(),
+            summary: article.summary_text.clone(),
+            feed_link: feed_link.into(),
+        }
+    }
+}
+
+impl StoredIndex {
+    /// Load the index kept in `cache_dir`, or start a new one if there isn't one that can be read.
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(STORED_INDEX_FILE_NAME);
+        match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                log::warn!(
+                    "Failed to read search index {}, indexing every site again: {e}",
+                    path.display()
+                );
+                Self::default()
+            }),
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!(
+                        "Failed to read search index {}, indexing every site again: {e}",
+                        path.display()
+                    );
+                }
+                Self::default()
+            }
+        }
+    }
+
+    /// Save the index in `cache_dir`, if it changed.
+    pub fn save(&mut self, cache_dir: &Path) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let encoded = serde_json::to_vec(self).context("Failed to encode search index")?;
+        std::fs::create_dir_all(cache_dir).context("Failed to create cache directory")?;
+        replace::write_atomically(&cache_dir.join(STORED_INDEX_FILE_NAME), &encoded)
+            .context("Failed to save search index")?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Whether `site` was last indexed from the feed and settings with the given `fingerprint`,
+    /// so its articles are still up to date.
+    pub fn is_current(&self, site: &str, fingerprint: &str) -> bool {
+        self.sites
+            .get(site)
+            .is_some_and(|index| *index.fingerprint == *fingerprint)
+    }
+
+    /// Index `site` again, with the `articles` now in its feed, which has the given `fingerprint`.
+    ///
+    /// The articles indexed before are kept if the site's cache still remembers them, by
+    /// `entries_last_seen`.
+    pub fn update(
+        &mut self,
+        site: &str,
+        fingerprint: &str,
+        mut articles: Vec<StoredArticle>,
+        entries_last_seen: &HashMap<Box<str>, SystemTime>,
+    ) {
+        log::debug!("Indexing {} articles from {site}", articles.len());
+        if let Some(old) = self.sites.remove(site) {
+            let kept = old
+                .articles
+                .into_iter()
+                .filter(|old| {
+                    entries_last_seen.contains_key(&old.feed_link)
+                        && !articles.iter().any(|new| new.feed_link == old.feed_link)
+                })
+                .collect::<Vec<_>>();
+            articles.extend(kept);
+        }
+        self.sites.insert(
+            site.into(),
+            SiteIndex {
+                fingerprint: fingerprint.into(),
+                articles,
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Forget the articles of `site` which its cache no longer remembers, by `entries_last_seen`,
+    /// without indexing it again.
+    pub fn forget_old(&mut self, site: &str, entries_last_seen: &HashMap<Box<str>, SystemTime>) {
+        let Some(index) = self.sites.get_mut(site) else {
+            return;
+        };
+        let before = index.articles.len();
+        index
+            .articles
+            .retain(|article| entries_last_seen.contains_key(&article.feed_link));
+        if index.articles.len() != before {
+            self.dirty = true;
+        }
+    }
+
+    /// Forget the sites for which `keep` is false, such as those no longer displayed.
+    pub fn retain_sites(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        let before = self.sites.len();
+        self.sites.retain(|site, _| keep(site));
+        if self.sites.len() != before {
+            self.dirty = true;
+        }
+    }
+
+    /// Every indexed article, newest first.
+    pub fn articles(&self) -> Vec<&StoredArticle> {
+        let mut articles = self
+            .sites
+            .values()
+            .flat_map(|index| &index.articles)
+            .collect::<Vec<_>>();
+        articles.sort_by_key(|article| std::cmp::Reverse(article.published));
+        articles
+    }
+
+    /// Encode the index as JSON for the page, with the positions of the articles on it from
+    /// `page`.
+    pub fn encode(&self, page: &[FeedEntryInfo]) -> Result<Vec<u8>> {
+        let ranks = page
+            .iter()
+            .map(|article| (&*article.link, article.rank))
+            .collect::<HashMap<_, _>>();
+        let index = self
+            .articles()
+            .into_iter()
+            .map(|article| {
+                let mut published = serde_json::Map::new();
+                timestamp::insert(&mut published, "published", Some(article.published.into()));
+                IndexEntry {
+                    title: &article.title,
+                    site: &article.site,
+                    date: article.date,
+                    published,
+                    link: &article.link,
+                    rank: ranks.get(&*article.link).copied(),
+                    summary: article.summary.as_deref(),
+                }
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_vec(&index).context("Failed to encode search index")
+    }
+}
+
+/// The fingerprint of a site's feed and settings, for [`StoredIndex::is_current`].
+///
+/// This covers everything its indexed articles are made from, which is the body of the feed, the
+/// site's settings, the limits and the global junk filters, and where its links were resolved to.
+pub fn fingerprint(
+    site: &SiteConfig,
+    limits: &sanitize::Limits,
+    junk: &junk::JunkConfig,
+    body_hash: &[u8; 32],
+    resolved_links: &HashMap<Box<str>, Box<str>>,
+) -> Box<str> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(body_hash);
+    for settings in [
+        serde_json::to_vec(site),
+        serde_json::to_vec(limits),
+        serde_json::to_vec(junk),
+    ] {
+        // They're all plain data, so always encode.
+        hasher.update(&settings.unwrap_or_default());
+        hasher.update(&[0]);
+    }
+    let resolved_links = resolved_links.iter().collect::<BTreeMap<_, _>>();
+    hasher.update(&serde_json::to_vec(&resolved_links).unwrap_or_default());
+    hasher.finalize().to_hex().as_str().into()
+}
+
+/// Write the encoded search index to `path`, unless it's already there.
+pub fn write_index(path: &Path, index: &[u8]) -> Result<()> {
+    if std::fs::read(path).is_ok_and(|old| old == index) {
+        log::debug!("The search index is unchanged");
+        return Ok(());
+    }
+    replace::write_atomically(path, index).context("Failed to write search index")
+}
+
+/// Find the indexed articles matching any of the given terms, best matches first.
+///
+/// Matching is case-insensitive. A term found in the title counts for more than one found only in
+/// the summary, which counts for more than one found only in the site name. Ties are broken by
+/// putting newer articles first.
+pub fn search<'a>(index: &'a StoredIndex, terms: &[String]) -> Vec<&'a StoredArticle> {
+    let terms = terms
+        .iter()
+        .map(|term| term.to_lowercase())
+        .collect::<Vec<_>>();
+    let mut matches = index
+        .articles()
+        .into_iter()
+        .filter_map(|article| {
+            let title = article.title.to_lowercase();
+            let summary = article.summary.as_deref().unwrap_or("").to_lowercase();
+            let site = article.site.to_lowercase();
+            let score = terms
+                .iter()
+                .map(|term| {
+                    if title.contains(term.as_str()) {
+                        3
+                    } else if summary.contains(term.as_str()) {
+                        2
+                    } else if site.contains(term.as_str()) {
+                        1
+                    } else {
+                        0
+                    }
+                })
+                .sum::<u32>();
+            (score > 0).then_some((score, article))
+        })
+        .collect::<Vec<_>>();
+    // Stable, so ties stay newest first.
+    matches.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(_, article)| article).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Datelike as _;
+
+    /// An article from `site` titled `title`, published on the given day of January 2024.
+    fn article(site: &str, title: &str, summary: Option<&str>, day: u32) -> FeedEntryInfo {
+        let published = chrono::NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        let link: Box<str> = format!("https://{site}.example/{day}").into();
+        FeedEntryInfo {
+            site: site.into(),
+            published,
+            publish_date: published.date_naive(),
+            id: link.clone(),
+            site_link: None,
+            mentions: 1,
+            title: title.into(),
+            link_display: link.clone(),
+            link,
+            summary: None,
+            content: None,
+            summary_text: summary.map(Into::into),
+            suspect: false,
+            original_link: None,
+            also_on: Vec::new(),
+            is_new: false,
+            rank: 0,
+            raw: None,
+            site_index: 0,
+            feed_position: 0,
+            last_seen_in_feed: None,
+        }
+    }
+
+    /// An index of the given articles, as if each site's feed had just been fetched.
+    fn index_of(articles: &[FeedEntryInfo]) -> StoredIndex {
+        let mut index = StoredIndex::default();
+        let mut sites = BTreeMap::<&str, Vec<_>>::new();
+        for article in articles {
+            sites
+                .entry(&article.site)
+                .or_default()
+                .push(StoredArticle::new(article, &article.link));
+        }
+        for (site, articles) in sites {
+            index.update(site, "fetched", articles, &HashMap::new());
+        }
+        index
+    }
+
+    /// When their sites' caches last saw the given articles, which is now.
+    fn last_seen(articles: &[&FeedEntryInfo]) -> HashMap<Box<str>, SystemTime> {
+        articles
+            .iter()
+            .map(|article| (article.link.clone(), SystemTime::now()))
+            .collect()
+    }
+
+    fn titles<'a>(matches: &[&'a StoredArticle]) -> Vec<&'a str> {
+        matches.iter().map(|article| &*article.title).collect()
+    }
+
+    #[test]
+    fn title_matches_rank_above_summary_matches_above_site_matches() {
+        let index = index_of(&[
+            article("rust", "Release notes", None, 4),
+            article("blog", "Weekly links", Some("All about Rust this week"), 3),
+            article("blog", "Rust in production", None, 2),
+            article("blog", "Gardening", None, 1),
+        ]);
+        assert_eq!(
+            titles(&search(&index, &["RUST".to_owned()])),
+            ["Rust in production", "Weekly links", "Release notes"]
+        );
+    }
+
+    #[test]
+    fn more_matching_terms_rank_higher_and_ties_go_newest_first() {
+        let index = index_of(&[
+            article("blog", "Rust", None, 1),
+            article("blog", "Rust", None, 3),
+            article("blog", "Rust async", None, 2),
+        ]);
+        let matches = search(&index, &["rust".to_owned(), "async".to_owned()]);
+        assert_eq!(
+            matches
+                .iter()
+                .map(|article| article.published.day())
+                .collect::<Vec<_>>(),
+            [2, 3, 1]
+        );
+    }
+
+    #[test]
+    fn articles_stay_indexed_while_their_sites_remember_them() {
+        let old = article("blog", "Old", None, 1);
+        let kept = article("blog", "Kept", None, 2);
+        let mut index = index_of(&[old.clone(), kept.clone()]);
+        assert!(index.is_current("blog", "fetched"));
+        assert!(!index.is_current("blog", "refetched"));
+        assert!(!index.is_current("news", "fetched"));
+
+        // Both have left the feed, but the cache still remembers one of them.
+        let new = article("blog", "New", None, 3);
+        index.update(
+            "blog",
+            "refetched",
+            vec![StoredArticle::new(&new, &new.link)],
+            &last_seen(&[&new, &kept]),
+        );
+        assert_eq!(titles(&index.articles()), ["New", "Kept"]);
+
+        // The feed didn't change, so only forgotten articles go.
+        index.dirty = false;
+        index.forget_old("blog", &last_seen(&[&new, &kept]));
+        assert!(!index.dirty);
+        index.forget_old("blog", &last_seen(&[&new]));
+        assert!(index.dirty);
+        assert_eq!(titles(&index.articles()), ["New"]);
+
+        index.retain_sites(|site| site != "blog");
+        assert!(index.articles().is_empty());
+    }
+
+    #[test]
+    fn the_stored_index_is_only_saved_when_it_changes() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("jarss-test-{}-stored-index", std::process::id()));
+        let path = cache_dir.join(STORED_INDEX_FILE_NAME);
+        assert!(StoredIndex::load(&cache_dir).articles().is_empty());
+        let mut index = index_of(&[article("blog", "Rust", Some("A summary"), 1)]);
+        index.save(&cache_dir).unwrap();
+        let mut loaded = StoredIndex::load(&cache_dir);
+        assert_eq!(loaded.articles(), index.articles());
+        assert!(loaded.is_current("blog", "fetched"));
+        std::fs::remove_file(&path).unwrap();
+        loaded.save(&cache_dir).unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn only_articles_on_the_page_have_ranks() {
+        let mut on_page = article("blog", "On the page", None, 2);
+        on_page.rank = 1;
+        let index = index_of(&[on_page.clone(), article("blog", "Older", None, 1)]);
+        let encoded: serde_json::Value =
+            serde_json::from_slice(&index.encode(&[on_page]).unwrap()).unwrap();
+        assert_eq!(encoded[0]["rank"], 1);
+        assert_eq!(encoded[0]["date"], "2024-01-02");
+        assert_eq!(encoded[0]["published_unix"], 1_704_153_600);
+        assert_eq!(encoded[0]["published_rfc3339"], "2024-01-02T00:00:00Z");
+        assert_eq!(encoded[1]["title"], "Older");
+        assert!(encoded[1].get("rank").is_none());
+    }
+
+    #[test]
+    fn the_index_is_only_rewritten_when_it_changes() {
+        let path = std::env::temp_dir().join(format!(
+            "jarss-test-{}-{INDEX_FILE_NAME}",
+            std::process::id()
+        ));
+        let encoded = index_of(&[article("blog", "Rust", Some("A summary"), 1)])
+            .encode(&[])
+            .unwrap();
+        write_index(&path, &encoded).unwrap();
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(index[0]["title"], "Rust");
+        assert_eq!(index[0]["date"], "2024-01-01");
+        assert_eq!(index[0]["summary"], "A summary");
+        let written = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write_index(&path, &encoded).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            written
+        );
+        let encoded = index_of(&[article("blog", "Go", None, 1)])
+            .encode(&[])
+            .unwrap();
+        write_index(&path, &encoded).unwrap();
+        assert_ne!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            written
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
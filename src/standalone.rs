@@ -0,0 +1,237 @@
+use super::discover;
+
+/// The elements and attributes through which a page can load other resources.
+const RESOURCE_ATTRS: &[(&str, &str)] = &[
+    ("script", "src"),
+    ("img", "src"),
+    ("img", "srcset"),
+    ("iframe", "src"),
+    ("source", "src"),
+    ("source", "srcset"),
+    ("video", "src"),
+    ("video", "poster"),
+    ("audio", "src"),
+    ("track", "src"),
+    ("embed", "src"),
+    ("object", "data"),
+    ("input", "src"),
+];
+
+/// The `rel`s of `<link>` elements which load whatever they link to.
+const RESOURCE_RELS: &[&str] = &[
+    "stylesheet",
+    "icon",
+    "apple-touch-icon",
+    "preload",
+    "prefetch",
+    "modulepreload",
+    "manifest",
+];
+
+/// Encode the given contents as a `data:` URI.
+///
+/// Everything but unreserved characters is percent-encoded, which is bigger than base64 for binary
+/// data but means the URI is safe to put in any attribute or string without escaping.
+pub fn data_uri(contents: &[u8]) -> String {
+    let mut uri = String::with_capacity(6 + contents.len() * 3);
+    uri.push_str("data:,");
+    for &byte in contents {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+/// Find the resources the given page would load from elsewhere.
+///
+/// Links the reader has to follow aren't counted, only things the browser loads by itself.
+pub fn external_resources(html: &str) -> Vec<String> {
+    let mut external = Vec::new();
+    for (tag, attr) in RESOURCE_ATTRS {
+        for attrs in discover::tags(html, tag) {
+            external.extend(
+                attrs
+                    .iter()
+                    .filter(|(name, _)| name.eq_ignore_ascii_case(attr))
+                    .map(|(_, value)| value.trim())
+                    .filter(|value| is_external(value))
+                    .map(str::to_owned),
+            );
+        }
+    }
+    for attrs in discover::tags(html, "link") {
+        let attr = |name: &str| {
+            attrs
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let loads = attr("rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|rel| RESOURCE_RELS.iter().any(|r| rel.eq_ignore_ascii_case(r)))
+        });
+        if let Some(href) = attr("href")
+            .map(str::trim)
+            .filter(|href| loads && is_external(href))
+        {
+            external.push(href.to_owned());
+        }
+    }
+    // Stylesheets can load things too.
+    let lowercase = html.to_ascii_lowercase();
+    let mut rest = &lowercase[..];
+    while let Some(start) = rest.find("<style") {
+        rest = &rest[start..];
+        let end = rest.find("</style").unwrap_or(rest.len());
+        let offset = lowercase.len() - rest.len();
+        external.extend(
+            css_urls(&html[offset..offset + end])
+                .filter(|url| is_external(url))
+                .map(str::to_owned),
+        );
+        rest = &rest[end..];
+    }
+    external
+}
+
+/// Iterate over the arguments of the `url()`s in some CSS.
+fn css_urls(css: &str) -> impl Iterator<Item = &str> {
+    css.split("url(").skip(1).map(|rest| {
+        rest[..rest.find(')').unwrap_or(rest.len())]
+            .trim()
+            .trim_matches(['"', '\''])
+    })
+}
+
+/// Whether loading the given URL would need anything besides the page itself.
+fn is_external(url: &str) -> bool {
+    !(url.is_empty()
+        || url.starts_with('#')
+        || url
+            .get(..5)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Config, cache, summary, templates::Templates, test_server, test_util, trace::ArticleTrace,
+    };
+
+    /// A page which loads a stylesheet, an icon and the search index, and links to the articles.
+    const TEMPLATE: &str = "<html><head>\
+        <link rel=\"stylesheet\" href=\"data:text/css,body%7Bmargin:0%7D\">\
+        <link rel=\"icon\" href=\"data:image/svg+xml,%3Csvg%3E%3C/svg%3E\">\
+        <link rel=\"preload\" as=\"fetch\" href=\"{{ search_index }}\">\
+        <style>body { background: url(\"data:,\") }</style>\
+        </head><body>{% for article in articles %}\
+        <a href=\"{{ article.link }}\">{{ article.title }}</a>{% endfor %}</body></html>";
+
+    /// Render a page in `dir` with the given template, or the default one, and return it.
+    async fn render(dir: &std::path::Path, template: Option<&str>, self_contained: bool) -> String {
+        let addr = test_server::serve_http(|_| {
+            test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                "<rss version=\"2.0\"><channel><title>Ex</title><item><title>Hello</title>\
+                 <link>https://example.com/hello</link>\
+                 <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item></channel></rss>",
+            )
+        })
+        .await;
+        let config: Config = toml::from_str(&format!(
+            "min_fetch_interval = 0\nsearch_index = true\nself_contained = {self_contained}\n\
+             [[sites]]\nname = \"Ex\"\nfeed_url = \"http://{addr}/feed\"\n"
+        ))
+        .unwrap();
+        let caches =
+            cache::CacheManager::new(dir.join("cache"), cache::CacheKey::Name, &config.sites)
+                .unwrap();
+        let template = template.unwrap_or(include_str!("../default-render.html.tera"));
+        crate::run(
+            &config,
+            &caches,
+            true,
+            &mut Templates::with_page(template),
+            &crate::OutputPaths {
+                html: Some(&dir.join("index.html")),
+                feed: None,
+                json: None,
+                manifest: None,
+                force: false,
+            },
+            &ArticleTrace::new(None),
+            &mut summary::RunSummary::new(&config),
+        )
+        .await
+        .unwrap();
+        std::fs::read_to_string(dir.join("index.html")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn self_contained_pages_load_nothing_else() {
+        let dir = test_util::test_dir("standalone");
+        let page = render(&dir, Some(TEMPLATE), true).await;
+        assert_eq!(external_resources(&page), Vec::<String>::new(), "{page}");
+        assert!(
+            page.contains("href=\"https://example.com/hello\""),
+            "{page}"
+        );
+        assert!(page.contains("<link rel=\"preload\" as=\"fetch\" href=\"data:,"));
+        assert!(!dir.join(crate::search::INDEX_FILE_NAME).exists());
+
+        let page = render(&dir, None, true).await;
+        assert_eq!(external_resources(&page), Vec::<String>::new(), "{page}");
+        assert!(page.contains("fetch(\"data:,"), "{page}");
+
+        // Without `self_contained`, the index is loaded from its own file.
+        let page = render(&dir, Some(TEMPLATE), false).await;
+        assert_eq!(external_resources(&page), [crate::search::INDEX_FILE_NAME]);
+        assert!(dir.join(crate::search::INDEX_FILE_NAME).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resources_from_elsewhere_are_found() {
+        let page = "<html><head>\
+            <link rel=\"alternate stylesheet\" href=\" https://cdn.example/theme.css \">\
+            <LINK REL=\"Icon\" HREF=\"/favicon.ico\">\
+            <link rel=\"alternate\" href=\"https://example.com/feed.xml\">\
+            <link rel=\"canonical\" href=\"https://example.com/\">\
+            <script src=\"app.js\"></script>\
+            <style>@font-face { src: url('https://fonts.example/a.woff2') }\
+            .b { background: url(\"#pattern\") }</style>\
+            </head><body>\
+            <img src=\"data:,\" srcset=\"big.png 2x\">\
+            <video poster=\"poster.jpg\"></video>\
+            <a href=\"https://example.com/article\">Article</a>\
+            <iframe src=\"\"></iframe>\
+            </body></html>";
+        let mut found = external_resources(page);
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                "/favicon.ico",
+                "app.js",
+                "big.png 2x",
+                "https://cdn.example/theme.css",
+                "https://fonts.example/a.woff2",
+                "poster.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn data_uris_escape_everything_special() {
+        assert_eq!(data_uri(b""), "data:,");
+        assert_eq!(
+            data_uri(b"{\"a\": \"<b>\"}\n\xFF~-_."),
+            "data:,%7B%22a%22%3A%20%22%3Cb%3E%22%7D%0A%FF~-_."
+        );
+    }
+}
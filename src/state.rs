@@ -0,0 +1,197 @@
+use super::replace;
+
+use std::path::{Path, PathBuf};
+
+/// The name of the file recording the state of each origin, see [`Origins`](super::origins).
+const ORIGINS_FILE_NAME: &str = "origins.json";
+
+/// The name of the file recording GitHub's rate limits, see [`GitHub`](super::github::GitHub).
+const GITHUB_RATE_LIMITS_FILE_NAME: &str = "github-rate-limits.json";
+
+/// The name of the file recording what each notification channel has been sent, and what it has
+/// yet to be.
+const NOTIFICATIONS_FILE_NAME: &str = "notifications.json";
+
+/// The name of the file listing the articles in the last rendered fragment's page.
+const RENDERED_ARTICLES_FILE_NAME: &str = "rendered-articles.json";
+
+/// Every file of state, which earlier versions kept in the cache directory.
+const FILE_NAMES: [&str; 4] = [
+    ORIGINS_FILE_NAME,
+    GITHUB_RATE_LIMITS_FILE_NAME,
+    NOTIFICATIONS_FILE_NAME,
+    RENDERED_ARTICLES_FILE_NAME,
+];
+
+/// Where the state we keep between runs is stored.
+///
+/// State is what can't be got back by fetching the feeds again, so losing it changes what we do
+/// rather than only costing time: which origins and GitHub hosts to hold off on, what each
+/// notification channel has been sent and has yet to be, and which articles the last fragment
+/// had. It's kept apart from the caches, so that the cache directory can be deleted without
+/// sending every notification again.
+///
+/// Which of a feed's entries have been seen stays in its site's cache, along with the feed it
+/// describes. Losing that only means nothing is marked new on the next fetch of the site, and
+/// notifications remember what they've sent themselves.
+#[derive(Clone, Debug)]
+pub struct StatePaths {
+    dir: PathBuf,
+}
+impl StatePaths {
+    /// Keep the state in `dir`.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Where to keep the state, with the caches in `cache_dir`.
+    ///
+    /// This is `dir` if it's given. Otherwise, a cache directory which was chosen rather than the
+    /// default keeps the state too, so separate setups each with their own caches don't share it.
+    /// Failing that, it's `jarss` in the system's state directory, or the cache directory if the
+    /// system doesn't have one.
+    pub fn resolve(dir: Option<PathBuf>, cache_dir: &Path, default_cache_dir: bool) -> Self {
+        let dir = dir.unwrap_or_else(|| {
+            dirs::state_dir()
+                .filter(|_| default_cache_dir)
+                .map_or_else(|| cache_dir.to_owned(), |dir| dir.join("jarss"))
+        });
+        Self { dir }
+    }
+
+    /// The directory the state is kept in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The file recording the state of each origin.
+    pub fn origins(&self) -> PathBuf {
+        self.dir.join(ORIGINS_FILE_NAME)
+    }
+
+    /// The file recording GitHub's rate limits.
+    pub fn github_rate_limits(&self) -> PathBuf {
+        self.dir.join(GITHUB_RATE_LIMITS_FILE_NAME)
+    }
+
+    /// The file recording what each notification channel has been sent, and what it has yet to
+    /// be.
+    pub fn notifications(&self) -> PathBuf {
+        self.dir.join(NOTIFICATIONS_FILE_NAME)
+    }
+
+    /// The file listing the articles in the last rendered fragment's page.
+    pub fn rendered_articles(&self) -> PathBuf {
+        self.dir.join(RENDERED_ARTICLES_FILE_NAME)
+    }
+
+    /// Move the state which earlier versions kept in `cache_dir` to where it's kept now.
+    ///
+    /// Files which are already there are left alone, along with the old ones. Failures are
+    /// logged, and the state which couldn't be moved starts over.
+    pub fn migrate_from(&self, cache_dir: &Path) {
+        if self.dir == cache_dir {
+            return;
+        }
+        for name in FILE_NAMES {
+            let (old, new) = (cache_dir.join(name), self.dir.join(name));
+            if new.exists() {
+                continue;
+            }
+            let contents = match std::fs::read(&old) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    log::warn!("Failed to read {} to move it: {e}", old.display());
+                    continue;
+                }
+            };
+            // Written and synced before the old file goes, since it may be on another device.
+            let moved = std::fs::create_dir_all(&self.dir)
+                .and_then(|()| replace::write_atomically(&new, &contents))
+                .and_then(|()| std::fs::remove_file(&old));
+            match moved {
+                Ok(()) => log::info!("Moved {} to {}", old.display(), new.display()),
+                Err(e) => log::warn!("Failed to move {} to {}: {e}", old.display(), new.display()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn chosen_directories_keep_their_own_state() {
+        let cache_dir = Path::new("/srv/jarss/cache");
+        let state = |dir: Option<&str>, default_cache_dir| {
+            StatePaths::resolve(dir.map(PathBuf::from), cache_dir, default_cache_dir)
+                .dir
+                .clone()
+        };
+        assert_eq!(state(Some("/srv/state"), false), Path::new("/srv/state"));
+        assert_eq!(state(Some("/srv/state"), true), Path::new("/srv/state"));
+        assert_eq!(state(None, false), cache_dir);
+        assert_eq!(
+            state(None, true),
+            dirs::state_dir().map_or_else(|| cache_dir.to_owned(), |dir| dir.join("jarss"))
+        );
+    }
+
+    #[test]
+    fn state_is_moved_out_of_the_cache_directory() {
+        let dir = test_dir("state-migrate");
+        let cache_dir = dir.join("cache");
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        for (name, contents) in [
+            (ORIGINS_FILE_NAME, "old origins"),
+            (GITHUB_RATE_LIMITS_FILE_NAME, "old limits"),
+            (NOTIFICATIONS_FILE_NAME, "old notifications"),
+            (RENDERED_ARTICLES_FILE_NAME, "old render"),
+            ("site.lz4", "a cache"),
+        ] {
+            std::fs::write(cache_dir.join(name), contents).unwrap();
+        }
+        let state = StatePaths::new(dir.join("state"));
+
+        state.migrate_from(&cache_dir);
+        let read = |path: PathBuf| std::fs::read_to_string(path).ok();
+        assert_eq!(read(state.origins()).as_deref(), Some("old origins"));
+        assert_eq!(
+            read(state.rendered_articles()).as_deref(),
+            Some("old render")
+        );
+        assert_eq!(
+            read(state.github_rate_limits()).as_deref(),
+            Some("old limits")
+        );
+        assert_eq!(
+            read(state.notifications()).as_deref(),
+            Some("old notifications")
+        );
+        let left = std::fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(left, ["site.lz4"]);
+
+        // Newer state wins over what's left in the cache directory.
+        std::fs::write(cache_dir.join(RENDERED_ARTICLES_FILE_NAME), "older render").unwrap();
+        state.migrate_from(&cache_dir);
+        assert_eq!(
+            read(state.rendered_articles()).as_deref(),
+            Some("old render")
+        );
+
+        // State kept with the caches stays where it is.
+        let in_cache = StatePaths::new(cache_dir.clone());
+        in_cache.migrate_from(&cache_dir);
+        assert_eq!(
+            read(in_cache.rendered_articles()).as_deref(),
+            Some("older render")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+use super::{
+    Config, FeedEntryInfo, SiteConfig, cache::CacheManager, errors::ErrorCode, sanitize,
+    summary::RunSummary,
+};
+
+use std::time::{Duration, SystemTime};
+
+/// How worried we should be about a site's fetch errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The most recent fetch succeeded.
+    Ok,
+    /// The most recent fetch failed, but not for long enough to warn about.
+    Failing,
+    /// The site has been failing for at least [`Config::warn_after`].
+    Warn,
+    /// The site has been failing for at least [`Config::alert_after`].
+    Alert,
+}
+impl Severity {
+    /// The severity of a site which has been failing for `error_age`, if it is failing at all.
+    pub fn for_error_age(config: &Config, error_age: Option<Duration>) -> Self {
+        match error_age {
+            None => Self::Ok,
+            Some(age) if age >= config.alert_after => Self::Alert,
+            Some(age) if age >= config.warn_after => Self::Warn,
+            Some(_) => Self::Failing,
+        }
+    }
+}
+
+/// The fetch status of a site, as given to templates.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SiteStatus {
+    /// The name of the site.
+    pub name: Box<str>,
+    /// How long the site has been failing for, in seconds, if the latest fetch failed.
+    pub error_age_secs: Option<u64>,
+    /// How worried we should be about the site's errors.
+    pub severity: Severity,
+}
+
+/// Compute the status of every configured site, as of `now`.
+pub async fn site_statuses(
+    config: &Config,
+    caches: &CacheManager,
+    now: SystemTime,
+) -> Vec<SiteStatus> {
+    let guard = caches.cache_guard();
+    let mut statuses = Vec::with_capacity(config.sites.len());
+    for site in &config.sites {
+        let failing_since = match caches.get_mut(site, &guard).await {
+            Ok(cache) => cache.failing_since,
+            // We've already logged the error loading the cache, so don't repeat it here.
+            Err(_) => continue,
+        };
+        let error_age =
+            failing_since.map(|since| now.duration_since(since).unwrap_or(Duration::ZERO));
+        statuses.push(SiteStatus {
+            name: site.name.clone(),
+            error_age_secs: error_age.map(|age| age.as_secs()),
+            severity: Severity::for_error_age(config, error_age),
+        });
+    }
+    statuses
+}
+
+/// What a site's feed says about the site itself.
+#[derive(Clone, Debug, Default)]
+pub struct FeedMetadata {
+    /// The site's homepage.
+    pub link: Option<Box<str>>,
+    /// The site's icon, or else its logo.
+    pub icon_url: Option<Box<str>>,
+}
+impl FeedMetadata {
+    /// Read the metadata from the given site's parsed feed.
+    ///
+    /// Relative URLs are resolved against the site's `feed_url`, and any which aren't HTTP are
+    /// left out.
+    pub fn of(site: &SiteConfig, feed: &feed_rs::model::Feed) -> Self {
+        let url = |url: &str| {
+            let url = reqwest::Url::parse(&site.feed_url).ok()?.join(url).ok()?;
+            matches!(url.scheme(), "http" | "https")
+                .then(|| sanitize::neutralize_link(url.as_str()))
+        };
+        Self {
+            link: feed
+                .links
+                .iter()
+                .find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+                .and_then(|link| url(&link.href)),
+            icon_url: feed
+                .icon
+                .iter()
+                .chain(&feed.logo)
+                .find_map(|image| url(&image.uri)),
+        }
+    }
+}
+
+/// A configured site, as given to templates as `sites`.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SiteInfo {
+    /// The name of the site.
+    pub name: Box<str>,
+    /// The site's homepage, if its feed gives one.
+    pub link: Option<Box<str>>,
+    /// The site's icon or logo, if its feed gives one.
+    pub icon_url: Option<Box<str>>,
+    /// How many of the articles shown are from the site.
+    pub entry_count: usize,
+    /// When the site was last fetched successfully, if it ever was.
+    pub last_fetched: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Describe every configured site, in order, with `metadata` holding what each one's feed said
+/// about it.
+pub async fn site_infos(
+    config: &Config,
+    caches: &CacheManager,
+    metadata: &[FeedMetadata],
+    articles: &[FeedEntryInfo],
+) -> Vec<SiteInfo> {
+    let guard = caches.cache_guard();
+    let mut infos = Vec::with_capacity(config.sites.len());
+    for (index, site) in config.sites.iter().enumerate() {
+        let metadata = metadata.get(index).cloned().unwrap_or_default();
+        let last_fetched = match caches.get_mut(site, &guard).await {
+            Ok(cache) => cache.last_fetch_time,
+            Err(_) => None,
+        };
+        infos.push(SiteInfo {
+            name: site.name.clone(),
+            link: metadata.link,
+            icon_url: metadata.icon_url,
+            entry_count: articles
+                .iter()
+                .filter(|article| article.site_index == index)
+                .count(),
+            last_fetched: last_fetched.map(Into::into),
+        });
+    }
+    infos
+}
+
+/// A site which had an error fetching or reading it in this run, as given to templates.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SiteError {
+    /// The name of the site.
+    pub name: Box<str>,
+    /// The kind of error.
+    pub code: ErrorCode,
+    /// What went wrong, in short.
+    pub error: Box<str>,
+    /// When the site was last fetched successfully, if it ever was.
+    pub last_fetch_time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The errors fetching and reading the sites in this run, as recorded in `summary`, in the
+/// order the sites are configured.
+///
+/// A site with both kinds of error is listed with its fetch error, since that's usually why it
+/// couldn't be read.
+pub async fn site_errors(
+    config: &Config,
+    caches: &CacheManager,
+    summary: &RunSummary,
+) -> Vec<SiteError> {
+    let guard = caches.cache_guard();
+    let mut errors = Vec::new();
+    for site in &config.sites {
+        let Some(error) = summary.sites.iter().find_map(|site_summary| {
+            (site_summary.name == site.name)
+                .then(|| {
+                    site_summary
+                        .fetch_error
+                        .as_ref()
+                        .or(site_summary.parse_error.as_ref())
+                })
+                .flatten()
+        }) else {
+            continue;
+        };
+        let last_fetch_time = match caches.get_mut(site, &guard).await {
+            Ok(cache) => cache.last_fetch_time,
+            Err(_) => None,
+        };
+        errors.push(SiteError {
+            name: site.name.clone(),
+            code: error.code,
+            error: error.cause.as_str().into(),
+            last_fetch_time: last_fetch_time.map(Into::into),
+        });
+    }
+    errors
+}
+
+/// Log a line for each failing site, at a level matching its severity.
+pub fn log_statuses(statuses: &[SiteStatus]) {
+    for status in statuses {
+        let Some(age) = status.error_age_secs else {
+            continue;
+        };
+        let age = humantime::format_duration(Duration::from_secs(age));
+        match status.severity {
+            Severity::Ok => {}
+            Severity::Failing => log::info!("Site {} has been failing for {age}", status.name),
+            Severity::Warn => log::warn!("Site {} has been failing for {age}", status.name),
+            Severity::Alert => log::error!("Site {} has been failing for {age}", status.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CacheKey, test_util::test_dir};
+
+    #[tokio::test]
+    async fn sites_warn_and_alert_from_exactly_their_thresholds() {
+        let dir = test_dir("status");
+        let config: Config = toml::from_str(
+            "min_fetch_interval = 0\nwarn_after = \"1h\"\nalert_after = \"1day\"\n\
+             [[sites]]\nname = \"Down\"\nfeed_url = \"https://example.com/feed\"\n",
+        )
+        .unwrap();
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let failing_since = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let guard = caches.cache_guard();
+        let mut cache = caches.get_mut(&config.sites[0], &guard).await.unwrap();
+        cache.record_failure(failing_since);
+        // Later failures don't move when it started failing.
+        cache.record_failure(failing_since + Duration::from_secs(60));
+        drop(cache);
+        drop(guard);
+
+        let status_after = async |secs| {
+            let now = failing_since + Duration::from_secs(secs);
+            let statuses = site_statuses(&config, &caches, now).await;
+            (statuses[0].error_age_secs, statuses[0].severity)
+        };
+        let hour = 60 * 60;
+        let day = 24 * hour;
+        assert_eq!(status_after(0).await, (Some(0), Severity::Failing));
+        assert_eq!(
+            status_after(hour - 1).await,
+            (Some(hour - 1), Severity::Failing)
+        );
+        assert_eq!(status_after(hour).await, (Some(hour), Severity::Warn));
+        assert_eq!(status_after(day - 1).await, (Some(day - 1), Severity::Warn));
+        assert_eq!(status_after(day).await, (Some(day), Severity::Alert));
+        // A clock which went backwards doesn't make the failure any newer than just now.
+        let statuses =
+            site_statuses(&config, &caches, failing_since - Duration::from_secs(60)).await;
+        assert_eq!(statuses[0].error_age_secs, Some(0));
+        assert_eq!(Severity::for_error_age(&config, None), Severity::Ok);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn sites_are_described_by_their_feeds() {
+        let dir = test_dir("status-sites");
+        let config: Config = toml::from_str(
+            "min_fetch_interval = 0\n\
+             [[sites]]\nname = \"Blog\"\nfeed_url = \"https://blog.example/feeds/atom.xml\"\n\
+             [[sites]]\nname = \"Bare\"\nfeed_url = \"https://bare.example/feed\"\n",
+        )
+        .unwrap();
+        let feed = |xml: &str| feed_rs::parser::parse(xml.as_bytes()).unwrap();
+        // The self link isn't the homepage, and an icon which isn't HTTP falls back to the logo.
+        let blog = FeedMetadata::of(
+            &config.sites[0],
+            &feed(
+                "<feed xmlns=\"http://www.w3.org/2005/Atom\"><title>Blog</title>\
+                 <link rel=\"self\" href=\"atom.xml\"/><link rel=\"alternate\" href=\"/\"/>\
+                 <icon>javascript:alert(1)</icon><logo>../logo.png</logo></feed>",
+            ),
+        );
+        assert_eq!(blog.link.as_deref(), Some("https://blog.example/"));
+        assert_eq!(
+            blog.icon_url.as_deref(),
+            Some("https://blog.example/logo.png")
+        );
+        let bare = FeedMetadata::of(
+            &config.sites[1],
+            &feed("<rss version=\"2.0\"><channel><title>Bare</title></channel></rss>"),
+        );
+        assert!(bare.link.is_none() && bare.icon_url.is_none());
+
+        let caches = CacheManager::new(dir.clone(), CacheKey::Name, &config.sites).unwrap();
+        let fetched = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let guard = caches.cache_guard();
+        caches
+            .get_mut(&config.sites[0], &guard)
+            .await
+            .unwrap()
+            .last_fetch_time = Some(fetched);
+        drop(guard);
+        // Sites whose feeds couldn't be read are still listed.
+        let infos = site_infos(&config, &caches, &[blog], &[]).await;
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(
+            serde_json::to_value(&infos).unwrap(),
+            serde_json::json!([
+                {
+                    "name": "Blog",
+                    "link": "https://blog.example/",
+                    "icon_url": "https://blog.example/logo.png",
+                    "entry_count": 0,
+                    "last_fetched": "2023-11-14T22:13:20Z",
+                },
+                {
+                    "name": "Bare",
+                    "link": null,
+                    "icon_url": null,
+                    "entry_count": 0,
+                    "last_fetched": null,
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+/// The text the default template shows, by key, in English.
+///
+/// `{site}` and `{days}` in `site_failing` are replaced with the site's name and how many days
+/// it's been failing for. The keys ending in `_ago`, along with `just_now`, `yesterday`,
+/// `last_month` and `last_year`, are what the `relative_time` filter gives, with `{count}` replaced
+/// with the number of units.
+const DEFAULT_STRINGS: &[(&str, &str)] = &[
+    ("search_placeholder", "Search"),
+    ("site_failing", "{site} has been failing for {days} days"),
+    ("also_on", "also on"),
+    ("feeds_with_problems", "Feeds with problems"),
+    ("last_fetched", "last fetched"),
+    ("never_fetched", "never fetched"),
+    ("from_the_archives", "From the archives"),
+    ("reading_by_site", "Reading by site"),
+    ("site", "Site"),
+    ("days", "days"),
+    ("articles", "articles"),
+    ("minutes", "min"),
+    (
+        "safe_mode",
+        "Generated in safe mode, with optional features turned off",
+    ),
+    ("just_now", "just now"),
+    ("minute_ago", "1 minute ago"),
+    ("minutes_ago", "{count} minutes ago"),
+    ("hour_ago", "1 hour ago"),
+    ("hours_ago", "{count} hours ago"),
+    ("yesterday", "yesterday"),
+    ("days_ago", "{count} days ago"),
+    ("last_month", "last month"),
+    ("months_ago", "{count} months ago"),
+    ("last_year", "last year"),
+    ("years_ago", "{count} years ago"),
+];
+
+/// Settings for how the page is rendered.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct RenderConfig {
+    /// Replacements for the default template's text, such as for translating it.
+    ///
+    /// The keys are those of [`DEFAULT_STRINGS`], and any not given keep their English defaults.
+    #[serde(default)]
+    pub strings: HashMap<Box<str>, Box<str>>,
+    /// Whether to give templates each article's entry as parsed from its feed, at `raw`, for
+    /// things jarss doesn't otherwise show, like every link or the media attached.
+    ///
+    /// Its layout is that of [`feed_rs::model::Entry`] and may change between releases. Its text
+    /// is as in the feed apart from having tera syntax broken up, so it's escaped when rendered
+    /// but not sanitized, and must never be marked `safe`. It isn't counted towards
+    /// `max_entry_bytes`, so turning this on doesn't drop any more entries.
+    #[serde(default)]
+    pub expose_raw_entries: bool,
+    /// The URL the page's directory is served from, such as `https://example.com/feeds/` behind a
+    /// reverse proxy.
+    ///
+    /// With this, `output_url` links to the other outputs with absolute URLs under it, rather
+    /// than with paths relative to the page.
+    #[serde(default)]
+    pub base_url: Option<Box<str>>,
+}
+
+/// The text for templates to show, as given to them at `strings`.
+///
+/// Overrides for keys we don't know of are warned about and ignored, as they're most likely typos.
+pub fn strings(config: &RenderConfig) -> HashMap<&str, &str> {
+    for key in config.strings.keys() {
+        if !DEFAULT_STRINGS
+            .iter()
+            .any(|(known, _)| known == &key.as_ref())
+        {
+            log::warn!("Unknown key `{key}` in [render.strings], ignoring it");
+        }
+    }
+    resolved(config)
+}
+
+/// The same as [`strings`], without warning about unknown keys again, for the filters which show
+/// some of the text themselves.
+pub fn resolved(config: &RenderConfig) -> HashMap<&str, &str> {
+    DEFAULT_STRINGS
+        .iter()
+        .map(|&(key, default)| (key, config.strings.get(key).map_or(default, AsRef::as_ref)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{links::OutputLinks, outputs::PlannedOutput, sanitize, test_util};
+
+    /// The context of a page showing every part of the default template, with articles published
+    /// at the times given, in seconds before now.
+    fn full_page_context(config: &RenderConfig, ages: &[i64]) -> tera::Context {
+        let now = chrono::Utc::now();
+        let articles = ages
+            .iter()
+            .map(|age| {
+                let published = now - chrono::TimeDelta::seconds(*age);
+                serde_json::json!({
+                    "published": published,
+                    "publish_date": published.date_naive(),
+                    "site": "Ex",
+                    "link": "https://example.com/1",
+                    "title": "T",
+                    "also_on": [{"link": "https://example.org/1", "site": "Eg"}],
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut context = tera::Context::from_value(serde_json::json!({
+            "articles": articles,
+            "random_picks": articles,
+            "include_summaries": true,
+            "search_index": "search.json",
+            "site_status": [{"severity": "warn", "error_age_secs": 3 * 86400, "name": "Ex"}],
+            "errors": [
+                {"name": "Ex", "error": "E", "last_fetch_time": "2024-01-01T00:00:00Z"},
+                {"name": "Eg", "error": "E", "last_fetch_time": null},
+            ],
+            "site_stats": [{
+                "name": "Ex",
+                "windows": [{"days": 7, "articles": 2, "articles_with_text": 1, "minutes": 3}],
+            }],
+            "safe_mode": true,
+        }))
+        .unwrap();
+        context.insert("strings", &strings(config));
+        context
+    }
+
+    #[test]
+    fn overriding_every_string_leaves_no_english_in_the_default_template() {
+        let config = RenderConfig {
+            strings: DEFAULT_STRINGS
+                .iter()
+                .enumerate()
+                .map(|(i, (key, _))| (Box::from(*key), format!("«{i} {{count}}»").into()))
+                .collect(),
+            ..Default::default()
+        };
+        let mut tera = sanitize::tera(&config);
+        tera.add_raw_template("output", include_str!("../default-render.html.tera"))
+            .unwrap();
+        OutputLinks::new(
+            None,
+            "out.html".as_ref(),
+            &[PlannedOutput {
+                name: "search_index",
+                path: "search.json".into(),
+                source: "the search index",
+            }],
+        )
+        .unwrap()
+        .register(&mut tera);
+        // One article for each way of describing its age.
+        let (minute, hour, day) = (60, 60 * 60, 24 * 60 * 60);
+        let ages = [
+            0,
+            minute,
+            5 * minute,
+            hour,
+            5 * hour,
+            day,
+            5 * day,
+            45 * day,
+            100 * day,
+            400 * day,
+            1000 * day,
+        ];
+        let page = tera
+            .render("output", &full_page_context(&config, &ages))
+            .unwrap();
+        let placeholder = regex::Regex::new(r"\{\w+\}").unwrap();
+        for (i, (key, default)) in DEFAULT_STRINGS.iter().enumerate() {
+            assert!(page.contains(&format!("«{i} ")), "{key} isn't shown");
+            // The parts of the text around its placeholders, apart from short ones like "min"
+            // which could be part of anything.
+            for part in placeholder
+                .split(default)
+                .filter(|part| part.trim().len() > 3)
+            {
+                assert!(!page.contains(part), "{part:?} of {key} is still shown");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn raw_entries_give_templates_what_only_the_feed_has() {
+        let settings = |expose| {
+            format!(
+                "min_fetch_interval = 0\n[render]\nexpose_raw_entries = {expose}\n\
+                 [limits]\nmax_entry_bytes = 1000\n\
+                 [[sites]]\nname = \"Videos\"\nfeed_url = \"https://videos.example.com/feed\"\n"
+            )
+        };
+        let feed = |_: &str| include_str!("../testdata/media-rss.xml").to_owned();
+        let template = "{% for article in articles %}{% if article.raw %}\
+                        {% for media in article.raw.media %}\
+                        {{ media.title.content }} {{ media.thumbnails.0.image.uri }}\
+                        {% endfor %}{% endif %}{% endfor %}";
+        let render = |articles: &[crate::FeedEntryInfo]| {
+            let mut tera = sanitize::tera(&RenderConfig::default());
+            tera.add_raw_template("output", template).unwrap();
+            let mut context = tera::Context::new();
+            context.insert("articles", articles);
+            tera.render("output", &context).unwrap()
+        };
+
+        let exposed = test_util::collect_cached("raw-entries", &settings(true), feed).await;
+        // The whole entry is more than `max_entry_bytes`, but only what we take from it counts.
+        assert_eq!(exposed.articles.len(), 1);
+        assert_eq!(
+            render(&exposed.articles),
+            "The video&#x27;s own {\u{200B}{ title }} https://videos.example.com/1.jpg"
+        );
+
+        let hidden = test_util::collect_cached("raw-entries-hidden", &settings(false), feed).await;
+        assert_eq!(hidden.articles.len(), 1);
+        assert_eq!(render(&hidden.articles), "");
+    }
+}
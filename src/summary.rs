@@ -0,0 +1,86 @@
+use super::{Config, canary::Shortfall, errors::ErrorInfo};
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// A machine-readable summary of how a run went.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct RunSummary {
+    /// Whether the run finished without any errors, from any site, and with as many articles as
+    /// expected.
+    pub success: bool,
+    /// Whether the run was in safe mode, with every optional feature turned off.
+    pub safe_mode: bool,
+    /// The error which stopped the run, if any.
+    pub error: Option<ErrorInfo>,
+    /// How each configured site fared, in the order they're configured.
+    pub sites: Vec<SiteSummary>,
+    /// The counts of articles which fell below `min_expected_articles` or a site's
+    /// `min_expected_entries`.
+    pub shortfalls: Vec<Shortfall>,
+    /// The errors sending to notification channels.
+    pub notification_errors: Vec<NotificationError>,
+}
+
+/// How one site fared in a run.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SiteSummary {
+    pub name: Box<str>,
+    /// The error fetching the site's feed, if any.
+    pub fetch_error: Option<ErrorInfo>,
+    /// The error reading articles from the site's cached feed, if any.
+    pub parse_error: Option<ErrorInfo>,
+    /// The error saving the site's cache, if any.
+    pub save_error: Option<ErrorInfo>,
+}
+
+/// An error sending to a notification channel.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct NotificationError {
+    pub channel: Box<str>,
+    pub error: ErrorInfo,
+}
+
+impl RunSummary {
+    /// Start a summary for a run over the given config, with nothing having gone wrong yet.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            success: true,
+            safe_mode: config.safe_mode,
+            error: None,
+            sites: config
+                .sites
+                .iter()
+                .map(|site| SiteSummary {
+                    name: site.name.clone(),
+                    fetch_error: None,
+                    parse_error: None,
+                    save_error: None,
+                })
+                .collect(),
+            shortfalls: Vec::new(),
+            notification_errors: Vec::new(),
+        }
+    }
+
+    /// The summary of the site with the given name.
+    pub fn site_mut(&mut self, name: &str) -> Option<&mut SiteSummary> {
+        self.sites
+            .iter_mut()
+            .find(|site| site.name.as_ref() == name)
+    }
+
+    /// Write the summary to `path` as JSON.
+    pub fn write(&mut self, path: &Path) -> Result<()> {
+        self.success = self.error.is_none()
+            && self.shortfalls.is_empty()
+            && self.notification_errors.is_empty()
+            && self.sites.iter().all(|site| {
+                site.fetch_error.is_none()
+                    && site.parse_error.is_none()
+                    && site.save_error.is_none()
+            });
+        let encoded = serde_json::to_vec_pretty(self).context("Failed to encode run summary")?;
+        std::fs::write(path, encoded).context("Failed to write run summary")
+    }
+}
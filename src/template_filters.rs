@@ -0,0 +1,231 @@
+use super::strings::{self, RenderConfig};
+
+use std::collections::HashMap;
+use tera::Value;
+
+/// Register our filters on `tera`, with the text from `render`'s strings:
+/// - `relative_time` turns a time, like an article's `published`, into text like "3 days ago".
+/// - `domain` gives the host of a URL, without any leading `www.`.
+/// - `truncate_words(count=N)` cuts text to its first `N` words, ending it with `end` (`…` by
+///   default) if anything was cut.
+///
+/// Registering them again replaces them, so changes to the strings apply to templates which are
+/// already parsed.
+pub fn register(tera: &mut tera::Tera, render: &RenderConfig) {
+    let strings = strings::resolved(render)
+        .into_iter()
+        .map(|(key, text)| (key.to_owned(), text.to_owned()))
+        .collect::<HashMap<_, _>>();
+    tera.register_filter(
+        "relative_time",
+        move |value: &Value, _: &HashMap<String, Value>| relative_time(value, &strings),
+    );
+    tera.register_filter("domain", domain);
+    tera.register_filter("truncate_words", truncate_words);
+}
+
+/// The `relative_time` filter, which takes an RFC 3339 time, as our times are serialized, or a
+/// number of seconds since the Unix epoch.
+///
+/// Times in the future, which only come from clocks being off, are "just now".
+fn relative_time(value: &Value, strings: &HashMap<String, String>) -> tera::Result<Value> {
+    let time = match value {
+        Value::String(time) => chrono::DateTime::parse_from_rfc3339(time)
+            .map_err(|e| tera::Error::msg(format!("`relative_time` got an invalid time: {e}")))?
+            .to_utc(),
+        Value::Number(secs) => secs
+            .as_i64()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .ok_or_else(|| tera::Error::msg("`relative_time` got an invalid timestamp"))?,
+        _ => return Err(tera::Error::msg("`relative_time` needs a time")),
+    };
+    Ok(describe_age(chrono::Utc::now() - time, strings).into())
+}
+
+/// Describe how long ago something was, given how long it's been, in the text of `strings`.
+fn describe_age(age: chrono::TimeDelta, strings: &HashMap<String, String>) -> String {
+    let text = |key: &str| strings[key].clone();
+    // There's never one day, month, or year ago, as those are "yesterday", "last month", and
+    // "last year", so those are always counted.
+    let counted = |key: &str, count: i64| text(key).replace("{count}", &count.to_string());
+    let plural = |count: i64, singular: &str, plural: &str| {
+        if count == 1 {
+            text(singular)
+        } else {
+            counted(plural, count)
+        }
+    };
+    let days = age.num_days();
+    if age.num_minutes() < 1 {
+        text("just_now")
+    } else if age.num_hours() < 1 {
+        plural(age.num_minutes(), "minute_ago", "minutes_ago")
+    } else if days < 1 {
+        plural(age.num_hours(), "hour_ago", "hours_ago")
+    } else if days == 1 {
+        text("yesterday")
+    } else if days < 30 {
+        counted("days_ago", days)
+    } else if days < 60 {
+        text("last_month")
+    } else if days < 365 {
+        counted("months_ago", days / 30)
+    } else if days < 2 * 365 {
+        text("last_year")
+    } else {
+        counted("years_ago", days / 365)
+    }
+}
+
+/// The `domain` filter.
+///
+/// Anything which isn't a URL with a host gives an empty string, so one bad link from a feed
+/// can't stop the page rendering.
+fn domain(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let url = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("`domain` needs a URL"))?;
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .unwrap_or_default();
+    Ok(host.strip_prefix("www.").unwrap_or(&host).into())
+}
+
+/// The `truncate_words` filter.
+fn truncate_words(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("`truncate_words` needs text"))?;
+    let count = args
+        .get("count")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| tera::Error::msg("`truncate_words` needs a `count` of words"))?;
+    let end = match args.get("end") {
+        Some(end) => end
+            .as_str()
+            .ok_or_else(|| tera::Error::msg("`truncate_words`'s `end` must be text"))?,
+        None => "…",
+    };
+    let mut words = text.split_whitespace();
+    let kept = words
+        .by_ref()
+        .take(usize::try_from(count).unwrap_or(usize::MAX))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok(if words.next().is_some() {
+        format!("{kept}{end}").into()
+    } else {
+        text.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{sanitize, strings::RenderConfig};
+
+    /// Render `template` with `value` as `value`, through the same filters as the page.
+    fn render(template: &str, value: impl serde::Serialize) -> String {
+        let mut tera = sanitize::tera(&RenderConfig::default());
+        tera.add_raw_template("test", template).unwrap();
+        let mut context = tera::Context::new();
+        context.insert("value", &value);
+        tera.render("test", &context).unwrap()
+    }
+
+    fn ago(delta: chrono::TimeDelta) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc::now() - delta
+    }
+
+    #[test]
+    fn relative_times_are_described_from_rfc_3339_times() {
+        let relative = |delta| render("{{ value | relative_time }}", ago(delta));
+        assert_eq!(relative(chrono::TimeDelta::seconds(10)), "just now");
+        assert_eq!(relative(chrono::TimeDelta::seconds(90)), "1 minute ago");
+        assert_eq!(relative(chrono::TimeDelta::minutes(5)), "5 minutes ago");
+        assert_eq!(relative(chrono::TimeDelta::minutes(61)), "1 hour ago");
+        assert_eq!(relative(chrono::TimeDelta::hours(23)), "23 hours ago");
+        assert_eq!(relative(chrono::TimeDelta::hours(30)), "yesterday");
+        assert_eq!(relative(chrono::TimeDelta::days(29)), "29 days ago");
+        assert_eq!(relative(chrono::TimeDelta::days(45)), "last month");
+        assert_eq!(relative(chrono::TimeDelta::days(100)), "3 months ago");
+        assert_eq!(relative(chrono::TimeDelta::days(400)), "last year");
+        assert_eq!(relative(chrono::TimeDelta::days(1000)), "2 years ago");
+        // Times with offsets, as feeds give them, work as well as those in UTC.
+        assert_eq!(
+            render(
+                "{{ value | relative_time }}",
+                ago(chrono::TimeDelta::days(3))
+                    .with_timezone(&chrono::FixedOffset::east_opt(-5 * 3600).unwrap())
+                    .to_rfc3339()
+            ),
+            "3 days ago"
+        );
+    }
+
+    #[test]
+    fn relative_times_are_described_from_timestamps() {
+        let timestamp = ago(chrono::TimeDelta::hours(5)).timestamp();
+        assert_eq!(
+            render("{{ value | relative_time }}", timestamp),
+            "5 hours ago"
+        );
+    }
+
+    #[test]
+    fn future_times_are_just_now() {
+        assert_eq!(
+            render(
+                "{{ value | relative_time }}",
+                ago(-chrono::TimeDelta::days(3))
+            ),
+            "just now"
+        );
+    }
+
+    #[test]
+    fn invalid_times_are_errors() {
+        let mut tera = sanitize::tera(&RenderConfig::default());
+        tera.add_raw_template("test", "{{ value | relative_time }}")
+            .unwrap();
+        let mut context = tera::Context::new();
+        for value in [
+            serde_json::json!("yesterday"),
+            serde_json::json!(i64::MAX),
+            serde_json::json!(true),
+        ] {
+            context.insert("value", &value);
+            assert!(tera.render("test", &context).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn domains_are_given_without_www() {
+        let domain = |url| render("{{ value | domain }}", url);
+        assert_eq!(domain("https://www.example.com/a/b?c"), "example.com");
+        assert_eq!(domain("https://blog.example.com/"), "blog.example.com");
+        // Only a leading `www.` is removed.
+        assert_eq!(domain("https://wwwx.example.com/"), "wwwx.example.com");
+        assert_eq!(
+            domain("https://sub.www.example.com/"),
+            "sub.www.example.com"
+        );
+        assert_eq!(domain("not a url"), "");
+        assert_eq!(domain("mailto:someone@example.com"), "");
+    }
+
+    #[test]
+    fn words_past_the_count_are_cut_with_an_ellipsis() {
+        let truncate =
+            |text, args| render(&format!("{{{{ value | truncate_words({args}) }}}}"), text);
+        assert_eq!(truncate("one two three four", "count=2"), "one two…");
+        assert_eq!(
+            truncate("one two three four", "count=3, end=\" [more]\""),
+            "one two three [more]"
+        );
+        // Text with no more words than the count is left as it was, whitespace and all.
+        assert_eq!(truncate("  one\ttwo ", "count=2"), "  one\ttwo ");
+        // The ellipsis is escaped like any other text.
+        assert_eq!(truncate("a <b> c", "count=1, end=\"<…>\""), "a&lt;…&gt;");
+    }
+}
@@ -0,0 +1,169 @@
+use super::{
+    errors::{ErrorCode, WithCode as _},
+    sanitize,
+    strings::RenderConfig,
+    template_filters,
+};
+
+use anyhow::{Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The templates a run renders with, kept parsed between the runs of `jarss watch`.
+pub struct Templates {
+    page: Template,
+    /// The fragment's template, once a run has written a fragment.
+    fragment: Option<Template>,
+}
+impl Templates {
+    /// Read the page's template from `page`, or use the default one if there's none.
+    ///
+    /// The fragment's template isn't read until it's first needed, since the config says where
+    /// it is.
+    pub fn new(page: Option<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            page: Template::read("output", page, include_str!("../default-render.html.tera"))
+                .context("Error reading feed template from file")?,
+            fragment: None,
+        })
+    }
+
+    /// The page's template, parsed as `output`, with filters showing `render`'s text.
+    pub fn page(&mut self, render: &RenderConfig) -> Result<&mut tera::Tera> {
+        self.page.parsed(render)
+    }
+
+    /// The fragment's template at `path`, parsed as `fragment`, with filters showing `render`'s
+    /// text.
+    pub fn fragment(&mut self, path: &Path, render: &RenderConfig) -> Result<&mut tera::Tera> {
+        if self
+            .fragment
+            .as_ref()
+            .is_none_or(|fragment| fragment.path.as_deref() != Some(path))
+        {
+            self.fragment = Some(
+                Template::read("fragment", Some(path.to_owned()), "")
+                    .context("Error reading fragment template")?,
+            );
+        }
+        self.fragment
+            .as_mut()
+            .expect("The fragment's template was just read")
+            .parsed(render)
+    }
+}
+
+#[cfg(test)]
+impl Templates {
+    /// Use `page` as the page's template, in place of the default one.
+    pub fn with_page(page: &str) -> Self {
+        Self {
+            page: Template::read("output", None, page).expect("Built-in templates aren't read"),
+            fragment: None,
+        }
+    }
+}
+
+/// A template, which is only parsed again once its file changes.
+struct Template {
+    /// The name the template is added to [`tera`] with.
+    name: &'static str,
+    /// The file the template is read from, if it isn't built in.
+    path: Option<PathBuf>,
+    /// When `path` was modified, as of when it was read.
+    modified: Option<SystemTime>,
+    source: Box<str>,
+    /// The parsed template, if it's been parsed since it was read.
+    tera: Option<tera::Tera>,
+}
+impl Template {
+    /// Read the template from `path`, or use `default` if there's none.
+    fn read(name: &'static str, path: Option<PathBuf>, default: &str) -> Result<Self> {
+        let (source, modified) = match &path {
+            Some(path) => (
+                std::fs::read_to_string(path)?.into_boxed_str(),
+                modified(path),
+            ),
+            None => (default.into(), None),
+        };
+        Ok(Self {
+            name,
+            path,
+            modified,
+            source,
+            tera: None,
+        })
+    }
+
+    /// The parsed template, read and parsed again if its file changed since it was last read.
+    ///
+    /// The filters are registered again each time, in case `render` changed since it was parsed.
+    fn parsed(&mut self, render: &RenderConfig) -> Result<&mut tera::Tera> {
+        if let Some(path) = &self.path
+            && modified(path) != self.modified
+        {
+            log::info!("Template {} changed, reading it again", path.display());
+            let reread = Self::read(self.name, Some(path.clone()), "")
+                .with_context(|| format!("Error reading template {}", path.display()))?;
+            *self = reread;
+        }
+        match &mut self.tera {
+            Some(tera) => {
+                template_filters::register(tera, render);
+                Ok(tera)
+            }
+            tera @ None => {
+                let mut parsed = sanitize::tera(render);
+                parsed
+                    .add_raw_template(self.name, &self.source)
+                    .context("Error parsing tera template")
+                    .code(ErrorCode::TemplateError)?;
+                Ok(tera.insert(parsed))
+            }
+        }
+    }
+}
+
+/// When the file at `path` was last modified, if that can be told.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn templates_are_only_read_again_once_their_file_changes() {
+        let dir = test_dir("templates");
+        let path = dir.join("page.html.tera");
+        std::fs::write(&path, "first").unwrap();
+        let mut templates = Templates::new(Some(path.clone())).unwrap();
+        let render = RenderConfig::default();
+        let rendered = |templates: &mut Templates| {
+            templates
+                .page(&render)
+                .unwrap()
+                .render("output", &tera::Context::new())
+                .unwrap()
+        };
+        assert_eq!(rendered(&mut templates), "first");
+
+        // Changing the file without changing when it was modified goes unnoticed.
+        let written = modified(&path).unwrap();
+        std::fs::write(&path, "second").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(written).unwrap();
+        assert_eq!(rendered(&mut templates), "first");
+
+        file.set_modified(written + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(rendered(&mut templates), "second");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
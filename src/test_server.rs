@@ -0,0 +1,182 @@
+//! Servers on local ports for tests to make requests to.
+
+use crate::{Config, cache::CacheManager, github::GitHub, origins::Origins};
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+
+/// A self-signed certificate for `localhost`, which nothing trusts.
+const UNTRUSTED_CERT: &[u8] = include_bytes!("../testdata/self-signed.der");
+
+/// Serve HTTP on a local port, answering each request with the whole response `respond` gives
+/// for the head of the request, the request line and headers.
+///
+/// Each connection gets one response and is then closed.
+pub async fn serve_http(respond: impl Fn(&str) -> String + Send + Sync + 'static) -> SocketAddr {
+    let respond = Arc::new(respond);
+    serve(move |mut stream| {
+        let respond = Arc::clone(&respond);
+        async move {
+            let head = read_head(&mut stream).await;
+            let _ = stream.write_all(respond(&head).as_bytes()).await;
+            let _ = stream.shutdown().await;
+        }
+    })
+    .await
+}
+
+/// Serve HTTP like [`serve_http`], except that `respond` can wait before giving its response.
+pub async fn serve_http_after<F, Fut>(respond: F) -> SocketAddr
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = String> + Send + 'static,
+{
+    let respond = Arc::new(respond);
+    serve(move |mut stream| {
+        let respond = Arc::clone(&respond);
+        async move {
+            let head = read_head(&mut stream).await;
+            let _ = stream.write_all(respond(head).await.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        }
+    })
+    .await
+}
+
+/// Accept connections on a local port, and never answer them.
+pub async fn serve_nothing() -> SocketAddr {
+    serve(|stream| async move {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        drop(stream);
+    })
+    .await
+}
+
+/// A local port which nothing is listening on.
+pub async fn closed_port() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// Start TLS 1.2 handshakes on a local port with [`UNTRUSTED_CERT`], which clients give up on
+/// once they fail to verify it.
+///
+/// Nothing past the certificate is sent, since that's as far as clients get.
+pub async fn serve_untrusted_tls() -> SocketAddr {
+    serve(|mut stream| async move {
+        // The client hello, which is answered the same whatever it offers.
+        let _ = stream.read(&mut [0; 4096]).await;
+        let server_hello = [
+            &[0x03, 0x03][..],
+            &[0x42; 32],
+            // No session ID, TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256, and no compression.
+            &[0x00, 0xc0, 0x2f, 0x00],
+            // Only the `renegotiation_info` extension, which OpenSSL insists on.
+            &[0x00, 0x05, 0xff, 0x01, 0x00, 0x01, 0x00],
+        ]
+        .concat();
+        let certificate = [
+            &u24(UNTRUSTED_CERT.len() + 3)[..],
+            &u24(UNTRUSTED_CERT.len()),
+            UNTRUSTED_CERT,
+        ]
+        .concat();
+        let handshake = [handshake(2, &server_hello), handshake(11, &certificate)].concat();
+        let length = u16::try_from(handshake.len()).unwrap().to_be_bytes();
+        let record = [&[0x16, 0x03, 0x03][..], &length, &handshake].concat();
+        let _ = stream.write_all(&record).await;
+        // Wait for the client's alert.
+        let _ = stream.read(&mut [0; 4096]).await;
+    })
+    .await
+}
+
+/// A TLS handshake message of the given type.
+fn handshake(message_type: u8, body: &[u8]) -> Vec<u8> {
+    [&[message_type][..], &u24(body.len()), body].concat()
+}
+
+/// A length as TLS's 24-bit big-endian integers.
+fn u24(length: usize) -> [u8; 3] {
+    let [.., a, b, c] = u32::try_from(length).unwrap().to_be_bytes();
+    [a, b, c]
+}
+
+/// A response with the given status line, `content-type` and body.
+pub fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\n\
+         connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Serve chains of permanent redirects, from `/redirect/{n}` to `/redirect/{n - 1}`, ending with
+/// an empty feed at `/redirect/0`.
+pub async fn serve_redirect_chains() -> SocketAddr {
+    serve_http(|head| {
+        let path = head.split(' ').nth(1).unwrap_or_default();
+        match path.strip_prefix("/redirect/").map(str::parse::<usize>) {
+            Some(Ok(0)) => response("200 OK", "application/rss+xml", "<rss></rss>"),
+            Some(Ok(n)) => format!(
+                "HTTP/1.1 301 Moved Permanently\r\nlocation: /redirect/{}\r\n\
+                 content-length: 0\r\nconnection: close\r\n\r\n",
+                n - 1
+            ),
+            _ => response("404 Not Found", "text/plain", ""),
+        }
+    })
+    .await
+}
+
+/// Fetch each of the config's sites into `caches` as a run would, and save them, returning the
+/// error of each site which failed.
+pub async fn fetch_all(config: &Config, caches: &CacheManager) -> Vec<anyhow::Error> {
+    let client = config.http.client_builder().build().unwrap();
+    let github = GitHub::new(config, caches.state()).unwrap();
+    let origins = Origins::load(caches.state());
+    let guard = caches.cache_guard();
+    let mut errors = Vec::new();
+    for site in &config.sites {
+        let mut cache = caches.get_mut(site, &guard).await.unwrap();
+        let res =
+            crate::cache::query_site(&client, config, &github, &origins, site, &mut cache).await;
+        errors.extend(res.err());
+    }
+    drop(guard);
+    let saved = caches.save(1).await;
+    assert!(saved.is_empty(), "{saved:?}");
+    errors
+}
+
+/// Accept connections on a local port in the background, handling each with `handle`.
+async fn serve<F, Fut>(handle: F) -> SocketAddr
+where
+    F: Fn(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream));
+        }
+    });
+    addr
+}
+
+/// Read the head of an HTTP request, up to the blank line after the headers.
+async fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => head.extend_from_slice(&buf[..read]),
+        }
+    }
+    String::from_utf8_lossy(&head).into_owned()
+}
@@ -0,0 +1,38 @@
+//! Helpers shared by tests.
+
+use crate::{CollectedArticles, cache, collect_articles, config_files, trace};
+
+use std::path::PathBuf;
+
+/// A directory for the calling test to write files in, which is empty and only used by it.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jarss-test-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Collect the articles of the config with `settings`, where each site's cached feed is `feeds`
+/// of its name, with the entry with ID `{name}-1` new.
+///
+/// `name` names the test's directory, as for [`test_dir`].
+pub async fn collect_cached(
+    name: &str,
+    settings: &str,
+    feeds: impl Fn(&str) -> String,
+) -> CollectedArticles {
+    let config = config_files::to_config(toml::from_str(settings).unwrap(), false).unwrap();
+    let dir = test_dir(name);
+    let caches = cache::CacheManager::new(dir.clone(), config.cache_key, &config.sites).unwrap();
+    let guard = caches.cache_guard();
+    for site in &config.sites {
+        let mut cache = caches.get_mut(site, &guard).await.unwrap();
+        cache.last_body = Some(feeds(&site.name).into());
+        cache.new_entries = [format!("{}-1", site.name).into()].into();
+    }
+    drop(guard);
+    let collected = collect_articles(&config, &caches, &trace::ArticleTrace::new(None)).await;
+    drop(caches);
+    let _ = std::fs::remove_dir_all(&dir);
+    collected
+}
@@ -0,0 +1,599 @@
+use super::{Config, SiteConfig, cache::SiteCache, github::GitHub, origins::Origins, timestamp};
+
+use std::time::{Duration, SystemTime};
+
+/// One of the checks which decides whether a site can be fetched yet.
+pub struct Gate {
+    /// What the gate checks.
+    pub name: &'static str,
+    /// Whether the gate checks the state of the site itself, or of every site on its origin.
+    pub scope: Scope,
+    /// The values the gate's verdict was based on, as names and human-readable values.
+    pub inputs: Vec<(&'static str, String)>,
+    /// If this gate stops the site being fetched, when it'll stop doing so.
+    pub blocks_until: Option<SystemTime>,
+}
+
+/// What a [`Gate`] checks the state of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Site,
+    Origin,
+}
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Site => "site",
+            Self::Origin => "origin",
+        })
+    }
+}
+
+/// Whether a site can be fetched, and why.
+pub struct FetchDecision {
+    /// The gates, in the order they're evaluated.
+    pub gates: Vec<Gate>,
+}
+impl FetchDecision {
+    /// Evaluate every gate for a site with the given cache, as of `now`.
+    pub fn new(
+        config: &Config,
+        site: &SiteConfig,
+        cache: &SiteCache,
+        origins: &Origins,
+        now: SystemTime,
+    ) -> Self {
+        let min_interval =
+            Duration::from_secs(site.min_fetch_interval.unwrap_or(config.min_fetch_interval));
+        let mut gates = vec![
+            // Check if we've recently fetched, so we don't spam.
+            Gate {
+                name: "min_fetch_interval",
+                scope: Scope::Site,
+                inputs: vec![
+                    ("last_fetch_time", describe_time(cache.last_fetch_time)),
+                    (
+                        "min_fetch_interval",
+                        humantime::format_duration(min_interval).to_string(),
+                    ),
+                ],
+                blocks_until: cache
+                    .last_fetch_time
+                    .map(|time| time + min_interval)
+                    .filter(|&until| until > now),
+            },
+            // Check if we've been asked to retry later.
+            Gate {
+                name: "retry_after",
+                scope: Scope::Site,
+                inputs: vec![("retry_after", describe_time(cache.last_retry_after))],
+                blocks_until: cache
+                    .last_retry_after
+                    .filter(|&retry_after| retry_after >= now),
+            },
+        ];
+        if let Some((origin, state)) = origins.state(site, cache) {
+            gates.extend([
+                // Check if another site on the origin has been asked to retry later.
+                Gate {
+                    name: "origin_retry_after",
+                    scope: Scope::Origin,
+                    inputs: vec![
+                        ("origin", origin.clone()),
+                        ("retry_after", describe_time(state.retry_after)),
+                    ],
+                    blocks_until: state.retry_after.filter(|&retry_after| retry_after >= now),
+                },
+                // Check if fetches from the origin keep failing, so it's probably down.
+                Gate {
+                    name: "origin_failures",
+                    scope: Scope::Origin,
+                    inputs: vec![
+                        ("origin", origin.clone()),
+                        (
+                            "consecutive_failures",
+                            state.consecutive_failures.to_string(),
+                        ),
+                        ("last_failure", describe_time(state.last_failure)),
+                    ],
+                    blocks_until: state.backoff_until().filter(|&until| until > now),
+                },
+                // Check if the origin's rate limit is used up.
+                Gate {
+                    name: "origin_rate_limit",
+                    scope: Scope::Origin,
+                    inputs: vec![
+                        ("origin", origin),
+                        (
+                            "remaining",
+                            state.rate_limit.map_or_else(
+                                || "unknown".to_owned(),
+                                |limit| limit.remaining.to_string(),
+                            ),
+                        ),
+                        (
+                            "reset",
+                            describe_time(state.rate_limit.map(|limit| limit.reset)),
+                        ),
+                    ],
+                    blocks_until: state
+                        .rate_limit
+                        .filter(|limit| limit.remaining < 1.0)
+                        .map(|limit| limit.reset)
+                        .filter(|&reset| reset > now),
+                },
+            ]);
+        }
+        Self { gates }
+    }
+
+    /// If the site can't be fetched yet, when it'll next be allowed.
+    pub fn blocked_until(&self) -> Option<SystemTime> {
+        self.gates.iter().filter_map(|gate| gate.blocks_until).max()
+    }
+}
+
+/// Check whether we shouldn't fetch the given site yet, logging why not if so.
+pub fn is_throttled(
+    config: &Config,
+    site: &SiteConfig,
+    cache: &SiteCache,
+    origins: &Origins,
+) -> bool {
+    let now = SystemTime::now();
+    let decision = FetchDecision::new(config, site, cache, origins, now);
+    for gate in &decision.gates {
+        let Some(until) = gate.blocks_until else {
+            continue;
+        };
+        let wait = until.duration_since(now).unwrap_or_default().as_secs();
+        match gate.name {
+            "retry_after" => log::warn!(
+                "Site {} has `retry-after`ed us, will not fetch for {wait}s",
+                site.name,
+            ),
+            "origin_retry_after" => log::warn!(
+                "The origin of {} has `retry-after`ed us, will not fetch for {wait}s",
+                site.name,
+            ),
+            "origin_failures" => log::warn!(
+                "Fetches from the same origin as {} keep failing, will not fetch for {wait}s",
+                site.name,
+            ),
+            "origin_rate_limit" => log::warn!(
+                "The rate limit for the origin of {} is used up, will not fetch for {wait}s",
+                site.name,
+            ),
+            _ => log::info!(
+                "Site {} has been fetched recently, will not be fetched again for {wait}s",
+                site.name
+            ),
+        }
+    }
+    decision.blocked_until().is_some()
+}
+
+/// Whether the given site can be fetched now, going by its gates and GitHub's rate limits.
+pub fn is_due(
+    config: &Config,
+    site: &SiteConfig,
+    cache: &SiteCache,
+    github: &GitHub,
+    origins: &Origins,
+    now: SystemTime,
+) -> bool {
+    FetchDecision::new(config, site, cache, origins, now)
+        .blocked_until()
+        .is_none()
+        && github.blocked_until(site, now).is_none()
+}
+
+/// Pick which of the given sites to fetch next, when fetching one at a time.
+///
+/// This is the site we tried to fetch longest ago, of those which can be fetched now, counting
+/// sites we've never tried as the stalest and breaking ties by the order of the sites given. A
+/// site we've just tried becomes the freshest, so picking one site after another cycles through
+/// them all.
+pub fn next_due<'a, 'b>(
+    config: &Config,
+    sites: impl IntoIterator<Item = (&'a SiteConfig, &'b SiteCache)>,
+    github: &GitHub,
+    origins: &Origins,
+    now: SystemTime,
+) -> Option<&'a SiteConfig> {
+    sites
+        .into_iter()
+        .filter(|(site, cache)| is_due(config, site, cache, github, origins, now))
+        .min_by_key(|(_, cache)| cache.last_attempt_time())
+        .map(|(site, _)| site)
+}
+
+/// Describe a time for [`Gate::inputs`].
+pub fn describe_time(time: Option<SystemTime>) -> String {
+    time.map_or_else(
+        || "none".to_owned(),
+        |time| humantime::format_rfc3339_seconds(time).to_string(),
+    )
+}
+
+/// Print how the decision about whether to fetch the site was reached.
+///
+/// This prints JSON if `json` is set, and otherwise a human-readable explanation.
+pub fn explain(site: &SiteConfig, decision: &FetchDecision, now: SystemTime, json: bool) {
+    if json {
+        println!("{:#}", explanation_json(site, decision, now));
+        return;
+    }
+    let blocked_until = decision.blocked_until();
+    println!("{}, as of {}:", site.name, describe_time(Some(now)));
+    for gate in &decision.gates {
+        let inputs = gate
+            .inputs
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let verdict = match gate.blocks_until {
+            Some(until) => format!("blocks until {}", describe_time(Some(until))),
+            None => "allows".to_owned(),
+        };
+        println!("  {} ({}): {inputs} → {verdict}", gate.name, gate.scope);
+    }
+    match blocked_until {
+        Some(until) => println!("VERDICT: skip until {}", describe_time(Some(until))),
+        None => println!("VERDICT: fetch"),
+    }
+}
+
+/// The explanation of the decision printed by [`explain`] as JSON.
+fn explanation_json(
+    site: &SiteConfig,
+    decision: &FetchDecision,
+    now: SystemTime,
+) -> serde_json::Value {
+    let blocked_until = decision.blocked_until();
+    let gates = decision
+        .gates
+        .iter()
+        .map(|gate| {
+            let mut gate_json = serde_json::Map::new();
+            gate_json.insert("name".to_owned(), gate.name.into());
+            gate_json.insert("scope".to_owned(), gate.scope.to_string().into());
+            gate_json.insert(
+                "inputs".to_owned(),
+                gate.inputs
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.as_str().into()))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into(),
+            );
+            timestamp::insert(&mut gate_json, "blocks_until", gate.blocks_until);
+            gate_json
+        })
+        .collect::<Vec<_>>();
+    let mut explanation = serde_json::Map::new();
+    explanation.insert("site".to_owned(), site.name.as_ref().into());
+    timestamp::insert(&mut explanation, "now", Some(now));
+    explanation.insert("gates".to_owned(), gates.into());
+    explanation.insert(
+        "verdict".to_owned(),
+        if blocked_until.is_some() {
+            "skip"
+        } else {
+            "fetch"
+        }
+        .into(),
+    );
+    timestamp::insert(&mut explanation, "skip_until", blocked_until);
+    explanation.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state::StatePaths, test_util::test_dir};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    /// A config with a site for each of the given feed URLs and `min_fetch_interval`s, fetched at
+    /// most hourly by default.
+    fn config(sites: &[(&str, Option<u64>)]) -> Config {
+        let sites = sites
+            .iter()
+            .enumerate()
+            .map(|(index, (feed_url, interval))| {
+                let interval = interval
+                    .map(|interval| format!("min_fetch_interval = {interval}\n"))
+                    .unwrap_or_default();
+                format!("[[sites]]\nname = \"Site {index}\"\nfeed_url = \"{feed_url}\"\n{interval}")
+            })
+            .collect::<String>();
+        crate::config_files::to_config(
+            toml::from_str(&format!("min_fetch_interval = 3600\n{sites}")).unwrap(),
+            false,
+        )
+        .unwrap()
+    }
+
+    /// A cache for a site last fetched at `last_fetch_time`, and told to retry at `retry_after`.
+    fn cache(last_fetch_time: Option<SystemTime>, retry_after: Option<SystemTime>) -> SiteCache {
+        let mut cache = SiteCache::default();
+        cache.last_fetch_time = last_fetch_time;
+        cache.last_retry_after = retry_after;
+        cache
+    }
+
+    /// The gates which block, and until when.
+    fn blocking(decision: &FetchDecision) -> Vec<(&'static str, SystemTime)> {
+        decision
+            .gates
+            .iter()
+            .filter_map(|gate| Some((gate.name, gate.blocks_until?)))
+            .collect()
+    }
+
+    #[test]
+    fn sites_wait_out_their_min_fetch_interval() {
+        let dir = test_dir("throttle-interval");
+        let config = config(&[
+            ("https://example.com/default.xml", None),
+            ("https://example.com/often.xml", Some(600)),
+            ("https://example.com/always.xml", Some(0)),
+        ]);
+        let origins = Origins::load(&StatePaths::new(dir.clone()));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let fetched = |ago| cache(Some(now - ago), None);
+        let decide = |site, cache: &SiteCache| {
+            FetchDecision::new(&config, &config.sites[site], cache, &origins, now)
+        };
+        // Never fetched.
+        assert_eq!(decide(0, &SiteCache::default()).blocked_until(), None);
+        // Fetched half an hour ago, so there's half an hour to go.
+        let decision = decide(0, &fetched(MINUTE * 30));
+        assert_eq!(
+            blocking(&decision),
+            [("min_fetch_interval", now + MINUTE * 30)]
+        );
+        assert_eq!(decision.blocked_until(), Some(now + MINUTE * 30));
+        // Due as soon as the interval is up.
+        assert_eq!(decide(0, &fetched(MINUTE * 60)).blocked_until(), None);
+        assert_eq!(
+            decide(0, &fetched(MINUTE * 60 - Duration::from_secs(1))).blocked_until(),
+            Some(now + Duration::from_secs(1))
+        );
+        // Sites' own intervals take the place of the global one.
+        assert_eq!(
+            decide(1, &fetched(MINUTE * 5)).blocked_until(),
+            Some(now + MINUTE * 5)
+        );
+        assert_eq!(decide(1, &fetched(MINUTE * 10)).blocked_until(), None);
+        assert_eq!(decide(2, &fetched(Duration::ZERO)).blocked_until(), None);
+        // With the clock gone backwards, the wait is from when we think we fetched.
+        let future = cache(Some(now + MINUTE), None);
+        assert_eq!(decide(0, &future).blocked_until(), Some(now + MINUTE * 61));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sites_wait_for_the_latest_of_their_gates() {
+        let dir = test_dir("throttle-gates");
+        let config = config(&[
+            ("https://example.com/a.xml", None),
+            ("https://example.com/b.xml", Some(0)),
+            ("https://example.org/c.xml", Some(0)),
+        ]);
+        let origins = Origins::load(&StatePaths::new(dir.clone()));
+        let now = SystemTime::now();
+        let asked_to_wait = cache(Some(now - MINUTE * 50), Some(now + MINUTE * 30));
+        let decision = FetchDecision::new(&config, &config.sites[0], &asked_to_wait, &origins, now);
+        assert_eq!(
+            blocking(&decision),
+            [
+                ("min_fetch_interval", now + MINUTE * 10),
+                ("retry_after", now + MINUTE * 30),
+            ]
+        );
+        assert_eq!(decision.blocked_until(), Some(now + MINUTE * 30));
+        // A retry-after which has passed doesn't block, but one for right now still does.
+        for (retry_after, blocked_until) in [(now - MINUTE, None), (now, Some(now))] {
+            let cache = cache(None, Some(retry_after));
+            let decision = FetchDecision::new(&config, &config.sites[1], &cache, &origins, now);
+            assert_eq!(decision.blocked_until(), blocked_until);
+        }
+
+        // One site being asked to wait holds back the others on its origin, and only those.
+        let mut headers = http::HeaderMap::new();
+        headers.insert("ratelimit-remaining", "0".parse().unwrap());
+        headers.insert("ratelimit-reset", "600".parse().unwrap());
+        origins.record_response(
+            &config.sites[0],
+            &asked_to_wait,
+            http::StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            asked_to_wait.last_retry_after,
+        );
+        let fresh = SiteCache::default();
+        let decision = FetchDecision::new(&config, &config.sites[1], &fresh, &origins, now);
+        let gates = blocking(&decision);
+        assert_eq!(
+            gates.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            ["origin_retry_after", "origin_rate_limit"]
+        );
+        assert_eq!(gates[0].1, now + MINUTE * 30);
+        // The reset is from when the response was recorded, which is just after `now`.
+        assert!(gates[1].1 >= now + MINUTE * 10, "{:?}", gates[1].1);
+        assert_eq!(decision.blocked_until(), Some(now + MINUTE * 30));
+        let decision = FetchDecision::new(&config, &config.sites[2], &fresh, &origins, now);
+        assert_eq!(decision.blocked_until(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_stalest_due_site_is_next() {
+        let dir = test_dir("throttle-next-due");
+        let config = config(&[
+            ("https://example.com/a.xml", Some(0)),
+            ("https://example.com/b.xml", Some(0)),
+            ("https://example.com/c.xml", Some(0)),
+            ("https://example.com/d.xml", Some(600)),
+        ]);
+        let state = StatePaths::new(dir.clone());
+        let github = GitHub::new(&config, &state).unwrap();
+        let origins = Origins::load(&state);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut caches = vec![SiteCache::default(); 4];
+        let next = |caches: &[SiteCache], now| {
+            next_due(
+                &config,
+                config.sites.iter().zip(caches),
+                &github,
+                &origins,
+                now,
+            )
+            .map(|site| &*site.name)
+        };
+        // Sites never tried come first, in order.
+        assert_eq!(next(&caches, now), Some("Site 0"));
+        caches[0].last_fetch_time = Some(now - MINUTE * 5);
+        assert_eq!(next(&caches, now), Some("Site 1"));
+        // Then the one tried longest ago, counting failures as tries.
+        caches[1].last_failure_time = Some(now - MINUTE * 30);
+        caches[2].last_fetch_time = Some(now - MINUTE * 60);
+        caches[2].last_failure_time = Some(now - MINUTE);
+        assert_eq!(
+            caches[2].last_attempt_time(),
+            Some(now - MINUTE),
+            "The latest of the two"
+        );
+        // Site 3 was tried longest ago, but isn't due.
+        caches[3].last_fetch_time = Some(now - MINUTE * 9);
+        assert_eq!(next(&caches, now), Some("Site 1"));
+
+        // Fetching the next one each minute cycles through every site, and Site 3 gets its turn
+        // each time its interval is up, rather than being starved by the sites always due.
+        let mut fetches = vec![Vec::new(); config.sites.len()];
+        for call in 1..=30 {
+            let now = now + MINUTE * call;
+            let name = next(&caches, now).unwrap();
+            let index = config
+                .sites
+                .iter()
+                .position(|site| *site.name == *name)
+                .unwrap();
+            fetches[index].push(call);
+            caches[index].last_fetch_time = Some(now);
+        }
+        for (site, calls) in fetches.iter().enumerate() {
+            let bound = if site == 3 { 11 } else { 4 };
+            assert!(calls[0] <= bound, "Site {site} first fetched at {calls:?}");
+            assert!(
+                calls.windows(2).all(|pair| pair[1] - pair[0] <= bound),
+                "Site {site} fetched at {calls:?}"
+            );
+        }
+        assert!(fetches[3].len() >= 3, "{fetches:?}");
+
+        // With nothing due, there's nothing to fetch.
+        let config = crate::config_files::to_config(
+            toml::from_str(
+                "min_fetch_interval = 3600\n\
+                 [[sites]]\nname = \"Site 0\"\nfeed_url = \"https://example.com/a.xml\"\n",
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        let cache = cache(Some(now), None);
+        assert!(
+            next_due(
+                &config,
+                [(&config.sites[0], &cache)],
+                &github,
+                &origins,
+                now
+            )
+            .is_none()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn explanations_in_json_have_fixed_field_names_and_formats() {
+        let dir = test_dir("throttle-explain");
+        let config = config(&[("https://example.com/feed.xml", None)]);
+        let origins = Origins::load(&StatePaths::new(dir.clone()));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let fetched = cache(Some(now - MINUTE * 30), None);
+        let decision = FetchDecision::new(&config, &config.sites[0], &fetched, &origins, now);
+        let origin_inputs = |inputs: serde_json::Value| {
+            let mut inputs = inputs;
+            inputs["origin"] = "https://example.com".into();
+            inputs
+        };
+        let unblocked = |name: &str, scope: &str, inputs: serde_json::Value| {
+            serde_json::json!({
+                "name": name,
+                "scope": scope,
+                "inputs": inputs,
+                "blocks_until_unix": null,
+                "blocks_until_rfc3339": null,
+            })
+        };
+        assert_eq!(
+            explanation_json(&config.sites[0], &decision, now),
+            serde_json::json!({
+                "site": "Site 0",
+                "now_unix": 1_700_000_000,
+                "now_rfc3339": "2023-11-14T22:13:20Z",
+                "gates": [
+                    {
+                        "name": "min_fetch_interval",
+                        "scope": "site",
+                        "inputs": {
+                            "last_fetch_time": "2023-11-14T21:43:20Z",
+                            "min_fetch_interval": "1h",
+                        },
+                        "blocks_until_unix": 1_700_001_800,
+                        "blocks_until_rfc3339": "2023-11-14T22:43:20Z",
+                    },
+                    unblocked("retry_after", "site", serde_json::json!({"retry_after": "none"})),
+                    unblocked(
+                        "origin_retry_after",
+                        "origin",
+                        origin_inputs(serde_json::json!({"retry_after": "none"})),
+                    ),
+                    unblocked(
+                        "origin_failures",
+                        "origin",
+                        origin_inputs(serde_json::json!({
+                            "consecutive_failures": "0",
+                            "last_failure": "none",
+                        })),
+                    ),
+                    unblocked(
+                        "origin_rate_limit",
+                        "origin",
+                        origin_inputs(serde_json::json!({"remaining": "unknown", "reset": "none"})),
+                    ),
+                ],
+                "verdict": "skip",
+                "skip_until_unix": 1_700_001_800,
+                "skip_until_rfc3339": "2023-11-14T22:43:20Z",
+            })
+        );
+
+        // Sites which can be fetched have no time to skip until.
+        let due = cache(Some(now - MINUTE * 60), None);
+        let decision = FetchDecision::new(&config, &config.sites[0], &due, &origins, now);
+        let explanation = explanation_json(&config.sites[0], &decision, now);
+        assert_eq!(explanation["verdict"], "fetch");
+        assert_eq!(explanation["skip_until_unix"], serde_json::Value::Null);
+        assert_eq!(explanation["skip_until_rfc3339"], serde_json::Value::Null);
+        assert_eq!(
+            explanation["gates"][0]["blocks_until_unix"],
+            serde_json::Value::Null
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::SystemTime;
+
+/// Add a time to machine-readable output, as the fields `{name}_unix` and `{name}_rfc3339`.
+///
+/// Every time in the JSON we print or write goes through here, so consumers can count on the same
+/// form everywhere: `_unix` holds whole seconds since the Unix epoch, rounded down, and `_rfc3339`
+/// holds the same second in UTC, like `2025-10-06T10:00:00Z`. Both are `null` if there's no
+/// time. Internal files, like the caches, needn't follow this.
+pub fn insert(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    name: &str,
+    time: Option<SystemTime>,
+) {
+    let time = time.map(chrono::DateTime::<chrono::Utc>::from);
+    map.insert(
+        format!("{name}_unix"),
+        time.map(|time| time.timestamp()).into(),
+    );
+    map.insert(
+        format!("{name}_rfc3339"),
+        time.map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            .into(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// The fields [`insert`] adds for `time`, under the name `at`.
+    fn inserted(time: Option<SystemTime>) -> serde_json::Value {
+        let mut map = serde_json::Map::new();
+        insert(&mut map, "at", time);
+        map.into()
+    }
+
+    #[test]
+    fn times_are_given_in_both_forms() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_759_744_800);
+        assert_eq!(
+            inserted(Some(time)),
+            serde_json::json!({
+                "at_unix": 1_759_744_800,
+                "at_rfc3339": "2025-10-06T10:00:00Z",
+            })
+        );
+    }
+
+    #[test]
+    fn fractions_of_a_second_are_rounded_down() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_759_744_800_999);
+        assert_eq!(
+            inserted(Some(time)),
+            serde_json::json!({
+                "at_unix": 1_759_744_800,
+                "at_rfc3339": "2025-10-06T10:00:00Z",
+            })
+        );
+        let before_the_epoch = SystemTime::UNIX_EPOCH - Duration::from_millis(500);
+        assert_eq!(
+            inserted(Some(before_the_epoch)),
+            serde_json::json!({
+                "at_unix": -1,
+                "at_rfc3339": "1969-12-31T23:59:59Z",
+            })
+        );
+    }
+
+    #[test]
+    fn missing_times_are_null() {
+        assert_eq!(
+            inserted(None),
+            serde_json::json!({"at_unix": null, "at_rfc3339": null})
+        );
+    }
+}
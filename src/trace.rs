@@ -0,0 +1,86 @@
+use super::FeedEntryInfo;
+
+use std::{collections::HashMap, time::SystemTime};
+
+/// Follows one article through collecting articles, logging what happens to it at each stage.
+///
+/// The article is picked out by its link or, while it's still in its feed, its entry ID. If no
+/// article was asked for, this does nothing.
+pub struct ArticleTrace {
+    target: Option<Box<str>>,
+    /// Whether we've seen the article in any parsed feed.
+    found: std::cell::Cell<bool>,
+}
+impl ArticleTrace {
+    pub fn new(target: Option<Box<str>>) -> Self {
+        Self {
+            target,
+            found: std::cell::Cell::new(false),
+        }
+    }
+
+    /// Whether the given entry from a parsed feed is the article being traced.
+    pub fn matches_entry(&self, entry: &feed_rs::model::Entry) -> bool {
+        self.target.as_deref().is_some_and(|target| {
+            entry.id == target || entry.links.iter().any(|link| link.href == target)
+        })
+    }
+
+    /// Whether the given article is the one being traced.
+    pub fn matches(&self, article: &FeedEntryInfo) -> bool {
+        self.target.as_deref().is_some_and(|target| {
+            article.link.as_ref() == target || article.original_link.as_deref() == Some(target)
+        })
+    }
+
+    /// Log what happened to the traced article.
+    pub fn event(&self, message: std::fmt::Arguments<'_>) {
+        if let Some(target) = &self.target {
+            log::info!("Trace {target}: {message}");
+        }
+    }
+
+    /// Note that the traced article was found in the feed from `site`.
+    pub fn found(&self, site: &str, last_seen: Option<SystemTime>) {
+        self.found.set(true);
+        self.event(format_args!(
+            "found in the feed from {site}, last seen in the feed {}",
+            describe_time(last_seen)
+        ));
+    }
+
+    /// Check whether the traced article used to be in the feed from `site`, given that it isn't in
+    /// the current one.
+    pub fn check_history(&self, site: &str, last_seen: &HashMap<Box<str>, SystemTime>) {
+        let Some(&last_seen) = self
+            .target
+            .as_deref()
+            .and_then(|target| last_seen.get(target))
+        else {
+            return;
+        };
+        self.found.set(true);
+        self.event(format_args!(
+            "no longer in the feed from {site}, which last had it {}",
+            describe_time(Some(last_seen))
+        ));
+    }
+
+    /// Finish the trace, once every stage is done.
+    pub fn finish(&self, shown: bool) {
+        if shown {
+            self.event(format_args!("included in the output"));
+        } else if !self.found.get() {
+            self.event(format_args!(
+                "not in any parsed feed, nor remembered from an earlier one"
+            ));
+        }
+    }
+}
+
+fn describe_time(time: Option<SystemTime>) -> String {
+    match time {
+        Some(time) => humantime::format_rfc3339_seconds(time).to_string(),
+        None => "never".to_owned(),
+    }
+}
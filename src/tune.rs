@@ -0,0 +1,292 @@
+use super::{
+    Config,
+    cache::{CacheManager, FetchRecord},
+    config_files::ConfigFiles,
+    replace,
+};
+
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+/// The shortest interval we'll suggest, however often a site changes.
+const MIN_SUGGESTED_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// The longest interval we'll suggest, however rarely a site changes.
+const MAX_SUGGESTED_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How many times shorter than the suggestion the configured interval has to be for us to point
+/// it out.
+const FLAG_FACTOR: u32 = 4;
+
+/// How useful a site's recent fetches were.
+pub struct Efficiency {
+    /// The number of fetches which got a 304 Not Modified.
+    pub not_modified: usize,
+    /// The number of fetches which got the whole feed.
+    pub full: usize,
+    /// The mean time between fetches which found the feed changed.
+    pub mean_change_interval: Option<Duration>,
+    /// The `min_fetch_interval` we'd suggest for the site, if we've seen it change enough.
+    pub suggested_interval: Option<Duration>,
+}
+impl Efficiency {
+    /// Work out the efficiency of the given fetches, oldest first.
+    pub fn of(history: &[FetchRecord]) -> Self {
+        let not_modified = history.iter().filter(|record| record.not_modified).count();
+        let changes = history
+            .iter()
+            .filter(|record| record.changed)
+            .map(|record| record.time)
+            .collect::<Vec<_>>();
+        let intervals = changes
+            .windows(2)
+            .filter_map(|pair| pair[1].duration_since(pair[0]).ok())
+            .collect::<Vec<_>>();
+        Self {
+            not_modified,
+            full: history.len() - not_modified,
+            mean_change_interval: (!intervals.is_empty()).then(|| {
+                intervals.iter().sum::<Duration>() / u32::try_from(intervals.len()).unwrap_or(1)
+            }),
+            suggested_interval: suggest_interval(intervals),
+        }
+    }
+}
+
+/// The `min_fetch_interval` to suggest for a site with the given intervals between its changes.
+///
+/// This is half the median interval, so we still pick up most changes reasonably soon, clamped to
+/// a sensible range. With no intervals, there's nothing to suggest.
+pub fn suggest_interval(mut change_intervals: Vec<Duration>) -> Option<Duration> {
+    if change_intervals.is_empty() {
+        return None;
+    }
+    change_intervals.sort_unstable();
+    let mid = change_intervals.len() / 2;
+    let median = if change_intervals.len().is_multiple_of(2) {
+        (change_intervals[mid - 1] + change_intervals[mid]) / 2
+    } else {
+        change_intervals[mid]
+    };
+    Some((median / 2).clamp(MIN_SUGGESTED_INTERVAL, MAX_SUGGESTED_INTERVAL))
+}
+
+/// Print how efficient each site's fetches have been, and optionally apply the suggested intervals
+/// of the sites we flag to the [editable](ConfigFiles::editable) config file.
+pub async fn tune(
+    config: &Config,
+    caches: &CacheManager,
+    config_files: &ConfigFiles,
+    apply: bool,
+) -> Result<()> {
+    let guard = caches.cache_guard();
+    let mut flagged = Vec::new();
+    println!("site\t304s/200s\tmean change interval\tconfigured\tsuggested");
+    for site in &config.sites {
+        let mut cache = caches
+            .get_mut(site, &guard)
+            .await
+            .with_context(|| format!("Error reading cache for {}", site.name))?;
+        let efficiency = Efficiency::of(cache.fetch_history.make_contiguous());
+        let configured =
+            Duration::from_secs(site.min_fetch_interval.unwrap_or(config.min_fetch_interval));
+        let describe = |duration: Option<Duration>| {
+            duration.map_or_else(
+                || "-".to_owned(),
+                |duration| {
+                    humantime::format_duration(Duration::from_secs(duration.as_secs())).to_string()
+                },
+            )
+        };
+        let flag = efficiency
+            .suggested_interval
+            .is_some_and(|suggested| configured * FLAG_FACTOR < suggested);
+        println!(
+            "{}\t{}/{}\t{}\t{}\t{}{}",
+            site.name,
+            efficiency.not_modified,
+            efficiency.full,
+            describe(efficiency.mean_change_interval),
+            describe(Some(configured)),
+            describe(efficiency.suggested_interval),
+            if flag { "\t(fetched too often)" } else { "" },
+        );
+        if let Some(suggested) = efficiency.suggested_interval.filter(|_| flag) {
+            flagged.push((site.name.clone(), suggested));
+        }
+    }
+    if apply && !flagged.is_empty() {
+        apply_intervals(config_files, &flagged)?;
+        println!(
+            "Set min_fetch_interval for {} sites in {}",
+            flagged.len(),
+            config_files.editable().display()
+        );
+    }
+    Ok(())
+}
+
+/// Set the given sites' `min_fetch_interval`s in the config file, keeping everything else in it
+/// as it was.
+fn apply_intervals(config_files: &ConfigFiles, intervals: &[(Box<str>, Duration)]) -> Result<()> {
+    let config_path = config_files.editable();
+    let contents = std::fs::read_to_string(config_path).context("Failed to read config file")?;
+    let mut doc = contents
+        .parse::<toml_edit::DocumentMut>()
+        .context("Failed to parse config file")?;
+    let sites = doc
+        .get_mut("sites")
+        .and_then(toml_edit::Item::as_array_of_tables_mut)
+        .context("Config file has no [[sites]] to update")?;
+    for (name, interval) in intervals {
+        let site = sites
+            .iter_mut()
+            .find(|site| site.get("name").and_then(toml_edit::Item::as_str) == Some(name))
+            .with_context(|| format!("No [[sites]] entry named {name} in the config file"))?;
+        let secs = i64::try_from(interval.as_secs()).context("Interval too long")?;
+        site["min_fetch_interval"] = toml_edit::value(secs);
+    }
+    let updated = doc.to_string();
+    config_files
+        .load_with_edit(&updated)
+        .context("Updated config would be invalid")?;
+    replace::write_atomically(config_path, updated.as_bytes()).context("Failed to write config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CacheKey, test_util::test_dir};
+    use std::time::SystemTime;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// Fetches at each of the given hours, and whether each got a 304 and whether it changed.
+    fn history(fetches: &[(u32, bool, bool)]) -> Vec<FetchRecord> {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        fetches
+            .iter()
+            .map(|&(hour, not_modified, changed)| FetchRecord {
+                time: start + HOUR * hour,
+                not_modified,
+                changed,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn efficiency_counts_fetches_and_times_changes() {
+        // Fetched hourly, changing at hours 0, 4 and 12.
+        let fetches = (0..=12)
+            .map(|hour| {
+                (
+                    hour,
+                    ![0, 4, 12].contains(&hour),
+                    [0, 4, 12].contains(&hour),
+                )
+            })
+            .collect::<Vec<_>>();
+        let efficiency = Efficiency::of(&history(&fetches));
+        assert_eq!(efficiency.not_modified, 10);
+        assert_eq!(efficiency.full, 3);
+        assert_eq!(efficiency.mean_change_interval, Some(HOUR * 6));
+        // Half the median of 4 and 8 hours.
+        assert_eq!(efficiency.suggested_interval, Some(HOUR * 3));
+
+        // Full fetches which found nothing new aren't changes.
+        let efficiency = Efficiency::of(&history(&[(0, false, true), (1, false, false)]));
+        assert_eq!((efficiency.not_modified, efficiency.full), (0, 2));
+        assert_eq!(efficiency.mean_change_interval, None);
+        assert_eq!(efficiency.suggested_interval, None);
+    }
+
+    #[test]
+    fn nothing_is_suggested_without_two_changes() {
+        for fetches in [
+            &[][..],
+            &[(0, true, false), (1, true, false)],
+            &[(0, false, true), (5, true, false)],
+        ] {
+            let efficiency = Efficiency::of(&history(fetches));
+            assert_eq!(efficiency.mean_change_interval, None, "{fetches:?}");
+            assert_eq!(efficiency.suggested_interval, None, "{fetches:?}");
+        }
+    }
+
+    #[test]
+    fn changes_out_of_order_are_skipped() {
+        // As if the clock went back an hour between the second and third fetches.
+        let efficiency = Efficiency::of(&history(&[
+            (2, false, true),
+            (4, false, true),
+            (3, false, true),
+            (9, false, true),
+        ]));
+        assert_eq!(efficiency.mean_change_interval, Some(HOUR * 4));
+        assert_eq!(efficiency.suggested_interval, Some(HOUR * 2));
+    }
+
+    #[test]
+    fn suggestions_are_half_the_median_within_limits() {
+        let hours = |hours: &[u32]| hours.iter().map(|&hours| HOUR * hours).collect::<Vec<_>>();
+        // An outlier doesn't move the median.
+        assert_eq!(suggest_interval(hours(&[6, 2, 1000, 4, 4])), Some(HOUR * 2));
+        assert_eq!(suggest_interval(hours(&[1, 3, 5, 100])), Some(HOUR * 2));
+        assert_eq!(
+            suggest_interval(vec![Duration::from_secs(60); 3]),
+            Some(MIN_SUGGESTED_INTERVAL)
+        );
+        assert_eq!(
+            suggest_interval(hours(&[24 * 7])),
+            Some(MAX_SUGGESTED_INTERVAL)
+        );
+        assert_eq!(suggest_interval(Vec::new()), None);
+    }
+
+    #[tokio::test]
+    async fn suggestions_are_only_applied_to_sites_fetched_too_often() {
+        let dir = test_dir("tune");
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            "min_fetch_interval = 0\n\n\
+             # Changes every four hours, and is fetched on every run.\n\
+             [[sites]]\nname = \"Often\"\nfeed_url = \"https://example.com/often.xml\"\n\n\
+             # Changes every four hours, and is fetched hourly at most.\n\
+             [[sites]]\nname = \"Hourly\"\nfeed_url = \"https://example.com/hourly.xml\"\n\
+             min_fetch_interval = 3600\n",
+        )
+        .unwrap();
+        let config_files = ConfigFiles::new(vec![config_path.clone()]);
+        let config = config_files.load().unwrap();
+        let caches = CacheManager::new(dir.join("cache"), CacheKey::Name, &config.sites).unwrap();
+        let guard = caches.cache_guard();
+        for site in &config.sites {
+            let mut cache = caches.get_mut(site, &guard).await.unwrap();
+            cache.fetch_history = history(&[(0, false, true), (4, false, true), (8, false, true)])
+                .into_iter()
+                .collect();
+        }
+        drop(guard);
+
+        tune(&config, &caches, &config_files, false).await.unwrap();
+        let unchanged = std::fs::read_to_string(&config_path).unwrap();
+        assert!(!unchanged.contains("7200"), "{unchanged}");
+
+        tune(&config, &caches, &config_files, true).await.unwrap();
+        let updated = config_files.load().unwrap();
+        let intervals = updated
+            .sites
+            .iter()
+            .map(|site| (&*site.name, site.min_fetch_interval))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            intervals,
+            [("Often", Some(2 * 60 * 60)), ("Hourly", Some(60 * 60))]
+        );
+        let contents = std::fs::read_to_string(&config_path).unwrap();
+        assert!(
+            contents.contains("# Changes every four hours, and is fetched on every run."),
+            "{contents}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
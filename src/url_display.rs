@@ -0,0 +1,265 @@
+/// Characters which look like ASCII letters, in scripts where a whole label could be made of
+/// them.
+///
+/// A label made only of these could pass for an ASCII one, so it's shown as punycode.
+const LATIN_LOOKALIKES: &str = "аеорсухіјѕԁԛԝһӏьёґαοιερτυνκχ";
+
+/// The form of a URL to show to readers, as opposed to the one to link to or fetch.
+///
+/// Internationalized domain names are shown in unicode rather than punycode, and percent-encoded
+/// unicode in the path is decoded. To avoid making spoofed domains look legitimate, a label is
+/// only decoded if it round-trips through IDNA and doesn't mix scripts or pass for ASCII, and only
+/// printable characters are decoded in the path. URLs we can't parse are shown as they are.
+pub fn display_url(url: &str) -> Box<str> {
+    let Ok(parsed) = url::Url::parse(url) else {
+        return url.into();
+    };
+    let Some(domain) = parsed.domain() else {
+        return url.into();
+    };
+    use url::Position;
+    let host = domain
+        .split('.')
+        .map(display_label)
+        .collect::<Vec<_>>()
+        .join(".");
+    format!(
+        "{}{host}{}{}{}",
+        &parsed[..Position::BeforeHost],
+        &parsed[Position::AfterHost..Position::BeforePath],
+        decode_path(&parsed[Position::BeforePath..Position::AfterPath]),
+        &parsed[Position::AfterPath..],
+    )
+    .into_boxed_str()
+}
+
+/// The unicode form of one label of a domain, if it's safe to show that instead.
+fn display_label(label: &str) -> std::borrow::Cow<'_, str> {
+    if !label
+        .get(..4)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("xn--"))
+    {
+        return label.into();
+    }
+    let (unicode, Ok(())) = idna::domain_to_unicode(label) else {
+        return label.into();
+    };
+    let round_trips =
+        idna::domain_to_ascii(&unicode).is_ok_and(|ascii| ascii.eq_ignore_ascii_case(label));
+    if round_trips && !is_confusable(&unicode) {
+        unicode.into()
+    } else {
+        log::debug!("Not showing {label} as unicode, it could be mistaken for another domain");
+        label.into()
+    }
+}
+
+/// Whether the given label could be mistaken for a different one.
+fn is_confusable(label: &str) -> bool {
+    let mut scripts = label.chars().filter_map(Script::of).collect::<Vec<_>>();
+    scripts.sort_unstable();
+    scripts.dedup();
+    let mixed = match scripts[..] {
+        [] | [_] => false,
+        // Japanese, Chinese and Korean are commonly written alongside Latin letters.
+        _ => !scripts
+            .iter()
+            .all(|script| matches!(script, Script::Latin | Script::Cjk)),
+    };
+    let passes_for_ascii = matches!(scripts[..], [Script::Cyrillic | Script::Greek])
+        && label
+            .chars()
+            .all(|c| !c.is_alphabetic() || LATIN_LOOKALIKES.contains(c));
+    mixed || passes_for_ascii
+}
+
+/// Roughly which script a character is in, for spotting labels which mix them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Cjk,
+    /// Anything else, by the block of 128 code points it's in.
+    Other(u32),
+}
+impl Script {
+    /// The script of the given character, or `None` for ones like digits and hyphens which are
+    /// used with every script.
+    fn of(c: char) -> Option<Self> {
+        Some(match c as u32 {
+            _ if !c.is_alphabetic() => return None,
+            0x0000..=0x024F | 0x1E00..=0x1EFF => Self::Latin,
+            0x0370..=0x03FF | 0x1F00..=0x1FFF => Self::Greek,
+            0x0400..=0x052F | 0x1C80..=0x1C8F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => Self::Cyrillic,
+            0x0530..=0x058F => Self::Armenian,
+            0x0590..=0x05FF => Self::Hebrew,
+            0x0600..=0x06FF | 0x0750..=0x077F => Self::Arabic,
+            0x1100..=0x11FF
+            | 0x3040..=0x30FF
+            | 0x3100..=0x312F
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xAC00..=0xD7AF => Self::Cjk,
+            code => Self::Other(code >> 7),
+        })
+    }
+}
+
+/// Decode the percent-encoded unicode in a URL path.
+///
+/// Only runs of escapes which decode to printable non-ASCII characters are decoded, so escaped
+/// ASCII like `%2F` keeps its meaning and nothing invisible or direction-changing is let in.
+fn decode_path(path: &str) -> std::borrow::Cow<'_, str> {
+    if !path.contains('%') {
+        return path.into();
+    }
+    let bytes = path.as_bytes();
+    let escaped_byte = |i: usize| {
+        let hex = std::str::from_utf8(bytes.get(i..i + 3)?.strip_prefix(b"%")?).ok()?;
+        u8::from_str_radix(hex, 16)
+            .ok()
+            .filter(|&byte| byte >= 0x80)
+    };
+    let mut out = String::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let mut run = Vec::new();
+        while let Some(byte) = escaped_byte(i) {
+            run.push(byte);
+            i += 3;
+        }
+        match String::from_utf8(run) {
+            Ok(decoded) if i > start && decoded.chars().all(is_printable) => {
+                out.push_str(&decoded);
+            }
+            _ if i > start => out.push_str(&path[start..i]),
+            _ => {
+                let c = path[i..]
+                    .chars()
+                    .next()
+                    .expect("Not at the end of the path");
+                out.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    out.into()
+}
+
+/// Whether a decoded path character is safe to show.
+fn is_printable(c: char) -> bool {
+    let invisible = matches!(
+        c,
+        '\u{AD}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}'
+    ) || c == '\u{FEFF}';
+    !(c.is_control() || c.is_whitespace() || invisible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legitimate_idns_are_shown_in_unicode() {
+        for (url, expected) in [
+            ("https://xn--mnchen-3ya.de/", "https://münchen.de/"),
+            ("https://XN--MNCHEN-3YA.de/", "https://münchen.de/"),
+            ("https://xn--strae-oqa.example/", "https://straße.example/"),
+            ("https://xn--e1afmkfd.xn--p1ai/", "https://пример.рф/"),
+            ("https://xn--hxargifdar.gr/", "https://ελληνικά.gr/"),
+            ("https://xn--r8jz45g.jp/feed", "https://例え.jp/feed"),
+            // Only the host and path change.
+            (
+                "https://user@xn--bcher-kva.example:8080/a?q=%C3%A9#%C3%A9",
+                "https://user@bücher.example:8080/a?q=%C3%A9#%C3%A9",
+            ),
+        ] {
+            assert_eq!(&*display_url(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn labels_which_could_be_mistaken_for_others_are_kept_as_punycode() {
+        for url in [
+            // "pаypal", with a Cyrillic "а".
+            "https://xn--pypal-4ve.com/",
+            // "аррӏе", all in Cyrillic lookalikes of Latin letters.
+            "https://xn--80ak6aa92e.com/",
+        ] {
+            assert_eq!(&*display_url(url), url);
+        }
+        assert_eq!(
+            &*display_url("https://xn--80ak6aa92e.xn--mnchen-3ya.de/"),
+            "https://xn--80ak6aa92e.münchen.de/"
+        );
+    }
+
+    #[test]
+    fn labels_which_dont_decode_cleanly_are_kept_as_punycode() {
+        for url in [
+            // Not valid punycode.
+            "https://xn--abc-.example/",
+            // "münchen" with a combining diaeresis, which doesn't encode back to the same label.
+            "https://xn--munchen-gie.de/",
+            // A zero-width joiner, which IDNA doesn't allow there.
+            "https://xn--x-dha379u.example/",
+        ] {
+            assert_eq!(&*display_url(url), url);
+        }
+    }
+
+    #[test]
+    fn unicode_in_paths_is_decoded() {
+        assert_eq!(
+            &*display_url("https://example.com/caf%C3%A9/%E4%BE%8B"),
+            "https://example.com/café/例"
+        );
+        // Escaped ASCII keeps its meaning.
+        assert_eq!(
+            &*display_url("https://example.com/a%2Fb%20c/%C3%A9%2F"),
+            "https://example.com/a%2Fb%20c/é%2F"
+        );
+        // As does anything which isn't UTF-8.
+        assert_eq!(
+            &*display_url("https://example.com/%C3/%E9"),
+            "https://example.com/%C3/%E9"
+        );
+    }
+
+    #[test]
+    fn invisible_and_direction_changing_characters_are_kept_escaped() {
+        for path in [
+            // Right-to-left override.
+            "/%E2%80%AE",
+            // Zero-width space, next to something which would be decoded on its own.
+            "/caf%C3%A9%E2%80%8B",
+            // First strong isolate.
+            "/%E2%81%A8",
+            // Byte order mark.
+            "/%EF%BB%BF",
+            // Soft hyphen.
+            "/%C2%AD",
+            // Non-breaking space.
+            "/%C2%A0",
+        ] {
+            let url = format!("https://example.com{path}");
+            assert_eq!(&*display_url(&url), url);
+        }
+    }
+
+    #[test]
+    fn urls_without_domains_are_shown_as_they_are() {
+        for url in [
+            "not a url",
+            "https://127.0.0.1/caf%C3%A9",
+            "mailto:someone@xn--mnchen-3ya.de",
+        ] {
+            assert_eq!(&*display_url(url), url);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+/// The form of `url` to compare with other URLs, so different spellings of the same URL match.
+///
+/// The scheme and host are lowercased, default ports and `.` and `..` path segments are removed,
+/// percent-encoding is put in one form, and any fragment is dropped. If `unify_trailing_slash`, a
+/// trailing slash is removed from the path as well. URLs which can't be parsed are compared as
+/// they're given.
+pub fn normalize(url: &str, unify_trailing_slash: bool) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url.trim()) else {
+        return url.to_owned();
+    };
+    parsed.set_fragment(None);
+    match parsed.query().map(normalize_escapes) {
+        Some(query) if query.is_empty() => parsed.set_query(None),
+        Some(query) => parsed.set_query(Some(&query)),
+        None => {}
+    }
+    if !parsed.cannot_be_a_base() {
+        let mut path = normalize_escapes(parsed.path());
+        if unify_trailing_slash && path.len() > 1 && path.ends_with('/') {
+            path.pop();
+        }
+        parsed.set_path(&path);
+    }
+    parsed.into()
+}
+
+/// `text` with its percent-escapes of unreserved characters decoded, and the rest in upper case,
+/// so that `%7e` and `%7E` both become `~`, and `%2f` becomes `%2F`.
+fn normalize_escapes(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut normalized = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(char::from(byte));
+                i += 3;
+            }
+            Some(byte) => {
+                normalized.push_str(&format!("%{byte:02X}"));
+                i += 3;
+            }
+            None => {
+                // Escapes are ASCII, so this is the start of a character.
+                let ch = text[i..].chars().next().expect("In bounds");
+                normalized.push(ch);
+                i += ch.len_utf8();
+            }
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equivalent_urls_match() {
+        for (a, b) in [
+            ("https://example.com/feed", "HTTPS://EXAMPLE.COM/feed"),
+            ("https://example.com/feed", "https://example.com:443/feed"),
+            ("http://example.com/feed", "http://example.com:80/feed"),
+            (
+                "https://example.com/feed",
+                "https://example.com/./a/../feed",
+            ),
+            ("https://example.com/feed", "https://example.com/feed#top"),
+            ("https://example.com/feed", "https://example.com/feed?"),
+            ("https://example.com/~me", "https://example.com/%7eme"),
+            ("https://example.com/~me", "https://example.com/%7Eme"),
+            ("https://example.com/a%2Fb", "https://example.com/a%2fb"),
+            (
+                "https://example.com/?q=a%2Fb",
+                "https://example.com/?q=a%2fb",
+            ),
+            ("https://example.com/?q=-", "https://example.com/?q=%2D"),
+            ("https://example.com/feed", " https://example.com/feed "),
+        ] {
+            assert_eq!(normalize(a, false), normalize(b, false), "{a} and {b}");
+        }
+    }
+
+    #[test]
+    fn different_urls_dont_match() {
+        for (a, b) in [
+            ("https://example.com/feed", "http://example.com/feed"),
+            ("https://example.com/feed", "https://example.com:8443/feed"),
+            ("https://example.com/feed", "https://www.example.com/feed"),
+            ("https://example.com/feed", "https://example.com/Feed"),
+            (
+                "https://example.com/feed",
+                "https://example.com/feed?page=2",
+            ),
+            ("https://example.com/a%2Fb", "https://example.com/a/b"),
+            ("https://example.com/?q=a%26b", "https://example.com/?q=a&b"),
+            ("https://example.com/%2Bf", "https://example.com/%+f"),
+            ("https://example.com/%0F", "https://example.com/%+f"),
+        ] {
+            assert_ne!(normalize(a, false), normalize(b, false), "{a} and {b}");
+        }
+    }
+
+    #[test]
+    fn trailing_slashes_only_match_when_unified() {
+        let (a, b) = ("https://example.com/feed/", "https://example.com/feed");
+        assert_ne!(normalize(a, false), normalize(b, false));
+        assert_eq!(normalize(a, true), normalize(b, true));
+        // The root path keeps its slash, since it can't be empty.
+        assert_eq!(
+            normalize("https://example.com/", true),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn malformed_escapes_are_kept() {
+        assert_eq!(normalize_escapes("%+f"), "%+f");
+        assert_eq!(normalize_escapes("%-1"), "%-1");
+        assert_eq!(normalize_escapes("100%"), "100%");
+        assert_eq!(normalize_escapes("%g0%7e"), "%g0~");
+    }
+
+    #[test]
+    fn unparseable_urls_are_kept() {
+        assert_eq!(normalize("not a url", true), "not a url");
+    }
+}
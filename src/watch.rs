@@ -0,0 +1,374 @@
+use super::{
+    Config, RunOptions, cache::CacheManager, config_files::ConfigFiles, run_and_summarize,
+    trace::ArticleTrace,
+};
+
+use anyhow::{Context, Result};
+use std::{num::NonZeroU32, process::ExitCode, time::Duration};
+
+/// Fetch the feeds which are due and generate the outputs every `interval`, until we're asked to
+/// stop.
+///
+/// The same caches are used by every run, so each is only read from disk once, and throttling
+/// carries over between runs as it would between separate invocations. The config is reloaded
+/// before a run if its files changed since the last one, as are the templates.
+///
+/// With `report_every`, memory use is logged after that many runs, and every that many after.
+pub async fn watch(
+    config_files: &ConfigFiles,
+    mut config: Config,
+    caches: &mut CacheManager,
+    interval: Duration,
+    report_every: Option<NonZeroU32>,
+    options: &mut RunOptions,
+    trace: &ArticleTrace,
+) -> Result<ExitCode> {
+    let mut stop = StopSignals::new().context("Failed to listen for signals to stop on")?;
+    let mut modified = config_files.modified();
+    for runs in 1_u32.. {
+        tokio::select! {
+            res = run_and_summarize(config_files, &mut config, caches, true, options, trace) => {
+                // We'll try again next time, which may well work if this was a network problem.
+                if let Err(e) = res {
+                    log::error!("Run failed: {e:?}");
+                }
+            }
+            () = stop.recv() => break,
+        }
+        if let Some(report_every) = report_every
+            && runs % report_every == 0
+        {
+            report_memory(runs, caches).await;
+        }
+        log::info!(
+            "Waiting {} before the next run",
+            humantime::format_duration(interval)
+        );
+        tokio::select! {
+            () = tokio::time::sleep(interval) => {}
+            () = stop.recv() => break,
+        }
+        let now_modified = config_files.modified();
+        if now_modified != modified {
+            modified = now_modified;
+            if let Err(e) = reload(config_files, &mut config, caches).await {
+                log::error!("{e:?}");
+            }
+        }
+    }
+    log::info!("Saving caches before exiting");
+    let failed = caches.save(config.max_concurrent_saves).await;
+    for (_, e) in &failed {
+        log::error!("{e:?}");
+    }
+    Ok(if failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Log how much memory is in use, and what the caches hold, after the given number of runs.
+async fn report_memory(runs: u32, caches: &CacheManager) {
+    let stats = caches.stats().await;
+    let resident =
+        resident_bytes().map_or_else(|| "unknown".to_owned(), |bytes| format!("{bytes} bytes"));
+    log::info!(
+        "After {runs} runs: resident memory is {resident}. {} site caches are in memory, with {} \
+         bodies of {} bytes in all, and remember {} fetches, entries and links.",
+        stats.caches,
+        stats.bodies,
+        stats.body_bytes,
+        stats.remembered
+    );
+}
+
+/// How much of our memory is resident, in bytes, if that can be told, which it only can on Linux.
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Reload the config, and switch the caches to its sites.
+///
+/// If the new config is invalid, the old one is kept, so a mistake while editing it doesn't stop
+/// the feeds being refreshed.
+async fn reload(
+    config_files: &ConfigFiles,
+    config: &mut Config,
+    caches: &mut CacheManager,
+) -> Result<()> {
+    log::info!("Reloading config from {}", config_files.describe());
+    let new_config = config_files
+        .load()
+        .context("Couldn't reload the config, keeping the old one")?;
+    // The caches of removed sites are dropped, so anything not yet saved would be lost.
+    for (_, e) in caches.save(config.max_concurrent_saves).await {
+        log::error!("{e:?}");
+    }
+    caches
+        .set_sites(new_config.cache_key, &new_config.sites)
+        .context("Couldn't switch to the reloaded config, keeping the old one")?;
+    *config = new_config;
+    Ok(())
+}
+
+/// The signals which ask us to stop: SIGINT, like from Ctrl-C, and on Unix, SIGTERM.
+///
+/// These are listened for from the start, so one arriving between waits isn't missed.
+struct StopSignals {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    ctrl_c: tokio::signal::windows::CtrlC,
+}
+impl StopSignals {
+    #[cfg(unix)]
+    fn new() -> Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(windows)]
+    fn new() -> Result<Self> {
+        Ok(Self {
+            ctrl_c: tokio::signal::windows::ctrl_c()?,
+        })
+    }
+
+    /// Wait for one of the signals.
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = self.interrupt.recv() => log::info!("Got SIGINT, stopping"),
+            _ = self.terminate.recv() => log::info!("Got SIGTERM, stopping"),
+        }
+        #[cfg(windows)]
+        {
+            self.ctrl_c.recv().await;
+            log::info!("Got Ctrl-C, stopping");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CacheKey, templates::Templates, test_server, test_util::test_dir};
+
+    /// Run as `watch` does, giving the page written.
+    async fn run(
+        config_files: &ConfigFiles,
+        config: &mut Config,
+        caches: &CacheManager,
+        options: &mut RunOptions,
+    ) -> String {
+        run_and_summarize(
+            config_files,
+            config,
+            caches,
+            true,
+            options,
+            &ArticleTrace::new(None),
+        )
+        .await
+        .unwrap();
+        std::fs::read_to_string(options.out_html.as_ref().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reloading_switches_to_the_new_sites_unless_the_config_is_invalid() {
+        let dir = test_dir("watch-reload");
+        let feeds = test_server::serve_http(|head| {
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                &format!(
+                    "<rss version=\"2.0\"><channel><title>{path}</title><item>\
+                     <title>{path}</title><link>https://example.com{path}</link>\
+                     <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item>\
+                     </channel></rss>"
+                ),
+            )
+        })
+        .await;
+        let config_path = dir.join("jarss.toml");
+        let site_configs = ["one", "two"].map(|name| {
+            format!("[[sites]]\nname = \"{name}\"\nfeed_url = \"http://{feeds}/{name}\"\n")
+        });
+        std::fs::write(
+            &config_path,
+            format!("min_fetch_interval = 0\n{}", site_configs.concat()),
+        )
+        .unwrap();
+        let config_files = ConfigFiles::new(vec![config_path.clone()]);
+        let mut config = config_files.load().unwrap();
+        let mut caches =
+            CacheManager::new(dir.join("cache"), CacheKey::Url, &config.sites).unwrap();
+        let template = dir.join("links.html.tera");
+        std::fs::write(
+            &template,
+            "{% for article in articles %}{{ article.link }}\n{% endfor %}",
+        )
+        .unwrap();
+        let mut options = RunOptions {
+            templates: Templates::new(Some(template)).unwrap(),
+            manifest: None,
+            summary: None,
+            out_html: Some(dir.join("out.html")),
+            out_feed: None,
+            out_json: None,
+            force: false,
+        };
+        let page = run(&config_files, &mut config, &caches, &mut options).await;
+        assert_eq!(page, "https://example.com/one\nhttps://example.com/two\n");
+
+        // Dropping a site from the config drops it from the page.
+        let modified = config_files.modified();
+        std::fs::write(
+            &config_path,
+            format!("min_fetch_interval = 0\n{}", site_configs[0]),
+        )
+        .unwrap();
+        assert_ne!(config_files.modified(), modified);
+        reload(&config_files, &mut config, &mut caches)
+            .await
+            .unwrap();
+        let page = run(&config_files, &mut config, &caches, &mut options).await;
+        assert_eq!(page, "https://example.com/one\n");
+
+        // A config which doesn't load is ignored.
+        std::fs::write(&config_path, "min_fetch_interval = \"soon\"\n").unwrap();
+        let e = reload(&config_files, &mut config, &mut caches)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("keeping the old one"), "{e}");
+        let page = run(&config_files, &mut config, &caches, &mut options).await;
+        assert_eq!(page, "https://example.com/one\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Run `cycles` runs as `watch` would, returning what the caches hold after each.
+    async fn soak(
+        config_files: &ConfigFiles,
+        config: &mut Config,
+        caches: &CacheManager,
+        options: &mut RunOptions,
+        cycles: usize,
+    ) -> Vec<(usize, usize, usize, usize)> {
+        let mut stats = Vec::new();
+        for _ in 0..cycles {
+            run_and_summarize(
+                config_files,
+                config,
+                caches,
+                true,
+                options,
+                &ArticleTrace::new(None),
+            )
+            .await
+            .unwrap();
+            let cycle = caches.stats().await;
+            stats.push((
+                cycle.caches,
+                cycle.bodies,
+                cycle.body_bytes,
+                cycle.remembered,
+            ));
+        }
+        stats
+    }
+
+    #[tokio::test]
+    async fn repeated_runs_only_remember_their_fetches() {
+        let dir = test_dir("soak");
+        let feeds = test_server::serve_http(|head| {
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            test_server::response(
+                "200 OK",
+                "application/rss+xml",
+                &format!(
+                    "<rss version=\"2.0\"><channel><title>{path}</title><item>\
+                     <title>{path}</title><link>https://example.com{path}</link></item>\
+                     </channel></rss>"
+                ),
+            )
+        })
+        .await;
+        let config_path = dir.join("jarss.toml");
+        let site_configs = ["one", "two", "three"].map(|name| {
+            format!("[[sites]]\nname = \"{name}\"\nfeed_url = \"http://{feeds}/{name}\"\n")
+        });
+        std::fs::write(
+            &config_path,
+            format!("min_fetch_interval = 0\n{}", site_configs.concat()),
+        )
+        .unwrap();
+        let config_files = ConfigFiles::new(vec![config_path.clone()]);
+        let mut config = config_files.load().unwrap();
+        let mut caches =
+            CacheManager::new(dir.join("cache"), CacheKey::Url, &config.sites).unwrap();
+        let mut options = RunOptions {
+            templates: Templates::new(None).unwrap(),
+            manifest: None,
+            summary: None,
+            out_html: Some(dir.join("out.html")),
+            out_feed: None,
+            out_json: None,
+            force: false,
+        };
+
+        let stats = soak(&config_files, &mut config, &caches, &mut options, 5).await;
+        let page = std::fs::read_to_string(dir.join("out.html")).unwrap();
+        let (sites, bodies, body_bytes, remembered) = stats[0];
+        assert_eq!((sites, bodies), (3, 3));
+        // Only the fetch history grows, by a fetch of each site every run.
+        for (runs, cycle) in stats.iter().enumerate() {
+            assert_eq!(
+                *cycle,
+                (3, 3, body_bytes, remembered + runs * 3),
+                "{stats:?}"
+            );
+        }
+
+        // Dropping a site from the config drops its cache, leaving the others as they were.
+        std::fs::write(
+            &config_path,
+            format!(
+                "min_fetch_interval = 0\n{}{}",
+                site_configs[0], site_configs[1]
+            ),
+        )
+        .unwrap();
+        reload(&config_files, &mut config, &mut caches)
+            .await
+            .unwrap();
+        let stats = soak(&config_files, &mut config, &caches, &mut options, 5).await;
+        let (_, _, body_bytes, remembered) = stats[0];
+        for (runs, cycle) in stats.iter().enumerate() {
+            assert_eq!(
+                *cycle,
+                (2, 2, body_bytes, remembered + runs * 2),
+                "{stats:?}"
+            );
+        }
+        let reloaded_page = std::fs::read_to_string(dir.join("out.html")).unwrap();
+        assert!(page.contains("https://example.com/three"));
+        assert!(!reloaded_page.contains("https://example.com/three"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}